@@ -1,73 +1,422 @@
+use crate::array::ArraySize;
 use crate::ast::Ast;
+use crate::chc;
+use crate::disassemble::disassemble_function;
+use crate::error::{err_load, ErrorKind, RuntimeError};
 use crate::lexer::Lexer;
+use crate::loader::{CompiledModule, Loader};
 use crate::parser::Parser;
+use crate::function::Function;
 use crate::generator::Generator;
+use crate::memory::{Memory, Mutator, MutatorView};
+use crate::safe_ptr::ScopedPtr;
+use crate::vm::{StackLimits, Thread};
 
 use std::env;
+use std::fs;
+use std::path::Path;
+use std::process;
 
 pub struct App {
     lexer: Lexer,
     parser: Parser,
     ast: Ast,
     generator: Generator,
-    //heap: Heap,
+    /// Compiles the modules a script imports
+    loader: Loader,
+    memory: Memory,
+    /// Whether generated code is optimized before it is run or saved
+    optimize: bool,
+    /// Recursion limits for the Threads that run scripts
+    limits: StackLimits,
+    /// The command line arguments given to scripts, as `io.args`
+    args: Vec<String>,
     // interpreter: Interpreter,
 }
 
 impl App {
     pub fn init() -> Self {
+        Self::with_memory(Memory::new())
+    }
+
+    /// Initialize an App whose heap will refuse to grow past `limit` bytes
+    pub fn with_heap_limit(limit: usize) -> Self {
+        Self::with_memory(Memory::with_limit(limit))
+    }
+
+    fn with_memory(memory: Memory) -> Self {
         Self {
             lexer: Lexer::init(),
             parser: Parser::init(),
             ast: Ast::init(),
             generator: Generator::init(),
-            //heap: Heap::default()
+            loader: Loader::from_env(),
+            memory,
+            optimize: false,
+            limits: StackLimits::default(),
+            args: Vec::new(),
         }
     }
 
+    /// Enable or disable the optimization passes over generated code
+    pub fn set_optimize(&mut self, optimize: bool) {
+        self.optimize = optimize;
+        self.loader.set_optimize(optimize);
+    }
+
+    /// Limit the number of call frames a script may have before it raises a stack overflow error
+    pub fn set_max_call_depth(&mut self, depth: usize) {
+        self.limits.max_call_depth = depth.min(ArraySize::MAX as usize) as ArraySize;
+    }
+
+    /// Limit the number of value stack slots a script may use before it raises a stack overflow
+    /// error
+    pub fn set_max_stack_size(&mut self, slots: usize) {
+        self.limits.max_stack_size = slots.min(ArraySize::MAX as usize) as ArraySize;
+    }
+
+    /// Set the command line arguments given to scripts
+    pub fn set_script_args(&mut self, args: &[&str]) {
+        self.args = args.iter().map(|arg| String::from(*arg)).collect();
+    }
+
+    /// Run a script, or a compiled `.chc` file
     pub fn run(&mut self, file_path: &str) {
+        if is_compiled(file_path) {
+            if let Ok(bytes) = read_compiled(file_path) {
+                let script = Script(Program::Compiled(&bytes), &[], self.limits, &self.args);
+                let result = self.memory.mutate(&script, ());
+                if let Err(error) = result {
+                    report_error(error, "");
+                }
+            }
+            return;
+        }
+
+        if self.lexer.open_file(file_path).is_err() { return; }
+
+        let compiled = self.compile(file_path);
+        let script = Script(
+            Program::Generated(&self.generator),
+            self.loader.modules(),
+            self.limits,
+            &self.args,
+        );
+        let result = compiled.and_then(|_| self.memory.mutate(&script, ()));
+        self.memory.release_arena();
+        if let Err(error) = result {
+            report_error(error, self.error_source());
+        }
+
+        self.ast.clear();
+    }
+
+    /// Compile a file, or load a `.chc` file, and print its bytecode listing rather than running it
+    pub fn disassemble(&mut self, file_path: &str) {
+        if is_compiled(file_path) {
+            if let Ok(bytes) = read_compiled(file_path) {
+                match self.memory.mutate(&Listing(Program::Compiled(&bytes)), ()) {
+                    Ok(listing) => print!("{}", listing),
+                    Err(error) => error.print_with_source(""),
+                }
+            }
+            return;
+        }
+
+        if self.lexer.open_file(file_path).is_err() { return; }
+
+        let compiled = self.compile(file_path);
+        let result = compiled
+            .and_then(|_| self.memory.mutate(&Listing(Program::Generated(&self.generator)), ()));
+        self.memory.release_arena();
+        match result {
+            Ok(listing) => print!("{}", listing),
+            Err(error) => error.print_with_source(self.error_source()),
+        }
+
+        self.ast.clear();
+    }
+
+    /// Compile a script and save its bytecode to `output` so it can be run without recompiling.
+    /// The source modules it imports could not be saved with it, so a script that imports any is
+    /// refused rather than written out unrunnable; native modules need nothing saved.
+    pub fn compile_file(&mut self, file_path: &str, output: &str) {
         if self.lexer.open_file(file_path).is_err() { return; }
 
-        self.parser.build_ast(&mut self.lexer, &mut self.ast);
-        self.ast.traverse(&mut self.generator);
-        // generator.optimize();
+        let mut compiled = self.compile(file_path);
+        if compiled.is_ok() && !self.loader.modules().is_empty() {
+            let reason = "Cannot compile a script that imports source modules";
+            compiled = Err(err_load(reason));
+        }
+        let result = compiled
+            .and_then(|_| self.memory.mutate(&Save(&self.generator), ()))
+            .and_then(|bytes| Ok(fs::write(output, bytes)?));
+        self.memory.release_arena();
+        if let Err(error) = result {
+            error.print_with_source(self.error_source());
+        }
+
+        self.ast.clear();
+    }
+
+    /// Parse the open file, `file_path`, and generate code for it and the modules it imports
+    fn compile(&mut self, file_path: &str) -> Result<(), RuntimeError> {
+        self.loader.clear();
+
+        let parsed = self.parser.build_ast(&mut self.lexer, &mut self.ast);
+        let compiled = parsed.and_then(|_| self.ast.traverse(&mut self.generator));
+        if self.optimize && compiled.is_ok() {
+            self.generator.optimize();
+        }
 
         if env::var("DEBUG").is_ok() {
             self.ast.display();
         }
 
-        // if errors
-        //   display lex errors
-        //   display ast errors
+        compiled?;
+        self.loader
+            .load_imports(&mut self.parser, Path::new(file_path), &mut self.generator)
+    }
+
+    /// The source of the script or module an error was raised compiling
+    fn error_source(&self) -> &str {
+        self.loader.failed_source().unwrap_or(self.lexer.source())
+    }
+}
 
-        self.ast.clear();
+/// Show an error that ended a script in the context of its source, unless the script asked to
+/// exit, in which case the process exits with the status it gave
+fn report_error(error: RuntimeError, source: &str) {
+    match error.error_kind() {
+        ErrorKind::Exit(status) => process::exit(*status),
+        _ => error.print_with_source(source),
+    }
+}
+
+fn is_compiled(file_path: &str) -> bool {
+    file_path.ends_with(".chc")
+}
+
+fn read_compiled(file_path: &str) -> Result<Vec<u8>, ()> {
+    fs::read(file_path).map_err(|_| println!("Unable to open file: {}", file_path))
+}
+
+/// The top-level code of a script, either freshly generated or saved in a `.chc` file
+enum Program<'a> {
+    Generated(&'a Generator),
+    Compiled(&'a [u8]),
+}
+
+impl<'a> Program<'a> {
+    fn function<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+    ) -> Result<ScopedPtr<'guard, Function>, RuntimeError> {
+        match self {
+            Program::Generated(generator) => generator.function(mem),
+            Program::Compiled(bytes) => chc::load(mem, bytes),
+        }
+    }
+}
+
+/// Evaluates the top-level code of a script, which may import the given modules, on a new Thread
+/// with the given recursion limits and command line arguments
+struct Script<'a>(Program<'a>, &'a [CompiledModule], StackLimits, &'a [String]);
+
+impl<'a> Mutator for Script<'a> {
+    type Input = ();
+    type Output = ();
+
+    fn run(&self, mem: &MutatorView, _input: ()) -> Result<(), RuntimeError> {
+        let function = self.0.function(mem)?;
+        let thread = Thread::alloc(mem)?;
+        thread.set_limits(self.2);
+        thread.set_args(mem, self.3)?;
+
+        for module in self.1 {
+            thread.add_module(mem, &module.path, module.generator.function(mem)?)?;
+        }
+
+        thread.quick_vm_eval(mem, function)?;
+        Ok(())
+    }
+}
+
+/// Renders the top-level code of a script, and any functions it contains, as text
+struct Listing<'a>(Program<'a>);
+
+impl<'a> Mutator for Listing<'a> {
+    type Input = ();
+    type Output = String;
+
+    fn run(&self, mem: &MutatorView, _input: ()) -> Result<String, RuntimeError> {
+        let function = self.0.function(mem)?;
+        Ok(disassemble_function(mem, function))
     }
 }
+
+/// Serializes the generated top-level code of a script to the `.chc` file format
+struct Save<'a>(&'a Generator);
+
+impl<'a> Mutator for Save<'a> {
+    type Input = ();
+    type Output = Vec<u8>;
+
+    fn run(&self, mem: &MutatorView, _input: ()) -> Result<Vec<u8>, RuntimeError> {
+        let function = self.0.function(mem)?;
+        chc::save(mem, function)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::builtins::capture_output;
+
+    /// Run an example script and return the lines it printed
+    fn run_example(app: &mut App, path: &str) -> Vec<String> {
+        let output = capture_output(|| app.run(path));
+        output.lines().map(String::from).collect()
+    }
 
     #[test]
     fn first_words() {
         let mut app = App::init();
-        app.run("examples/first_words.ch");
+        let output = run_example(&mut app, "examples/first_words.ch");
+        assert!(output == ["Niland loves Margo"]);
     }
 
     #[test]
     fn hello_world() {
         let mut app = App::init();
-        app.run("examples/hello_world.ch");
+        let output = run_example(&mut app, "examples/hello_world.ch");
+        assert!(output.len() == 6);
+        for pair in output.chunks(2) {
+            assert!(pair == ["hello world! This is Chorus", "im a nested func"]);
+        }
     }
 
     #[test]
     fn foobar() {
         let mut app = App::init();
-        app.run("examples/foobar.ch");
+        let output = run_example(&mut app, "examples/foobar.ch");
+        assert!(output == ["69", "420", "489"]);
     }
 
     #[test]
     fn numbers() {
         let mut app = App::init();
-        app.run("examples/numbers.ch");
+        let output = run_example(&mut app, "examples/numbers.ch");
+        assert!(output == ["1", "2", "3", "-1", "69"]);
+    }
+
+    #[test]
+    fn math() {
+        let mut app = App::init();
+        let output = run_example(&mut app, "examples/math.ch");
+        let expected = [
+            "1.4142135623730951",
+            "1024",
+            "(-4 . 1)",
+            "2",
+            "3.141592653589793",
+            "3",
+        ];
+        assert!(output == expected);
+    }
+
+    #[test]
+    fn io() {
+        let mut app = App::init();
+        app.set_script_args(&["first", "second"]);
+        let output = run_example(&mut app, "examples/io.ch");
+        assert!(output == [r#"["first", "second"]"#, "true", "true", "10", "nil"]);
+    }
+
+    #[test]
+    fn io_reads_and_writes_files_from_a_script() {
+        let dir = env::temp_dir().join(format!("chorus-io-script-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+        env::set_var("CHORUS_IO_SCRIPT_SET", "set");
+
+        let script = format!(
+            "import \"io\" as io;\n\
+             import \"fs\" as fs;\n\
+             notes = {:?}\n\
+             io.write_file(notes, \"one\\n\");\n\
+             io.append_file(notes, \"two\\n\");\n\
+             print(io.lines(notes));\n\
+             print(len(io.read_file(notes)));\n\
+             print(fs.list_dir({:?}));\n\
+             print(io.env(\"CHORUS_IO_SCRIPT_SET\"));\n\
+             print(io.env(\"CHORUS_IO_SCRIPT_UNSET\"));\n\
+             print(io.args);\n\
+             try {{ io.read_file({:?}); }} catch e {{ print(message(e)); }}\n",
+            path("notes.txt"),
+            dir.to_str().unwrap(),
+            path("missing.txt"),
+        );
+        fs::write(dir.join("script.ch"), script).unwrap();
+
+        let mut app = App::init();
+        app.set_script_args(&["-v", "input.txt"]);
+        let output = run_example(&mut app, &path("script.ch"));
+
+        let expected = [
+            r#"["one", "two"]"#,
+            "8",
+            r#"["notes.txt", "script.ch"]"#,
+            "set",
+            "nil",
+            r#"["-v", "input.txt"]"#,
+        ];
+        assert!(output.len() == 7);
+        assert!(output[..6] == expected);
+        let missing = format!("IO Error: {}: ", path("missing.txt"));
+        assert!(output[6].starts_with(&missing));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn json() {
+        let mut app = App::init();
+        let output = run_example(&mut app, "examples/json.ch");
+
+        let expected = [
+            r#"{"name": "chorus", "retries": 3, "tags": ["fast", "small"]}"#,
+            r#"{"name":"chorus","retries":3,"tags":["fast","small"]}"#,
+            "{",
+            r#"  "name": "chorus","#,
+            r#"  "retries": 3,"#,
+            r#"  "tags": ["#,
+            r#"    "fast","#,
+            r#"    "small""#,
+            "  ]",
+            "}",
+        ];
+        assert!(output == expected);
+    }
+
+    #[test]
+    fn compile_file_refuses_imported_modules() {
+        let dir = env::temp_dir().join(format!("chorus-compile-import-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("lib.ch"), "x = 1\n").unwrap();
+        fs::write(dir.join("main.ch"), "import \"lib.ch\" as lib\n").unwrap();
+        fs::write(dir.join("plain.ch"), "x = 1\n").unwrap();
+
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+        let mut app = App::init();
+
+        app.compile_file(&path("main.ch"), &path("main.chc"));
+        assert!(!dir.join("main.chc").exists());
+
+        app.compile_file(&path("plain.ch"), &path("plain.chc"));
+        assert!(dir.join("plain.chc").exists());
+
+        fs::remove_dir_all(&dir).unwrap();
     }
 }
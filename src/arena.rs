@@ -1,81 +1,256 @@
-use std::ptr::NonNull;
+/// A non-collecting region allocator
+///
+/// An `Arena` hands out memory by bumping a pointer through fixed-size blocks and never frees
+/// individual objects; everything is released together when the arena is reset or dropped.
+/// It is meant for objects that live exactly as long as a compilation: interned Symbols,
+/// literal lists and the ByteCode of top-level scripts.
+///
+/// Objects are laid out exactly as the Immix heap lays them out - an `ObjectHeader` immediately
+/// followed by the object - so a `TaggedPtr` to an arena object decodes through its header the
+/// same way as a pointer to a heap object.
+use std::alloc::{alloc, dealloc, Layout};
+use std::cell::{Cell, RefCell};
+use std::mem::size_of;
+use std::ptr::{write, write_bytes, NonNull};
 
 use crate::allocator::{
     AllocError, AllocHeader, AllocObject, AllocRaw, ArraySize, Mark, SizeClass };
-use crate::heap::ImmixHeap;
 use crate::raw_ptr::RawPtr;
 
-use crate::header::TypeList;
+use crate::constants::{BLOCK_SIZE, MAX_ALLOC_SIZE};
+use crate::header::{ObjectHeader, TypeList};
 
-pub struct ArenaHeader {}
+/// Round an allocation size up to the next word boundary
+fn alloc_size_of(size: usize) -> usize {
+    let align = size_of::<usize>();
+    (size + align - 1) & !(align - 1)
+}
+
+/// A chunk of memory owned by the arena
+struct ArenaBlock {
+    ptr: NonNull<u8>,
+    layout: Layout,
+}
+
+impl ArenaBlock {
+    fn new(size: usize) -> Result<ArenaBlock, AllocError> {
+        let layout =
+            Layout::from_size_align(size, size_of::<usize>()).map_err(|_| AllocError::BadRequest)?;
 
-impl AllocHeader for ArenaHeader {
-    type TypeId = TypeList;
+        let ptr = NonNull::new(unsafe { alloc(layout) }).ok_or(AllocError::OOM)?;
 
-    fn new<O: AllocObject<Self::TypeId>>(
-        _size: u32,
-        _size_class: SizeClass,
-        _mark: Mark,
-    ) -> ArenaHeader {
-        ArenaHeader {}
+        Ok(ArenaBlock { ptr, layout })
     }
+}
 
-    fn new_array(_size: ArraySize, _size_class: SizeClass, _mark: Mark) -> ArenaHeader {
-        ArenaHeader {}
+impl Drop for ArenaBlock {
+    fn drop(&mut self) {
+        unsafe { dealloc(self.ptr.as_ptr(), self.layout) }
     }
+}
 
-    fn mark(&mut self) {}
+/// A bump-pointer region allocator
+// ANCHOR: DefArena
+pub struct Arena {
+    /// Every block allocated so far, freed together
+    blocks: RefCell<Vec<ArenaBlock>>,
+    /// Address of the next free byte in the current block
+    cursor: Cell<usize>,
+    /// Address of the end of the current block
+    limit: Cell<usize>,
+}
+// ANCHOR_END: DefArena
 
-    fn is_marked(&self) -> bool {
-        true
+impl Arena {
+    pub fn new() -> Arena {
+        Arena {
+            blocks: RefCell::new(Vec::new()),
+            cursor: Cell::new(0),
+            limit: Cell::new(0),
+        }
     }
 
-    fn size_class(&self) -> SizeClass {
-        SizeClass::Small
+    /// Free everything allocated in the arena. Any pointers into the arena must be dead by now,
+    /// which exclusive access to the arena goes some way to ensuring.
+    pub fn reset(&mut self) {
+        self.blocks.borrow_mut().clear();
+        self.cursor.set(0);
+        self.limit.set(0);
     }
 
-    fn size(&self) -> u32 {
-        1
+    /// Return the number of bytes of block memory the arena currently owns
+    pub fn capacity(&self) -> usize {
+        self.blocks
+            .borrow()
+            .iter()
+            .map(|block| block.layout.size())
+            .sum()
     }
 
-    fn type_id(&self) -> TypeList {
-        TypeList::Symbol
+    /// Return true if the address is inside one of the arena's blocks
+    pub fn contains(&self, addr: usize) -> bool {
+        self.blocks.borrow().iter().any(|block| {
+            let start = block.ptr.as_ptr() as usize;
+            addr >= start && addr < start + block.layout.size()
+        })
     }
-}
 
-pub struct Arena {
-    heap: ImmixHeap<ArenaHeader>,
-}
+    /// Return the number of bytes of new block memory that allocating an object of `size_bytes`
+    /// would take, which is zero if it fits in the current block
+    pub fn growth_for(&self, size_bytes: usize) -> usize {
+        let size = alloc_size_of(size_of::<ObjectHeader>() + size_bytes);
 
-impl Arena {
-    pub fn new() -> Arena {
-        Arena {
-            heap: ImmixHeap::new(),
+        if size > BLOCK_SIZE {
+            size
+        } else if self.cursor.get() + size > self.limit.get() {
+            BLOCK_SIZE
+        } else {
+            0
         }
     }
+
+    /// Find space for `size` bytes, allocating a new block if the current one is full
+    fn bump(&self, size: usize) -> Result<*mut u8, AllocError> {
+        if size > MAX_ALLOC_SIZE {
+            return Err(AllocError::BadRequest);
+        }
+
+        if size > BLOCK_SIZE {
+            // large requests get a block of their own so the current block can still be used
+            let block = ArenaBlock::new(size)?;
+            let ptr = block.ptr.as_ptr();
+            self.blocks.borrow_mut().push(block);
+            return Ok(ptr);
+        }
+
+        if self.cursor.get() + size > self.limit.get() {
+            let block = ArenaBlock::new(BLOCK_SIZE)?;
+            let start = block.ptr.as_ptr() as usize;
+            self.cursor.set(start);
+            self.limit.set(start + BLOCK_SIZE);
+            self.blocks.borrow_mut().push(block);
+        }
+
+        let ptr = self.cursor.get();
+        self.cursor.set(ptr + size);
+        Ok(ptr as *mut u8)
+    }
 }
 
 impl AllocRaw for Arena {
-    type Header = ArenaHeader;
+    type Header = ObjectHeader;
 
     // ANCHOR: DefArenaAlloc
     fn alloc<T>(&self, object: T) -> Result<RawPtr<T>, AllocError>
     where
         T: AllocObject<TypeList>,
     {
-        self.heap.alloc(object)
+        let header_size = size_of::<ObjectHeader>();
+        let object_size = size_of::<T>();
+        let alloc_size = alloc_size_of(header_size + object_size);
+        let size_class = SizeClass::get_for_size(alloc_size)?;
+
+        let space = self.bump(alloc_size)?;
+
+        // Arena objects are never collected, so they are born marked: as far as the collector
+        // is concerned they are permanently old.
+        let header = ObjectHeader::new::<T>(object_size as ArraySize, size_class, Mark::Marked);
+
+        unsafe {
+            write(space as *mut ObjectHeader, header);
+            let object_space = space.offset(header_size as isize) as *mut T;
+            write(object_space, object);
+            Ok(RawPtr::new(object_space))
+        }
     }
     // ANCHOR_END: DefArenaAlloc
 
-    fn alloc_array(&self, _size_bytes: ArraySize) -> Result<RawPtr<u8>, AllocError> {
-        unimplemented!()
+    fn alloc_array(&self, size_bytes: ArraySize) -> Result<RawPtr<u8>, AllocError> {
+        let header_size = size_of::<ObjectHeader>();
+        let alloc_size = alloc_size_of(header_size + size_bytes as usize);
+        let size_class = SizeClass::get_for_size(alloc_size)?;
+
+        let space = self.bump(alloc_size)?;
+
+        let header = ObjectHeader::new_array(size_bytes, size_class, Mark::Marked);
+
+        unsafe {
+            write(space as *mut ObjectHeader, header);
+            let array_space = space.offset(header_size as isize);
+            write_bytes(array_space, 0, size_bytes as usize);
+            Ok(RawPtr::new(array_space))
+        }
+    }
+
+    fn get_header(object: NonNull<()>) -> NonNull<Self::Header> {
+        unsafe { NonNull::new_unchecked(object.cast::<ObjectHeader>().as_ptr().offset(-1)) }
+    }
+
+    fn get_object(header: NonNull<Self::Header>) -> NonNull<()> {
+        unsafe { NonNull::new_unchecked(header.as_ptr().offset(1).cast::<()>()) }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::slice;
+
+    use super::Arena;
+    use crate::allocator::{AllocHeader, AllocRaw};
+    use crate::constants::BLOCK_SIZE;
+    use crate::memory::HeapStorage;
+    use crate::pair::Pair;
+    use crate::symbol::Symbol;
+
+    #[test]
+    fn arena_matches_heap_layout() {
+        let arena = Arena::new();
+        let heap = HeapStorage::new();
+
+        let name = String::from("arena");
+        let in_arena = arena.alloc(Symbol::new(&name)).unwrap();
+        let in_heap = heap.alloc(Symbol::new(&name)).unwrap();
+
+        // the object reads back the same from either allocator
+        unsafe {
+            assert!(in_arena.as_ref().unguarded_as_str() == in_heap.as_ref().unguarded_as_str());
+        }
+
+        // headers are found, and describe the object, the same way
+        let arena_header = Arena::get_header(in_arena.as_untyped());
+        let heap_header = HeapStorage::get_header(in_heap.as_untyped());
+        unsafe {
+            assert!(arena_header.as_ref().type_id() == heap_header.as_ref().type_id());
+            assert!(arena_header.as_ref().size() == heap_header.as_ref().size());
+        }
+        assert!(Arena::get_object(arena_header) == in_arena.as_untyped());
+        assert!(HeapStorage::get_header(in_arena.as_untyped()) == arena_header);
     }
 
-    fn get_header(_object: NonNull<()>) -> NonNull<Self::Header> {
-        unimplemented!()
+    #[test]
+    fn arena_arrays_are_zeroed() {
+        let arena = Arena::new();
+
+        let array = arena.alloc_array(64).unwrap();
+        let bytes = unsafe { slice::from_raw_parts(array.as_ptr(), 64) };
+        assert!(bytes.iter().all(|byte| *byte == 0));
     }
 
-    fn get_object(_header: NonNull<Self::Header>) -> NonNull<()> {
-        unimplemented!()
+    #[test]
+    fn arena_grows_and_resets() {
+        let mut arena = Arena::new();
+
+        // enough pairs to fill several blocks
+        for _ in 0..(BLOCK_SIZE / 8) {
+            arena.alloc(Pair::new()).unwrap();
+        }
+        assert!(arena.capacity() > BLOCK_SIZE);
+
+        // a large array gets a dedicated block
+        arena.alloc_array((BLOCK_SIZE * 2) as u32).unwrap();
+        assert!(arena.capacity() > BLOCK_SIZE * 3);
+
+        arena.reset();
+        assert!(arena.capacity() == 0);
     }
 }
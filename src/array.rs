@@ -17,12 +17,14 @@ use crate::container::{
     StackAnyContainer, StackContainer,
 };
 use crate::error::{ErrorKind, RuntimeError};
+use crate::gc::write_barrier_tagged;
 use crate::header::TypeList;
 use crate::memory::MutatorView;
 use crate::printer::Print;
 use crate::raw_array::{default_array_growth, RawArray, DEFAULT_ARRAY_SIZE};
 use crate::safe_ptr::{MutatorScope, ScopedPtr, TaggedCellPtr, TaggedScopedPtr};
 use crate::tagged_ptr::Value;
+use crate::trace::{visit_raw_array, visit_tagged_cell, Trace, Visitor};
 
 // For a RefCell-style interior mutability pattern
 type BorrowFlag = isize;
@@ -80,6 +82,19 @@ impl<T: Sized + Clone> Array<T> {
         mem.alloc(Array::with_capacity(mem, capacity)?)
     }
 
+    /// Return a new instance whose backing storage, of the given capacity, is in the compilation
+    /// arena
+    pub fn with_capacity_in_arena<'guard>(
+        mem: &'guard MutatorView,
+        capacity: ArraySize,
+    ) -> Result<Array<T>, RuntimeError> {
+        Ok(Array {
+            length: Cell::new(0),
+            data: Cell::new(RawArray::with_capacity_in_arena(mem, capacity)?),
+            borrow: Cell::new(INTERIOR_ONLY),
+        })
+    }
+
     /// Return a bounds-checked pointer to the object at the given index
     // ANCHOR: DefArrayGetOffset
     fn get_offset(&self, index: ArraySize) -> Result<*mut T, RuntimeError> {
@@ -167,6 +182,11 @@ impl<T: Sized + Clone> Array<T> {
             &mut []
         }
     }
+
+    /// Visit the backing storage of the array
+    pub fn trace_backing(&self, visit: &mut Visitor) {
+        visit_raw_array(&self.data.get(), visit);
+    }
 }
 
 impl<T: Sized + Clone> Container<T> for Array<T> {
@@ -376,6 +396,34 @@ impl Print for ArrayU32 {
     }
 }
 
+/// Arrays of plain values only reference their backing storage
+macro_rules! trace_backing_only {
+    ($T:ty) => {
+        impl Trace for Array<$T> {
+            fn trace<'guard>(&self, _guard: &'guard dyn MutatorScope, visit: &mut Visitor) {
+                self.trace_backing(visit);
+            }
+        }
+    };
+}
+
+trace_backing_only!(u8);
+trace_backing_only!(u16);
+trace_backing_only!(u32);
+trace_backing_only!(u64);
+
+impl Trace for Array<TaggedCellPtr> {
+    fn trace<'guard>(&self, guard: &'guard dyn MutatorScope, visit: &mut Visitor) {
+        self.trace_backing(visit);
+
+        for index in 0..self.length() {
+            if let Ok(item) = self.read_ref(guard, index) {
+                visit_tagged_cell(item, visit);
+            }
+        }
+    }
+}
+
 impl FillAnyContainer for Array<TaggedCellPtr> {
     fn fill<'guard>(
         &self,
@@ -404,6 +452,7 @@ impl FillAnyContainer for Array<TaggedCellPtr> {
 
             self.length.set(size);
 
+            write_barrier_tagged(item.get_ptr());
             for index in length..size {
                 self.write(mem, index, TaggedCellPtr::new_with(item))?;
             }
@@ -421,6 +470,7 @@ impl StackAnyContainer for Array<TaggedCellPtr> {
         mem: &'guard MutatorView,
         item: TaggedScopedPtr<'guard>,
     ) -> Result<(), RuntimeError> {
+        write_barrier_tagged(item.get_ptr());
         StackContainer::<TaggedCellPtr>::push(self, mem, TaggedCellPtr::new_with(item))
     }
     // ANCHOR_END: DefStackAnyContainerArrayPush
@@ -475,8 +525,8 @@ impl AnyContainerFromPairList for Array<TaggedCellPtr> {
 
         let mut head = pair_list;
         while let Value::Pair(p) = *head {
-            StackAnyContainer::push(self, mem, p.first.get(mem))?;
-            head = p.second.get(mem);
+            StackAnyContainer::push(self, mem, p.first(mem))?;
+            head = p.second(mem);
         }
 
         Ok(())
@@ -517,6 +567,25 @@ impl AnyContainerFromSlice for Array<TaggedCellPtr> {
     }
 }
 
+impl Array<TaggedCellPtr> {
+    /// Allocate a list of the given items, and its backing storage, in the compilation arena
+    pub fn from_slice_in_arena<'guard>(
+        mem: &'guard MutatorView,
+        data: &[TaggedScopedPtr<'guard>],
+    ) -> Result<ScopedPtr<'guard, Self>, RuntimeError> {
+        let capacity = data.len() as ArraySize;
+        let array = mem.alloc_in_arena(Array::with_capacity_in_arena(mem, capacity)?)?;
+        let slice = unsafe { array.as_capacity_slice(mem) };
+
+        for index in 0..data.len() {
+            slice[index] = TaggedCellPtr::new_with(data[index])
+        }
+
+        array.length.set(data.len() as ArraySize);
+        Ok(array)
+    }
+}
+
 impl Print for Array<TaggedCellPtr> {
     fn print<'guard>(
         &self,
@@ -526,7 +595,7 @@ impl Print for Array<TaggedCellPtr> {
         write!(f, "[")?;
 
         for i in 0..self.length() {
-            if i > 1 {
+            if i > 0 {
                 write!(f, ", ")?;
             }
 
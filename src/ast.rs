@@ -1,4 +1,6 @@
+use crate::error::{err_compile, RuntimeError};
 use crate::generator::Generator;
+use crate::number::floor_mod;
 use crate::tokens::Tok;
 use std::collections::HashMap;
 
@@ -40,28 +42,6 @@ impl Node {
             _ => true,
         }
     }
-
-    pub fn traverse(&mut self, generator: &mut Generator) {
-        self.preprocess_node(generator);
-
-        for child in self.children.iter_mut().rev() {
-            child.traverse(generator);
-        }
-
-        self.process_node(generator);
-    }
-
-    fn preprocess_node(&mut self, generator: &mut Generator) {
-        match self.token {
-            _ => {}
-        }
-    }
-
-    fn process_node(&mut self, generator: &mut Generator) {
-        match self.token {
-            _ => {}
-        }
-    }
 }
 
 impl Ast {
@@ -81,21 +61,28 @@ impl Ast {
         symbol_table
     }
 
-    pub fn synthesize_expr(&mut self, op: Tok, left_val: NodeVal, right_val: NodeVal) {
-        match op {
-            Tok::Plus => match (left_val, right_val) {
-                (NodeVal::Int(l), NodeVal::Int(r)) => {
-                    self.push_node(Tok::Int, Some(NodeVal::Int(l + r)));
-                }
-                _ => {}
-            },
-            Tok::Minus => match (left_val, right_val) {
-                (NodeVal::Int(l), NodeVal::Int(r)) => {
-                    self.push_node(Tok::Int, Some(NodeVal::Int(l - r)));
-                }
-                _ => {}
-            },
-            _ => {}
+    /// Push the result of a binary operation on two constants and return true, or return false
+    /// to leave it to run time: when either is not an integer, the result does not fit in an
+    /// integer literal or a modulo is by zero
+    pub fn synthesize_expr(&mut self, op: Tok, left_val: &NodeVal, right_val: &NodeVal) -> bool {
+        let (l, r) = match (left_val, right_val) {
+            (NodeVal::Int(l), NodeVal::Int(r)) => (*l as isize, *r as isize),
+            _ => return false,
+        };
+
+        let value = match op {
+            Tok::Plus => Some(l + r),
+            Tok::Minus => Some(l - r),
+            Tok::Modulo => floor_mod(l, r),
+            _ => None,
+        };
+
+        match value.and_then(|value| i32::try_from(value).ok()) {
+            Some(value) => {
+                self.push_node(Tok::Int, Some(NodeVal::Int(value)));
+                true
+            }
+            None => false,
         }
     }
 
@@ -132,9 +119,12 @@ impl Ast {
         }
     }
 
-    pub fn traverse(&mut self, generator: &mut Generator) {
-        self.node_stack[0].traverse(generator);
-        // generator.complete_generation();
+    pub fn traverse(&mut self, generator: &mut Generator) -> Result<(), RuntimeError> {
+        if self.node_stack.is_empty() {
+            return Err(err_compile("Nothing to compile, the AST is empty"));
+        }
+
+        generator.generate(&self.node_stack[0], &self.symbol_table)
     }
 
     pub fn display(&self) {
@@ -0,0 +1,470 @@
+/// Native functions bound as globals in every new Thread
+use std::cell::RefCell;
+use std::cmp::Ordering;
+
+use crate::container::{
+    Container, HashIndexedAnyContainer, SliceableContainer, StackAnyContainer,
+};
+use crate::dict::Dict;
+use crate::error::{err_eval, ErrorKind, RuntimeError};
+use crate::exception::Exception;
+use crate::function::Partial;
+use crate::iter::Range;
+use crate::list::List;
+use crate::memory::MutatorView;
+use crate::native::{NativeFn, NativeFunction};
+use crate::safe_ptr::{MutatorScope, ScopedPtr, TaggedCellPtr, TaggedScopedPtr};
+use crate::tagged_ptr::{TaggedPtr, Value};
+use crate::text::Text;
+
+/// Bind every builtin function in the given globals dict
+pub fn install<'guard>(mem: &'guard MutatorView, globals: &Dict) -> Result<(), RuntimeError> {
+    define(mem, globals, "keys", 1, keys)?;
+    define(mem, globals, "values", 1, values)?;
+    define(mem, globals, "items", 1, items)?;
+    define(mem, globals, "print", 1, print)?;
+    define(mem, globals, "str", 1, str)?;
+    define_variadic(mem, globals, "range", 1, 3, range)?;
+
+    define(mem, globals, "len", 1, len)?;
+    define(mem, globals, "char_at", 2, char_at)?;
+    define(mem, globals, "slice", 3, slice)?;
+    define(mem, globals, "split", 2, split)?;
+    define(mem, globals, "join", 2, join)?;
+    define(mem, globals, "trim", 1, trim)?;
+    define(mem, globals, "replace", 3, replace)?;
+    define(mem, globals, "find", 2, find)?;
+    define(mem, globals, "upper", 1, upper)?;
+    define(mem, globals, "lower", 1, lower)?;
+    define(mem, globals, "starts_with", 2, starts_with)?;
+    define(mem, globals, "compare", 2, compare)?;
+
+    define(mem, globals, "message", 1, message)?;
+    define(mem, globals, "traceback", 1, traceback)?;
+
+    define_variadic(mem, globals, "bind", 1, u8::MAX, bind)?;
+
+    Ok(())
+}
+
+/// Allocate a NativeFunction and bind it to `name`
+pub fn define<'guard>(
+    mem: &'guard MutatorView,
+    globals: &Dict,
+    name: &str,
+    arity: u8,
+    function: NativeFn,
+) -> Result<(), RuntimeError> {
+    let native = NativeFunction::alloc(mem, name, arity, function)?;
+    globals.assoc(mem, mem.lookup_sym(name), native.as_tagged(mem))
+}
+
+/// Allocate a NativeFunction that takes a variable number of arguments and bind it to `name`
+pub fn define_variadic<'guard>(
+    mem: &'guard MutatorView,
+    globals: &Dict,
+    name: &str,
+    arity: u8,
+    max_arity: u8,
+    function: NativeFn,
+) -> Result<(), RuntimeError> {
+    let native = NativeFunction::alloc_variadic(mem, name, arity, max_arity, function)?;
+    globals.assoc(mem, mem.lookup_sym(name), native.as_tagged(mem))
+}
+
+/// Extract an integer from an argument or return an error naming the function
+pub fn integer_arg<'guard>(
+    guard: &'guard dyn MutatorScope,
+    function: &str,
+    arg: &TaggedCellPtr,
+) -> Result<isize, RuntimeError> {
+    match *arg.get(guard) {
+        Value::Number(n) => Ok(n),
+        _ => Err(err_eval(&format!("{}() expects integer arguments", function))),
+    }
+}
+
+/// Extract a non-negative index from an argument, or return an error naming the function
+fn index_arg<'guard>(
+    guard: &'guard dyn MutatorScope,
+    function: &str,
+    arg: &TaggedCellPtr,
+) -> Result<usize, RuntimeError> {
+    match integer_arg(guard, function, arg)? {
+        n if n >= 0 => Ok(n as usize),
+        _ => Err(RuntimeError::new(ErrorKind::BoundsError)),
+    }
+}
+
+/// Extract a Text from an argument or return an error naming the function
+pub fn text_arg<'guard>(
+    guard: &'guard dyn MutatorScope,
+    function: &str,
+    arg: &TaggedCellPtr,
+) -> Result<ScopedPtr<'guard, Text>, RuntimeError> {
+    match *arg.get(guard) {
+        Value::Text(text) => Ok(text),
+        _ => Err(err_eval(&format!("{}() expects a string", function))),
+    }
+}
+
+/// Allocate a new Text from a string slice
+pub fn new_text<'guard>(
+    mem: &'guard MutatorView,
+    content: &str,
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    mem.alloc_tagged(Text::new_from_str(mem, content)?)
+}
+
+/// Convert a Rust bool to a Chorus truth value: the symbol `true` or nil
+pub fn truth<'guard>(mem: &'guard MutatorView, value: bool) -> TaggedScopedPtr<'guard> {
+    match value {
+        true => mem.lookup_sym("true"),
+        false => mem.nil(),
+    }
+}
+
+/// Extract a Dict from an argument or return an error naming the function
+fn dict_arg<'guard>(
+    guard: &'guard dyn MutatorScope,
+    function: &str,
+    arg: &TaggedCellPtr,
+) -> Result<ScopedPtr<'guard, Dict>, RuntimeError> {
+    match *arg.get(guard) {
+        Value::Dict(dict) => Ok(dict),
+        _ => Err(err_eval(&format!("{}() expects a dict", function))),
+    }
+}
+
+/// Extract an Exception from an argument or return an error naming the function
+fn exception_arg<'guard>(
+    guard: &'guard dyn MutatorScope,
+    function: &str,
+    arg: &TaggedCellPtr,
+) -> Result<ScopedPtr<'guard, Exception>, RuntimeError> {
+    match *arg.get(guard) {
+        Value::Exception(exception) => Ok(exception),
+        _ => Err(err_eval(&format!("{}() expects an exception", function))),
+    }
+}
+
+/// keys(dict): a list of the dict's keys in insertion order
+fn keys<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let dict = dict_arg(mem, "keys", &args[0])?;
+    Ok(dict.keys(mem)?.as_tagged(mem))
+}
+
+/// values(dict): a list of the dict's values in insertion order
+fn values<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let dict = dict_arg(mem, "values", &args[0])?;
+    Ok(dict.values(mem)?.as_tagged(mem))
+}
+
+/// items(dict): a list of (key . value) pairs in insertion order
+fn items<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let dict = dict_arg(mem, "items", &args[0])?;
+    Ok(dict.items(mem)?.as_tagged(mem))
+}
+
+/// print(value): write the value to stdout followed by a newline
+fn print<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let line = match *args[0].get(mem) {
+        // Strings are written as they are rather than quoted and escaped
+        Value::Text(text) => String::from(text.as_str(mem)),
+        value => format!("{}", value),
+    };
+
+    OUTPUT.with(|output| match output.borrow_mut().as_mut() {
+        Some(captured) => {
+            captured.push_str(&line);
+            captured.push('\n');
+        }
+        None => println!("{}", line),
+    });
+    Ok(mem.nil())
+}
+
+thread_local! {
+    /// The text `print` has written on this thread while its output is being captured
+    static OUTPUT: RefCell<Option<String>> = RefCell::new(None);
+}
+
+/// Call `f`, collecting everything `print` writes on this thread meanwhile instead of writing it
+/// to stdout, and return what was written
+#[cfg(test)]
+pub fn capture_output(f: impl FnOnce()) -> String {
+    let previous = OUTPUT.with(|output| output.replace(Some(String::new())));
+    f();
+    OUTPUT
+        .with(|output| output.replace(previous))
+        .unwrap_or_default()
+}
+
+/// str(value): the value itself if it is a string, otherwise its printed form as a string
+fn str<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let value = args[0].get(mem);
+
+    match *value {
+        Value::Text(_) => Ok(value),
+        _ => new_text(mem, &format!("{}", value)),
+    }
+}
+
+/// range(stop), range(start, stop) or range(start, stop, step): a lazy sequence of integers
+fn range<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let (start, stop, step) = match args.len() {
+        1 => (0, integer_arg(mem, "range", &args[0])?, 1),
+        2 => (
+            integer_arg(mem, "range", &args[0])?,
+            integer_arg(mem, "range", &args[1])?,
+            1,
+        ),
+        _ => (
+            integer_arg(mem, "range", &args[0])?,
+            integer_arg(mem, "range", &args[1])?,
+            integer_arg(mem, "range", &args[2])?,
+        ),
+    };
+
+    Ok(Range::alloc(mem, start, stop, step)?.as_tagged(mem))
+}
+
+/// len(value): the number of code points in a string or items in a list or dict
+fn len<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let length = match *args[0].get(mem) {
+        Value::Text(text) => text.char_length(mem),
+        Value::List(list) => list.length() as usize,
+        Value::Dict(dict) => dict.length() as usize,
+        _ => return Err(err_eval("len() expects a string, list or dict")),
+    };
+
+    Ok(TaggedScopedPtr::new(mem, TaggedPtr::number(length as isize)))
+}
+
+/// char_at(string, index): the code point at the given index as a one character string
+fn char_at<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let text = text_arg(mem, "char_at", &args[0])?;
+    let index = index_arg(mem, "char_at", &args[1])?;
+    mem.alloc_tagged(text.char_at(mem, index)?)
+}
+
+/// slice(string, start, end): the code points from start up to, but not including, end
+fn slice<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let text = text_arg(mem, "slice", &args[0])?;
+    let start = index_arg(mem, "slice", &args[1])?;
+    let end = index_arg(mem, "slice", &args[2])?;
+    mem.alloc_tagged(text.slice(mem, start, end)?)
+}
+
+/// split(string, separator): a list of the substrings between each separator
+fn split<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let text = text_arg(mem, "split", &args[0])?;
+    let separator = text_arg(mem, "split", &args[1])?;
+
+    if separator.as_str(mem).is_empty() {
+        return Err(err_eval("split() separator must not be empty"));
+    }
+
+    let parts = List::alloc(mem)?;
+    for part in text.as_str(mem).split(separator.as_str(mem)) {
+        StackAnyContainer::push(&*parts, mem, new_text(mem, part)?)?;
+    }
+
+    Ok(parts.as_tagged(mem))
+}
+
+/// join(list, separator): the strings in the list concatenated with the separator between them
+fn join<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let list = match *args[0].get(mem) {
+        Value::List(list) => list,
+        _ => return Err(err_eval("join() expects a list")),
+    };
+    let separator = text_arg(mem, "join", &args[1])?;
+
+    let mut parts = Vec::new();
+    list.access_slice(mem, |items| -> Result<(), RuntimeError> {
+        for item in items.iter() {
+            parts.push(text_arg(mem, "join", item)?.as_str(mem).to_string());
+        }
+        Ok(())
+    })?;
+
+    new_text(mem, &parts.join(separator.as_str(mem)))
+}
+
+/// trim(string): the string without leading or trailing whitespace
+fn trim<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let text = text_arg(mem, "trim", &args[0])?;
+    new_text(mem, text.as_str(mem).trim())
+}
+
+/// replace(string, from, to): the string with every occurrence of from replaced by to
+fn replace<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let text = text_arg(mem, "replace", &args[0])?;
+    let from = text_arg(mem, "replace", &args[1])?;
+    let to = text_arg(mem, "replace", &args[2])?;
+
+    if from.as_str(mem).is_empty() {
+        return Err(err_eval("replace() pattern must not be empty"));
+    }
+
+    new_text(mem, &text.as_str(mem).replace(from.as_str(mem), to.as_str(mem)))
+}
+
+/// find(string, needle): the code point index of the first occurrence of needle, or nil
+fn find<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let text = text_arg(mem, "find", &args[0])?;
+    let needle = text_arg(mem, "find", &args[1])?;
+
+    match text.find(mem, needle.as_str(mem)) {
+        Some(index) => Ok(TaggedScopedPtr::new(mem, TaggedPtr::number(index as isize))),
+        None => Ok(mem.nil()),
+    }
+}
+
+/// upper(string): the string in upper case
+fn upper<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let text = text_arg(mem, "upper", &args[0])?;
+    new_text(mem, &text.as_str(mem).to_uppercase())
+}
+
+/// lower(string): the string in lower case
+fn lower<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let text = text_arg(mem, "lower", &args[0])?;
+    new_text(mem, &text.as_str(mem).to_lowercase())
+}
+
+/// starts_with(string, prefix): true if the string begins with prefix
+fn starts_with<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let text = text_arg(mem, "starts_with", &args[0])?;
+    let prefix = text_arg(mem, "starts_with", &args[1])?;
+    Ok(truth(mem, text.as_str(mem).starts_with(prefix.as_str(mem))))
+}
+
+/// compare(a, b): -1, 0 or 1 as string a sorts before, equal to or after string b
+fn compare<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let left = text_arg(mem, "compare", &args[0])?;
+    let right = text_arg(mem, "compare", &args[1])?;
+
+    let order = match left.compare(mem, &right) {
+        Ordering::Less => -1,
+        Ordering::Equal => 0,
+        Ordering::Greater => 1,
+    };
+
+    Ok(TaggedScopedPtr::new(mem, TaggedPtr::number(order)))
+}
+
+/// message(exception): the description of an error raised by the runtime
+fn message<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let exception = exception_arg(mem, "message", &args[0])?;
+    Ok(exception.message(mem).as_tagged(mem))
+}
+
+/// traceback(exception): the call frames an error was raised in, one per line
+fn traceback<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let exception = exception_arg(mem, "traceback", &args[0])?;
+    Ok(exception.traceback(mem).as_tagged(mem))
+}
+
+/// bind(function, args...): a Partial that calls the function with the given arguments
+/// followed by those it is called with
+fn bind<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let callee = args[0].get(mem);
+    let bound = &args[1..];
+
+    let (name, used, max_arity) = match *callee {
+        Value::Function(function) => (
+            function.name(mem),
+            0,
+            (!function.is_variadic()).then(|| function.max_arity()),
+        ),
+        Value::NativeFunction(native) => (native.name(mem), 0, Some(native.max_arity())),
+        Value::Partial(partial) => {
+            let max_arity = match *partial.callee(mem) {
+                Value::Function(function) => {
+                    (!function.is_variadic()).then(|| function.max_arity())
+                }
+                Value::NativeFunction(native) => Some(native.max_arity()),
+                _ => None,
+            };
+            let used = partial.used() as usize - partial.holes(mem).len();
+            (partial.name(mem), used, max_arity)
+        }
+        _ => return Err(err_eval("bind() expects a function")),
+    };
+
+    if let Some(max_arity) = max_arity {
+        if used + bound.len() > max_arity as usize {
+            return Err(err_eval(&format!(
+                "bind() was given more arguments than {} takes",
+                name
+            )));
+        }
+    }
+
+    Ok(Partial::alloc_bound(mem, callee, bound, &[])?.as_tagged(mem))
+}
@@ -1,4 +1,3 @@
-use itertools::join;
 use std::cell::Cell;
 use std::fmt;
 
@@ -6,12 +5,14 @@ use crate::array::{Array, ArraySize};
 use crate::container::{
     Container, IndexedContainer, SliceableContainer, StackAnyContainer, StackContainer,
 };
+use crate::disassemble::disassemble_bytecode;
 use crate::error::{err_eval, RuntimeError};
 use crate::list::List;
 use crate::memory::MutatorView;
 use crate::printer::Print;
 use crate::safe_ptr::{CellPtr, MutatorScope, ScopedPtr, TaggedScopedPtr};
 use crate::tagged_ptr::TaggedPtr;
+use crate::trace::{visit_cell, Trace, Visitor};
 
 /// A register can be in the range 0..255
 // ANCHOR: DefRegister
@@ -27,11 +28,18 @@ pub type LiteralId = u16;
 /// Upvalues are stored in a list on a Partial, an UpvalueId is the index into the list
 pub type UpvalueId = u8;
 
+/// Registers spilled by the compiler are saved in a per-call-frame list of locals, a LocalId is the
+/// index into the list
+pub type LocalId = u16;
+
 /// An instruction jump target is a signed integer, relative to the jump instruction
 pub type JumpOffset = i16;
 /// Jump offset when the target is still unknown.
 pub const JUMP_UNKNOWN: i16 = 0x7fff;
 
+/// The high half of an operand extended by a `Wide` prefix
+pub type WideOperand = u16;
+
 /// Argument count for a function call or partial application
 pub type NumArgs = u8;
 
@@ -150,6 +158,169 @@ pub enum Opcode {
         reg2: Register,
         reg3: Register,
     },
+    GetIter {
+        dest: Register,
+        src: Register,
+    },
+    IterNext {
+        dest: Register,
+        iter: Register,
+    },
+    IterNextPair {
+        dest: Register,
+        iter: Register,
+    },
+    LoadLocal {
+        dest: Register,
+        local: LocalId,
+    },
+    StoreLocal {
+        src: Register,
+        local: LocalId,
+    },
+    /// A prefix that extends the 16 bit operand of the instruction after it to 32 bits, for
+    /// literal ids, integers and jump offsets that do not fit
+    Wide {
+        high: WideOperand,
+    },
+    /// As Call, but a function that is entered reuses the current call frame and register
+    /// window. Always followed by a Return of `dest`, which returns the result when no function
+    /// is entered.
+    TailCall {
+        function: Register,
+        dest: Register,
+        arg_count: NumArgs,
+    },
+    /// Raise the value in `reg` as an exception, unwinding to the nearest handler
+    Throw {
+        reg: Register,
+    },
+    /// As Call, but the last arguments are matched to parameters by name, and `names` holds a List
+    /// of their names as Symbols. The function is taken from `dest`, which receives the result.
+    CallKeywords {
+        dest: Register,
+        arg_count: NumArgs,
+        names: Register,
+    },
+    /// Set `dest` to true if the caller supplied a value for parameter number `param`, or nil if
+    /// the function must compute its default
+    IsSupplied {
+        dest: Register,
+        param: NumArgs,
+    },
+    /// Set `dest` to a Partial application of `function` to arguments laid out as for a Call,
+    /// without calling it. The register `dest + ENV_REG` holds a List of the positions of the
+    /// arguments that are placeholders, as Numbers.
+    MakePartial {
+        function: Register,
+        dest: Register,
+        arg_count: NumArgs,
+    },
+    /// Set `dest` to the Module whose canonical path, or native module name, is the Symbol in
+    /// `path`. The first time a Module is imported its top-level code is run, in a register window
+    /// starting at `dest`, and then the Import is executed again. `path` must be below `dest`.
+    Import {
+        dest: Register,
+        path: Register,
+    },
+    /// Set `dest` to the member of the Module in `object` named by the Symbol in `name`
+    GetField {
+        dest: Register,
+        object: Register,
+        name: Register,
+    },
+    /// Set `dest` to the remainder of dividing `num` by `denom`, rounding the quotient down so
+    /// that the remainder takes the sign of `denom`
+    Modulo {
+        dest: Register,
+        num: Register,
+        denom: Register,
+    },
+}
+
+/// Combine the high half carried by a `Wide` prefix with the 16 bit operand it extends
+pub fn wide_operand(high: WideOperand, low: u16) -> u32 {
+    ((high as u32) << 16) | low as u32
+}
+
+/// Split a 32 bit operand into the high half for a `Wide` prefix and the low half for the
+/// instruction
+pub fn split_operand(operand: u32) -> (WideOperand, u16) {
+    ((operand >> 16) as WideOperand, operand as u16)
+}
+
+/// Return the high half of the operand of the instruction at `index` if it has a `Wide` prefix
+pub fn wide_prefix(code: &[Opcode], index: usize) -> Option<WideOperand> {
+    match index.checked_sub(1).map(|prev| code[prev]) {
+        Some(Opcode::Wide { high }) => Some(high),
+        _ => None,
+    }
+}
+
+/// Return the index of the instruction the jump at `index` will continue from, if it is a jump
+/// with a known offset
+pub fn jump_target(code: &[Opcode], index: usize) -> Option<usize> {
+    let offset = match code[index] {
+        Opcode::Jump { offset }
+        | Opcode::JumpIfTrue { offset, .. }
+        | Opcode::JumpIfNotTrue { offset, .. } => offset,
+        _ => return None,
+    };
+
+    let offset = match wide_prefix(code, index) {
+        Some(high) => wide_operand(high, offset as u16) as i32 as isize,
+        None if offset == JUMP_UNKNOWN => return None,
+        None => offset as isize,
+    };
+
+    let target = index as isize + 1 + offset;
+    match target >= 0 {
+        true => Some(target as usize),
+        false => None,
+    }
+}
+
+/// Return the indexes of the instructions that may be executed after the one at `index`. These
+/// may be past the end of the code.
+pub fn successors(code: &[Opcode], index: usize) -> Vec<usize> {
+    match code[index] {
+        Opcode::Return { .. } | Opcode::Throw { .. } => vec![],
+        Opcode::Jump { .. } => jump_target(code, index).into_iter().collect(),
+        Opcode::JumpIfTrue { .. } | Opcode::JumpIfNotTrue { .. } => {
+            let mut next = vec![index + 1];
+            next.extend(jump_target(code, index));
+            next
+        }
+        // These skip the next instruction when they produce a value
+        Opcode::IterNext { .. } | Opcode::IterNextPair { .. } => vec![index + 1, index + 2],
+        _ => vec![index + 1],
+    }
+}
+
+/// An entry in a ByteCode's exception handler table. An exception raised by any instruction from
+/// `start` up to but not including `end` is caught by continuing from `target` with the exception
+/// in the `dest` register.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct Handler {
+    pub start: ArraySize,
+    pub end: ArraySize,
+    pub target: ArraySize,
+    pub dest: Register,
+}
+
+impl Handler {
+    /// Return true if the instruction at `index` is protected by this handler
+    pub fn covers(&self, index: usize) -> bool {
+        index >= self.start as usize && index < self.end as usize
+    }
+
+    /// Move the handler along with the code it refers to, given the new index of each instruction
+    /// and of the end of the code
+    pub fn renumber(&mut self, new_index: &[usize]) {
+        self.start = new_index[self.start as usize] as ArraySize;
+        self.end = new_index[self.end as usize] as ArraySize;
+        self.target = new_index[self.target as usize] as ArraySize;
+    }
 }
 
 /// Bytecode is stored as fixed-width 32-bit values.
@@ -164,12 +335,16 @@ pub type ArrayOpcode = Array<Opcode>;
 pub type Literals = List;
 // ANCHOR_END: DefLiterals
 
-/// Byte code consists of the code and any literals used.
+/// Exception handlers are stored in the order they are searched, innermost first
+pub type HandlerTable = Array<Handler>;
+
+/// Byte code consists of the code, any literals used and the exception handler table.
 // ANCHOR: DefByteCode
 #[derive(Clone)]
 pub struct ByteCode {
     code: ArrayOpcode,
     literals: Literals,
+    handlers: HandlerTable,
 }
 // ANCHOR_END: DefByteCode
 
@@ -181,6 +356,23 @@ impl ByteCode {
         mem.alloc(ByteCode {
             code: ArrayOpcode::new(),
             literals: Literals::new(),
+            handlers: HandlerTable::new(),
+        })
+    }
+
+    /// Instantiate an empty ByteCode instance in the compilation arena, with room in the arena for
+    /// the given numbers of instructions, literals and handlers. Intended for the top-level code of
+    /// a script, which is never needed again once it has run.
+    pub fn alloc_in_arena<'guard>(
+        mem: &'guard MutatorView,
+        code_size: ArraySize,
+        literal_count: ArraySize,
+        handler_count: ArraySize,
+    ) -> Result<ScopedPtr<'guard, ByteCode>, RuntimeError> {
+        mem.alloc_in_arena(ByteCode {
+            code: ArrayOpcode::with_capacity_in_arena(mem, code_size)?,
+            literals: Literals::with_capacity_in_arena(mem, literal_count)?,
+            handlers: HandlerTable::with_capacity_in_arena(mem, handler_count)?,
         })
     }
 
@@ -243,6 +435,45 @@ impl ByteCode {
     pub fn next_instruction(&self) -> ArraySize {
         self.code.length()
     }
+
+    /// Return a copy of the instruction sequence
+    pub fn opcodes<'guard>(&self, guard: &'guard dyn MutatorScope) -> Vec<Opcode> {
+        self.code.access_slice(guard, |code| code.to_vec())
+    }
+
+    /// Return the literals list
+    pub fn literals(&self) -> &Literals {
+        &self.literals
+    }
+
+    /// Append an entry to the exception handler table. Handlers are searched in the order they
+    /// were pushed.
+    pub fn push_handler<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        handler: Handler,
+    ) -> Result<(), RuntimeError> {
+        self.handlers.push(mem, handler)
+    }
+
+    /// Return a copy of the exception handler table
+    pub fn handlers<'guard>(&self, guard: &'guard dyn MutatorScope) -> Vec<Handler> {
+        self.handlers.access_slice(guard, |handlers| handlers.to_vec())
+    }
+
+    /// Return the first handler that protects the instruction at `index`
+    pub fn handler_for<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+        index: ArraySize,
+    ) -> Option<Handler> {
+        self.handlers.access_slice(guard, |handlers| {
+            handlers
+                .iter()
+                .find(|handler| handler.covers(index as usize))
+                .copied()
+        })
+    }
 }
 
 impl Print for ByteCode {
@@ -251,13 +482,27 @@ impl Print for ByteCode {
         guard: &'guard dyn MutatorScope,
         f: &mut fmt::Formatter,
     ) -> fmt::Result {
-        let mut instr_str = String::new();
+        write!(f, "{}", disassemble_bytecode(guard, self))
+    }
+}
 
-        self.code.access_slice(guard, |code| {
-            instr_str = join(code.iter().map(|opcode| format!("{:?}", opcode)), "\n")
-        });
+impl Trace for ArrayOpcode {
+    fn trace<'guard>(&self, _guard: &'guard dyn MutatorScope, visit: &mut Visitor) {
+        self.trace_backing(visit);
+    }
+}
+
+impl Trace for HandlerTable {
+    fn trace<'guard>(&self, _guard: &'guard dyn MutatorScope, visit: &mut Visitor) {
+        self.trace_backing(visit);
+    }
+}
 
-        write!(f, "{}", instr_str)
+impl Trace for ByteCode {
+    fn trace<'guard>(&self, guard: &'guard dyn MutatorScope, visit: &mut Visitor) {
+        self.code.trace(guard, visit);
+        self.literals.trace(guard, visit);
+        self.handlers.trace(guard, visit);
     }
 }
 
@@ -311,12 +556,12 @@ impl InstructionStream {
     pub fn get_literal<'guard>(
         &self,
         guard: &'guard dyn MutatorScope,
-        lit_id: LiteralId,
+        lit_id: ArraySize,
     ) -> Result<TaggedPtr, RuntimeError> {
         Ok(IndexedContainer::get(
             &self.instructions.get(guard).literals,
             guard,
-            lit_id as ArraySize,
+            lit_id,
         )?
         .get_ptr())
     }
@@ -328,10 +573,27 @@ impl InstructionStream {
 
     /// Adjust the instruction pointer by the given signed offset from the current ip
     pub fn jump(&self, offset: JumpOffset) {
+        self.jump_wide(offset as i32);
+    }
+
+    /// As `jump`, for an offset extended by a `Wide` prefix
+    pub fn jump_wide(&self, offset: i32) {
         let mut ip = self.ip.get() as i32;
-        ip += offset as i32;
+        ip += offset;
         self.ip.set(ip as ArraySize);
     }
+
+    /// Move past the next instruction without executing it. The verifier ensures it is not a
+    /// `Wide` prefix.
+    pub fn skip_instruction(&self) {
+        self.jump(1);
+    }
+}
+
+impl Trace for InstructionStream {
+    fn trace<'guard>(&self, _guard: &'guard dyn MutatorScope, visit: &mut Visitor) {
+        visit_cell(&self.instructions, visit);
+    }
 }
 
 #[cfg(test)]
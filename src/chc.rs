@@ -0,0 +1,848 @@
+/// The `.chc` compiled bytecode file format
+///
+/// A `.chc` file holds a single top-level Function so that a script can be run without lexing,
+/// parsing or building the parser tables again. The layout, with every integer little-endian, is:
+///
+/// ```text
+/// magic     4 bytes  "CHC\0"
+/// version   u16      FORMAT_VERSION
+/// length    u32      number of payload bytes
+/// checksum  u32      CRC-32 of the payload
+/// payload            the top-level Function
+/// ```
+///
+/// A Function is written as its name, its parameter names, its required argument count and
+/// variadic flag, its nonlocal references and then its ByteCode: the literal pool, the
+/// instructions, each packed into four bytes, and then the exception handler table. Literals are
+/// tagged values, and a literal Function or List is written out in full, recursively, nested no
+/// deeper than `MAX_NESTING`.
+use crate::bytecode::{ByteCode, Handler, Opcode};
+use crate::container::{AnyContainerFromSlice, Container, ContainerFromSlice, SliceableContainer};
+use crate::error::{err_load, RuntimeError};
+use crate::function::Function;
+use crate::iter::{MAX_INLINE_INTEGER, MIN_INLINE_INTEGER};
+use crate::list::List;
+use crate::memory::MutatorView;
+use crate::number::NumberObject;
+use crate::safe_ptr::{MutatorScope, ScopedPtr, TaggedScopedPtr};
+use crate::tagged_ptr::{TaggedPtr, Value};
+use crate::text::Text;
+
+/// Identifies a compiled bytecode file
+pub const MAGIC: &[u8; 4] = b"CHC\0";
+
+/// Incremented whenever the encoding of anything in the file changes
+pub const FORMAT_VERSION: u16 = 9;
+
+const HEADER_SIZE: usize = 14;
+
+/// How deeply literal Functions and Lists may nest, so that a crafted file cannot exhaust the
+/// native stack while it is read
+const MAX_NESTING: usize = 256;
+
+// Literal tags
+const LIT_NIL: u8 = 0;
+const LIT_NUMBER: u8 = 1;
+const LIT_SYMBOL: u8 = 2;
+const LIT_TEXT: u8 = 3;
+const LIT_FUNCTION: u8 = 4;
+const LIT_LIST: u8 = 5;
+
+/// Serialize a Function, and every Function in its literals, to the bytes of a `.chc` file
+pub fn save<'guard>(
+    guard: &'guard dyn MutatorScope,
+    function: ScopedPtr<'guard, Function>,
+) -> Result<Vec<u8>, RuntimeError> {
+    let mut payload = Writer { bytes: Vec::new() };
+    payload.function(guard, function)?;
+
+    let mut file = Writer {
+        bytes: Vec::with_capacity(HEADER_SIZE + payload.bytes.len()),
+    };
+    file.bytes.extend_from_slice(MAGIC);
+    file.u16(FORMAT_VERSION);
+    file.u32(payload.bytes.len() as u32);
+    file.u32(crc32(&payload.bytes));
+    file.bytes.extend_from_slice(&payload.bytes);
+
+    Ok(file.bytes)
+}
+
+/// Validate the header of a `.chc` file and rebuild the Function it contains on the heap
+pub fn load<'guard>(
+    mem: &'guard MutatorView,
+    bytes: &[u8],
+) -> Result<ScopedPtr<'guard, Function>, RuntimeError> {
+    if bytes.len() < HEADER_SIZE || &bytes[0..4] != MAGIC {
+        return Err(err_load("Not a compiled Chorus file"));
+    }
+
+    let mut header = Reader {
+        bytes,
+        pos: 4,
+        depth: 0,
+    };
+    let version = header.u16()?;
+    let length = header.u32()? as usize;
+    let checksum = header.u32()?;
+
+    if version != FORMAT_VERSION {
+        return Err(err_load(&format!(
+            "File format version {} is not supported, expected {}",
+            version, FORMAT_VERSION
+        )));
+    }
+
+    let payload = &bytes[HEADER_SIZE..];
+    if payload.len() != length {
+        return Err(err_load("File is truncated or has trailing data"));
+    }
+    if crc32(payload) != checksum {
+        return Err(err_load("Checksum mismatch, the file is corrupt"));
+    }
+
+    let mut reader = Reader {
+        bytes: payload,
+        pos: 0,
+        depth: 0,
+    };
+    let function = reader.function(mem)?;
+
+    if reader.pos != payload.len() {
+        return Err(err_load("Unexpected data after the top-level function"));
+    }
+
+    Ok(function)
+}
+
+struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn i64(&mut self, value: i64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn str(&mut self, value: &str) {
+        self.u32(value.len() as u32);
+        self.bytes.extend_from_slice(value.as_bytes());
+    }
+
+    fn function<'guard>(
+        &mut self,
+        guard: &'guard dyn MutatorScope,
+        function: ScopedPtr<'guard, Function>,
+    ) -> Result<(), RuntimeError> {
+        self.literal(guard, function.name_ptr(guard))?;
+
+        let params = function.param_names(guard);
+        self.u8(params.length() as u8);
+        params.access_slice(guard, |names| -> Result<(), RuntimeError> {
+            for name in names.iter() {
+                self.literal(guard, name.get(guard))?;
+            }
+            Ok(())
+        })?;
+        self.u8(function.arity());
+        self.u8(function.is_variadic() as u8);
+
+        match function.is_closure() {
+            true => {
+                let nonlocals = function.nonlocals(guard);
+                self.u8(1);
+                self.u16(nonlocals.length() as u16);
+                nonlocals.access_slice(guard, |refs| {
+                    for r in refs.iter() {
+                        self.u16(*r);
+                    }
+                });
+            }
+            false => self.u8(0),
+        }
+
+        self.bytecode(guard, &function.code(guard))
+    }
+
+    fn bytecode<'guard>(
+        &mut self,
+        guard: &'guard dyn MutatorScope,
+        code: &ByteCode,
+    ) -> Result<(), RuntimeError> {
+        let literals = code.literals();
+        self.u32(literals.length() as u32);
+        literals.access_slice(guard, |items| -> Result<(), RuntimeError> {
+            for item in items.iter() {
+                self.literal(guard, item.get(guard))?;
+            }
+            Ok(())
+        })?;
+
+        let opcodes = code.opcodes(guard);
+        self.u32(opcodes.len() as u32);
+        for opcode in opcodes {
+            self.bytes.extend_from_slice(&encode_opcode(opcode));
+        }
+
+        let handlers = code.handlers(guard);
+        self.u32(handlers.len() as u32);
+        for handler in handlers {
+            self.u32(handler.start);
+            self.u32(handler.end);
+            self.u32(handler.target);
+            self.u8(handler.dest);
+        }
+
+        Ok(())
+    }
+
+    fn literal<'guard>(
+        &mut self,
+        guard: &'guard dyn MutatorScope,
+        value: TaggedScopedPtr<'guard>,
+    ) -> Result<(), RuntimeError> {
+        match *value {
+            Value::Nil => self.u8(LIT_NIL),
+            Value::Number(n) => {
+                self.u8(LIT_NUMBER);
+                self.i64(n as i64);
+            }
+            Value::NumberObject(n) if n.as_isize(guard).is_some() => {
+                self.u8(LIT_NUMBER);
+                self.i64(n.as_isize(guard).unwrap() as i64);
+            }
+            Value::Symbol(s) => {
+                self.u8(LIT_SYMBOL);
+                self.str(s.as_str(guard));
+            }
+            Value::Text(t) => {
+                self.u8(LIT_TEXT);
+                self.str(t.as_str(guard));
+            }
+            Value::Function(f) => {
+                self.u8(LIT_FUNCTION);
+                self.function(guard, f)?;
+            }
+            Value::List(l) => {
+                self.u8(LIT_LIST);
+                self.u32(l.length() as u32);
+                l.access_slice(guard, |items| -> Result<(), RuntimeError> {
+                    for item in items.iter() {
+                        self.literal(guard, item.get(guard))?;
+                    }
+                    Ok(())
+                })?;
+            }
+            _ => {
+                return Err(err_load(&format!(
+                    "Cannot save literal {} in a compiled file",
+                    value
+                )))
+            }
+        }
+
+        Ok(())
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    /// How many literal Functions and Lists enclose the one being read
+    depth: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], RuntimeError> {
+        if self.bytes.len() - self.pos < count {
+            return Err(err_load("Unexpected end of file"));
+        }
+
+        let slice = &self.bytes[self.pos..self.pos + count];
+        self.pos += count;
+        Ok(slice)
+    }
+
+    fn u8(&mut self) -> Result<u8, RuntimeError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, RuntimeError> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, RuntimeError> {
+        let mut word = [0; 4];
+        word.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(word))
+    }
+
+    fn i64(&mut self) -> Result<i64, RuntimeError> {
+        let mut word = [0; 8];
+        word.copy_from_slice(self.take(8)?);
+        Ok(i64::from_le_bytes(word))
+    }
+
+    fn str(&mut self) -> Result<&'a str, RuntimeError> {
+        let length = self.u32()? as usize;
+        std::str::from_utf8(self.take(length)?).map_err(|_| err_load("Invalid UTF-8 string"))
+    }
+
+    /// Enter a literal Function or List, failing if they are nested too deeply
+    fn nest(&mut self) -> Result<(), RuntimeError> {
+        if self.depth == MAX_NESTING {
+            return Err(err_load("Literals are nested too deeply"));
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    fn function<'guard>(
+        &mut self,
+        mem: &'guard MutatorView,
+    ) -> Result<ScopedPtr<'guard, Function>, RuntimeError> {
+        let name = self.literal(mem)?;
+        match *name {
+            Value::Nil | Value::Symbol(_) => (),
+            _ => return Err(err_load("Function name must be a symbol or nil")),
+        }
+
+        let param_count = self.u8()?;
+        let mut names = Vec::with_capacity(param_count as usize);
+        for _ in 0..param_count {
+            names.push(self.literal(mem)?);
+        }
+        let params: ScopedPtr<'_, List> = AnyContainerFromSlice::from_slice(mem, &names)?;
+
+        let arity = self.u8()?;
+        let variadic = match self.u8()? {
+            0 => false,
+            1 => true,
+            _ => return Err(err_load("Invalid variadic flag")),
+        };
+
+        let nonlocal_refs = match self.u8()? {
+            0 => None,
+            1 => {
+                let count = self.u16()?;
+                let mut refs = Vec::with_capacity(count as usize);
+                for _ in 0..count {
+                    refs.push(self.u16()?);
+                }
+                Some(ContainerFromSlice::from_slice(mem, &refs)?)
+            }
+            _ => return Err(err_load("Invalid nonlocal reference flag")),
+        };
+
+        let code = self.bytecode(mem)?;
+
+        Function::alloc_variadic(mem, name, params, arity, variadic, code, nonlocal_refs)
+            .map_err(|_| err_load("Invalid function parameters"))
+    }
+
+    fn bytecode<'guard>(
+        &mut self,
+        mem: &'guard MutatorView,
+    ) -> Result<ScopedPtr<'guard, ByteCode>, RuntimeError> {
+        let code = ByteCode::alloc(mem)?;
+
+        let literal_count = self.u32()?;
+        for _ in 0..literal_count {
+            let literal = self.literal(mem)?;
+            code.push_lit(mem, literal)?;
+        }
+
+        let opcode_count = self.u32()?;
+        for _ in 0..opcode_count {
+            let mut word = [0; 4];
+            word.copy_from_slice(self.take(4)?);
+            code.push(mem, decode_opcode(word)?)?;
+        }
+
+        let handler_count = self.u32()?;
+        for _ in 0..handler_count {
+            let handler = Handler {
+                start: self.u32()?,
+                end: self.u32()?,
+                target: self.u32()?,
+                dest: self.u8()?,
+            };
+            code.push_handler(mem, handler)?;
+        }
+
+        Ok(code)
+    }
+
+    fn literal<'guard>(
+        &mut self,
+        mem: &'guard MutatorView,
+    ) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+        match self.u8()? {
+            LIT_NIL => Ok(mem.nil()),
+            LIT_NUMBER => {
+                let n = isize::try_from(self.i64()?)
+                    .map_err(|_| err_load("Integer literal is too large"))?;
+                match n {
+                    MIN_INLINE_INTEGER..=MAX_INLINE_INTEGER => {
+                        Ok(TaggedScopedPtr::new(mem, TaggedPtr::number(n)))
+                    }
+                    _ => NumberObject::alloc_from_isize(mem, n),
+                }
+            }
+            LIT_SYMBOL => Ok(mem.lookup_sym(self.str()?)),
+            LIT_TEXT => {
+                let text = Text::new_from_str(mem, self.str()?)?;
+                mem.alloc_tagged(text)
+            }
+            LIT_FUNCTION => {
+                self.nest()?;
+                let function = self.function(mem)?;
+                self.depth -= 1;
+                Ok(function.as_tagged(mem))
+            }
+            LIT_LIST => {
+                self.nest()?;
+                let count = self.u32()?;
+                let mut items = Vec::new();
+                for _ in 0..count {
+                    items.push(self.literal(mem)?);
+                }
+                self.depth -= 1;
+                let list: ScopedPtr<'_, List> = AnyContainerFromSlice::from_slice(mem, &items)?;
+                Ok(list.as_tagged(mem))
+            }
+            tag => Err(err_load(&format!("Unknown literal tag {}", tag))),
+        }
+    }
+}
+
+/// Pack an instruction into four bytes: an opcode number followed by its operands, with 16 bit
+/// operands little-endian and unused bytes zero
+fn encode_opcode(opcode: Opcode) -> [u8; 4] {
+    let wide = |tag: u8, a: u8, b: u16| {
+        let b = b.to_le_bytes();
+        [tag, a, b[0], b[1]]
+    };
+
+    match opcode {
+        Opcode::NoOp => [0, 0, 0, 0],
+        Opcode::Return { reg } => [1, reg, 0, 0],
+        Opcode::LoadLiteral { dest, literal_id } => wide(2, dest, literal_id),
+        Opcode::IsNil { dest, test } => [3, dest, test, 0],
+        Opcode::IsAtom { dest, test } => [4, dest, test, 0],
+        Opcode::FirstOfPair { dest, reg } => [5, dest, reg, 0],
+        Opcode::SecondOfPair { dest, reg } => [6, dest, reg, 0],
+        Opcode::MakePair { dest, reg1, reg2 } => [7, dest, reg1, reg2],
+        Opcode::IsIdentical { dest, test1, test2 } => [8, dest, test1, test2],
+        Opcode::Jump { offset } => wide(9, 0, offset as u16),
+        Opcode::JumpIfTrue { test, offset } => wide(10, test, offset as u16),
+        Opcode::JumpIfNotTrue { test, offset } => wide(11, test, offset as u16),
+        Opcode::LoadNil { dest } => [12, dest, 0, 0],
+        Opcode::LoadGlobal { dest, name } => [13, dest, name, 0],
+        Opcode::StoreGlobal { src, name } => [14, src, name, 0],
+        Opcode::Call {
+            function,
+            dest,
+            arg_count,
+        } => [15, function, dest, arg_count],
+        Opcode::MakeClosure { dest, function } => [16, dest, function, 0],
+        Opcode::LoadInteger { dest, integer } => wide(17, dest, integer as u16),
+        Opcode::CopyRegister { dest, src } => [18, dest, src, 0],
+        Opcode::Add { dest, reg1, reg2 } => [19, dest, reg1, reg2],
+        Opcode::Subtract { dest, left, right } => [20, dest, left, right],
+        Opcode::Multiply { dest, reg1, reg2 } => [21, dest, reg1, reg2],
+        Opcode::DivideInteger { dest, num, denom } => [22, dest, num, denom],
+        Opcode::GetUpvalue { dest, src } => [23, dest, src, 0],
+        Opcode::SetUpvalue { dest, src } => [24, dest, src, 0],
+        Opcode::CloseUpvalues { reg1, reg2, reg3 } => [25, reg1, reg2, reg3],
+        Opcode::GetIter { dest, src } => [26, dest, src, 0],
+        Opcode::IterNext { dest, iter } => [27, dest, iter, 0],
+        Opcode::IterNextPair { dest, iter } => [28, dest, iter, 0],
+        Opcode::LoadLocal { dest, local } => wide(29, dest, local),
+        Opcode::StoreLocal { src, local } => wide(30, src, local),
+        Opcode::Wide { high } => wide(31, 0, high),
+        Opcode::TailCall {
+            function,
+            dest,
+            arg_count,
+        } => [32, function, dest, arg_count],
+        Opcode::Throw { reg } => [33, reg, 0, 0],
+        Opcode::CallKeywords {
+            dest,
+            arg_count,
+            names,
+        } => [34, dest, arg_count, names],
+        Opcode::IsSupplied { dest, param } => [35, dest, param, 0],
+        Opcode::MakePartial {
+            function,
+            dest,
+            arg_count,
+        } => [36, function, dest, arg_count],
+        Opcode::Import { dest, path } => [37, dest, path, 0],
+        Opcode::GetField { dest, object, name } => [38, dest, object, name],
+        Opcode::Modulo { dest, num, denom } => [39, dest, num, denom],
+    }
+}
+
+/// Unpack an instruction written by `encode_opcode`
+fn decode_opcode(word: [u8; 4]) -> Result<Opcode, RuntimeError> {
+    let [tag, a, b, c] = word;
+    let wide = u16::from_le_bytes([b, c]);
+
+    let opcode = match tag {
+        0 => Opcode::NoOp,
+        1 => Opcode::Return { reg: a },
+        2 => Opcode::LoadLiteral {
+            dest: a,
+            literal_id: wide,
+        },
+        3 => Opcode::IsNil { dest: a, test: b },
+        4 => Opcode::IsAtom { dest: a, test: b },
+        5 => Opcode::FirstOfPair { dest: a, reg: b },
+        6 => Opcode::SecondOfPair { dest: a, reg: b },
+        7 => Opcode::MakePair {
+            dest: a,
+            reg1: b,
+            reg2: c,
+        },
+        8 => Opcode::IsIdentical {
+            dest: a,
+            test1: b,
+            test2: c,
+        },
+        9 => Opcode::Jump {
+            offset: wide as i16,
+        },
+        10 => Opcode::JumpIfTrue {
+            test: a,
+            offset: wide as i16,
+        },
+        11 => Opcode::JumpIfNotTrue {
+            test: a,
+            offset: wide as i16,
+        },
+        12 => Opcode::LoadNil { dest: a },
+        13 => Opcode::LoadGlobal { dest: a, name: b },
+        14 => Opcode::StoreGlobal { src: a, name: b },
+        15 => Opcode::Call {
+            function: a,
+            dest: b,
+            arg_count: c,
+        },
+        16 => Opcode::MakeClosure {
+            dest: a,
+            function: b,
+        },
+        17 => Opcode::LoadInteger {
+            dest: a,
+            integer: wide as i16,
+        },
+        18 => Opcode::CopyRegister { dest: a, src: b },
+        19 => Opcode::Add {
+            dest: a,
+            reg1: b,
+            reg2: c,
+        },
+        20 => Opcode::Subtract {
+            dest: a,
+            left: b,
+            right: c,
+        },
+        21 => Opcode::Multiply {
+            dest: a,
+            reg1: b,
+            reg2: c,
+        },
+        22 => Opcode::DivideInteger {
+            dest: a,
+            num: b,
+            denom: c,
+        },
+        23 => Opcode::GetUpvalue { dest: a, src: b },
+        24 => Opcode::SetUpvalue { dest: a, src: b },
+        25 => Opcode::CloseUpvalues {
+            reg1: a,
+            reg2: b,
+            reg3: c,
+        },
+        26 => Opcode::GetIter { dest: a, src: b },
+        27 => Opcode::IterNext { dest: a, iter: b },
+        28 => Opcode::IterNextPair { dest: a, iter: b },
+        29 => Opcode::LoadLocal {
+            dest: a,
+            local: wide,
+        },
+        30 => Opcode::StoreLocal {
+            src: a,
+            local: wide,
+        },
+        31 => Opcode::Wide { high: wide },
+        32 => Opcode::TailCall {
+            function: a,
+            dest: b,
+            arg_count: c,
+        },
+        33 => Opcode::Throw { reg: a },
+        34 => Opcode::CallKeywords {
+            dest: a,
+            arg_count: b,
+            names: c,
+        },
+        35 => Opcode::IsSupplied { dest: a, param: b },
+        36 => Opcode::MakePartial {
+            function: a,
+            dest: b,
+            arg_count: c,
+        },
+        37 => Opcode::Import { dest: a, path: b },
+        38 => Opcode::GetField {
+            dest: a,
+            object: b,
+            name: c,
+        },
+        39 => Opcode::Modulo {
+            dest: a,
+            num: b,
+            denom: c,
+        },
+        _ => return Err(err_load(&format!("Unknown opcode {}", tag))),
+    };
+
+    Ok(opcode)
+}
+
+/// The CRC-32 checksum used by zip and PNG, computed a bit at a time
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+
+    !crc
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::disassemble::disassemble_function;
+    use crate::memory::{Memory, Mutator};
+
+    #[test]
+    fn crc32_check_value() {
+        assert!(crc32(b"123456789") == 0xcbf4_3926);
+    }
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let mem = Memory::new();
+
+        struct Test {}
+        impl Mutator for Test {
+            type Input = ();
+            type Output = ();
+
+            fn run(
+                &self,
+                mem: &MutatorView,
+                _input: Self::Input,
+            ) -> Result<Self::Output, RuntimeError> {
+                // a named inner function with a default and rest parameter and a closure
+                // reference
+                let inner_code = ByteCode::alloc(mem)?;
+                let text = mem.alloc_tagged(Text::new_from_str(mem, "naïve")?)?;
+                let text_id = inner_code.push_lit(mem, text)?;
+                inner_code.push_loadlit(mem, 0, text_id)?;
+                inner_code.push(mem, Opcode::IsSupplied { dest: 5, param: 1 })?;
+                inner_code.push(mem, Opcode::Return { reg: 0 })?;
+                let params = AnyContainerFromSlice::from_slice(
+                    mem,
+                    &[
+                        mem.lookup_sym("x"),
+                        mem.lookup_sym("y"),
+                        mem.lookup_sym("rest"),
+                    ],
+                )?;
+                let refs = ContainerFromSlice::from_slice(mem, &[0x0102u16])?;
+                let inner = Function::alloc_variadic(
+                    mem,
+                    mem.lookup_sym("inner"),
+                    params,
+                    1,
+                    true,
+                    inner_code,
+                    Some(refs),
+                )?;
+                let keywords: ScopedPtr<'_, List> =
+                    AnyContainerFromSlice::from_slice(mem, &[mem.lookup_sym("y")])?;
+
+                let code = ByteCode::alloc(mem)?;
+                code.push_lit(mem, inner.as_tagged(mem))?;
+                code.push_lit(
+                    mem,
+                    TaggedScopedPtr::new(mem, TaggedPtr::number(-1_000_000)),
+                )?;
+                code.push_lit(mem, keywords.as_tagged(mem))?;
+                code.push_loadlit(mem, 2, 0)?;
+                code.push(mem, Opcode::Wide { high: 0xffff })?;
+                code.push(
+                    mem,
+                    Opcode::LoadInteger {
+                        dest: 3,
+                        integer: -7,
+                    },
+                )?;
+                code.push(
+                    mem,
+                    Opcode::JumpIfNotTrue {
+                        test: 3,
+                        offset: -3,
+                    },
+                )?;
+                code.push(mem, Opcode::Return { reg: 2 })?;
+                code.push(mem, Opcode::Throw { reg: 4 })?;
+                code.push(
+                    mem,
+                    Opcode::CallKeywords {
+                        dest: 3,
+                        arg_count: 1,
+                        names: 2,
+                    },
+                )?;
+                code.push(
+                    mem,
+                    Opcode::MakePartial {
+                        function: 2,
+                        dest: 3,
+                        arg_count: 2,
+                    },
+                )?;
+                code.push(mem, Opcode::Import { dest: 4, path: 2 })?;
+                code.push(
+                    mem,
+                    Opcode::GetField {
+                        dest: 5,
+                        object: 4,
+                        name: 3,
+                    },
+                )?;
+                code.push(
+                    mem,
+                    Opcode::Modulo {
+                        dest: 6,
+                        num: 5,
+                        denom: 4,
+                    },
+                )?;
+                code.push_handler(
+                    mem,
+                    Handler {
+                        start: 1,
+                        end: 4,
+                        target: 5,
+                        dest: 4,
+                    },
+                )?;
+                let outer = Function::alloc(mem, mem.nil(), List::alloc(mem)?, code, None)?;
+
+                let bytes = save(mem, outer)?;
+                let loaded = load(mem, &bytes)?;
+
+                assert!(disassemble_function(mem, loaded) == disassemble_function(mem, outer));
+                assert!(save(mem, loaded)? == bytes);
+
+                // header validation
+                let mut corrupt = bytes.clone();
+                *corrupt.last_mut().unwrap() ^= 1;
+                assert!(load(mem, &corrupt).is_err());
+
+                let mut future = bytes.clone();
+                future[4] = 99;
+                assert!(load(mem, &future).is_err());
+
+                assert!(load(mem, &bytes[..bytes.len() - 1]).is_err());
+                assert!(load(mem, b"#!/usr/bin/env chorus").is_err());
+
+                Ok(())
+            }
+        }
+
+        let test = Test {};
+        mem.mutate(&test, ()).unwrap();
+    }
+
+    #[test]
+    fn load_checks_literals() {
+        let mem = Memory::new();
+
+        struct Test {}
+        impl Mutator for Test {
+            type Input = ();
+            type Output = ();
+
+            fn run(
+                &self,
+                mem: &MutatorView,
+                _input: Self::Input,
+            ) -> Result<Self::Output, RuntimeError> {
+                let save_literal = |literal: TaggedScopedPtr<'_>| -> Result<_, RuntimeError> {
+                    let code = ByteCode::alloc(mem)?;
+                    code.push_lit(mem, literal)?;
+                    code.push(mem, Opcode::Return { reg: 0 })?;
+                    save(
+                        mem,
+                        Function::alloc(mem, mem.nil(), List::alloc(mem)?, code, None)?,
+                    )
+                };
+
+                // an integer outside the inline range is loaded as a NumberObject
+                let large = NumberObject::alloc_from_isize(mem, isize::MAX)?;
+                let bytes = save_literal(large)?;
+                let loaded = load(mem, &bytes)?;
+                loaded.code(mem).literals().access_slice(mem, |literals| {
+                    match *literals[0].get(mem) {
+                        Value::NumberObject(n) => assert!(n.as_isize(mem) == Some(isize::MAX)),
+                        _ => panic!("Large integer literal was not loaded as a NumberObject"),
+                    }
+                });
+
+                // Lists nested deeper than the limit are refused rather than overflowing the stack
+                let mut nested = mem.nil();
+                for _ in 0..MAX_NESTING {
+                    let list: ScopedPtr<'_, List> =
+                        AnyContainerFromSlice::from_slice(mem, &[nested])?;
+                    nested = list.as_tagged(mem);
+                }
+                assert!(load(mem, &save_literal(nested)?).is_ok());
+
+                let list: ScopedPtr<'_, List> = AnyContainerFromSlice::from_slice(mem, &[nested])?;
+                match load(mem, &save_literal(list.as_tagged(mem))?) {
+                    Err(error) => assert!(format!("{}", error).contains("nested too deeply")),
+                    Ok(_) => panic!("Deeply nested literals were loaded"),
+                }
+
+                Ok(())
+            }
+        }
+
+        let test = Test {};
+        mem.mutate(&test, ()).unwrap();
+    }
+}
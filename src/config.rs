@@ -1,19 +1,94 @@
 pub struct Config<'a> {
     pub filename: &'a str,
+    /// Maximum number of bytes the heap may allocate, if limited
+    pub max_heap: Option<usize>,
+    /// Maximum number of call frames, if not the default
+    pub max_depth: Option<usize>,
+    /// Maximum number of value stack slots, if not the default
+    pub max_stack: Option<usize>,
+    /// Print the compiled bytecode instead of running it
+    pub disassemble: bool,
+    /// Run the optimizer over the generated bytecode
+    pub optimize: bool,
+    /// Save the compiled bytecode to a `.chc` file instead of running it
+    pub compile: bool,
+    /// Where to save the compiled bytecode, if not next to the source file
+    pub output: Option<&'a str>,
+    /// The arguments after the script path, which are passed to the script rather than parsed
+    pub script_args: Vec<&'a str>,
 }
 
 impl<'a> Config<'a> {
     pub fn build(args: &'a Vec<String>) -> Result<Config, &'static str> {
-        if args.len() > 2 {
-            return Err("Too many args");
-        }
+        let mut filename = None;
+        let mut max_heap = None;
+        let mut max_depth = None;
+        let mut max_stack = None;
+        let mut disassemble = false;
+        let mut optimize = false;
+        let mut output = None;
+        let mut script_args = Vec::new();
+
+        // `chorus compile file.ch [-o file.chc]` compiles rather than runs
+        let compile = args.get(1).map(|arg| arg == "compile").unwrap_or(false);
+        let skip = if compile { 2 } else { 1 };
+
+        let mut iter = args.iter().skip(skip);
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "--max-heap" => {
+                    let size = iter.next().ok_or("--max-heap expects a size")?;
+                    max_heap = Some(parse_size(size).ok_or("Invalid --max-heap size")?);
+                }
+                "--max-depth" => {
+                    let depth = iter.next().ok_or("--max-depth expects a frame count")?;
+                    max_depth = Some(depth.parse().map_err(|_| "Invalid --max-depth count")?);
+                }
+                "--max-stack" => {
+                    let size = iter.next().ok_or("--max-stack expects a slot count")?;
+                    max_stack = Some(parse_size(size).ok_or("Invalid --max-stack size")?);
+                }
+                "--disassemble" => disassemble = true,
+                "-O" => optimize = true,
+                "-o" if compile => {
+                    output = Some(iter.next().ok_or("-o expects a filename")?.as_str());
+                }
+                _ => {
+                    if filename.is_some() {
+                        return Err("Too many args");
+                    }
+                    filename = Some(arg.as_str());
 
-        if args.len() == 1 {
-            return Err("No filename passed");
+                    // `chorus file.ch a b` runs the script with the arguments `a` and `b`
+                    if !compile {
+                        script_args.extend(iter.by_ref().map(String::as_str));
+                    }
+                }
+            }
         }
 
         Ok(Self {
-            filename: args[1].as_str(),
+            filename: filename.ok_or("No filename passed")?,
+            max_heap,
+            max_depth,
+            max_stack,
+            disassemble,
+            optimize,
+            compile,
+            output,
+            script_args,
         })
     }
 }
+
+/// Parse a byte or slot count with an optional K, M or G suffix, e.g. "512K" or "64M"
+fn parse_size(size: &str) -> Option<usize> {
+    let (digits, multiplier) = match size.chars().last()? {
+        'K' | 'k' => (&size[..size.len() - 1], 1024),
+        'M' | 'm' => (&size[..size.len() - 1], 1024 * 1024),
+        'G' | 'g' => (&size[..size.len() - 1], 1024 * 1024 * 1024),
+        _ => (size, 1),
+    };
+
+    digits.parse::<usize>().ok()?.checked_mul(multiplier)
+}
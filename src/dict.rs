@@ -1,21 +1,29 @@
 use std::cell::Cell;
 use std::fmt;
 use std::hash::Hasher;
+use std::ptr::write;
 
 use fnv::FnvHasher;
 
-use crate::container::{Container, HashIndexedAnyContainer};
+use crate::container::{Container, HashIndexedAnyContainer, StackAnyContainer};
 use crate::error::{ErrorKind, RuntimeError};
+use crate::gc::write_barrier_raw_array;
 use crate::hashable::Hashable;
+use crate::list::List;
 use crate::memory::MutatorView;
+use crate::pair::Pair;
 use crate::printer::Print;
 use crate::raw_array::{default_array_growth, ArraySize, RawArray};
 use crate::safe_ptr::{MutatorScope, ScopedPtr, TaggedCellPtr, TaggedScopedPtr};
 use crate::tagged_ptr::Value;
+use crate::trace::{visit_raw_array, visit_tagged_cell, Trace, Visitor};
 
 // max load factor before resizing the table
 const LOAD_FACTOR: f32 = 0.80;
-const TOMBSTONE: u64 = 1;
+// index table slot that has never been used
+const EMPTY: ArraySize = ArraySize::MAX;
+// index table slot whose entry has been removed
+const DELETED: ArraySize = ArraySize::MAX - 1;
 
 /// Internal entry representation, keeping copy of hash for the key
 // ANCHOR: DefDictItem
@@ -27,15 +35,9 @@ pub struct DictItem {
 }
 // ANCHOR_END: DefDictItem
 
-impl DictItem {
-    fn blank() -> DictItem {
-        DictItem {
-            key: TaggedCellPtr::new_nil(),
-            value: TaggedCellPtr::new_nil(),
-            hash: 0,
-        }
-    }
-}
+/// Maximum number of values hashed or compared within a single compound key, which also stops a
+/// cyclic Pair from being hashed forever
+const MAX_KEY_NODES: usize = 4096;
 
 /// Generate a hash value for a key
 /// TODO move this function somewhere more suitable
@@ -44,81 +46,208 @@ fn hash_key<'guard>(
     guard: &'guard dyn MutatorScope,
     key: TaggedScopedPtr<'guard>,
 ) -> Result<u64, RuntimeError> {
+    if let Some(n) = integer_key(guard, key) {
+        return Ok(n as u64);
+    }
+
     match *key {
         Value::Symbol(s) => {
             let mut hasher = FnvHasher::default();
             s.hash(guard, &mut hasher);
             Ok(hasher.finish())
         }
-        Value::Number(n) => Ok(n as u64),
+        Value::Text(_) | Value::Pair(_) | Value::NumberObject(_) => {
+            let mut hasher = FnvHasher::default();
+            let mut budget = MAX_KEY_NODES;
+            hash_value(guard, key, &mut hasher, &mut budget)?;
+            Ok(hasher.finish())
+        }
         _ => Err(RuntimeError::new(ErrorKind::UnhashableError)),
     }
 }
+
+/// Return the value of an integer key, whether it is an inline Number or a NumberObject small
+/// enough to hold the same value, so that the two hash and compare as the same key
+fn integer_key<'guard>(
+    guard: &'guard dyn MutatorScope,
+    key: TaggedScopedPtr<'guard>,
+) -> Option<isize> {
+    match *key {
+        Value::Number(n) => Some(n),
+        Value::NumberObject(n) => n.as_isize(guard),
+        _ => None,
+    }
+}
+
+/// Feed a compound key into a hasher. Pairs are hashed by their contents, so a Pair in a key is
+/// frozen when the key is added, see `freeze_key`.
+fn hash_value<'guard, H: Hasher>(
+    guard: &'guard dyn MutatorScope,
+    value: TaggedScopedPtr<'guard>,
+    hasher: &mut H,
+    budget: &mut usize,
+) -> Result<(), RuntimeError> {
+    if *budget == 0 {
+        return Err(RuntimeError::new(ErrorKind::UnhashableError));
+    }
+    *budget -= 1;
+
+    match *value {
+        Value::Nil => hasher.write_u8(0),
+        Value::Number(n) => hasher.write_isize(n),
+        Value::Symbol(s) => s.hash(guard, hasher),
+        Value::Text(t) => {
+            // distinguish a Text from a Symbol with the same spelling
+            hasher.write_u8(1);
+            t.hash(guard, hasher)
+        }
+        Value::NumberObject(n) => match n.as_isize(guard) {
+            Some(n) => hasher.write_isize(n),
+            None => n.hash(guard, hasher),
+        },
+        Value::Pair(p) => {
+            hasher.write_u8(2);
+            hash_value(guard, p.first(guard), hasher, budget)?;
+            hash_value(guard, p.second(guard), hasher, budget)?;
+        }
+        _ => return Err(RuntimeError::new(ErrorKind::UnhashableError)),
+    }
+
+    Ok(())
+}
+
+/// Freeze every Pair in a key that is being added to a table, so that changing one cannot move
+/// the key out from under its hash. A Pair that is already frozen has had its contents frozen
+/// too, which stops a cyclic key from being walked forever.
+fn freeze_key<'guard>(guard: &'guard dyn MutatorScope, key: TaggedScopedPtr<'guard>) {
+    let mut pending = vec![key];
+
+    while let Some(value) = pending.pop() {
+        if let Value::Pair(pair) = *value {
+            if !pair.is_frozen() {
+                pair.freeze();
+                pending.push(pair.first(guard));
+                pending.push(pair.second(guard));
+            }
+        }
+    }
+}
+
+/// Compare two keys. Symbols are interned, so identity is enough for those; integers are compared
+/// by value whichever way they are stored, and Text, Pairs and NumberObjects by their contents.
+fn keys_equal(guard: &dyn MutatorScope, left: TaggedScopedPtr, right: TaggedScopedPtr) -> bool {
+    let mut budget = MAX_KEY_NODES;
+    values_equal(guard, left, right, &mut budget)
+}
+
+/// Structural comparison of the values in two compound keys
+fn values_equal(
+    guard: &dyn MutatorScope,
+    left: TaggedScopedPtr,
+    right: TaggedScopedPtr,
+    budget: &mut usize,
+) -> bool {
+    if left == right {
+        return true;
+    }
+
+    if *budget == 0 {
+        return false;
+    }
+    *budget -= 1;
+
+    if let (Some(l), Some(r)) = (integer_key(guard, left), integer_key(guard, right)) {
+        return l == r;
+    }
+
+    match (*left, *right) {
+        (Value::Text(l), Value::Text(r)) => l.as_str(guard) == r.as_str(guard),
+        (Value::NumberObject(l), Value::NumberObject(r)) => l.equals(guard, &r),
+        (Value::Pair(l), Value::Pair(r)) => {
+            values_equal(guard, l.first(guard), r.first(guard), budget)
+                && values_equal(guard, l.second(guard), r.second(guard), budget)
+        }
+        _ => false,
+    }
+}
 // ANCHOR_END: DefHashKey
 
+/// The result of searching the index table for a key
+enum Slot {
+    /// The key was found: `slot` is the index table position, `entry` the entries position
+    Found { slot: ArraySize, entry: ArraySize },
+    /// The key was not found: `slot` is the index table position a new entry should take
+    Vacant { slot: ArraySize },
+}
+
+/// Read an index table slot
+unsafe fn index_at(indices: &RawArray<ArraySize>, slot: ArraySize) -> ArraySize {
+    *indices.as_ptr().unwrap().offset(slot as isize)
+}
+
+/// Write an index table slot
+unsafe fn set_index(indices: &RawArray<ArraySize>, slot: ArraySize, entry: ArraySize) {
+    *(indices.as_ptr().unwrap().offset(slot as isize) as *mut ArraySize) = entry;
+}
+
+/// Borrow an entry. The entry must already have been written.
+unsafe fn entry_at<'guard>(entries: &RawArray<DictItem>, index: ArraySize) -> &'guard DictItem {
+    &*entries.as_ptr().unwrap().offset(index as isize)
+}
+
 // ANCHOR: DefFindEntry
-/// Given a key, generate the hash and search for an entry that either matches this hash
-/// or the next available blank entry.
-fn find_entry<'guard>(
-    _guard: &'guard dyn MutatorScope,
-    data: &RawArray<DictItem>,
+/// Given a key and its hash, search the index table for the slot referring to the key's entry
+/// or, failing that, the slot a new entry for the key should take.
+fn find_slot<'guard>(
+    guard: &'guard dyn MutatorScope,
+    indices: &RawArray<ArraySize>,
+    entries: &RawArray<DictItem>,
+    key: TaggedScopedPtr,
     hash: u64,
-) -> Result<&'guard mut DictItem, RuntimeError> {
-    // get raw pointer to base of array
-    let ptr = data
-        .as_ptr()
-        .ok_or(RuntimeError::new(ErrorKind::BoundsError))?;
+) -> Result<Slot, RuntimeError> {
+    if indices.as_ptr().is_none() {
+        return Err(RuntimeError::new(ErrorKind::BoundsError));
+    }
 
-    // calculate the starting index into `data` to begin scanning at
-    let mut index = (hash % data.capacity() as u64) as ArraySize;
+    // calculate the starting index into `indices` to begin scanning at
+    let mut slot = (hash % indices.capacity() as u64) as ArraySize;
 
-    // the first tombstone we find will be saved here
-    let mut tombstone: Option<&mut DictItem> = None;
+    // the first removed slot we find will be saved here
+    let mut tombstone: Option<ArraySize> = None;
 
     loop {
-        let entry = unsafe { &mut *(ptr.offset(index as isize) as *mut DictItem) as &mut DictItem };
-
-        if entry.hash == TOMBSTONE && entry.key.is_nil() {
-            // this is a tombstone: save the first tombstone reference we find
+        let entry_index = unsafe { index_at(indices, slot) };
+
+        if entry_index == EMPTY {
+            // reuse the first removed slot on the way here if there was one
+            return Ok(Slot::Vacant {
+                slot: tombstone.unwrap_or(slot),
+            });
+        } else if entry_index == DELETED {
             if tombstone.is_none() {
-                tombstone = Some(entry);
+                tombstone = Some(slot);
             }
-        } else if entry.hash == hash {
-            // this is an exact match slot
-            return Ok(entry);
-        } else if entry.key.is_nil() {
-            // this is a non-tombstone empty slot
-            if let Some(earlier_entry) = tombstone {
-                // if we recorded a tombstone, return _that_ slot to be reused
-                return Ok(earlier_entry);
-            } else {
-                return Ok(entry);
+        } else {
+            let entry = unsafe { entry_at(entries, entry_index) };
+            if entry.hash == hash && keys_equal(guard, entry.key.get(guard), key) {
+                return Ok(Slot::Found {
+                    slot,
+                    entry: entry_index,
+                });
             }
         }
 
         // increment the index, wrapping back to 0 when we get to the end of the array
-        index = (index + 1) % data.capacity();
+        slot = (slot + 1) % indices.capacity();
     }
 }
 // ANCHOR_END: DefFindEntry
 
-/// Reset all slots to a blank entry
-fn fill_with_blank_entries<'guard>(
-    _guard: &'guard dyn MutatorScope,
-    data: &RawArray<DictItem>,
-) -> Result<(), RuntimeError> {
-    let ptr = data
-        .as_ptr()
-        .ok_or(RuntimeError::new(ErrorKind::BoundsError))?;
-
-    let blank_entry = DictItem::blank();
-
-    for index in 0..data.capacity() {
-        let entry = unsafe { &mut *(ptr.offset(index as isize) as *mut DictItem) as &mut DictItem };
-        *entry = blank_entry.clone();
+/// Reset all index table slots to empty
+fn fill_with_empty_slots(indices: &RawArray<ArraySize>) {
+    for slot in 0..indices.capacity() {
+        unsafe { set_index(indices, slot, EMPTY) };
     }
-
-    Ok(())
 }
 
 /// Returns true if the dict has reached it's defined load factor and needs to be resized before inserting
@@ -128,15 +257,22 @@ fn needs_to_grow(used_entries: ArraySize, capacity: ArraySize) -> bool {
     ratio > LOAD_FACTOR
 }
 
-/// A mutable Dict key/value associative data structure.
+/// A mutable Dict key/value associative data structure that remembers insertion order.
+///
+/// Entries are appended to a dense array in the order they are inserted, and a separate
+/// open-addressed index table maps hashes to positions in that array. Removing an entry leaves a
+/// hole in the entries array, so the order of the remaining entries never changes; holes are
+/// squeezed out when the table is next rebuilt.
 // ANCHOR: DefDict
 pub struct Dict {
     /// Number of items stored
     length: Cell<ArraySize>,
-    /// Total count of items plus tombstones
+    /// Total count of entries written, including removed ones
     used_entries: Cell<ArraySize>,
-    /// Backing array for key/value entries
-    data: Cell<RawArray<DictItem>>,
+    /// Hash table of positions in `entries`
+    indices: Cell<RawArray<ArraySize>>,
+    /// Backing array for key/value entries, in insertion order
+    entries: Cell<RawArray<DictItem>>,
 }
 // ANCHOR_END: DefDict
 
@@ -156,28 +292,149 @@ impl Dict {
         mem.alloc(Dict::with_capacity(mem, capacity)?)
     }
 
-    /// Scale capacity up if needed
+    /// Rebuild the table, growing it if needed, and dropping the holes left by removed entries
     fn grow_capacity<'guard>(&self, mem: &'guard MutatorView) -> Result<(), RuntimeError> {
-        let data = self.data.get();
-
-        let new_capacity = default_array_growth(data.capacity())?;
-        let new_data = RawArray::<DictItem>::with_capacity(mem, new_capacity)?;
-
-        let maybe_ptr = data.as_ptr();
-        if let Some(ptr) = maybe_ptr {
-            for index in 0..data.capacity() {
-                let entry =
-                    unsafe { &mut *(ptr.offset(index as isize) as *mut DictItem) as &mut DictItem };
-                if !entry.key.is_nil() {
-                    let new_entry = find_entry(mem, &new_data, entry.hash)?;
-                    *new_entry = entry.clone();
+        let entries = self.entries.get();
+        let capacity = entries.capacity();
+
+        // if enough entries have been removed, compacting them away is enough
+        let new_capacity = if needs_to_grow(self.length.get() + 1, capacity) {
+            default_array_growth(capacity)?
+        } else {
+            capacity
+        };
+
+        let new_indices = RawArray::<ArraySize>::with_capacity(mem, new_capacity)?;
+        let new_entries = RawArray::<DictItem>::with_capacity(mem, new_capacity)?;
+        fill_with_empty_slots(&new_indices);
+
+        let mut count = 0;
+        for index in 0..self.used_entries.get() {
+            let entry = unsafe { entry_at(&entries, index) };
+            if entry.key.is_nil() {
+                continue;
+            }
+
+            if let Slot::Vacant { slot } =
+                find_slot(mem, &new_indices, &new_entries, entry.key.get(mem), entry.hash)?
+            {
+                unsafe {
+                    write(
+                        new_entries.as_ptr().unwrap().offset(count as isize) as *mut DictItem,
+                        entry.clone(),
+                    );
+                    set_index(&new_indices, slot, count);
                 }
+                count += 1;
             }
         }
 
-        self.data.set(new_data);
+        // the dict may be old and now points at new storage
+        write_barrier_raw_array(&new_indices);
+        write_barrier_raw_array(&new_entries);
+        self.indices.set(new_indices);
+        self.entries.set(new_entries);
+        self.used_entries.set(count);
         Ok(())
     }
+
+    /// Return an iterator over the keys and values in the dict, in insertion order.
+    ///
+    /// The iterator walks the entries as they were when it was created: items removed since are
+    /// skipped, items added since are not seen.
+    pub fn iter<'guard>(&self, guard: &'guard dyn MutatorScope) -> DictIter<'guard> {
+        DictIter {
+            guard,
+            entries: self.entries.get(),
+            next: 0,
+            end: self.used_entries.get(),
+        }
+    }
+
+    /// Return the first item at or after `position` in insertion order, along with the position
+    /// following it. Used by iterators that must not hold a borrow of the dict between steps;
+    /// positions are invalidated when the table is rebuilt by an insertion.
+    pub fn next_item<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+        position: ArraySize,
+    ) -> Option<(ArraySize, TaggedScopedPtr<'guard>, TaggedScopedPtr<'guard>)> {
+        let entries = self.entries.get();
+
+        for index in position..self.used_entries.get() {
+            let entry = unsafe { entry_at(&entries, index) };
+            if !entry.key.is_nil() {
+                return Some((index + 1, entry.key.get(guard), entry.value.get(guard)));
+            }
+        }
+
+        None
+    }
+
+    /// Return a new List of the keys in the dict, in insertion order
+    pub fn keys<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+    ) -> Result<ScopedPtr<'guard, List>, RuntimeError> {
+        let list = List::alloc_with_capacity(mem, self.length())?;
+        for (key, _) in self.iter(mem) {
+            StackAnyContainer::push(&*list, mem, key)?;
+        }
+        Ok(list)
+    }
+
+    /// Return a new List of the values in the dict, in insertion order
+    pub fn values<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+    ) -> Result<ScopedPtr<'guard, List>, RuntimeError> {
+        let list = List::alloc_with_capacity(mem, self.length())?;
+        for (_, value) in self.iter(mem) {
+            StackAnyContainer::push(&*list, mem, value)?;
+        }
+        Ok(list)
+    }
+
+    /// Return a new List of (key . value) Pairs, in insertion order
+    pub fn items<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+    ) -> Result<ScopedPtr<'guard, List>, RuntimeError> {
+        let list = List::alloc_with_capacity(mem, self.length())?;
+        for (key, value) in self.iter(mem) {
+            let item = Pair::new();
+            item.set_first(key)?;
+            item.set_second(value)?;
+            StackAnyContainer::push(&*list, mem, mem.alloc_tagged(item)?)?;
+        }
+        Ok(list)
+    }
+}
+
+/// An iterator over the (key, value) pairs of a Dict, in insertion order
+pub struct DictIter<'guard> {
+    guard: &'guard dyn MutatorScope,
+    entries: RawArray<DictItem>,
+    next: ArraySize,
+    end: ArraySize,
+}
+
+impl<'guard> Iterator for DictIter<'guard> {
+    type Item = (TaggedScopedPtr<'guard>, TaggedScopedPtr<'guard>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.next < self.end {
+            let entry = unsafe { entry_at(&self.entries, self.next) };
+            self.next += 1;
+
+            // removed entries have nil keys
+            if !entry.key.is_nil() {
+                return Some((entry.key.get(self.guard), entry.value.get(self.guard)));
+            }
+        }
+
+        None
+    }
 }
 
 impl Container<DictItem> for Dict {
@@ -185,7 +442,8 @@ impl Container<DictItem> for Dict {
         Dict {
             length: Cell::new(0),
             used_entries: Cell::new(0),
-            data: Cell::new(RawArray::new()),
+            indices: Cell::new(RawArray::new()),
+            entries: Cell::new(RawArray::new()),
         }
     }
 
@@ -196,18 +454,17 @@ impl Container<DictItem> for Dict {
         let dict = Dict {
             length: Cell::new(0),
             used_entries: Cell::new(0),
-            data: Cell::new(RawArray::with_capacity(mem, capacity)?),
+            indices: Cell::new(RawArray::with_capacity(mem, capacity)?),
+            entries: Cell::new(RawArray::with_capacity(mem, capacity)?),
         };
 
-        let data = dict.data.get();
-        fill_with_blank_entries(mem, &data)?;
+        fill_with_empty_slots(&dict.indices.get());
 
         Ok(dict)
     }
 
-    fn clear<'guard>(&self, mem: &'guard MutatorView) -> Result<(), RuntimeError> {
-        let data = self.data.get();
-        fill_with_blank_entries(mem, &data)?;
+    fn clear<'guard>(&self, _mem: &'guard MutatorView) -> Result<(), RuntimeError> {
+        fill_with_empty_slots(&self.indices.get());
         self.length.set(0);
         self.used_entries.set(0);
         Ok(())
@@ -226,13 +483,14 @@ impl HashIndexedAnyContainer for Dict {
         key: TaggedScopedPtr,
     ) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
         let hash = hash_key(guard, key)?;
-        let data = self.data.get();
-        let entry = find_entry(guard, &data, hash)?;
+        if self.length() == 0 {
+            return Err(RuntimeError::new(ErrorKind::KeyError));
+        }
 
-        if !entry.key.is_nil() {
-            Ok(entry.value.get(guard))
-        } else {
-            Err(RuntimeError::new(ErrorKind::KeyError))
+        let entries = self.entries.get();
+        match find_slot(guard, &self.indices.get(), &entries, key, hash)? {
+            Slot::Found { entry, .. } => Ok(unsafe { entry_at(&entries, entry) }.value.get(guard)),
+            Slot::Vacant { .. } => Err(RuntimeError::new(ErrorKind::KeyError)),
         }
     }
 
@@ -245,33 +503,47 @@ impl HashIndexedAnyContainer for Dict {
     ) -> Result<(), RuntimeError> {
         let hash = hash_key(mem, key)?;
 
-        let mut data = self.data.get();
         // check the load factor (what percentage of the capacity is or has been used)
-        if needs_to_grow(self.used_entries.get() + 1, data.capacity()) {
-            // create a new, larger, backing array, and copy all existing entries over
+        if needs_to_grow(self.used_entries.get() + 1, self.entries.get().capacity()) {
+            // create new, larger, backing arrays, and copy all existing entries over
             self.grow_capacity(mem)?;
-            data = self.data.get();
         }
 
-        // find the slot whose entry matches the hash or is the nearest available entry
-        let entry = find_entry(mem, &data, hash)?;
-
-        // update counters if necessary
-        if entry.key.is_nil() {
-            // if `key` is nil, this entry is unused: increment the length
-            self.length.set(self.length.get() + 1);
-            if entry.hash == 0 {
-                // if `hash` is 0, this entry has _never_ been used: increment the count
-                // of used entries
-                self.used_entries.set(self.used_entries.get() + 1);
+        let indices = self.indices.get();
+        let entries = self.entries.get();
+
+        match find_slot(mem, &indices, &entries, key, hash)? {
+            // the key exists: replace the value, keeping the entry's position
+            Slot::Found { entry, .. } => unsafe { entry_at(&entries, entry) }.value.set(value),
+
+            // a new key: append an entry and point the index slot at it
+            Slot::Vacant { slot } => {
+                let index = self.used_entries.get();
+
+                let entry = unsafe {
+                    let ptr = entries.as_ptr().unwrap().offset(index as isize) as *mut DictItem;
+                    write(
+                        ptr,
+                        DictItem {
+                            key: TaggedCellPtr::new_nil(),
+                            value: TaggedCellPtr::new_nil(),
+                            hash,
+                        },
+                    );
+                    set_index(&indices, slot, index);
+                    &*ptr
+                };
+
+                // written through the cells so the write barrier sees them
+                freeze_key(mem, key);
+                entry.key.set(key);
+                entry.value.set(value);
+
+                self.used_entries.set(index + 1);
+                self.length.set(self.length.get() + 1);
             }
         }
 
-        // finally, write the key, value and hash to the entry
-        entry.key.set(key);
-        entry.value.set(value);
-        entry.hash = hash;
-
         Ok(())
     }
     // ANCHOR_END: DefHashIndexedAnyContainerForDictAssoc
@@ -283,24 +555,33 @@ impl HashIndexedAnyContainer for Dict {
         key: TaggedScopedPtr,
     ) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
         let hash = hash_key(guard, key)?;
-
-        let data = self.data.get();
-        let entry = find_entry(guard, &data, hash)?;
-
-        if entry.key.is_nil() {
-            // a nil key means the key was not found in the Dict
+        if self.length() == 0 {
             return Err(RuntimeError::new(ErrorKind::KeyError));
         }
 
-        // decrement the length but not the `used_entries` count
-        self.length.set(self.length.get() - 1);
+        let indices = self.indices.get();
+        let entries = self.entries.get();
+
+        match find_slot(guard, &indices, &entries, key, hash)? {
+            Slot::Found { slot, entry } => {
+                // decrement the length but not the `used_entries` count
+                self.length.set(self.length.get() - 1);
 
-        // write the "tombstone" markers to the entry
-        entry.key.set_to_nil();
-        entry.hash = TOMBSTONE;
+                // mark the index slot as removed and leave a hole in the entries, so the order
+                // of the remaining entries is unchanged
+                unsafe { set_index(&indices, slot, DELETED) };
+                let entry = unsafe { entry_at(&entries, entry) };
+                let value = entry.value.get(guard);
+                entry.key.set_to_nil();
+                entry.value.set_to_nil();
+
+                // return the value that was associated with the key
+                Ok(value)
+            }
 
-        // return the value that was associated with the key
-        Ok(entry.value.get(guard))
+            // the key was not found in the Dict
+            Slot::Vacant { .. } => Err(RuntimeError::new(ErrorKind::KeyError)),
+        }
     }
     // ANCHOR_END: DefHashIndexedAnyContainerForDictDissoc
 
@@ -310,20 +591,70 @@ impl HashIndexedAnyContainer for Dict {
         key: TaggedScopedPtr,
     ) -> Result<bool, RuntimeError> {
         let hash = hash_key(guard, key)?;
-        let data = self.data.get();
-        let entry = find_entry(guard, &data, hash)?;
-        Ok(!entry.key.is_nil())
+        if self.length() == 0 {
+            return Ok(false);
+        }
+
+        let entries = self.entries.get();
+        match find_slot(guard, &self.indices.get(), &entries, key, hash)? {
+            Slot::Found { .. } => Ok(true),
+            Slot::Vacant { .. } => Ok(false),
+        }
+    }
+}
+
+impl Trace for Dict {
+    fn trace<'guard>(&self, _guard: &'guard dyn MutatorScope, visit: &mut Visitor) {
+        let indices = self.indices.get();
+        let entries = self.entries.get();
+        visit_raw_array(&indices, visit);
+        visit_raw_array(&entries, visit);
+
+        for index in 0..self.used_entries.get() {
+            let entry = unsafe { entry_at(&entries, index) };
+            // removed entries have nil keys
+            if !entry.key.is_nil() {
+                visit_tagged_cell(&entry.key, visit);
+                visit_tagged_cell(&entry.value, visit);
+            }
+        }
     }
 }
 
 impl Print for Dict {
+    /// Prints the items in insertion order
     fn print<'guard>(
         &self,
-        _guard: &'guard dyn MutatorScope,
+        guard: &'guard dyn MutatorScope,
+        f: &mut fmt::Formatter,
+    ) -> fmt::Result {
+        write!(f, "{{")?;
+
+        for (index, (key, value)) in self.iter(guard).enumerate() {
+            if index > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}: {}", key, value)?;
+        }
+
+        write!(f, "}}")
+    }
+
+    fn debug<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
         f: &mut fmt::Formatter,
     ) -> fmt::Result {
-        // TODO
-        write!(f, "Dict[...]")
+        write!(f, "{{")?;
+
+        for (index, (key, value)) in self.iter(guard).enumerate() {
+            if index > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{:?}: {:?}", key, value)?;
+        }
+
+        write!(f, "}}")
     }
 }
 
@@ -332,6 +663,12 @@ mod test {
     use super::{Container, Dict, HashIndexedAnyContainer};
     use crate::error::{ErrorKind, RuntimeError};
     use crate::memory::{Memory, Mutator, MutatorView};
+    use crate::list::List;
+    use crate::number::NumberObject;
+    use crate::pair::Pair;
+    use crate::safe_ptr::TaggedScopedPtr;
+    use crate::tagged_ptr::{TaggedPtr, Value};
+    use crate::text::Text;
 
     #[test]
     fn dict_empty_assoc_lookup() {
@@ -642,4 +979,215 @@ mod test {
         let test = Test {};
         mem.mutate(&test, ()).unwrap();
     }
+
+    #[test]
+    fn dict_structural_keys() {
+        let mem = Memory::new();
+
+        struct Test {}
+        impl Mutator for Test {
+            type Input = ();
+            type Output = ();
+
+            fn run(
+                &self,
+                mem: &MutatorView,
+                _input: Self::Input,
+            ) -> Result<Self::Output, RuntimeError> {
+                let dict = Dict::new();
+                let val = mem.lookup_sym("bar");
+
+                // two separately allocated Texts with the same contents are the same key
+                let key = mem.alloc_tagged(Text::new_from_str(mem, "some key")?)?;
+                dict.assoc(mem, key, val)?;
+
+                let runtime_key = mem.alloc_tagged(Text::new_from_str(mem, "some key")?)?;
+                assert!(dict.lookup(mem, runtime_key)? == val);
+
+                // ...but are not the same key as a Symbol of the same spelling
+                let sym_key = mem.lookup_sym("some key");
+                assert!(!dict.exists(mem, sym_key)?);
+
+                // Pairs are compared by their contents
+                let make_pair = |first: &str| -> Result<_, RuntimeError> {
+                    let pair = Pair::new();
+                    pair.set_first(mem.alloc_tagged(Text::new_from_str(mem, first)?)?)?;
+                    pair.set_second(mem.lookup_sym("tail"))?;
+                    mem.alloc_tagged(pair)
+                };
+
+                let key_pair = make_pair("a")?;
+                dict.assoc(mem, key_pair, val)?;
+                assert!(dict.exists(mem, make_pair("a")?)?);
+
+                // and cannot be changed once they are keys
+                match *key_pair {
+                    Value::Pair(pair) => {
+                        assert!(pair.is_frozen());
+                        assert!(pair.dot(mem.nil()).is_err());
+                        assert!(pair.set_first(mem.nil()).is_err());
+                        assert!(pair.set_second(mem.nil()).is_err());
+                        assert!(pair.append(mem, mem.nil()).is_err());
+                    }
+                    _ => panic!("make_pair did not make a Pair"),
+                }
+                assert!(!dict.exists(mem, make_pair("b")?)?);
+
+                dict.dissoc(mem, make_pair("a")?)?;
+                assert!(!dict.exists(mem, make_pair("a")?)?);
+                assert!(dict.length() == 1);
+
+                // Lists are mutable containers and cannot be keys
+                let list = List::alloc(mem)?;
+                match dict.assoc(mem, list.as_tagged(mem), val) {
+                    Err(e) => assert!(*e.error_kind() == ErrorKind::UnhashableError),
+                    Ok(_) => panic!("List should not be hashable"),
+                }
+
+                Ok(())
+            }
+        }
+
+        let test = Test {};
+        mem.mutate(&test, ()).unwrap();
+    }
+
+    #[test]
+    fn dict_number_keys_by_value() {
+        let mem = Memory::new();
+
+        struct Test {}
+        impl Mutator for Test {
+            type Input = ();
+            type Output = ();
+
+            fn run(
+                &self,
+                mem: &MutatorView,
+                _input: Self::Input,
+            ) -> Result<Self::Output, RuntimeError> {
+                let dict = Dict::new();
+                let val = mem.lookup_sym("val");
+
+                // an inline Number and a NumberObject holding the same value are one key
+                let inline = TaggedScopedPtr::new(mem, TaggedPtr::number(12345));
+                dict.assoc(mem, inline, val)?;
+
+                let boxed = NumberObject::alloc_from_isize(mem, 12345)?;
+                assert!(dict.lookup(mem, boxed)? == val);
+
+                dict.assoc(mem, boxed, mem.lookup_sym("other"))?;
+                assert!(dict.length() == 1);
+                assert!(dict.lookup(mem, inline)? == mem.lookup_sym("other"));
+
+                let negative = NumberObject::alloc_from_isize(mem, -7)?;
+                dict.assoc(mem, negative, val)?;
+                let inline = TaggedScopedPtr::new(mem, TaggedPtr::number(-7));
+                assert!(dict.lookup(mem, inline)? == val);
+
+                // a NumberObject too large to be an integer is still its own key
+                let large = NumberObject::alloc_tagged(mem, false, &[0, 1])?;
+                assert!(!dict.exists(mem, large)?);
+                dict.assoc(mem, large, val)?;
+                assert!(dict.exists(mem, NumberObject::alloc_tagged(mem, false, &[0, 1])?)?);
+                assert!(dict.length() == 3);
+
+                Ok(())
+            }
+        }
+
+        let test = Test {};
+        mem.mutate(&test, ()).unwrap();
+    }
+
+    #[test]
+    fn dict_preserves_insertion_order() {
+        let mem = Memory::new();
+
+        struct Test {}
+        impl Mutator for Test {
+            type Input = ();
+            type Output = ();
+
+            fn run(
+                &self,
+                mem: &MutatorView,
+                _input: Self::Input,
+            ) -> Result<Self::Output, RuntimeError> {
+                let dict = Dict::new();
+
+                // enough keys to rebuild the table several times
+                for num in 0..100 {
+                    let key = mem.lookup_sym(&format!("key_{}", num));
+                    dict.assoc(mem, key, mem.lookup_sym(&format!("val_{}", num)))?;
+                }
+
+                // removing keys and overwriting values leaves the remaining order unchanged
+                for num in (0..100).filter(|num| num % 3 == 0) {
+                    dict.dissoc(mem, mem.lookup_sym(&format!("key_{}", num)))?;
+                }
+                dict.assoc(mem, mem.lookup_sym("key_1"), mem.lookup_sym("updated"))?;
+
+                // a new key goes at the end, even into a reused index slot
+                dict.assoc(mem, mem.lookup_sym("key_0"), mem.lookup_sym("readded"))?;
+
+                let mut expected: Vec<String> = (0..100)
+                    .filter(|num| num % 3 != 0)
+                    .map(|num| format!("key_{}", num))
+                    .collect();
+                expected.push(String::from("key_0"));
+
+                let keys: Vec<String> =
+                    dict.iter(mem).map(|(key, _)| format!("{}", key)).collect();
+                assert!(keys == expected);
+
+                let first_value = dict.iter(mem).next().unwrap().1;
+                assert!(first_value == mem.lookup_sym("updated"));
+
+                let keys = dict.keys(mem)?;
+                let values = dict.values(mem)?;
+                let items = dict.items(mem)?;
+                assert!(keys.length() == dict.length());
+                assert!(values.length() == dict.length());
+                assert!(items.length() == dict.length());
+
+                Ok(())
+            }
+        }
+
+        let test = Test {};
+        mem.mutate(&test, ()).unwrap();
+    }
+
+    #[test]
+    fn dict_print_in_insertion_order() {
+        let mem = Memory::new();
+
+        struct Test {}
+        impl Mutator for Test {
+            type Input = ();
+            type Output = ();
+
+            fn run(
+                &self,
+                mem: &MutatorView,
+                _input: Self::Input,
+            ) -> Result<Self::Output, RuntimeError> {
+                let dict = Dict::alloc(mem)?;
+
+                for name in &["foo", "bar", "inner", "qux"] {
+                    dict.assoc(mem, mem.lookup_sym(name), mem.lookup_sym("x"))?;
+                }
+                dict.dissoc(mem, mem.lookup_sym("inner"))?;
+
+                let printed = format!("{}", dict.as_tagged(mem));
+                assert!(printed == "{foo: x, bar: x, qux: x}");
+
+                Ok(())
+            }
+        }
+
+        let test = Test {};
+        mem.mutate(&test, ()).unwrap();
+    }
 }
@@ -0,0 +1,375 @@
+/// A human readable listing of compiled code
+///
+/// Each instruction is printed with its index, register operands as `rN`, jump offsets resolved to
+/// labels placed on their target instructions, and literal ids followed by the literal value
+/// itself. Exception handlers are listed after the code with their protected range and the label
+/// they continue from. Functions found in a literal pool are listed after the function that refers
+/// to them.
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use crate::bytecode::{
+    jump_target, wide_operand, wide_prefix, ByteCode, JumpOffset, Opcode, JUMP_UNKNOWN,
+};
+use crate::container::{Container, SliceableContainer};
+use crate::function::Function;
+use crate::safe_ptr::{MutatorScope, ScopedPtr};
+use crate::tagged_ptr::Value;
+
+/// Disassemble a Function and every Function nested in its literals
+pub fn disassemble_function<'guard>(
+    guard: &'guard dyn MutatorScope,
+    function: ScopedPtr<'guard, Function>,
+) -> String {
+    let mut output = String::new();
+    let mut pending = vec![function];
+
+    while let Some(function) = pending.pop() {
+        if !output.is_empty() {
+            output.push('\n');
+        }
+
+        let _ = writeln!(
+            output,
+            "function {} (arity {}):",
+            function.name(guard),
+            arity_string(&function)
+        );
+
+        let code = function.code(guard);
+        output.push_str(&disassemble_bytecode(guard, &code));
+
+        // Push in reverse so nested functions are listed in literal order
+        let mut nested = nested_functions(guard, &code);
+        nested.reverse();
+        pending.extend(nested);
+    }
+
+    output
+}
+
+/// Disassemble the literals and instructions of a single ByteCode object
+pub fn disassemble_bytecode<'guard>(guard: &'guard dyn MutatorScope, code: &ByteCode) -> String {
+    let mut output = String::new();
+    let literals = literal_strings(guard, code);
+    let opcodes = code.opcodes(guard);
+
+    if !literals.is_empty() {
+        output.push_str("  literals:\n");
+        for (id, literal) in literals.iter().enumerate() {
+            let _ = writeln!(output, "    #{:<4} {}", id, literal);
+        }
+    }
+
+    // Number the labels in the order their targets appear in the code
+    let handlers = code.handlers(guard);
+    let mut labels = BTreeMap::new();
+    for index in 0..opcodes.len() {
+        if let Some(target) = jump_target(&opcodes, index) {
+            labels.insert(target, 0);
+        }
+    }
+    for handler in &handlers {
+        labels.insert(handler.target as usize, 0);
+    }
+    for (number, label) in labels.values_mut().enumerate() {
+        *label = number;
+    }
+
+    output.push_str("  code:\n");
+    for index in 0..opcodes.len() {
+        let label = match labels.get(&index) {
+            Some(number) => format!("L{}:", number),
+            None => String::new(),
+        };
+
+        let _ = writeln!(
+            output,
+            "    {:04} {:<5} {}",
+            index,
+            label,
+            format_opcode(&opcodes, index, &labels, &literals)
+        );
+    }
+
+    // A jump may target the index just past the last instruction
+    if let Some(number) = labels.get(&opcodes.len()) {
+        let _ = writeln!(output, "    {:04} L{}:", opcodes.len(), number);
+    }
+
+    if !handlers.is_empty() {
+        output.push_str("  handlers:\n");
+        for handler in &handlers {
+            let _ = writeln!(
+                output,
+                "    {:04}..{:04} => L{}, r{}",
+                handler.start,
+                handler.end,
+                labels[&(handler.target as usize)],
+                handler.dest
+            );
+        }
+    }
+
+    output
+}
+
+fn format_jump(
+    code: &[Opcode],
+    index: usize,
+    offset: JumpOffset,
+    labels: &BTreeMap<usize, usize>,
+) -> String {
+    let target = jump_target(code, index);
+
+    match target.and_then(|target| labels.get(&target)) {
+        Some(number) => format!("L{}", number),
+        None if wide_prefix(code, index).is_none() && offset == JUMP_UNKNOWN => {
+            String::from("<unpatched>")
+        }
+        None => format!("{:+}", offset),
+    }
+}
+
+/// Format the instruction at `index`, showing the full operand of an instruction extended by a
+/// Wide prefix
+fn format_opcode(
+    code: &[Opcode],
+    index: usize,
+    labels: &BTreeMap<usize, usize>,
+    literals: &[String],
+) -> String {
+    let prefix = wide_prefix(code, index);
+    let extend = |low: u16| match prefix {
+        Some(high) => wide_operand(high, low),
+        None => low as u32,
+    };
+
+    let literal = |id: u16| {
+        let id = extend(id);
+        match literals.get(id as usize) {
+            Some(value) => format!("#{}  ; {}", id, value),
+            None => format!("#{}  ; <missing literal>", id),
+        }
+    };
+
+    let jump = |offset| format_jump(code, index, offset, labels);
+
+    match code[index] {
+        Opcode::NoOp => String::from("NoOp"),
+        Opcode::Return { reg } => format!("Return r{}", reg),
+        Opcode::LoadLiteral { dest, literal_id } => {
+            format!("LoadLiteral r{}, {}", dest, literal(literal_id))
+        }
+        Opcode::IsNil { dest, test } => format!("IsNil r{}, r{}", dest, test),
+        Opcode::IsAtom { dest, test } => format!("IsAtom r{}, r{}", dest, test),
+        Opcode::FirstOfPair { dest, reg } => format!("FirstOfPair r{}, r{}", dest, reg),
+        Opcode::SecondOfPair { dest, reg } => format!("SecondOfPair r{}, r{}", dest, reg),
+        Opcode::MakePair { dest, reg1, reg2 } => {
+            format!("MakePair r{}, r{}, r{}", dest, reg1, reg2)
+        }
+        Opcode::IsIdentical { dest, test1, test2 } => {
+            format!("IsIdentical r{}, r{}, r{}", dest, test1, test2)
+        }
+        Opcode::Jump { offset } => format!("Jump {}", jump(offset)),
+        Opcode::JumpIfTrue { test, offset } => {
+            format!("JumpIfTrue r{}, {}", test, jump(offset))
+        }
+        Opcode::JumpIfNotTrue { test, offset } => {
+            format!("JumpIfNotTrue r{}, {}", test, jump(offset))
+        }
+        Opcode::LoadNil { dest } => format!("LoadNil r{}", dest),
+        Opcode::LoadGlobal { dest, name } => format!("LoadGlobal r{}, r{}", dest, name),
+        Opcode::StoreGlobal { src, name } => format!("StoreGlobal r{}, r{}", src, name),
+        Opcode::Call {
+            function,
+            dest,
+            arg_count,
+        } => format!("Call r{}, r{}, {} args", function, dest, arg_count),
+        Opcode::MakeClosure { dest, function } => {
+            format!("MakeClosure r{}, r{}", dest, function)
+        }
+        Opcode::LoadInteger { dest, integer } => match prefix {
+            Some(_) => format!("LoadInteger r{}, {}", dest, extend(integer as u16) as i32),
+            None => format!("LoadInteger r{}, {}", dest, integer),
+        },
+        Opcode::CopyRegister { dest, src } => format!("CopyRegister r{}, r{}", dest, src),
+        Opcode::Add { dest, reg1, reg2 } => format!("Add r{}, r{}, r{}", dest, reg1, reg2),
+        Opcode::Subtract { dest, left, right } => {
+            format!("Subtract r{}, r{}, r{}", dest, left, right)
+        }
+        Opcode::Multiply { dest, reg1, reg2 } => {
+            format!("Multiply r{}, r{}, r{}", dest, reg1, reg2)
+        }
+        Opcode::DivideInteger { dest, num, denom } => {
+            format!("DivideInteger r{}, r{}, r{}", dest, num, denom)
+        }
+        Opcode::GetUpvalue { dest, src } => format!("GetUpvalue r{}, u{}", dest, src),
+        Opcode::SetUpvalue { dest, src } => format!("SetUpvalue u{}, r{}", dest, src),
+        Opcode::CloseUpvalues { reg1, reg2, reg3 } => {
+            format!("CloseUpvalues r{}, r{}, r{}", reg1, reg2, reg3)
+        }
+        Opcode::GetIter { dest, src } => format!("GetIter r{}, r{}", dest, src),
+        Opcode::IterNext { dest, iter } => format!("IterNext r{}, r{}", dest, iter),
+        Opcode::IterNextPair { dest, iter } => {
+            format!("IterNextPair r{}, r{}, r{}", dest, dest as u16 + 1, iter)
+        }
+        Opcode::LoadLocal { dest, local } => format!("LoadLocal r{}, l{}", dest, local),
+        Opcode::StoreLocal { src, local } => format!("StoreLocal l{}, r{}", local, src),
+        Opcode::Wide { high } => format!("Wide {:#06x}", high),
+        Opcode::TailCall {
+            function,
+            dest,
+            arg_count,
+        } => format!("TailCall r{}, r{}, {} args", function, dest, arg_count),
+        Opcode::Throw { reg } => format!("Throw r{}", reg),
+        Opcode::CallKeywords {
+            dest,
+            arg_count,
+            names,
+        } => format!(
+            "CallKeywords r{}, {} args, names r{}",
+            dest, arg_count, names
+        ),
+        Opcode::IsSupplied { dest, param } => format!("IsSupplied r{}, param {}", dest, param),
+        Opcode::MakePartial {
+            function,
+            dest,
+            arg_count,
+        } => format!("MakePartial r{}, r{}, {} args", function, dest, arg_count),
+        Opcode::Import { dest, path } => format!("Import r{}, r{}", dest, path),
+        Opcode::GetField { dest, object, name } => {
+            format!("GetField r{}, r{}, r{}", dest, object, name)
+        }
+        Opcode::Modulo { dest, num, denom } => format!("Modulo r{}, r{}, r{}", dest, num, denom),
+    }
+}
+
+/// The number of arguments a function accepts: a single count, or a range if some parameters
+/// have defaults, marked variadic if it collects any further arguments
+fn arity_string(function: &Function) -> String {
+    let mut arity = if function.arity() == function.max_arity() {
+        format!("{}", function.arity())
+    } else {
+        format!("{} to {}", function.arity(), function.max_arity())
+    };
+
+    if function.is_variadic() {
+        arity.push_str(", variadic");
+    }
+    arity
+}
+
+/// Render each literal in the pool. Functions are shown by name since they are listed separately.
+fn literal_strings<'guard>(guard: &'guard dyn MutatorScope, code: &ByteCode) -> Vec<String> {
+    let literals = code.literals();
+    let mut strings = Vec::with_capacity(literals.length() as usize);
+
+    literals.access_slice(guard, |items| {
+        for item in items.iter() {
+            let value = item.get(guard);
+            strings.push(match *value {
+                Value::Symbol(s) => format!("'{}", s.as_str(guard)),
+                Value::Function(f) => format!("<function {}>", f.name(guard)),
+                _ => format!("{}", value),
+            });
+        }
+    });
+
+    strings
+}
+
+/// Return the Functions in a literal pool
+fn nested_functions<'guard>(
+    guard: &'guard dyn MutatorScope,
+    code: &ByteCode,
+) -> Vec<ScopedPtr<'guard, Function>> {
+    let mut functions = Vec::new();
+
+    code.literals().access_slice(guard, |items| {
+        for item in items.iter() {
+            if let Value::Function(f) = *item.get(guard) {
+                functions.push(f);
+            }
+        }
+    });
+
+    functions
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::error::RuntimeError;
+    use crate::list::List;
+    use crate::memory::{Memory, Mutator, MutatorView};
+    use crate::text::Text;
+
+    #[test]
+    fn disassemble_labels_and_literals() {
+        let mem = Memory::new();
+
+        struct Test {}
+        impl Mutator for Test {
+            type Input = ();
+            type Output = ();
+
+            fn run(
+                &self,
+                mem: &MutatorView,
+                _input: Self::Input,
+            ) -> Result<Self::Output, RuntimeError> {
+                // an inner function that returns a string
+                let inner_code = ByteCode::alloc(mem)?;
+                let hello = mem.alloc_tagged(Text::new_from_str(mem, "hello")?)?;
+                let hello_id = inner_code.push_lit(mem, hello)?;
+                inner_code.push_loadlit(mem, 0, hello_id)?;
+                inner_code.push(mem, Opcode::Return { reg: 0 })?;
+                let inner = Function::alloc(
+                    mem,
+                    mem.lookup_sym("greet"),
+                    List::alloc(mem)?,
+                    inner_code,
+                    None,
+                )?;
+
+                // an outer function that loops back on itself before returning
+                let code = ByteCode::alloc(mem)?;
+                code.push_lit(mem, inner.as_tagged(mem))?;
+                code.push_lit(mem, mem.lookup_sym("x"))?;
+                code.push_loadlit(mem, 2, 1)?;
+                code.push(mem, Opcode::JumpIfTrue { test: 2, offset: 1 })?;
+                code.push(mem, Opcode::Jump { offset: -3 })?;
+                code.push(mem, Opcode::Return { reg: 2 })?;
+                let outer =
+                    Function::alloc(mem, mem.lookup_sym("main"), List::alloc(mem)?, code, None)?;
+
+                let listing = disassemble_function(mem, outer);
+                let expected = "\
+function main (arity 0):
+  literals:
+    #0    <function greet>
+    #1    'x
+  code:
+    0000 L0:   LoadLiteral r2, #1  ; 'x
+    0001       JumpIfTrue r2, L1
+    0002       Jump L0
+    0003 L1:   Return r2
+
+function greet (arity 0):
+  literals:
+    #0    \"hello\"
+  code:
+    0000       LoadLiteral r0, #0  ; \"hello\"
+    0001       Return r0
+";
+                assert!(listing == expected, "got:\n{}", listing);
+
+                Ok(())
+            }
+        }
+
+        let test = Test {};
+        mem.mutate(&test, ()).unwrap();
+    }
+}
@@ -22,7 +22,14 @@ pub enum ErrorKind {
     IOError(String),
     LexerError(String),
     ParseError(String),
+    CompileError(String),
+    LoadError(String),
+    ImportError(String),
+    VerifyError(String),
     EvalError(String),
+    DomainError(String),
+    StackOverflow(String),
+    Exit(i32),
     BadAllocationRequest,
     OutOfMemory,
     BoundsError,
@@ -60,6 +67,10 @@ impl RuntimeError {
         self.pos
     }
 
+    pub fn set_pos(&mut self, pos: SourcePos) {
+        self.pos = Some(pos);
+    }
+
     /// Given the relevant source code string, show the error in context
     pub fn print_with_source(&self, source: &str) {
         if let Some(ref pos) = self.pos {
@@ -87,7 +98,14 @@ impl fmt::Display for RuntimeError {
             ErrorKind::IOError(ref reason) => write!(f, "IO Error: {}", reason),
             ErrorKind::LexerError(ref reason) => write!(f, "Parse error: {}", reason),
             ErrorKind::ParseError(ref reason) => write!(f, "Parse error: {}", reason),
+            ErrorKind::CompileError(ref reason) => write!(f, "Compile error: {}", reason),
+            ErrorKind::LoadError(ref reason) => write!(f, "Load error: {}", reason),
+            ErrorKind::ImportError(ref reason) => write!(f, "Import error: {}", reason),
+            ErrorKind::VerifyError(ref reason) => write!(f, "Invalid bytecode {}", reason),
             ErrorKind::EvalError(ref reason) => write!(f, "Evaluation error: {}", reason),
+            ErrorKind::DomainError(ref reason) => write!(f, "Domain error: {}", reason),
+            ErrorKind::StackOverflow(ref reason) => write!(f, "Stack overflow: {}", reason),
+            ErrorKind::Exit(status) => write!(f, "Exit with status {}", status),
             ErrorKind::OutOfMemory => write!(f, "Out of memory!"),
             ErrorKind::BadAllocationRequest => {
                 write!(f, "An invalid memory size allocation was requested!")
@@ -158,6 +176,11 @@ pub fn spos(line: u32, column: u32) -> SourcePos {
     SourcePos::new(line, column)
 }
 
+/// Convenience shorthand function for building an error reading or writing a file
+pub fn err_io(reason: &str) -> RuntimeError {
+    RuntimeError::new(ErrorKind::IOError(String::from(reason)))
+}
+
 /// Convenience shorthand function for building a lexer error
 pub fn err_lexer(pos: SourcePos, reason: &str) -> RuntimeError {
     RuntimeError::with_pos(ErrorKind::LexerError(String::from(reason)), pos)
@@ -173,7 +196,38 @@ pub fn err_parser_wpos(pos: SourcePos, reason: &str) -> RuntimeError {
     RuntimeError::with_pos(ErrorKind::ParseError(String::from(reason)), pos)
 }
 
+/// Convenience shorthand function for building a compiler error
+pub fn err_compile(reason: &str) -> RuntimeError {
+    RuntimeError::new(ErrorKind::CompileError(String::from(reason)))
+}
+
+/// Convenience shorthand function for building an error loading a compiled file
+pub fn err_load(reason: &str) -> RuntimeError {
+    RuntimeError::new(ErrorKind::LoadError(String::from(reason)))
+}
+
+/// Convenience shorthand function for building an error finding or compiling an imported module
+pub fn err_import(reason: &str) -> RuntimeError {
+    RuntimeError::new(ErrorKind::ImportError(String::from(reason)))
+}
+
+/// Convenience shorthand function for building a bytecode verification error
+pub fn err_verify(reason: &str) -> RuntimeError {
+    RuntimeError::new(ErrorKind::VerifyError(String::from(reason)))
+}
+
 /// Convenience shorthand function for building an evaluation error
 pub fn err_eval(reason: &str) -> RuntimeError {
     RuntimeError::new(ErrorKind::EvalError(String::from(reason)))
 }
+
+/// Convenience shorthand function for building an error for an argument outside the domain of a
+/// math function
+pub fn err_domain(reason: &str) -> RuntimeError {
+    RuntimeError::new(ErrorKind::DomainError(String::from(reason)))
+}
+
+/// Convenience shorthand function for building a stack overflow error
+pub fn err_stack_overflow(reason: &str) -> RuntimeError {
+    RuntimeError::new(ErrorKind::StackOverflow(String::from(reason)))
+}
@@ -0,0 +1,84 @@
+/// Exceptions raised by the runtime
+///
+/// A script may `throw` any value. Errors raised by the VM itself, such as an index out of bounds
+/// or a division by zero, are converted to an `Exception` object before they are thrown so that a
+/// `catch` block receives them like any other value.
+use std::fmt;
+
+use crate::error::{ErrorKind, RuntimeError};
+use crate::memory::MutatorView;
+use crate::printer::Print;
+use crate::safe_ptr::{CellPtr, MutatorScope, ScopedPtr};
+use crate::text::Text;
+use crate::trace::{visit_cell, Trace, Visitor};
+
+/// An error raised by the runtime, with the call frames it was raised in
+// ANCHOR: DefException
+pub struct Exception {
+    message: CellPtr<Text>,
+    traceback: CellPtr<Text>,
+}
+// ANCHOR_END: DefException
+
+impl Exception {
+    /// Allocate a new Exception with the given message and traceback, one frame per line
+    pub fn alloc<'guard>(
+        mem: &'guard MutatorView,
+        message: &str,
+        traceback: &str,
+    ) -> Result<ScopedPtr<'guard, Exception>, RuntimeError> {
+        let message = mem.alloc(Text::new_from_str(mem, message)?)?;
+        let traceback = mem.alloc(Text::new_from_str(mem, traceback)?)?;
+
+        mem.alloc(Exception {
+            message: CellPtr::new_with(message),
+            traceback: CellPtr::new_with(traceback),
+        })
+    }
+
+    /// Return the description of the error
+    pub fn message<'guard>(&self, guard: &'guard dyn MutatorScope) -> ScopedPtr<'guard, Text> {
+        self.message.get(guard)
+    }
+
+    /// Return the call frames the error was raised in, outermost first, one per line
+    pub fn traceback<'guard>(&self, guard: &'guard dyn MutatorScope) -> ScopedPtr<'guard, Text> {
+        self.traceback.get(guard)
+    }
+}
+
+/// Return true if a script can catch the error. Corrupting the heap leaves nothing safe to
+/// continue with, and a call to `io.exit` must end the script. Running out of memory can be
+/// caught, though if there is no room left for the Exception itself the error is not.
+pub fn is_catchable(error: &RuntimeError) -> bool {
+    match error.error_kind() {
+        ErrorKind::OutOfMemory
+        | ErrorKind::IOError(_)
+        | ErrorKind::EvalError(_)
+        | ErrorKind::DomainError(_)
+        | ErrorKind::StackOverflow(_)
+        | ErrorKind::BoundsError
+        | ErrorKind::KeyError
+        | ErrorKind::UnhashableError => true,
+        _ => false,
+    }
+}
+
+impl Print for Exception {
+    fn print<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+        f: &mut fmt::Formatter,
+    ) -> fmt::Result {
+        write!(f, "(Exception ")?;
+        self.message(guard).print(guard, f)?;
+        write!(f, ")")
+    }
+}
+
+impl Trace for Exception {
+    fn trace<'guard>(&self, _guard: &'guard dyn MutatorScope, visit: &mut Visitor) {
+        visit_cell(&self.message, visit);
+        visit_cell(&self.traceback, visit);
+    }
+}
@@ -0,0 +1,147 @@
+/// The `fs` native module: asking about files and directories
+///
+/// As in the `io` module, paths are taken relative to the current directory of the process.
+use std::fs;
+use std::path::Path;
+
+use crate::builtins::{define, new_text, text_arg, truth};
+use crate::container::StackAnyContainer;
+use crate::dict::Dict;
+use crate::error::RuntimeError;
+use crate::io::path_error;
+use crate::list::List;
+use crate::memory::MutatorView;
+use crate::safe_ptr::{TaggedCellPtr, TaggedScopedPtr};
+use crate::vm::Thread;
+
+/// Bind every member of the fs module in the module's globals dict
+pub fn install<'guard>(
+    mem: &'guard MutatorView,
+    globals: &Dict,
+    _thread: &Thread,
+) -> Result<(), RuntimeError> {
+    define(mem, globals, "exists", 1, exists)?;
+    define(mem, globals, "is_dir", 1, is_dir)?;
+    define(mem, globals, "list_dir", 1, list_dir)?;
+
+    Ok(())
+}
+
+/// exists(path): true if there is a file or directory at the path
+fn exists<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let path = text_arg(mem, "exists", &args[0])?;
+    Ok(truth(mem, Path::new(path.as_str(mem)).exists()))
+}
+
+/// is_dir(path): true if the path is a directory
+fn is_dir<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let path = text_arg(mem, "is_dir", &args[0])?;
+    Ok(truth(mem, Path::new(path.as_str(mem)).is_dir()))
+}
+
+/// list_dir(path): a list of the names of the entries in the directory, in sorted order
+fn list_dir<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let path = text_arg(mem, "list_dir", &args[0])?;
+    let path = path.as_str(mem);
+
+    let mut names = Vec::new();
+    let entries = fs::read_dir(path).map_err(|error| path_error(path, error))?;
+    for entry in entries {
+        let entry = entry.map_err(|error| path_error(path, error))?;
+        names.push(entry.file_name().to_string_lossy().into_owned());
+    }
+    names.sort();
+
+    let list = List::alloc(mem)?;
+    for name in names {
+        StackAnyContainer::push(&*list, mem, new_text(mem, &name)?)?;
+    }
+
+    Ok(list.as_tagged(mem))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::container::{Container, HashIndexedAnyContainer, IndexedAnyContainer};
+    use crate::memory::{Memory, Mutator};
+    use crate::tagged_ptr::Value;
+    use std::env;
+    use std::process;
+
+    /// Call the fs function `name` with a path argument
+    fn call<'guard>(
+        mem: &'guard MutatorView,
+        globals: &Dict,
+        name: &str,
+        path: &Path,
+    ) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+        let native = match *globals.lookup(mem, mem.lookup_sym(name))? {
+            Value::NativeFunction(native) => native,
+            _ => panic!("{} is not a native function", name),
+        };
+
+        let path = new_text(mem, &path.display().to_string())?;
+        native.call(mem, &[TaggedCellPtr::new_with(path)])
+    }
+
+    /// Call the fs predicate `name` with a path argument and return whether it answered true
+    fn holds(mem: &MutatorView, globals: &Dict, name: &str, path: &Path) -> bool {
+        !matches!(*call(mem, globals, name, path).unwrap(), Value::Nil)
+    }
+
+    #[test]
+    fn directories_are_listed_in_order() {
+        let mem = Memory::new();
+
+        struct Test {}
+        impl Mutator for Test {
+            type Input = ();
+            type Output = ();
+
+            fn run(
+                &self,
+                mem: &MutatorView,
+                _input: Self::Input,
+            ) -> Result<Self::Output, RuntimeError> {
+                let globals = Dict::alloc(mem)?;
+                install(mem, &globals, &*Thread::alloc(mem)?)?;
+
+                let dir = env::temp_dir().join(format!("chorus-fs-{}", process::id()));
+                fs::create_dir_all(dir.join("sub"))?;
+                fs::write(dir.join("b.txt"), "b")?;
+                fs::write(dir.join("a.txt"), "a")?;
+
+                match *call(mem, &globals, "list_dir", &dir)? {
+                    Value::List(names) => {
+                        assert!(names.length() == 3);
+                        assert!(format!("{}", names.get(mem, 0)?) == r#""a.txt""#);
+                        assert!(format!("{}", names.get(mem, 2)?) == r#""sub""#);
+                    }
+                    _ => panic!("list_dir() did not return a list"),
+                }
+
+                assert!(holds(mem, &globals, "exists", &dir.join("a.txt")));
+                assert!(!holds(mem, &globals, "exists", &dir.join("c.txt")));
+                assert!(holds(mem, &globals, "is_dir", &dir.join("sub")));
+                assert!(!holds(mem, &globals, "is_dir", &dir.join("a.txt")));
+
+                assert!(call(mem, &globals, "list_dir", &dir.join("c")).is_err());
+
+                Ok(())
+            }
+        }
+
+        let test = Test {};
+        mem.mutate(&test, ()).unwrap();
+    }
+}
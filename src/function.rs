@@ -1,15 +1,17 @@
 use itertools::join;
 use std::fmt;
 
-use crate::array::ArrayU16;
+use crate::array::{ArrayU16, ArrayU8};
 use crate::bytecode::ByteCode;
 use crate::container::{Container, ContainerFromSlice, SliceableContainer, StackContainer};
-use crate::error::RuntimeError;
+use crate::dict::Dict;
+use crate::error::{err_eval, RuntimeError};
 use crate::list::List;
 use crate::memory::MutatorView;
 use crate::printer::Print;
 use crate::safe_ptr::{CellPtr, MutatorScope, ScopedPtr, TaggedCellPtr, TaggedScopedPtr};
 use crate::tagged_ptr::Value;
+use crate::trace::{visit_cell, visit_tagged_cell, Trace, Visitor};
 
 /// A function object type
 // ANCHOR: DefFunction
@@ -19,6 +21,12 @@ pub struct Function {
     name: TaggedCellPtr,
     /// Number of arguments required to activate the function
     arity: u8,
+    /// Number of arguments the function takes before any rest parameter. The parameters after
+    /// the first `arity` have defaults, which the function computes itself when they are not
+    /// supplied.
+    max_arity: u8,
+    /// Whether the last parameter collects any further arguments into a List
+    variadic: bool,
     /// Instructions comprising the function code
     code: CellPtr<ByteCode>,
     /// Param names are stored for introspection of a function signature
@@ -27,9 +35,25 @@ pub struct Function {
     /// declaration where nonlocal variables will be found. Needed when creating a closure. May be
     /// nil
     nonlocal_refs: TaggedCellPtr,
+    /// The globals Dict of the module the function was loaded from, or nil if it uses the
+    /// globals of whichever function calls it
+    globals: TaggedCellPtr,
 }
 // ANCHOR_END: DefFunction
 
+/// The most parameters with defaults a Function can have, one for each bit of the mask recording
+/// which of them a caller supplied
+pub const MAX_DEFAULTS: u8 = 64;
+
+/// The values of a Function's parameters for one call, matched up from the call's arguments
+pub struct BoundArguments {
+    /// The value of each parameter in order, nil for a parameter with a default that was not
+    /// supplied, followed by the List of rest arguments if the function is variadic
+    pub values: Vec<TaggedCellPtr>,
+    /// Bit `n` is set if the caller supplied the `n`th parameter with a default
+    pub supplied: u64,
+}
+
 impl Function {
     /// Allocate a Function object on the heap.
     ///
@@ -43,6 +67,40 @@ impl Function {
         code: ScopedPtr<'guard, ByteCode>,
         nonlocal_refs: Option<ScopedPtr<'guard, ArrayU16>>,
     ) -> Result<ScopedPtr<'guard, Function>, RuntimeError> {
+        let arity = param_names.length() as u8;
+        Function::alloc_variadic(mem, name, param_names, arity, false, code, nonlocal_refs)
+    }
+
+    /// Allocate a Function object that requires only the first `arity` of its parameters, the
+    /// rest having defaults. If `variadic` is true the last parameter is not counted, and collects
+    /// any arguments beyond the others.
+    pub fn alloc_variadic<'guard>(
+        mem: &'guard MutatorView,
+        name: TaggedScopedPtr<'guard>,
+        param_names: ScopedPtr<'guard, List>,
+        arity: u8,
+        variadic: bool,
+        code: ScopedPtr<'guard, ByteCode>,
+        nonlocal_refs: Option<ScopedPtr<'guard, ArrayU16>>,
+    ) -> Result<ScopedPtr<'guard, Function>, RuntimeError> {
+        let param_count = param_names.length() as u8;
+        if variadic && param_count == 0 {
+            return Err(err_eval("A variadic function needs a rest parameter"));
+        }
+
+        let max_arity = param_count - variadic as u8;
+        if arity > max_arity {
+            return Err(err_eval(
+                "A function cannot require more arguments than it has",
+            ));
+        }
+        if max_arity - arity > MAX_DEFAULTS {
+            return Err(err_eval(&format!(
+                "A function can have at most {} parameters with defaults",
+                MAX_DEFAULTS
+            )));
+        }
+
         // Store a nil ptr if no nonlocal references are given
         let nonlocal_refs = if let Some(refs_ptr) = nonlocal_refs {
             TaggedCellPtr::new_with(refs_ptr.as_tagged(mem))
@@ -52,10 +110,13 @@ impl Function {
 
         mem.alloc(Function {
             name: TaggedCellPtr::new_with(name),
-            arity: param_names.length() as u8,
+            arity,
+            max_arity,
+            variadic,
             code: CellPtr::new_with(code),
             param_names: CellPtr::new_with(param_names),
             nonlocal_refs,
+            globals: TaggedCellPtr::new_nil(),
         })
     }
 
@@ -68,11 +129,173 @@ impl Function {
         }
     }
 
-    /// Return the number of arguments the Function can take
+    /// Return the Function's name, a Symbol, or nil if it is anonymous
+    pub fn name_ptr<'guard>(&self, guard: &'guard dyn MutatorScope) -> TaggedScopedPtr<'guard> {
+        self.name.get(guard)
+    }
+
+    /// Return the globals Dict of the module the Function was loaded from, or nil if it has none
+    pub fn globals<'guard>(&self, guard: &'guard dyn MutatorScope) -> TaggedScopedPtr<'guard> {
+        self.globals.get(guard)
+    }
+
+    /// Make the Function look up and bind global names in the given Dict
+    pub fn set_globals<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+        globals: ScopedPtr<'guard, Dict>,
+    ) {
+        self.globals.set(globals.as_tagged(guard));
+    }
+
+    /// Return the number of arguments the Function requires
     pub fn arity(&self) -> u8 {
         self.arity
     }
 
+    /// Return the number of arguments the Function takes before any rest parameter
+    pub fn max_arity(&self) -> u8 {
+        self.max_arity
+    }
+
+    /// Return true if the last parameter collects any arguments beyond the others into a List
+    pub fn is_variadic(&self) -> bool {
+        self.variadic
+    }
+
+    /// Return the number of registers the parameters occupy, including the rest parameter
+    pub fn param_count(&self) -> u8 {
+        self.max_arity + self.variadic as u8
+    }
+
+    /// Match a call's arguments to the parameters. Positional arguments fill the parameters from
+    /// the left and any left over are collected for the rest parameter. Each keyword argument
+    /// fills the parameter of the same name, which must not also be given positionally.
+    pub fn bind_arguments<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        args: &[TaggedCellPtr],
+        keywords: &[(&str, TaggedCellPtr)],
+    ) -> Result<BoundArguments, RuntimeError> {
+        let arity = self.arity as usize;
+        let max_arity = self.max_arity as usize;
+
+        if args.len() > max_arity && !self.variadic {
+            let expected = match arity == max_arity {
+                true => format!("{}", arity),
+                false => format!("{} to {}", arity, max_arity),
+            };
+            return Err(err_eval(&format!(
+                "Function {} expected {} arguments, got {}",
+                self.name(mem),
+                expected,
+                args.len()
+            )));
+        }
+
+        let names: Vec<String> = self.param_names(mem).access_slice(mem, |items| {
+            items
+                .iter()
+                .map(|item| match *item.get(mem) {
+                    Value::Symbol(s) => String::from(s.as_str(mem)),
+                    _ => String::new(),
+                })
+                .collect()
+        });
+
+        let positional = args.len().min(max_arity);
+        let mut values: Vec<Option<TaggedCellPtr>> = vec![None; max_arity];
+        for (value, arg) in values.iter_mut().zip(&args[..positional]) {
+            *value = Some(arg.clone());
+        }
+
+        for (keyword, arg) in keywords {
+            let index = match names[..max_arity].iter().position(|name| name == keyword) {
+                Some(index) => index,
+                None => {
+                    return Err(err_eval(&format!(
+                        "Function {} got an unexpected keyword argument {}",
+                        self.name(mem),
+                        keyword
+                    )))
+                }
+            };
+
+            if values[index].is_some() {
+                return Err(err_eval(&format!(
+                    "Function {} got multiple values for argument {}",
+                    self.name(mem),
+                    keyword
+                )));
+            }
+            values[index] = Some(arg.clone());
+        }
+
+        let mut bound = BoundArguments {
+            values: Vec::with_capacity(self.param_count() as usize),
+            supplied: 0,
+        };
+
+        for (index, value) in values.into_iter().enumerate() {
+            match value {
+                Some(value) => {
+                    if index >= arity {
+                        bound.supplied |= 1 << (index - arity);
+                    }
+                    bound.values.push(value);
+                }
+
+                None if index < arity => {
+                    return Err(err_eval(&format!(
+                        "Function {} missing argument {}",
+                        self.name(mem),
+                        names[index]
+                    )))
+                }
+
+                None => bound.values.push(TaggedCellPtr::new_nil()),
+            }
+        }
+
+        if self.variadic {
+            let rest: ScopedPtr<'guard, List> =
+                ContainerFromSlice::from_slice(mem, &args[positional..])?;
+            let rest = TaggedCellPtr::new_with(rest.as_tagged(mem));
+            bound.values.push(rest);
+        }
+
+        Ok(bound)
+    }
+
+    /// Return the parameter names separated by spaces, with parameters that have defaults in
+    /// brackets and the rest parameter prefixed with `*`
+    fn param_string<'guard>(&self, guard: &'guard dyn MutatorScope) -> String {
+        let arity = self.arity as usize;
+        let max_arity = self.max_arity as usize;
+
+        self.param_names(guard).access_slice(guard, |items| {
+            let params = items.iter().enumerate().map(|(index, item)| {
+                let name = item.get(guard);
+                match index {
+                    index if index < arity => format!("{}", name),
+                    index if index < max_arity => format!("[{}]", name),
+                    _ => format!("*{}", name),
+                }
+            });
+            join(params, " ")
+        })
+    }
+
+    /// Return true if the caller supplied the parameter at `param`, given the mask of supplied
+    /// parameters with defaults. Required parameters are always supplied.
+    pub fn is_supplied(&self, param: u8, supplied: u64) -> bool {
+        match param.checked_sub(self.arity) {
+            Some(bit) if bit < MAX_DEFAULTS => supplied & (1 << bit) != 0,
+            Some(_) => false,
+            None => true,
+        }
+    }
+
     /// Return the names of the parameters that the Function takes
     pub fn param_names<'guard>(&self, guard: &'guard dyn MutatorScope) -> ScopedPtr<'guard, List> {
         self.param_names.get(guard)
@@ -110,12 +333,7 @@ impl Print for Function {
         f: &mut fmt::Formatter,
     ) -> fmt::Result {
         let name = self.name.get(guard);
-        let params = self.param_names.get(guard);
-
-        let mut param_string = String::new();
-        params.access_slice(guard, |items| {
-            param_string = join(items.iter().map(|item| item.get(guard)), " ")
-        });
+        let param_string = self.param_string(guard);
 
         match *name {
             Value::Symbol(s) => write!(f, "(Function {} ({}))", s.as_str(guard), param_string),
@@ -135,20 +353,33 @@ impl Print for Function {
     }
 }
 
+impl Trace for Function {
+    fn trace<'guard>(&self, _guard: &'guard dyn MutatorScope, visit: &mut Visitor) {
+        visit_tagged_cell(&self.name, visit);
+        visit_cell(&self.code, visit);
+        visit_cell(&self.param_names, visit);
+        visit_tagged_cell(&self.nonlocal_refs, visit);
+        visit_tagged_cell(&self.globals, visit);
+    }
+}
+
 /// A partial function application object type
 // ANCHOR: DefPartial
 #[derive(Clone)]
 pub struct Partial {
     /// Remaining number of arguments required to activate the function
     arity: u8,
-    /// Number of arguments already applied
+    /// Number of arguments already applied, counting placeholders
     used: u8,
     /// List of argument values already applied
     args: CellPtr<List>,
+    /// Either nil or an ArrayU8 of the positions in `args` that are placeholders, in order. The
+    /// first arguments of a call fill them before any are appended to `args`.
+    holes: TaggedCellPtr,
     /// Closure environment - must be either nil or a List of Upvalues
     env: TaggedCellPtr,
-    /// Function that will be activated when all arguments are applied
-    func: CellPtr<Function>,
+    /// Function or NativeFunction that will be activated when all arguments are applied
+    func: TaggedCellPtr,
 }
 // ANCHOR_END: DefPartial
 
@@ -160,9 +391,6 @@ impl Partial {
         env: Option<ScopedPtr<'guard, List>>,
         args: &[TaggedCellPtr],
     ) -> Result<ScopedPtr<'guard, Partial>, RuntimeError> {
-        let used = args.len() as u8;
-        let arity = function.arity() - used;
-
         // Store a nil ptr if no closure env is given
         let env = if let Some(env_ptr) = env {
             TaggedCellPtr::new_with(env_ptr.as_tagged(mem))
@@ -170,40 +398,103 @@ impl Partial {
             TaggedCellPtr::new_nil()
         };
 
-        // copy args to the Partial's own list
-        let args_list: ScopedPtr<'guard, List> = ContainerFromSlice::from_slice(mem, &args)?;
+        Partial::alloc_with(mem, function.as_tagged(mem), env, args, &[])
+    }
 
-        mem.alloc(Partial {
-            arity,
-            used,
-            args: CellPtr::new_with(args_list),
-            env,
-            func: CellPtr::new_with(function),
-        })
+    /// Allocate a Partial application of a Function, NativeFunction or another Partial with the
+    /// given arguments, where the arguments at the positions in `holes` are placeholders. Binding
+    /// a Partial fills its own placeholders before appending to its arguments.
+    pub fn alloc_bound<'guard>(
+        mem: &'guard MutatorView,
+        callee: TaggedScopedPtr<'guard>,
+        args: &[TaggedCellPtr],
+        holes: &[u8],
+    ) -> Result<ScopedPtr<'guard, Partial>, RuntimeError> {
+        let partial = match *callee {
+            Value::Partial(partial) => partial,
+            _ => return Partial::alloc_with(mem, callee, TaggedCellPtr::new_nil(), args, holes),
+        };
+
+        let open = partial.holes(mem);
+        let mut merged = partial.args(mem).access_slice(mem, |items| items.to_vec());
+        let mut merged_holes = Vec::new();
+
+        for (index, arg) in args.iter().enumerate() {
+            let position = match open.get(index) {
+                Some(position) => {
+                    merged[*position as usize] = arg.clone();
+                    *position as usize
+                }
+                None => {
+                    merged.push(arg.clone());
+                    merged.len() - 1
+                }
+            };
+
+            if holes.contains(&(index as u8)) {
+                merged_holes.push(position as u8);
+            }
+        }
+
+        // Placeholders that the new arguments did not reach stay open
+        merged_holes.extend(open.iter().skip(args.len()));
+        merged_holes.sort_unstable();
+
+        Partial::alloc_with(
+            mem,
+            partial.callee(mem),
+            partial.env.clone(),
+            &merged,
+            &merged_holes,
+        )
     }
 
-    /// Clone an existing Partial application, appending the given arguments to the list
-    pub fn alloc_clone<'guard>(
+    fn alloc_with<'guard>(
         mem: &'guard MutatorView,
-        partial: ScopedPtr<'guard, Partial>,
-        new_args: &[TaggedCellPtr],
+        callee: TaggedScopedPtr<'guard>,
+        env: TaggedCellPtr,
+        args: &[TaggedCellPtr],
+        holes: &[u8],
     ) -> Result<ScopedPtr<'guard, Partial>, RuntimeError> {
-        let used = partial.used() + new_args.len() as u8;
-        let arity = partial.arity() - new_args.len() as u8;
-
-        // clone the parent Partial's args
-        let arg_list = List::alloc_clone(mem, partial.args(mem))?;
-        // append any new args
-        for arg in new_args {
-            arg_list.push(mem, arg.clone())?
+        let callee_arity = match *callee {
+            Value::Function(function) => function.arity(),
+            Value::NativeFunction(native) => native.arity(),
+            _ => return Err(err_eval("Type is not callable")),
+        };
+
+        if args.len() > u8::MAX as usize {
+            return Err(err_eval("Too many arguments in partial application"));
         }
+        if holes
+            .iter()
+            .any(|position| *position as usize >= args.len())
+        {
+            return Err(err_eval("Placeholder position is past the arguments"));
+        }
+
+        let used = args.len() as u8;
+        let bound = used.saturating_sub(holes.len() as u8);
+        let arity = callee_arity.saturating_sub(bound).max(holes.len() as u8);
+
+        // copy args to the Partial's own list
+        let args_list: ScopedPtr<'guard, List> = ContainerFromSlice::from_slice(mem, args)?;
+
+        // Store a nil ptr if there are no placeholders
+        let holes = if holes.is_empty() {
+            TaggedCellPtr::new_nil()
+        } else {
+            let holes_array: ScopedPtr<'guard, ArrayU8> =
+                ContainerFromSlice::from_slice(mem, holes)?;
+            TaggedCellPtr::new_with(holes_array.as_tagged(mem))
+        };
 
         mem.alloc(Partial {
             arity,
             used,
-            args: CellPtr::new_with(arg_list),
-            env: partial.env.clone(),
-            func: partial.func.clone(),
+            args: CellPtr::new_with(args_list),
+            holes,
+            env,
+            func: TaggedCellPtr::new_with(callee),
         })
     }
 
@@ -222,39 +513,81 @@ impl Partial {
         self.args.get(guard)
     }
 
+    /// Return the positions of the placeholders in the arguments, in order
+    pub fn holes<'guard>(&self, guard: &'guard dyn MutatorScope) -> Vec<u8> {
+        match *self.holes.get(guard) {
+            Value::ArrayU8(holes) => holes.access_slice(guard, |items| items.to_vec()),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Return the arguments to call the function with when the Partial is called with `args`.
+    /// The first of them fill the placeholders and the rest follow the Partial's own arguments.
+    pub fn apply<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+        args: &[TaggedCellPtr],
+    ) -> Result<Vec<TaggedCellPtr>, RuntimeError> {
+        let holes = self.holes(guard);
+        if args.len() < holes.len() {
+            return Err(err_eval(&format!(
+                "Partial application of {} expected at least {} arguments, got {}",
+                self.name(guard),
+                holes.len(),
+                args.len()
+            )));
+        }
+
+        let mut applied = self.args(guard).access_slice(guard, |items| items.to_vec());
+        for (position, arg) in holes.iter().zip(args) {
+            applied[*position as usize] = arg.clone();
+        }
+        applied.extend_from_slice(&args[holes.len()..]);
+
+        Ok(applied)
+    }
+
     /// Return the closure environment. This will be nil if the Partial does not close over any
     /// variables.
     pub fn closure_env(&self) -> TaggedCellPtr {
         self.env.clone()
     }
 
-    /// Return the Function object that the Partial will call
-    pub fn function<'guard>(&self, guard: &'guard dyn MutatorScope) -> ScopedPtr<'guard, Function> {
+    /// Return the Function or NativeFunction that the Partial will call
+    pub fn callee<'guard>(&self, guard: &'guard dyn MutatorScope) -> TaggedScopedPtr<'guard> {
         self.func.get(guard)
     }
+
+    /// Return the name of the function that the Partial will call
+    pub fn name<'guard>(&self, guard: &'guard dyn MutatorScope) -> &'guard str {
+        match *self.func.get(guard) {
+            Value::Function(function) => function.name(guard),
+            Value::NativeFunction(native) => native.name(guard),
+            _ => unreachable!(),
+        }
+    }
 }
 
 impl Print for Partial {
-    /// Prints a string representation of the Partial object
+    /// Prints the name of the function and the arguments applied so far, with placeholders
+    /// shown as `_`
     fn print<'guard>(
         &self,
         guard: &'guard dyn MutatorScope,
         f: &mut fmt::Formatter,
     ) -> fmt::Result {
-        let function = self.func.get(guard);
-        let name = function.name.get(guard);
-        let params = function.param_names.get(guard);
-
-        let mut param_string = String::new();
-        params.access_slice(guard, |items| {
-            let start = self.used as usize;
-            param_string = join(items[start..].iter().map(|item| item.get(guard)), " ")
+        let holes = self.holes(guard);
+        let args = self.args(guard).access_slice(guard, |items| {
+            let args = items.iter().enumerate().map(|(index, item)| {
+                match holes.contains(&(index as u8)) {
+                    true => String::from("_"),
+                    false => format!("{}", item.get(guard)),
+                }
+            });
+            join(args, " ")
         });
 
-        match *name {
-            Value::Symbol(s) => write!(f, "(Partial {} ({}))", s.as_str(guard), param_string),
-            _ => write!(f, "(Partial ({}))", param_string),
-        }
+        write!(f, "(Partial {} ({}))", self.name(guard), args)
     }
 
     /// Prints the associated function's disassembled bytecode
@@ -264,8 +597,20 @@ impl Print for Partial {
         f: &mut fmt::Formatter,
     ) -> fmt::Result {
         self.print(guard, f)?;
-        write!(f, "\nbytecode follows:\n")?;
-        self.func.get(guard).code(guard).debug(guard, f)
+        if let Value::Function(function) = *self.func.get(guard) {
+            write!(f, "\nbytecode follows:\n")?;
+            function.code(guard).debug(guard, f)?;
+        }
+        Ok(())
+    }
+}
+
+impl Trace for Partial {
+    fn trace<'guard>(&self, _guard: &'guard dyn MutatorScope, visit: &mut Visitor) {
+        visit_cell(&self.args, visit);
+        visit_tagged_cell(&self.holes, visit);
+        visit_tagged_cell(&self.env, visit);
+        visit_tagged_cell(&self.func, visit);
     }
 }
 
@@ -0,0 +1,636 @@
+/// Sticky mark bit collection
+///
+/// Objects are allocated unmarked - young. A collection marks everything it can reach and the
+/// mark is never cleared by a minor collection, so surviving objects become old and a later
+/// minor collection stops tracing as soon as it reaches one. This means a minor collection only
+/// walks the objects allocated since the previous collection.
+///
+/// For that to be sound, any pointer to a young object that is written into an already-existing
+/// object must be found by the next minor collection. Every pointer write goes through a write
+/// barrier that records the young object in a remembered set, and a minor collection treats the
+/// remembered set as extra roots.
+///
+/// Cells do not know which object contains them, so the barrier remembers the young _target_ of
+/// the write rather than the old slot. This over-approximates the set of old-to-young references
+/// - a young object written only into another young object survives one more cycle than it needs
+/// to - but never misses one.
+///
+/// After marking, registered `WeakRef`s whose target was left unmarked are cleared, and objects
+/// registered for finalization that were left unmarked have their finalizers run. Nothing is
+/// swept yet, so an unreachable object's memory is still intact when its finalizer runs.
+///
+/// The remembered set and the WeakRef and finalizer registrations belong to a heap, in a
+/// `GcRegistry`. `Memory::mutate` makes its heap's registry current for as long as the mutator
+/// runs, which is where the write barrier and the collection functions find it.
+use std::cell::{Cell, RefCell};
+use std::collections::HashSet;
+use std::ptr::NonNull;
+
+use crate::allocator::AllocHeader;
+use crate::header::{header_of, header_of_mut};
+use crate::heapdump::Root;
+use crate::raw_array::RawArray;
+use crate::safe_ptr::MutatorScope;
+use crate::tagged_ptr::TaggedPtr;
+use crate::weak::WeakRef;
+
+/// A function called with an object's address once the object has become unreachable. It must
+/// not store the pointer anywhere: the object will not survive to the next collection.
+pub type Finalizer = fn(NonNull<()>);
+
+/// The collector's records for one heap. They hold raw addresses of that heap's objects, so the
+/// heap owns them, and they are only reachable while a mutator is running against it.
+#[derive(Default)]
+pub struct GcRegistry {
+    /// Young objects that have been written into some other object since the last collection
+    remembered: RefCell<HashSet<usize>>,
+    /// Every live WeakRef object
+    weak_refs: RefCell<Vec<usize>>,
+    /// Objects with a finalizer to run once they become unreachable
+    finalizers: RefCell<Vec<(usize, Finalizer)>>,
+}
+
+impl GcRegistry {
+    pub fn new() -> GcRegistry {
+        GcRegistry::default()
+    }
+
+    /// Drop every record of the objects at addresses that `freed` returns true for, which are
+    /// about to be freed: they leave the remembered set, WeakRefs to them are cleared and their
+    /// finalizers are forgotten
+    pub fn forget(&self, freed: impl Fn(usize) -> bool) {
+        self.remembered.borrow_mut().retain(|addr| !freed(*addr));
+
+        for addr in self.weak_refs.borrow().iter() {
+            let weak = unsafe { &*(*addr as *const WeakRef) };
+            if let Some(target) = weak.get_ptr().as_object_ptr() {
+                if freed(target.as_ptr() as usize) {
+                    weak.clear();
+                }
+            }
+        }
+
+        self.finalizers
+            .borrow_mut()
+            .retain(|(addr, _)| !freed(*addr));
+    }
+}
+
+thread_local! {
+    /// The registry of the heap that the running mutator is using. Write barriers are called
+    /// from inside objects, with no way to reach the heap, so they find its registry here.
+    static CURRENT: Cell<Option<NonNull<GcRegistry>>> = Cell::new(None);
+}
+
+/// Makes a heap's registry the current one until dropped, then restores whichever registry was
+/// current before
+pub struct ActiveRegistry {
+    previous: Option<NonNull<GcRegistry>>,
+}
+
+impl ActiveRegistry {
+    /// The registry must outlive the returned value
+    pub fn enter(registry: &GcRegistry) -> ActiveRegistry {
+        let previous = CURRENT.with(|current| current.replace(Some(NonNull::from(registry))));
+        ActiveRegistry { previous }
+    }
+}
+
+impl Drop for ActiveRegistry {
+    fn drop(&mut self) {
+        CURRENT.with(|current| current.set(self.previous));
+    }
+}
+
+/// Call `f` with the current registry, or return the default if no mutator is running
+fn with_registry<R: Default>(f: impl FnOnce(&GcRegistry) -> R) -> R {
+    match CURRENT.with(|current| current.get()) {
+        Some(registry) => f(unsafe { registry.as_ref() }),
+        None => R::default(),
+    }
+}
+
+/// Record a write of a pointer to `object` into another object
+// ANCHOR: DefWriteBarrier
+pub fn write_barrier(object: NonNull<()>) {
+    if !unsafe { header_of(object) }.is_marked() {
+        with_registry(|registry| {
+            registry
+                .remembered
+                .borrow_mut()
+                .insert(object.as_ptr() as usize);
+        });
+    }
+}
+// ANCHOR_END: DefWriteBarrier
+
+/// Record a write of a tagged pointer into another object. Inline values and Symbols are
+/// ignored.
+pub fn write_barrier_tagged(ptr: TaggedPtr) {
+    if let Some(object) = ptr.as_object_ptr() {
+        write_barrier(object);
+    }
+}
+
+/// Record the replacement of a container's backing array
+pub fn write_barrier_raw_array<T>(array: &RawArray<T>) {
+    if let Some(ptr) = array.as_ptr() {
+        if let Some(ptr) = NonNull::new(ptr as *mut ()) {
+            write_barrier(ptr);
+        }
+    }
+}
+
+/// Return the number of objects currently in the remembered set
+pub fn remembered_count() -> usize {
+    with_registry(|registry| registry.remembered.borrow().len())
+}
+
+/// Register a WeakRef so that its target is cleared when the target is collected
+pub fn register_weak_ref(weak: &WeakRef) {
+    with_registry(|registry| {
+        registry
+            .weak_refs
+            .borrow_mut()
+            .push(weak as *const WeakRef as usize)
+    });
+}
+
+/// Return the number of WeakRefs registered with the collector
+pub fn weak_ref_count() -> usize {
+    with_registry(|registry| registry.weak_refs.borrow().len())
+}
+
+/// Register a finalizer to be run once, after the collection that finds `object` unreachable
+pub fn register_finalizer(object: NonNull<()>, finalizer: Finalizer) {
+    with_registry(|registry| {
+        registry
+            .finalizers
+            .borrow_mut()
+            .push((object.as_ptr() as usize, finalizer))
+    });
+}
+
+/// Return the number of objects still waiting to be finalized
+pub fn finalizer_count() -> usize {
+    with_registry(|registry| registry.finalizers.borrow().len())
+}
+
+/// Counts of what a collection marked
+#[derive(Debug, Default, PartialEq)]
+pub struct MarkStats {
+    /// Number of objects newly marked
+    pub objects: usize,
+    /// Total size of the objects newly marked
+    pub bytes: usize,
+    /// Number of WeakRefs cleared
+    pub weak_cleared: usize,
+    /// Number of finalizers run
+    pub finalized: usize,
+}
+
+/// Mark everything reachable from `start` that is not already marked, stopping at old objects
+fn mark_from<'guard>(guard: &'guard dyn MutatorScope, start: NonNull<()>, stats: &mut MarkStats) {
+    let mut pending = vec![start];
+
+    while let Some(object) = pending.pop() {
+        let header = unsafe { header_of_mut(object) };
+        if header.is_marked() {
+            continue;
+        }
+
+        header.mark();
+        stats.objects += 1;
+        stats.bytes += header.size() as usize;
+
+        unsafe { header.trace_object(guard, &mut |child| pending.push(child)) };
+    }
+}
+
+/// Return the heap objects the given roots point at
+fn root_objects(roots: &[Root]) -> Vec<NonNull<()>> {
+    roots.iter().filter_map(|root| root.ptr.as_object_ptr()).collect()
+}
+
+/// Return true if the object at the given address is marked
+fn is_marked_addr(addr: usize) -> bool {
+    match NonNull::new(addr as *mut ()) {
+        Some(object) => unsafe { header_of(object) }.is_marked(),
+        None => false,
+    }
+}
+
+/// Run a minor collection: mark the young objects reachable from the given roots and the
+/// remembered set. Old objects are not traced.
+// ANCHOR: DefMinorCollection
+pub fn minor_collection<'guard>(guard: &'guard dyn MutatorScope, roots: &[Root]) -> MarkStats {
+    minor_collection_from(guard, &root_objects(roots))
+}
+
+/// Run a minor collection from a set of root objects
+pub fn minor_collection_from<'guard>(
+    guard: &'guard dyn MutatorScope,
+    roots: &[NonNull<()>],
+) -> MarkStats {
+    let mut stats = MarkStats::default();
+
+    for root in roots {
+        mark_from(guard, *root, &mut stats);
+    }
+
+    let remembered: Vec<usize> =
+        with_registry(|registry| registry.remembered.borrow_mut().drain().collect());
+
+    for addr in remembered {
+        if let Some(object) = NonNull::new(addr as *mut ()) {
+            mark_from(guard, object, &mut stats);
+        }
+    }
+
+    after_marking(&mut stats);
+    stats
+}
+// ANCHOR_END: DefMinorCollection
+
+/// Run a full collection: clear the mark on everything reachable from the given roots, then
+/// mark it all again from scratch.
+pub fn full_collection<'guard>(guard: &'guard dyn MutatorScope, roots: &[Root]) -> MarkStats {
+    full_collection_from(guard, &root_objects(roots))
+}
+
+/// Run a full collection from a set of root objects
+pub fn full_collection_from<'guard>(
+    guard: &'guard dyn MutatorScope,
+    roots: &[NonNull<()>],
+) -> MarkStats {
+    let mut seen = HashSet::new();
+    let mut pending: Vec<NonNull<()>> = roots.to_vec();
+
+    while let Some(object) = pending.pop() {
+        if !seen.insert(object.as_ptr() as usize) {
+            continue;
+        }
+
+        let header = unsafe { header_of_mut(object) };
+        header.unmark();
+        unsafe { header.trace_object(guard, &mut |child| pending.push(child)) };
+    }
+
+    // Old objects that are no longer reachable from the roots keep their mark, since nothing
+    // walks the whole heap. That only matters for objects whose liveness is observable: weak
+    // targets, WeakRefs themselves and finalizable objects.
+    for addr in observed_objects() {
+        if let Some(object) = NonNull::new(addr as *mut ()) {
+            unsafe { header_of_mut(object) }.unmark();
+        }
+    }
+
+    with_registry(|registry| registry.remembered.borrow_mut().clear());
+
+    let mut stats = MarkStats::default();
+    for root in roots {
+        mark_from(guard, *root, &mut stats);
+    }
+
+    after_marking(&mut stats);
+    stats
+}
+
+/// Return the address of every object whose liveness a WeakRef or finalizer depends on
+fn observed_objects() -> Vec<usize> {
+    let mut objects = Vec::new();
+
+    with_registry(|registry| {
+        for addr in registry.weak_refs.borrow().iter() {
+            objects.push(*addr);
+
+            let weak = unsafe { &*(*addr as *const WeakRef) };
+            if let Some(target) = weak.get_ptr().as_object_ptr() {
+                objects.push(target.as_ptr() as usize);
+            }
+        }
+    });
+
+    with_registry(|registry| {
+        objects.extend(registry.finalizers.borrow().iter().map(|(addr, _)| *addr));
+    });
+
+    objects
+}
+
+/// Clear WeakRefs to unmarked targets and run the finalizers of unmarked objects. Unmarked
+/// WeakRefs are unreachable themselves and are forgotten.
+// ANCHOR: DefAfterMarking
+fn after_marking(stats: &mut MarkStats) {
+    with_registry(|registry| {
+        registry.weak_refs.borrow_mut().retain(|addr| {
+            if !is_marked_addr(*addr) {
+                return false;
+            }
+
+            let weak = unsafe { &*(*addr as *const WeakRef) };
+            if let Some(target) = weak.get_ptr().as_object_ptr() {
+                if !unsafe { header_of(target) }.is_marked() {
+                    weak.clear();
+                    stats.weak_cleared += 1;
+                }
+            }
+
+            true
+        })
+    });
+
+    let mut ready = Vec::new();
+    with_registry(|registry| {
+        registry
+            .finalizers
+            .borrow_mut()
+            .retain(|(addr, finalizer)| {
+                if is_marked_addr(*addr) {
+                    true
+                } else {
+                    ready.push((*addr, *finalizer));
+                    false
+                }
+            })
+    });
+
+    // Finalizers are run once the registry is no longer borrowed so that they may register
+    // finalizers of their own
+    for (addr, finalizer) in ready {
+        if let Some(object) = NonNull::new(addr as *mut ()) {
+            finalizer(object);
+            stats.finalized += 1;
+        }
+    }
+}
+// ANCHOR_END: DefAfterMarking
+
+/// Return true if the object has survived a collection
+pub fn is_old(ptr: TaggedPtr) -> bool {
+    match ptr.as_object_ptr() {
+        Some(object) => unsafe { header_of(object) }.is_marked(),
+        None => true,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::ptr::NonNull;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::{
+        finalizer_count, full_collection, is_old, minor_collection, register_finalizer,
+        remembered_count, weak_ref_count, ActiveRegistry, GcRegistry,
+    };
+    use crate::container::StackAnyContainer;
+    use crate::error::RuntimeError;
+    use crate::heapdump::{Root, RootKind};
+    use crate::list::List;
+    use crate::memory::{Memory, Mutator, MutatorView};
+    use crate::text::Text;
+    use crate::weak::WeakRef;
+
+    #[test]
+    fn gc_minor_collection_follows_write_barrier() {
+        let mem = Memory::new();
+
+        struct Test {}
+        impl Mutator for Test {
+            type Input = ();
+            type Output = ();
+
+            fn run(
+                &self,
+                mem: &MutatorView,
+                _input: Self::Input,
+            ) -> Result<Self::Output, RuntimeError> {
+                let list = List::alloc(mem)?;
+                let roots = [Root {
+                    kind: RootKind::Global(String::from("list")),
+                    ptr: list.as_tagged(mem).get_ptr(),
+                }];
+
+                // promote the list
+                let stats = minor_collection(mem, &roots);
+                assert!(stats.objects == 1);
+                assert!(is_old(roots[0].ptr));
+
+                // an old list now points at a young Text, which only the barrier knows about
+                let text = mem.alloc_tagged(Text::new_from_str(mem, "young")?)?;
+                StackAnyContainer::push(&*list, mem, text)?;
+                assert!(!is_old(text.get_ptr()));
+
+                let garbage = mem.alloc_tagged(Text::new_from_str(mem, "garbage")?)?;
+
+                minor_collection(mem, &roots);
+                assert!(is_old(text.get_ptr()));
+                assert!(!is_old(garbage.get_ptr()));
+
+                // nothing young is left, so another minor collection marks nothing
+                let stats = minor_collection(mem, &roots);
+                assert!(stats.objects == 0);
+
+                // a full collection re-marks everything reachable
+                let stats = full_collection(mem, &roots);
+                assert!(stats.objects >= 3);
+
+                Ok(())
+            }
+        }
+
+        let test = Test {};
+        mem.mutate(&test, ()).unwrap();
+    }
+
+    static FINALIZED: AtomicUsize = AtomicUsize::new(0);
+
+    fn count_finalized(_object: NonNull<()>) {
+        FINALIZED.fetch_add(1, Ordering::SeqCst);
+    }
+
+    #[test]
+    fn gc_clears_weak_refs_and_runs_finalizers() {
+        let mem = Memory::new();
+
+        struct Test {}
+        impl Mutator for Test {
+            type Input = ();
+            type Output = ();
+
+            fn run(
+                &self,
+                mem: &MutatorView,
+                _input: Self::Input,
+            ) -> Result<Self::Output, RuntimeError> {
+                let list = List::alloc(mem)?;
+                let roots = [Root {
+                    kind: RootKind::Global(String::from("cache")),
+                    ptr: list.as_tagged(mem).get_ptr(),
+                }];
+
+                let kept = mem.alloc_tagged(Text::new_from_str(mem, "kept")?)?;
+                StackAnyContainer::push(&*list, mem, kept)?;
+                let dropped = mem.alloc_tagged(Text::new_from_str(mem, "dropped")?)?;
+
+                // both WeakRefs are reachable, but only one target is
+                let weak_kept = WeakRef::alloc(mem, kept)?;
+                let weak_dropped = WeakRef::alloc(mem, dropped)?;
+                StackAnyContainer::push(&*list, mem, weak_kept.as_tagged(mem))?;
+                StackAnyContainer::push(&*list, mem, weak_dropped.as_tagged(mem))?;
+
+                let file = mem.alloc_tagged(Text::new_from_str(mem, "file handle")?)?;
+                register_finalizer(file.get_ptr().as_object_ptr().unwrap(), count_finalized);
+                assert!(finalizer_count() == 1);
+
+                let stats = minor_collection(mem, &roots);
+                assert!(stats.weak_cleared == 1);
+                assert!(stats.finalized == 1);
+                assert!(FINALIZED.load(Ordering::SeqCst) == 1);
+                assert!(finalizer_count() == 0);
+
+                assert!(weak_kept.get(mem) == kept);
+                assert!(weak_dropped.is_cleared());
+                assert!(weak_dropped.get(mem).get_ptr().is_nil());
+
+                // a full collection finds old targets that have become unreachable
+                let list_items = List::alloc(mem)?;
+                let old = mem.alloc_tagged(Text::new_from_str(mem, "old")?)?;
+                StackAnyContainer::push(&*list_items, mem, old)?;
+                let weak_old = WeakRef::alloc(mem, old)?;
+                StackAnyContainer::push(&*list, mem, weak_old.as_tagged(mem))?;
+                StackAnyContainer::push(&*list, mem, list_items.as_tagged(mem))?;
+
+                minor_collection(mem, &roots);
+                assert!(!weak_old.is_cleared());
+
+                StackAnyContainer::pop(&*list, mem)?;
+                let stats = full_collection(mem, &roots);
+                assert!(stats.weak_cleared == 1);
+                assert!(weak_old.is_cleared());
+
+                Ok(())
+            }
+        }
+
+        let test = Test {};
+        mem.mutate(&test, ()).unwrap();
+    }
+
+    #[test]
+    fn gc_registries_belong_to_their_heap() {
+        let first = Memory::new();
+        let second = Memory::new();
+
+        struct Test {}
+        impl Mutator for Test {
+            type Input = bool;
+            type Output = usize;
+
+            fn run(&self, mem: &MutatorView, register: bool) -> Result<usize, RuntimeError> {
+                if register {
+                    let file = mem.alloc_tagged(Text::new_from_str(mem, "file handle")?)?;
+                    register_finalizer(file.get_ptr().as_object_ptr().unwrap(), count_finalized);
+                }
+
+                Ok(finalizer_count())
+            }
+        }
+
+        let test = Test {};
+        assert!(first.mutate(&test, true).unwrap() == 1);
+        assert!(second.mutate(&test, false).unwrap() == 0);
+        assert!(first.mutate(&test, false).unwrap() == 1);
+
+        // the registration went with the heap it pointed into
+        drop(first);
+        assert!(second.mutate(&test, false).unwrap() == 0);
+
+        // and nothing is registered outside a mutator
+        assert!(finalizer_count() == 0);
+    }
+
+    #[test]
+    fn gc_weak_refs_are_dropped_with_their_heap() {
+        let first = Memory::new();
+        let second = Memory::new();
+
+        struct Test {}
+        impl Mutator for Test {
+            type Input = bool;
+            type Output = usize;
+
+            fn run(&self, mem: &MutatorView, register: bool) -> Result<usize, RuntimeError> {
+                let list = List::alloc(mem)?;
+                let roots = [Root {
+                    kind: RootKind::Global(String::from("refs")),
+                    ptr: list.as_tagged(mem).get_ptr(),
+                }];
+
+                if register {
+                    let target = mem.alloc_tagged(Text::new_from_str(mem, "target")?)?;
+                    let weak = WeakRef::alloc(mem, target)?;
+                    StackAnyContainer::push(&*list, mem, weak.as_tagged(mem))?;
+                }
+
+                // a collection only visits the WeakRefs of the heap it runs in
+                full_collection(mem, &roots);
+                Ok(weak_ref_count())
+            }
+        }
+
+        let test = Test {};
+        assert!(first.mutate(&test, true).unwrap() == 1);
+        assert!(second.mutate(&test, false).unwrap() == 0);
+
+        drop(first);
+        assert!(second.mutate(&test, false).unwrap() == 0);
+    }
+
+    #[test]
+    fn gc_forget_drops_records_of_freed_objects() {
+        let mem = Memory::new();
+
+        struct Test {}
+        impl Mutator for Test {
+            type Input = ();
+            type Output = ();
+
+            fn run(
+                &self,
+                mem: &MutatorView,
+                _input: Self::Input,
+            ) -> Result<Self::Output, RuntimeError> {
+                let registry = GcRegistry::new();
+                let _active = ActiveRegistry::enter(&registry);
+
+                let list = List::alloc(mem)?;
+                let freed = mem.alloc_tagged(Text::new_from_str(mem, "freed")?)?;
+                let kept = mem.alloc_tagged(Text::new_from_str(mem, "kept")?)?;
+                let freed_addr = freed.get_ptr().as_object_ptr().unwrap();
+
+                // remember both Texts, watch them and register them for finalization
+                StackAnyContainer::push(&*list, mem, freed)?;
+                StackAnyContainer::push(&*list, mem, kept)?;
+                let weak_freed = WeakRef::alloc(mem, freed)?;
+                let weak_kept = WeakRef::alloc(mem, kept)?;
+                register_finalizer(freed_addr, count_finalized);
+                register_finalizer(kept.get_ptr().as_object_ptr().unwrap(), count_finalized);
+
+                let remembered = remembered_count();
+                registry.forget(|addr| addr == freed_addr.as_ptr() as usize);
+
+                assert!(remembered_count() == remembered - 1);
+                assert!(weak_ref_count() == 2);
+                assert!(weak_freed.is_cleared());
+                assert!(!weak_kept.is_cleared());
+                assert!(finalizer_count() == 1);
+
+                Ok(())
+            }
+        }
+
+        let test = Test {};
+        mem.mutate(&test, ()).unwrap();
+    }
+}
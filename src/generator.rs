@@ -1,9 +1,1841 @@
-pub struct Generator {}
+/// Bytecode generation from the Ast
+///
+/// The `Generator` walks the Ast produced by the parser and emits register machine instructions
+/// for the top-level script into a plain `Vec<Opcode>`, along with a list of the literals they
+/// load. Nothing touches the heap until `function()` is called with a `MutatorView`, at which
+/// point the code and literals are copied into a `ByteCode` object wrapped in a `Function` that a
+/// `Thread` can evaluate.
+///
+/// Temporaries are allocated registers in stack order and released as soon as the expression that
+/// consumes them has been generated. If an expression needs more registers than the window has
+/// left, it is generated again in a fresh window with the registers in use spilled to the call
+/// frame's locals, and the liveness analysis later removes spills of registers that are never
+/// read again.
+///
+/// Jump offsets are filled in once the whole script has been generated, and literal ids, integers
+/// and jump offsets that do not fit in their 16 bit operands are given a `Wide` prefix. A call
+/// whose result is returned straight away becomes a `TailCall`.
+///
+/// A `try` block is protected by entries in the exception handler table rather than by any
+/// instruction. A `finally` block is compiled once for each way out of the `try`: falling off the
+/// end, leaving the `catch`, `break`, `continue` or `return`, and an exception that is thrown on
+/// after it.
+///
+/// A function declaration is generated by a `Generator` of its own and loaded as a literal. Its
+/// parameters are held in the registers from FIRST_ARG_REG onwards, and a parameter with a default
+/// that the caller did not supply is given it on entry. Any other name is a global. A function
+/// returns nil unless it leaves by a `return` statement.
+///
+/// An `import` names its module by the path written in the source. Whoever loads the modules
+/// replaces each one with the canonical path of the file it resolved to with `resolve_imports`.
+use std::collections::HashMap;
+
+use crate::array::ArraySize;
+use crate::ast::{Node, NodeVal};
+use crate::bytecode::{
+    split_operand, ByteCode, Handler, JumpOffset, LiteralInteger, LocalId, NumArgs, Opcode,
+    Register, JUMP_UNKNOWN,
+};
+use crate::container::AnyContainerFromSlice;
+use crate::error::{err_compile, ErrorKind, RuntimeError};
+use crate::function::{Function, MAX_DEFAULTS};
+use crate::list::List;
+use crate::liveness;
+use crate::memory::MutatorView;
+use crate::optimizer;
+use crate::safe_ptr::{ScopedPtr, TaggedScopedPtr};
+use crate::tagged_ptr::TaggedPtr;
+use crate::text::Text;
+use crate::tokens::Tok;
+use crate::vm::{ENV_REG, FIRST_ARG_REG};
+
+/// The error raised when the register window is exhausted, which triggers spilling
+const OUT_OF_REGISTERS: &str = "Expression too complex, out of registers";
+
+/// The deepest an expression may nest. Each level recurses on the native stack, so a deeper
+/// expression is a compile error rather than a stack overflow.
+const MAX_EXPR_DEPTH: usize = 200;
+
+/// A literal value, held outside the heap until the code is materialized
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Literal {
+    Number(isize),
+    Text(String),
+    Symbol(String),
+    /// A List of literals, such as the names of a call's keyword arguments
+    List(Vec<Literal>),
+    /// The path of an imported module, materialized as a Symbol
+    Module(String),
+    /// A declared function, by its index in the Generator's `functions`
+    Function(usize),
+}
+
+/// The function a call is made to
+enum Callee<'a> {
+    /// A global variable bound to the function, by name
+    Global(String),
+    /// An expression that evaluates to the function, such as a module member
+    Expr(&'a Node),
+}
+
+/// Jump instructions in a loop body that still need their targets filled in
+struct Loop {
+    /// Indexes of the jumps emitted for `continue`, patched to the loop head
+    continues: Vec<usize>,
+    /// Indexes of the jumps emitted for `break`, patched to the loop exit
+    breaks: Vec<usize>,
+}
+
+/// Jumps out of a `try` block that must run its `finally` block on the way
+struct Finally {
+    /// The number of loops enclosing the `try` statement
+    loop_depth: usize,
+    /// Indexes of the jumps emitted for `break`, patched to a copy of the `finally` block
+    breaks: Vec<usize>,
+    /// Indexes of the jumps emitted for `continue`, patched to a copy of the `finally` block
+    continues: Vec<usize>,
+    /// Indexes of the jumps emitted for `return`, patched to a copy of the `finally` block
+    returns: Vec<usize>,
+    /// The register holding the value being returned while the `finally` block runs
+    value: Register,
+}
+
+pub struct Generator {
+    code: Vec<Opcode>,
+    literals: Vec<Literal>,
+    /// The index of each literal, to share one entry between every load of a value
+    literal_ids: HashMap<Literal, usize>,
+    /// Each jump instruction and the index of the instruction it targets
+    jumps: Vec<(usize, usize)>,
+    /// Symbol names indexed by SymID
+    symbols: Vec<String>,
+    /// The lowest register not currently holding a temporary value
+    next_reg: usize,
+    /// The lowest local not currently holding a spilled register
+    next_local: usize,
+    /// Whether any registers were spilled, so that liveness analysis is worth running
+    spilled: bool,
+    /// How deeply the expression being compiled is nested
+    expr_depth: usize,
+    /// The loops enclosing the code being generated, innermost last
+    loops: Vec<Loop>,
+    /// The `try` blocks with a `finally` enclosing the code being generated, innermost last
+    finallies: Vec<Finally>,
+    /// The exception handler table, innermost handlers first
+    handlers: Vec<Handler>,
+    /// The name of the function being generated, or None for a script
+    name: Option<String>,
+    /// The names of the function's parameters, in the registers from FIRST_ARG_REG onwards
+    params: Vec<String>,
+    /// The number of parameters without a default
+    arity: usize,
+    /// Whether the last parameter collects any further arguments into a List
+    variadic: bool,
+    /// The functions declared in the code, each generated by a Generator of its own
+    functions: Vec<Generator>,
+}
 
 impl Generator {
     pub fn init() -> Generator {
         Generator {
+            code: Vec::new(),
+            literals: Vec::new(),
+            literal_ids: HashMap::new(),
+            jumps: Vec::new(),
+            symbols: Vec::new(),
+            next_reg: FIRST_ARG_REG,
+            next_local: 0,
+            spilled: false,
+            expr_depth: 0,
+            loops: Vec::new(),
+            finallies: Vec::new(),
+            handlers: Vec::new(),
+            name: None,
+            params: Vec::new(),
+            arity: 0,
+            variadic: false,
+            functions: Vec::new(),
+        }
+    }
+
+    /// Generate code for a whole script, replacing anything previously generated
+    pub fn generate(
+        &mut self,
+        root: &Node,
+        symbol_table: &HashMap<String, usize>,
+    ) -> Result<(), RuntimeError> {
+        *self = Generator::init();
+
+        self.symbols = vec![String::new(); symbol_table.len()];
+        for (name, sym_id) in symbol_table {
+            self.symbols[*sym_id] = name.clone();
+        }
+
+        self.compile_stmt(root)?;
+        self.finish()
+    }
+
+    /// Return nil from the end of the generated code and fill in its jumps
+    fn finish(&mut self) -> Result<(), RuntimeError> {
+        let result = self.acquire_reg()?;
+        self.push(Opcode::LoadNil { dest: result });
+        self.push(Opcode::Return { reg: result });
+
+        self.mark_tail_calls();
+        self.resolve_jumps()?;
+
+        if self.spilled {
+            liveness::remove_dead_spills(&mut self.code, &mut self.handlers);
+        }
+
+        Ok(())
+    }
+
+    /// Run the optimization passes over the generated code and every declared function
+    pub fn optimize(&mut self) {
+        optimizer::optimize(&mut self.code, &mut self.literals, &mut self.handlers);
+
+        for function in &mut self.functions {
+            function.optimize();
+        }
+    }
+
+    /// The instructions generated so far
+    pub fn code(&self) -> &[Opcode] {
+        &self.code
+    }
+
+    /// The literals referenced by the generated code
+    pub fn literals(&self) -> &[Literal] {
+        &self.literals
+    }
+
+    /// The paths of the modules the generated code and its declared functions import, as written
+    /// in the source or as resolved
+    pub fn imports(&self) -> Vec<&str> {
+        let mut paths: Vec<&str> = self
+            .literals
+            .iter()
+            .filter_map(|literal| match literal {
+                Literal::Module(path) => Some(path.as_str()),
+                _ => None,
+            })
+            .collect();
+
+        for function in &self.functions {
+            paths.extend(function.imports());
+        }
+        paths
+    }
+
+    /// Replace the path of each imported module with the one `resolve` gives for it
+    pub fn resolve_imports<F>(&mut self, mut resolve: F) -> Result<(), RuntimeError>
+    where
+        F: FnMut(&str) -> Result<String, RuntimeError>,
+    {
+        self.resolve_paths(&mut resolve)
+    }
+
+    fn resolve_paths(
+        &mut self,
+        resolve: &mut dyn FnMut(&str) -> Result<String, RuntimeError>,
+    ) -> Result<(), RuntimeError> {
+        for literal in self.literals.iter_mut() {
+            if let Literal::Module(path) = literal {
+                *path = resolve(path)?;
+            }
+        }
+
+        for function in &mut self.functions {
+            function.resolve_paths(resolve)?;
+        }
+        Ok(())
+    }
+
+    /// Copy the generated code into the heap as an anonymous Function of no arguments. The
+    /// ByteCode, its arrays and the literal lists go into the compilation arena since they are
+    /// only needed for one run.
+    pub fn function<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+    ) -> Result<ScopedPtr<'guard, Function>, RuntimeError> {
+        let bytecode = ByteCode::alloc_in_arena(
+            mem,
+            self.code.len() as ArraySize,
+            self.literals.len() as ArraySize,
+            self.handlers.len() as ArraySize,
+        )?;
+        self.fill_bytecode(mem, bytecode, true)?;
+
+        Function::alloc(mem, mem.nil(), List::alloc(mem)?, bytecode, None)
+    }
+
+    /// Copy the code of a declared function into the heap as a Function. Its ByteCode is
+    /// allocated normally since the Function may be called long after compilation.
+    fn declared_function<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+    ) -> Result<ScopedPtr<'guard, Function>, RuntimeError> {
+        let bytecode = ByteCode::alloc(mem)?;
+        self.fill_bytecode(mem, bytecode, false)?;
+
+        let name = match &self.name {
+            Some(name) => mem.lookup_sym(name),
+            None => mem.nil(),
+        };
+        let params: Vec<TaggedScopedPtr<'_>> = self
+            .params
+            .iter()
+            .map(|param| mem.lookup_sym(param))
+            .collect();
+        let params: ScopedPtr<'_, List> = AnyContainerFromSlice::from_slice(mem, &params)?;
+
+        Function::alloc_variadic(
+            mem,
+            name,
+            params,
+            self.arity as u8,
+            self.variadic,
+            bytecode,
+            None,
+        )
+    }
+
+    /// Copy the literals, instructions and exception handlers into `bytecode`, putting literal
+    /// lists in the compilation arena if `in_arena` is set
+    fn fill_bytecode<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        bytecode: ScopedPtr<'guard, ByteCode>,
+        in_arena: bool,
+    ) -> Result<(), RuntimeError> {
+        for literal in &self.literals {
+            bytecode.push_lit(mem, self.materialize(mem, literal, in_arena)?)?;
+        }
+
+        for op in &self.code {
+            bytecode.push(mem, *op)?;
+        }
+
+        for handler in &self.handlers {
+            bytecode.push_handler(mem, *handler)?;
+        }
+
+        Ok(())
+    }
+
+    /// Allocate a literal on the heap, or a literal list in the compilation arena if `in_arena`
+    /// is set
+    fn materialize<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        literal: &Literal,
+        in_arena: bool,
+    ) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+        let ptr = match literal {
+            Literal::Number(n) => TaggedScopedPtr::new(mem, TaggedPtr::number(*n)),
+            Literal::Text(s) => mem.alloc_tagged(Text::new_from_str(mem, s)?)?,
+            Literal::Symbol(s) | Literal::Module(s) => mem.lookup_sym(s),
+            Literal::List(items) => {
+                let items = items
+                    .iter()
+                    .map(|item| self.materialize(mem, item, in_arena))
+                    .collect::<Result<Vec<_>, RuntimeError>>()?;
+                let list: ScopedPtr<'_, List> = match in_arena {
+                    true => List::from_slice_in_arena(mem, &items)?,
+                    false => AnyContainerFromSlice::from_slice(mem, &items)?,
+                };
+                list.as_tagged(mem)
+            }
+            Literal::Function(index) => self.functions[*index]
+                .declared_function(mem)?
+                .as_tagged(mem),
+        };
+
+        Ok(ptr)
+    }
+
+    fn push(&mut self, op: Opcode) {
+        self.code.push(op);
+    }
+
+    /// Reserve the next free register for a temporary value
+    fn acquire_reg(&mut self) -> Result<Register, RuntimeError> {
+        if self.next_reg > Register::MAX as usize {
+            return Err(err_compile(OUT_OF_REGISTERS));
+        }
+
+        let reg = self.next_reg as Register;
+        self.next_reg += 1;
+        Ok(reg)
+    }
+
+    /// Release every register acquired since `next_reg` was at `mark`
+    fn release_regs(&mut self, mark: usize) {
+        self.next_reg = mark;
+    }
+
+    fn push_literal(&mut self, literal: Literal) -> Result<usize, RuntimeError> {
+        if let Some(index) = self.literal_ids.get(&literal) {
+            return Ok(*index);
+        }
+
+        if self.literals.len() > ArraySize::MAX as usize {
+            return Err(err_compile("Too many literals in one function"));
+        }
+
+        self.literal_ids
+            .insert(literal.clone(), self.literals.len());
+        self.literals.push(literal);
+        Ok(self.literals.len() - 1)
+    }
+
+    /// Load the literal at `literal_id` into the `dest` register
+    fn load_literal(&mut self, dest: Register, literal_id: usize) {
+        let (high, low) = split_operand(literal_id as u32);
+
+        if high != 0 {
+            self.push(Opcode::Wide { high });
+        }
+        self.push(Opcode::LoadLiteral {
+            dest,
+            literal_id: low,
+        });
+    }
+
+    fn symbol_name(&self, node: &Node) -> Result<String, RuntimeError> {
+        match node.val {
+            Some(NodeVal::Sym(sym_id)) => Ok(self.symbols[sym_id].clone()),
+            _ => Err(err_compile(&format!("Expected a name, got {:?}", node.token))),
+        }
+    }
+
+    /// Load the symbol `name` into the `dest` register
+    fn load_symbol(&mut self, dest: Register, name: String) -> Result<(), RuntimeError> {
+        let literal_id = self.push_literal(Literal::Symbol(name))?;
+        self.load_literal(dest, literal_id);
+        Ok(())
+    }
+
+    /// The register holding the parameter `name` of the function being generated, if it has one
+    fn param_reg(&self, name: &str) -> Option<Register> {
+        self.params
+            .iter()
+            .position(|param| param == name)
+            .map(|index| (FIRST_ARG_REG + index) as Register)
+    }
+
+    /// The first register free for temporaries, above the parameters
+    fn first_temp(&self) -> usize {
+        FIRST_ARG_REG + self.params.len()
+    }
+
+    /// Bind the value in the `src` register to the parameter `name`, or to a global variable if
+    /// there is no such parameter
+    fn store_variable(&mut self, src: Register, name: String) -> Result<(), RuntimeError> {
+        match self.param_reg(&name) {
+            Some(dest) => {
+                self.push(Opcode::CopyRegister { dest, src });
+                Ok(())
+            }
+            None => self.store_global(src, name),
+        }
+    }
+
+    /// Bind the value in the `src` register to a global variable
+    fn store_global(&mut self, src: Register, name: String) -> Result<(), RuntimeError> {
+        let mark = self.next_reg;
+        let name_reg = self.acquire_reg()?;
+        self.load_symbol(name_reg, name)?;
+        self.push(Opcode::StoreGlobal { src, name: name_reg });
+        self.release_regs(mark);
+        Ok(())
+    }
+
+    /// Emit a jump with an unknown offset and return its index for patching
+    fn push_jump(&mut self) -> usize {
+        self.push(Opcode::Jump {
+            offset: JUMP_UNKNOWN,
+        });
+        self.code.len() - 1
+    }
+
+    /// Emit a jump taken if the `test` register holds true, with an unknown offset, and return its
+    /// index for patching
+    fn push_jump_if_true(&mut self, test: Register) -> usize {
+        self.push(Opcode::JumpIfTrue {
+            test,
+            offset: JUMP_UNKNOWN,
+        });
+        self.code.len() - 1
+    }
+
+    /// Point the jump at `jump` to the instruction at `target`
+    fn patch_jump(&mut self, jump: usize, target: usize) -> Result<(), RuntimeError> {
+        match self.code[jump] {
+            Opcode::Jump { .. } | Opcode::JumpIfTrue { .. } | Opcode::JumpIfNotTrue { .. } => {
+                self.jumps.push((jump, target))
+            }
+            _ => return Err(err_compile("Cannot patch a non-jump instruction")),
+        }
+
+        Ok(())
+    }
+
+    /// Emit a jump back to the instruction at `target`
+    fn push_jump_to(&mut self, target: usize) -> Result<(), RuntimeError> {
+        let jump = self.push_jump();
+        self.patch_jump(jump, target)
+    }
+
+    /// Turn each call in return position into a TailCall, so that the function it enters reuses
+    /// the current call frame. The value may be copied out of the callee's window on its way to
+    /// the Return, which still happens if the callee is native. A call inside a `try` block is
+    /// left alone since its exceptions must still find this frame's handlers.
+    fn mark_tail_calls(&mut self) {
+        for index in 0..self.code.len() {
+            if self.handlers.iter().any(|handler| handler.covers(index)) {
+                continue;
+            }
+
+            if let Opcode::Call {
+                function,
+                dest,
+                arg_count,
+            } = self.code[index]
+            {
+                let returned = match self.code[index + 1..] {
+                    [Opcode::Return { reg }, ..] => reg == dest,
+                    [Opcode::CopyRegister { dest: copy, src }, Opcode::Return { reg }, ..] => {
+                        src == dest && reg == copy
+                    }
+                    _ => false,
+                };
+
+                if returned {
+                    self.code[index] = Opcode::TailCall {
+                        function,
+                        dest,
+                        arg_count,
+                    };
+                }
+            }
+        }
+    }
+
+    /// Fill in the offset of every jump. A jump too far for a 16 bit offset is given a Wide
+    /// prefix, which moves the code after it along and may put other jumps out of reach in turn,
+    /// so prefixes are added until every jump fits.
+    fn resolve_jumps(&mut self) -> Result<(), RuntimeError> {
+        let mut wide = vec![false; self.code.len()];
+
+        let starts = loop {
+            // The index each instruction will start at, including its prefix
+            let mut starts = Vec::with_capacity(self.code.len() + 1);
+            let mut next = 0;
+            for is_wide in &wide {
+                starts.push(next);
+                next += 1 + *is_wide as usize;
+            }
+            starts.push(next);
+
+            let mut widened = false;
+            for &(jump, target) in &self.jumps {
+                let offset = jump_offset(&starts, &wide, jump, target);
+                if !wide[jump]
+                    && (offset < JumpOffset::MIN as isize || offset >= JUMP_UNKNOWN as isize)
+                {
+                    wide[jump] = true;
+                    widened = true;
+                }
+            }
+
+            if !widened {
+                break starts;
+            }
+        };
+
+        let mut code = Vec::with_capacity(starts[self.code.len()]);
+        for (op, is_wide) in self.code.iter().zip(&wide) {
+            if *is_wide {
+                code.push(Opcode::Wide { high: 0 });
+            }
+            code.push(*op);
+        }
+
+        for &(jump, target) in &self.jumps {
+            let offset = jump_offset(&starts, &wide, jump, target);
+            if offset < i32::MIN as isize || offset > i32::MAX as isize {
+                return Err(err_compile("Jump is too far, the code block is too long"));
+            }
+
+            let (high, low) = split_operand(offset as i32 as u32);
+            let index = starts[jump] + wide[jump] as usize;
+            if wide[jump] {
+                code[index - 1] = Opcode::Wide { high };
+            }
+            code[index] = with_offset(code[index], low as JumpOffset);
+        }
+
+        for handler in &mut self.handlers {
+            handler.renumber(&starts);
+        }
+
+        self.code = code;
+        self.jumps.clear();
+        Ok(())
+    }
+
+    fn compile_stmt(&mut self, node: &Node) -> Result<(), RuntimeError> {
+        let mark = self.next_reg;
+
+        match node.token {
+            // Children are held in reverse source order
+            Tok::Stmts => {
+                for child in node.children.iter().rev() {
+                    self.compile_stmt(child)?;
+                }
+            }
+
+            Tok::Eq => {
+                let value = self.acquire_reg()?;
+                self.compile_expr(&node.children[0], value)?;
+
+                let name = self.symbol_name(node)?;
+                self.store_variable(value, name)?;
+            }
+
+            Tok::ForKW => self.compile_for(node)?,
+
+            Tok::FuncDecl => self.compile_function(node)?,
+
+            Tok::BreakKW => self.emit_break()?,
+
+            Tok::ContinueKW => self.emit_continue()?,
+
+            Tok::TryKW => self.compile_try(node)?,
+
+            // Children are held in reverse source order: the name and then the path
+            Tok::ImportKW => {
+                let (name, path) = match node.children.as_slice() {
+                    [name, path] => (self.symbol_name(name)?, self.module_path(path)?),
+                    _ => return Err(err_compile("Malformed import statement")),
+                };
+
+                let module = self.emit_import(path)?;
+                self.store_variable(module, name)?;
+            }
+
+            Tok::FromKW => self.compile_from_import(node)?,
+
+            Tok::ThrowKW => {
+                let value = self.acquire_reg()?;
+                self.compile_expr(&node.children[0], value)?;
+                self.push(Opcode::Throw { reg: value });
+            }
+
+            Tok::ReturnKW => self.compile_return(node)?,
+
+            // Anything else is an expression evaluated for its side effects
+            _ => {
+                let dest = self.acquire_reg()?;
+                self.compile_expr(node, dest)?;
+            }
+        }
+
+        self.release_regs(mark);
+        Ok(())
+    }
+
+    /// Compile `return expr;` or `return;`, which leaves the function with the value of `expr` or
+    /// nil. A call whose value is returned is made a TailCall by `mark_tail_calls`.
+    fn compile_return(&mut self, node: &Node) -> Result<(), RuntimeError> {
+        if self.name.is_none() {
+            return Err(err_compile("return outside of a function"));
+        }
+
+        let value = match self.finallies.last() {
+            Some(finally) => finally.value,
+            None => self.acquire_reg()?,
+        };
+        match node.children.as_slice() {
+            [expr] => self.compile_expr(expr, value)?,
+            _ => self.push(Opcode::LoadNil { dest: value }),
+        }
+
+        self.emit_return(value)
+    }
+
+    /// Compile `fn name(a, b = default, *rest) { body }` with a Generator of its own, and bind
+    /// the Function to `name`. The children are held in reverse source order: the body and then
+    /// the parameters, which are also in reverse source order.
+    fn compile_function(&mut self, node: &Node) -> Result<(), RuntimeError> {
+        let (params, body) = match node.children.split_last() {
+            Some(split) => split,
+            None => return Err(err_compile("Malformed function declaration")),
+        };
+
+        let name = self.symbol_name(node)?;
+        let mut function = Generator::init();
+        function.symbols = self.symbols.clone();
+        function.name = Some(name.clone());
+
+        let mut defaults = Vec::new();
+        for param in params.children.iter().rev() {
+            let param_name = function.symbol_name(param)?;
+            if function.params.contains(&param_name) {
+                return Err(err_compile(&format!(
+                    "Duplicate parameter {} in function {}",
+                    param_name, name
+                )));
+            }
+
+            match param.token {
+                Tok::Var if defaults.is_empty() => function.arity += 1,
+                Tok::Var => {
+                    return Err(err_compile(&format!(
+                        "Parameter {} without a default follows one with a default",
+                        param_name
+                    )))
+                }
+                Tok::Eq => defaults.push((function.params.len(), &param.children[0])),
+                Tok::Star => function.variadic = true,
+                _ => return Err(err_compile("Malformed parameter list")),
+            }
+            function.params.push(param_name);
+        }
+
+        if function.first_temp() > Register::MAX as usize {
+            return Err(err_compile("Too many parameters in function declaration"));
+        }
+        if defaults.len() > MAX_DEFAULTS as usize {
+            return Err(err_compile(&format!(
+                "A function can have at most {} parameters with defaults",
+                MAX_DEFAULTS
+            )));
+        }
+
+        // Compute each default that the caller did not supply, in order, so that a default may
+        // refer to the parameters before it
+        function.next_reg = function.first_temp();
+        for (index, default) in defaults {
+            let supplied = function.acquire_reg()?;
+            function.push(Opcode::IsSupplied {
+                dest: supplied,
+                param: index as NumArgs,
+            });
+            let skip = function.push_jump_if_true(supplied);
+
+            function.compile_expr(default, (FIRST_ARG_REG + index) as Register)?;
+            let end = function.code.len();
+            function.patch_jump(skip, end)?;
+            function.release_regs(function.first_temp());
+        }
+
+        for stmt in body.iter().rev() {
+            function.compile_stmt(stmt)?;
+        }
+        function.finish()?;
+
+        let literal_id = self.push_literal(Literal::Function(self.functions.len()))?;
+        self.functions.push(function);
+
+        let value = self.acquire_reg()?;
+        self.load_literal(value, literal_id);
+        self.store_variable(value, name)
+    }
+
+    /// Compile `from "path" import a, b;` or `from m import a, b;`, binding each named member of
+    /// the module to a global of the same name. The children are held in reverse source order:
+    /// the names and then the module.
+    fn compile_from_import(&mut self, node: &Node) -> Result<(), RuntimeError> {
+        let (names, source) = match node.children.as_slice() {
+            [names, source] => (names, source),
+            _ => return Err(err_compile("Malformed import statement")),
+        };
+
+        let module = match source.token {
+            Tok::Var => {
+                let module = self.acquire_reg()?;
+                self.compile_expr(source, module)?;
+                module
+            }
+            _ => {
+                let path = self.module_path(source)?;
+                self.emit_import(path)?
+            }
+        };
+
+        let value = self.acquire_reg()?;
+        let name_reg = self.acquire_reg()?;
+        for name in names.children.iter().rev() {
+            let name = self.symbol_name(name)?;
+            self.load_symbol(name_reg, name.clone())?;
+            self.push(Opcode::GetField {
+                dest: value,
+                object: module,
+                name: name_reg,
+            });
+            self.store_variable(value, name)?;
+        }
+
+        Ok(())
+    }
+
+    /// The path of a module as written in an import statement
+    fn module_path(&self, node: &Node) -> Result<String, RuntimeError> {
+        match (node.token, &node.val) {
+            (Tok::String, Some(NodeVal::String(path))) => Ok(path.clone()),
+            _ => Err(err_compile("A module path must be a plain string")),
+        }
+    }
+
+    /// Import the module at `path` into a fresh register and return it. The first time, the
+    /// module's code runs in a register window starting there.
+    fn emit_import(&mut self, path: String) -> Result<Register, RuntimeError> {
+        let path_reg = self.acquire_reg()?;
+        let literal_id = self.push_literal(Literal::Module(path))?;
+        self.load_literal(path_reg, literal_id);
+
+        let module = self.acquire_reg()?;
+        self.push(Opcode::Import {
+            dest: module,
+            path: path_reg,
+        });
+        Ok(module)
+    }
+
+    /// Compile `for x in expr { }` or `for k, v in dict { }`. The children are held in reverse
+    /// source order: the body, the iterable expression and then the loop variable names.
+    ///
+    /// The loop head follows the body so that the jump to the exit, which the iteration opcode
+    /// skips over, only has to pass the jump back to the body and never needs a Wide prefix.
+    ///
+    /// ```text
+    ///         GetIter     iter <- iterable
+    ///         Jump        head
+    /// body:   StoreGlobal var
+    ///         ...body...
+    /// head:   IterNext    var <- iter       (skips the next instruction if a value was produced)
+    ///         Jump        exit
+    ///         Jump        body
+    /// exit:
+    /// ```
+    fn compile_for(&mut self, node: &Node) -> Result<(), RuntimeError> {
+        let (body, iterable, names) = match node.children.as_slice() {
+            [body, iterable, name] => (body, iterable, vec![self.symbol_name(name)?]),
+            [body, iterable, value_name, key_name] => (
+                body,
+                iterable,
+                vec![self.symbol_name(key_name)?, self.symbol_name(value_name)?],
+            ),
+            _ => return Err(err_compile("Malformed for loop")),
+        };
+
+        let iter = self.acquire_reg()?;
+        self.compile_expr(iterable, iter)?;
+        self.push(Opcode::GetIter {
+            dest: iter,
+            src: iter,
+        });
+
+        // IterNextPair writes to two consecutive registers
+        let pair = names.len() == 2;
+        let first_var = self.acquire_reg()?;
+        if pair {
+            self.acquire_reg()?;
+        }
+
+        let enter = self.push_jump();
+
+        let body_start = self.code.len();
+        for (index, name) in names.into_iter().enumerate() {
+            self.store_variable(first_var + index as Register, name)?;
+        }
+
+        self.loops.push(Loop {
+            continues: Vec::new(),
+            breaks: Vec::new(),
+        });
+        let compiled_body = self.compile_stmt(body);
+        let innermost = self.loops.pop().expect("loop stack underflow");
+        compiled_body?;
+
+        let head = self.code.len();
+        self.patch_jump(enter, head)?;
+        for jump in innermost.continues {
+            self.patch_jump(jump, head)?;
+        }
+
+        match pair {
+            false => self.push(Opcode::IterNext {
+                dest: first_var,
+                iter,
+            }),
+            true => self.push(Opcode::IterNextPair {
+                dest: first_var,
+                iter,
+            }),
+        }
+        let exit = self.push_jump();
+        self.push_jump_to(body_start)?;
+
+        let end = self.code.len();
+        self.patch_jump(exit, end)?;
+        for jump in innermost.breaks {
+            self.patch_jump(jump, end)?;
+        }
+
+        Ok(())
+    }
+
+    /// Emit a jump out of the innermost loop, by way of any `finally` blocks in between
+    fn emit_break(&mut self) -> Result<(), RuntimeError> {
+        if self.loops.is_empty() {
+            return Err(err_compile("break outside of a loop"));
+        }
+
+        let jump = self.push_jump();
+        let loop_depth = self.loops.len();
+        match self.finallies.last_mut() {
+            Some(finally) if finally.loop_depth == loop_depth => finally.breaks.push(jump),
+            _ => {
+                if let Some(innermost) = self.loops.last_mut() {
+                    innermost.breaks.push(jump);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Emit a jump to the head of the innermost loop, by way of any `finally` blocks in between
+    fn emit_continue(&mut self) -> Result<(), RuntimeError> {
+        if self.loops.is_empty() {
+            return Err(err_compile("continue outside of a loop"));
+        }
+
+        let jump = self.push_jump();
+        let loop_depth = self.loops.len();
+        match self.finallies.last_mut() {
+            Some(finally) if finally.loop_depth == loop_depth => finally.continues.push(jump),
+            _ => {
+                if let Some(innermost) = self.loops.last_mut() {
+                    innermost.continues.push(jump);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Emit a return of the value in `value`, by way of any `finally` blocks in between
+    fn emit_return(&mut self, value: Register) -> Result<(), RuntimeError> {
+        let held = match self.finallies.last() {
+            Some(finally) => finally.value,
+            None => {
+                self.push(Opcode::Return { reg: value });
+                return Ok(());
+            }
+        };
+
+        if held != value {
+            self.push(Opcode::CopyRegister {
+                dest: held,
+                src: value,
+            });
+        }
+        let jump = self.push_jump();
+        if let Some(finally) = self.finallies.last_mut() {
+            finally.returns.push(jump);
+        }
+
+        Ok(())
+    }
+
+    /// Compile a statement that runs the `finally` block of `finally` on the way out of it, if
+    /// there is one
+    fn compile_protected(
+        &mut self,
+        node: &Node,
+        finally: &mut Option<Finally>,
+    ) -> Result<(), RuntimeError> {
+        let finally_block = match finally.take() {
+            Some(finally) => finally,
+            None => return self.compile_stmt(node),
+        };
+
+        self.finallies.push(finally_block);
+        let compiled = self.compile_stmt(node);
+        *finally = self.finallies.pop();
+        compiled
+    }
+
+    /// Compile `try { } catch e { } finally { }`, where either the `catch` or the `finally` block
+    /// may be left out. The children are held in reverse source order: the `finally` and `catch`
+    /// nodes, if present, and then the body.
+    ///
+    /// ```text
+    /// start:  ...body...                  (handled by catch, then by finally)
+    /// end:    ...finally...
+    ///         Jump        exit
+    /// catch:  StoreGlobal e <- exception  (handled by finally)
+    ///         ...catch body...
+    ///         ...finally...
+    ///         Jump        exit
+    /// throw:  ...finally...
+    ///         Throw       exception
+    /// break:  ...finally...
+    ///         Jump        loop exit
+    /// return: ...finally...               (the value is held in the exception register)
+    ///         Return      exception
+    /// exit:
+    /// ```
+    fn compile_try(&mut self, node: &Node) -> Result<(), RuntimeError> {
+        let (body, clauses) = match node.children.split_last() {
+            Some((body, clauses)) if !clauses.is_empty() => (body, clauses),
+            _ => return Err(err_compile("Malformed try statement")),
+        };
+
+        let mut catch = None;
+        let mut finally_body = None;
+        for clause in clauses {
+            match (clause.token, clause.children.as_slice()) {
+                (Tok::CatchKW, [catch_body, name]) => {
+                    catch = Some((catch_body, self.symbol_name(name)?))
+                }
+                (Tok::FinallyKW, [body]) => finally_body = Some(body),
+                _ => return Err(err_compile("Malformed try statement")),
+            }
+        }
+
+        // The exception is handed to the catch or finally block in this register
+        let exception = self.acquire_reg()?;
+
+        let mut finally = finally_body.map(|_| Finally {
+            loop_depth: self.loops.len(),
+            breaks: Vec::new(),
+            continues: Vec::new(),
+            returns: Vec::new(),
+            value: exception,
+        });
+        let mut protected = Vec::new();
+        let mut exits = Vec::new();
+
+        let start = self.code.len();
+        self.compile_protected(body, &mut finally)?;
+        let end = self.code.len();
+        protected.push((start, end));
+
+        if let Some(finally_body) = finally_body {
+            self.compile_stmt(finally_body)?;
+        }
+        exits.push(self.push_jump());
+
+        let mut catch_handler = None;
+        if let Some((catch_body, name)) = catch {
+            let target = self.code.len();
+            catch_handler = Some((start, end, target));
+
+            self.store_variable(exception, name)?;
+            self.compile_protected(catch_body, &mut finally)?;
+            protected.push((target, self.code.len()));
+
+            if let Some(finally_body) = finally_body {
+                self.compile_stmt(finally_body)?;
+            }
+            exits.push(self.push_jump());
+        }
+
+        let mut finally_handlers = Vec::new();
+        if let (Some(finally_body), Some(finally)) = (finally_body, finally) {
+            let target = self.code.len();
+            for (start, end) in protected {
+                finally_handlers.push((start, end, target));
+            }
+            self.compile_stmt(finally_body)?;
+            self.push(Opcode::Throw { reg: exception });
+
+            if !finally.breaks.is_empty() {
+                let trampoline = self.code.len();
+                for jump in finally.breaks {
+                    self.patch_jump(jump, trampoline)?;
+                }
+                self.compile_stmt(finally_body)?;
+                self.emit_break()?;
+            }
+
+            if !finally.continues.is_empty() {
+                let trampoline = self.code.len();
+                for jump in finally.continues {
+                    self.patch_jump(jump, trampoline)?;
+                }
+                self.compile_stmt(finally_body)?;
+                self.emit_continue()?;
+            }
+
+            if !finally.returns.is_empty() {
+                let trampoline = self.code.len();
+                for jump in finally.returns {
+                    self.patch_jump(jump, trampoline)?;
+                }
+                self.compile_stmt(finally_body)?;
+                self.emit_return(exception)?;
+            }
+        }
+
+        let exit = self.code.len();
+        for jump in exits {
+            self.patch_jump(jump, exit)?;
+        }
+
+        // Handlers of try statements nested in this one were added first, so they are searched
+        // before these. An empty block has nothing to protect.
+        for (start, end, target) in catch_handler.into_iter().chain(finally_handlers) {
+            if start == end {
+                continue;
+            }
+
+            self.handlers.push(Handler {
+                start: start as ArraySize,
+                end: end as ArraySize,
+                target: target as ArraySize,
+                dest: exception,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Compile an expression, leaving its value in the `dest` register. If the registers run out
+    /// part way through, the expression is compiled again with the registers in use spilled.
+    fn compile_expr(&mut self, node: &Node, dest: Register) -> Result<(), RuntimeError> {
+        if self.expr_depth >= MAX_EXPR_DEPTH {
+            return Err(err_compile("Expression is nested too deeply"));
+        }
+
+        let start = self.code.len();
+        let mark = self.next_reg;
+        self.expr_depth += 1;
+
+        let result = match self.compile_expr_in_window(node, dest) {
+            Err(e) if out_of_registers(&e) && mark > self.first_temp() => {
+                self.code.truncate(start);
+                self.jumps.retain(|(jump, _)| *jump < start);
+                self.next_reg = mark;
+                self.compile_spilled(node, dest)
+            }
+            result => result,
+        };
+
+        self.expr_depth -= 1;
+        result
+    }
+
+    /// Compile an expression in a fresh register window. Every temporary register in use is saved
+    /// to a local first and restored afterwards, and the value is passed back to `dest` through a
+    /// local of its own. The parameters stay where they are.
+    fn compile_spilled(&mut self, node: &Node, dest: Register) -> Result<(), RuntimeError> {
+        let mark = self.next_reg;
+        let first_temp = self.first_temp();
+        let first_local = self.next_local;
+        let result_local = first_local + (mark - first_temp);
+
+        if result_local > LocalId::MAX as usize {
+            return Err(err_compile("Too many registers spilled in one function"));
+        }
+
+        let spills = (first_temp..mark).zip(first_local..);
+        for (reg, local) in spills.clone() {
+            self.push(Opcode::StoreLocal {
+                src: reg as Register,
+                local: local as LocalId,
+            });
+        }
+
+        self.spilled = true;
+        self.next_local = result_local + 1;
+        self.next_reg = first_temp;
+
+        let value = self.acquire_reg()?;
+        // Running out again in a fresh window means spilling cannot help
+        self.compile_expr(node, value)
+            .map_err(|e| match out_of_registers(&e) {
+                true => err_compile("Expression too complex, even with registers spilled"),
+                false => e,
+            })?;
+        self.push(Opcode::StoreLocal {
+            src: value,
+            local: result_local as LocalId,
+        });
+
+        for (reg, local) in spills {
+            self.push(Opcode::LoadLocal {
+                dest: reg as Register,
+                local: local as LocalId,
+            });
+        }
+        self.push(Opcode::LoadLocal {
+            dest,
+            local: result_local as LocalId,
+        });
+
+        self.next_local = first_local;
+        self.next_reg = mark;
+        Ok(())
+    }
+
+    fn compile_expr_in_window(&mut self, node: &Node, dest: Register) -> Result<(), RuntimeError> {
+        let mark = self.next_reg;
+
+        match (node.token, &node.val) {
+            (Tok::Int, Some(NodeVal::Int(value))) => {
+                let (high, low) = split_operand(*value as u32);
+
+                if LiteralInteger::try_from(*value).is_err() {
+                    self.push(Opcode::Wide { high });
+                }
+                self.push(Opcode::LoadInteger {
+                    dest,
+                    integer: low as LiteralInteger,
+                });
+            }
+
+            (Tok::String, Some(NodeVal::String(value))) => {
+                let literal_id = self.push_literal(Literal::Text(value.clone()))?;
+                self.load_literal(dest, literal_id);
+            }
+
+            (Tok::Var, Some(NodeVal::Sym(_))) => {
+                let name = self.symbol_name(node)?;
+                match self.param_reg(&name) {
+                    Some(src) => self.push(Opcode::CopyRegister { dest, src }),
+                    None => {
+                        self.load_symbol(dest, name)?;
+                        self.push(Opcode::LoadGlobal { dest, name: dest });
+                    }
+                }
+            }
+
+            (Tok::Plus, _) | (Tok::Minus, _) | (Tok::Modulo, _) => {
+                let right = self.acquire_reg()?;
+                self.compile_expr(&node.children[0], dest)?;
+                self.compile_expr(&node.children[1], right)?;
+
+                match node.token {
+                    Tok::Plus => self.push(Opcode::Add {
+                        dest,
+                        reg1: dest,
+                        reg2: right,
+                    }),
+                    Tok::Minus => self.push(Opcode::Subtract {
+                        dest,
+                        left: dest,
+                        right,
+                    }),
+                    _ => self.push(Opcode::Modulo {
+                        dest,
+                        num: dest,
+                        denom: right,
+                    }),
+                }
+            }
+
+            (Tok::FuncCall, Some(NodeVal::Sym(_))) => {
+                // Arguments are held in reverse source order
+                let name = self.symbol_name(node)?;
+                let args: Vec<&Node> = node.children.iter().rev().collect();
+                self.compile_call(Callee::Global(name), &args, dest)?;
+            }
+
+            // A call to a module member holds the callee after the arguments
+            (Tok::FuncCall, None) => {
+                let (callee, args) = match node.children.split_last() {
+                    Some(split) => split,
+                    None => return Err(err_compile("Malformed function call")),
+                };
+                let args: Vec<&Node> = args.iter().rev().collect();
+                self.compile_call(Callee::Expr(callee), &args, dest)?;
+            }
+
+            (Tok::Field, Some(NodeVal::Sym(_))) => {
+                let name = self.acquire_reg()?;
+                self.compile_expr(&node.children[0], dest)?;
+
+                let member = self.symbol_name(node)?;
+                self.load_symbol(name, member)?;
+                self.push(Opcode::GetField {
+                    dest,
+                    object: dest,
+                    name,
+                });
+            }
+
+            // Each segment is converted with str() unless it is already a string, then the
+            // segments are concatenated left to right
+            (Tok::Template, _) => {
+                let next = self.acquire_reg()?;
+
+                for (index, segment) in node.children.iter().rev().enumerate() {
+                    let target = match index {
+                        0 => dest,
+                        _ => next,
+                    };
+
+                    match segment.token {
+                        Tok::String => self.compile_expr(segment, target)?,
+                        _ => {
+                            let str_fn = Callee::Global(String::from("str"));
+                            self.compile_call(str_fn, &[segment], target)?
+                        }
+                    }
+
+                    if index > 0 {
+                        self.push(Opcode::Add {
+                            dest,
+                            reg1: dest,
+                            reg2: next,
+                        });
+                    }
+                }
+            }
+
+            (token, _) => {
+                return Err(err_compile(&format!(
+                    "Cannot compile {:?} as an expression",
+                    token
+                )))
+            }
+        }
+
+        self.release_regs(mark);
+        Ok(())
+    }
+
+    /// Compile a call to a function. The callee's register window starts at a fresh register above
+    /// every live temporary, with the arguments from its FIRST_ARG_REG onwards.
+    fn compile_call(
+        &mut self,
+        callee: Callee,
+        args: &[&Node],
+        dest: Register,
+    ) -> Result<(), RuntimeError> {
+        let mark = self.next_reg;
+        let arg_count = args.len();
+        if arg_count > u8::MAX as usize {
+            return Err(err_compile("Too many arguments in function call"));
+        }
+
+        let base = self.acquire_reg()?;
+        for _ in 1..FIRST_ARG_REG {
+            self.acquire_reg()?;
+        }
+
+        // Keyword arguments follow the positional arguments, and their values are passed in the
+        // same order as their names. A placeholder leaves its argument nil.
+        let mut keywords = Vec::new();
+        let mut placeholders = Vec::new();
+        for (position, arg) in args.iter().enumerate() {
+            let arg_reg = self.acquire_reg()?;
+
+            if arg.token == Tok::KeywordArg {
+                keywords.push(Literal::Symbol(self.symbol_name(arg)?));
+                self.compile_expr(&arg.children[0], arg_reg)?;
+            } else if !keywords.is_empty() {
+                return Err(err_compile("Positional argument follows keyword arguments"));
+            } else if arg.token == Tok::Placeholder {
+                placeholders.push(Literal::Number(position as isize));
+                self.push(Opcode::LoadNil { dest: arg_reg });
+            } else {
+                self.compile_expr(arg, arg_reg)?;
+            }
+        }
+
+        if !keywords.is_empty() && !placeholders.is_empty() {
+            return Err(err_compile(
+                "Keyword arguments cannot be combined with placeholders",
+            ));
+        }
+
+        // A call with keyword arguments takes the function from its result register
+        let function = match keywords.is_empty() {
+            true => self.acquire_reg()?,
+            false => base,
+        };
+        match callee {
+            Callee::Global(name) => match self.param_reg(&name) {
+                Some(src) => self.push(Opcode::CopyRegister {
+                    dest: function,
+                    src,
+                }),
+                None => {
+                    self.load_symbol(function, name)?;
+                    self.push(Opcode::LoadGlobal {
+                        dest: function,
+                        name: function,
+                    });
+                }
+            },
+            Callee::Expr(node) => self.compile_expr(node, function)?,
+        }
+
+        if !placeholders.is_empty() {
+            // The closure environment register carries the placeholder positions
+            let literal_id = self.push_literal(Literal::List(placeholders))?;
+            self.load_literal(base + ENV_REG as Register, literal_id);
+
+            self.push(Opcode::MakePartial {
+                function,
+                dest: base,
+                arg_count: arg_count as u8,
+            });
+        } else if !keywords.is_empty() {
+            let names = self.acquire_reg()?;
+            let literal_id = self.push_literal(Literal::List(keywords))?;
+            self.load_literal(names, literal_id);
+
+            self.push(Opcode::CallKeywords {
+                dest: base,
+                arg_count: arg_count as u8,
+                names,
+            });
+        } else {
+            self.push(Opcode::Call {
+                function,
+                dest: base,
+                arg_count: arg_count as u8,
+            });
+        }
+
+        if base != dest {
+            self.push(Opcode::CopyRegister { dest, src: base });
+        }
+
+        self.release_regs(mark);
+        Ok(())
+    }
+}
+
+/// Return the jump instruction `op` with its offset replaced by `offset`
+fn with_offset(op: Opcode, offset: JumpOffset) -> Opcode {
+    match op {
+        Opcode::JumpIfTrue { test, .. } => Opcode::JumpIfTrue { test, offset },
+        Opcode::JumpIfNotTrue { test, .. } => Opcode::JumpIfNotTrue { test, offset },
+        _ => Opcode::Jump { offset },
+    }
+}
+
+fn out_of_registers(error: &RuntimeError) -> bool {
+    *error.error_kind() == ErrorKind::CompileError(String::from(OUT_OF_REGISTERS))
+}
+
+/// Calculate the offset for the jump at index `jump` to reach index `target`, given the index
+/// each instruction will start at once prefixes are added. The VM applies the offset after
+/// fetching the jump, so it is relative to the following instruction.
+fn jump_offset(starts: &[usize], wide: &[bool], jump: usize, target: usize) -> isize {
+    let next = starts[jump] + wide[jump] as usize + 1;
+    starts[target] as isize - next as isize
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ast::Ast;
+    use crate::error::ErrorKind;
+    use crate::lexer::Lexer;
+    use crate::memory::{Memory, Mutator};
+    use crate::parser::Parser;
+    use crate::vm::{StackLimits, Thread};
+
+    struct Run<'a>(&'a Generator);
+
+    impl<'a> Mutator for Run<'a> {
+        type Input = &'static str;
+        type Output = String;
+
+        fn run(&self, mem: &MutatorView, result: &'static str) -> Result<String, RuntimeError> {
+            let thread = Thread::alloc(mem)?;
+            thread.quick_vm_eval(mem, self.0.function(mem)?)?;
+
+            match thread.global(mem, result) {
+                Some(value) => Ok(format!("{}", value)),
+                None => panic!("{} is not bound", result),
+            }
+        }
+    }
+
+    #[test]
+    fn for_loop_sums_a_range() {
+        let generator =
+            compile_source("total = 0\nfor i in range(10) { total = total + i }\n").unwrap();
+
+        let mem = Memory::new();
+        assert!(mem.mutate(&Run(&generator), "total").unwrap() == "45");
+    }
+
+    #[test]
+    fn modulo_takes_the_sign_of_the_denominator() {
+        // The operands are variables so that the parser leaves the modulo to run time
+        let generator = compile_source(
+            "n = 0 - 7\n\
+             remainder = n % 3\n\
+             import \"math\" as math;\n\
+             fraction = math.pi % 1\n",
+        )
+        .unwrap();
+
+        let mem = Memory::new();
+        assert!(mem.mutate(&Run(&generator), "remainder").unwrap() == "2");
+        assert!(mem.mutate(&Run(&generator), "fraction").unwrap() == "0.14159265358979312");
+    }
+
+    #[test]
+    fn break_and_continue_jump_out_of_and_back_to_the_loop_head() {
+        let generator = compile_source(
+            "before = 0\n\
+             after = 0\n\
+             for i in range(5) {\n\
+                 before = before + 1\n\
+                 continue\n\
+                 after = after + 1\n\
+             }\n\
+             for j in range(5) {\n\
+                 before = before + 1\n\
+                 break\n\
+             }\n",
+        )
+        .unwrap();
+
+        let mem = Memory::new();
+        assert!(mem.mutate(&Run(&generator), "before").unwrap() == "6");
+        assert!(mem.mutate(&Run(&generator), "after").unwrap() == "0");
+    }
+
+    #[test]
+    fn optimized_loops_give_the_same_results() {
+        let mut generator = compile_source(
+            "total = 2 + 4\n\
+             for i in range(4) {\n\
+                 total = i + total\n\
+                 continue\n\
+             }\n",
+        )
+        .unwrap();
+        let unoptimized = generator.code().len();
+        generator.optimize();
+        assert!(generator.code().len() < unoptimized);
+
+        let mem = Memory::new();
+        assert!(mem.mutate(&Run(&generator), "total").unwrap() == "12");
+    }
+
+    #[test]
+    fn deep_expressions_spill_registers() {
+        // text = str(str(str(...))), where each call needs a frame of registers
+        let nested_str = format!("{}7{}", "str(".repeat(60), ")".repeat(60));
+
+        // total = 1 + (1 + (... + len(str(str(...))))), which needs more registers than a
+        // window holds while staying within the nesting limit
+        let (open, close) = ("1 + (".repeat(90), ")".repeat(90));
+        let sum = format!("{}len({}){}", open, nested_str, close);
+
+        let source = format!("total = {}\ntext = {}\n", sum, nested_str);
+        let generator = compile_source(&source).unwrap();
+
+        let stores = |code: &[Opcode]| {
+            code.iter()
+                .filter(|op| matches!(op, Opcode::StoreLocal { .. }))
+                .count()
+        };
+        assert!(stores(generator.code()) > 0);
+
+        let mem = Memory::new();
+        assert!(mem.mutate(&Run(&generator), "total").unwrap() == "91");
+        assert!(mem.mutate(&Run(&generator), "text").unwrap() == "\"7\"");
+    }
+
+    #[test]
+    fn expressions_nested_too_deeply_are_compile_errors() {
+        // total = x + (x + (x + ...)), far deeper than the nesting limit, with a variable so
+        // that the parser cannot fold it away
+        let depth = MAX_EXPR_DEPTH * 3;
+        let sum = format!("{}x{}", "x + (".repeat(depth), ")".repeat(depth));
+
+        match compile_source(&format!("x = 1\ntotal = {}\n", sum)) {
+            Err(e) => assert!(matches!(e.error_kind(), ErrorKind::CompileError(_))),
+            Ok(_) => panic!("a deeply nested expression compiled"),
+        }
+    }
+
+    #[test]
+    fn very_large_functions_use_wide_operands() {
+        // for i in range(2) { total = total + 1; s = "0"; s = "1"; ... s = "69999" }
+        let mut source = String::from("total = 0\nbig = 100000\nfor i in range(2) {\n");
+        source.push_str("total = total + 1\n");
+        for n in 0..70000 {
+            source.push_str(&format!("s = \"{}\"\n", n));
+        }
+        source.push_str("}\n");
+
+        let mut generator = compile_source(&source).unwrap();
+        assert!(generator.literals().len() > u16::MAX as usize);
+
+        let code = generator.code();
+        let extended = |is_op: fn(Opcode) -> bool| {
+            code.windows(2)
+                .any(|pair| matches!(pair[0], Opcode::Wide { .. }) && is_op(pair[1]))
+        };
+        assert!(extended(|op| matches!(op, Opcode::LoadLiteral { .. })));
+        assert!(extended(|op| matches!(op, Opcode::LoadInteger { .. })));
+        assert!(extended(|op| matches!(op, Opcode::Jump { .. })));
+
+        let mem = Memory::new();
+        assert!(mem.mutate(&Run(&generator), "total").unwrap() == "2");
+        assert!(mem.mutate(&Run(&generator), "s").unwrap() == "\"69999\"");
+        assert!(mem.mutate(&Run(&generator), "big").unwrap() == "100000");
+
+        generator.optimize();
+        assert!(mem.mutate(&Run(&generator), "s").unwrap() == "\"69999\"");
+    }
+
+    #[test]
+    fn template_concatenates_converted_segments() {
+        let generator = compile_source("n = 5\nmessage = \"n = ${n}, ${\"ok\"}!\"\n").unwrap();
+
+        let mem = Memory::new();
+        assert!(mem.mutate(&Run(&generator), "message").unwrap() == "\"n = 5, ok!\"");
+    }
+
+    #[test]
+    fn catch_receives_thrown_values_and_runtime_errors() {
+        let generator = compile_source(
+            "reached = 0\n\
+             try { throw 7; } catch e { thrown = e }\n\
+             try { char_at(\"\", 5); reached = 1 } catch e { caught = message(e) }\n",
+        )
+        .unwrap();
+
+        let mem = Memory::new();
+        assert!(mem.mutate(&Run(&generator), "thrown").unwrap() == "7");
+        assert!(mem.mutate(&Run(&generator), "reached").unwrap() == "0");
+        assert!(mem.mutate(&Run(&generator), "caught").unwrap() != "nil");
+    }
+
+    #[test]
+    fn finally_runs_on_every_way_out_of_a_try() {
+        let mut generator = compile_source(
+            "log = 0\n\
+             try { log = log + 1 } finally { log = log + 10 }\n\
+             try {\n\
+                 try { throw 1; } finally { log = log + 100 }\n\
+             } catch e { log = e + log }\n\
+             for i in range(5) {\n\
+                 try {\n\
+                     log = log + 1000\n\
+                     break\n\
+                 } finally { log = log + 10000 }\n\
+             }\n\
+             for i in range(3) {\n\
+                 try { continue } catch e { } finally { log = log + 100000 }\n\
+             }\n",
+        )
+        .unwrap();
+
+        let mem = Memory::new();
+        assert!(mem.mutate(&Run(&generator), "log").unwrap() == "311112");
+
+        generator.optimize();
+        assert!(mem.mutate(&Run(&generator), "log").unwrap() == "311112");
+    }
+
+    #[test]
+    fn finally_runs_when_a_function_returns() {
+        let mut generator = compile_source(
+            "log = \"\"\n\
+             fn early(x) {\n\
+                 try {\n\
+                     for i in range(3) {\n\
+                         try { return \"${x}!\"; } finally { log = \"${log}inner,\" }\n\
+                     }\n\
+                 } finally { log = \"${log}outer\" }\n\
+             }\n\
+             fn caught() {\n\
+                 try { throw 7; } catch e { return e; } finally { seen = \"finally\" }\n\
+             }\n\
+             result = early(\"done\")\n\
+             thrown = caught()\n",
+        )
+        .unwrap();
+
+        let mem = Memory::new();
+        assert!(mem.mutate(&Run(&generator), "result").unwrap() == "\"done!\"");
+        assert!(mem.mutate(&Run(&generator), "log").unwrap() == "\"inner,outer\"");
+        assert!(mem.mutate(&Run(&generator), "thrown").unwrap() == "7");
+        assert!(mem.mutate(&Run(&generator), "seen").unwrap() == "\"finally\"");
+
+        generator.optimize();
+        assert!(mem.mutate(&Run(&generator), "log").unwrap() == "\"inner,outer\"");
+    }
+
+    fn compile_source(source: &str) -> Result<Generator, RuntimeError> {
+        let mut lexer = Lexer::init();
+        lexer.open_str(source);
+
+        let mut parser = Parser::init();
+        let mut ast = Ast::init();
+        let mut generator = Generator::init();
+        parser.build_ast(&mut lexer, &mut ast)?;
+        ast.traverse(&mut generator)?;
+        Ok(generator)
+    }
+
+    #[test]
+    fn default_and_rest_parameters_from_source() {
+        let generator = compile_source(
+            "fn describe(a, b = a, *rest) {\n\
+                 seen = \"${a} ${b} ${len(rest)}\"\n\
+             }\n\
+             describe(1);\n\
+             first = seen\n\
+             describe(1, 2, 3, 4);\n\
+             second = seen\n\
+             describe(b: \"x\", a: 5);\n\
+             third = seen\n\
+             try { describe(c: 1); } catch e { error = message(e) }\n",
+        )
+        .unwrap();
+
+        let mem = Memory::new();
+        assert!(mem.mutate(&Run(&generator), "first").unwrap() == "\"1 1 0\"");
+        assert!(mem.mutate(&Run(&generator), "second").unwrap() == "\"1 2 2\"");
+        assert!(mem.mutate(&Run(&generator), "third").unwrap() == "\"5 x 0\"");
+
+        let error = mem.mutate(&Run(&generator), "error").unwrap();
+        assert!(error.contains("unexpected keyword argument c"));
+
+        match compile_source("fn f(a = 1, b) { }") {
+            Err(e) => assert!(
+                *e.error_kind()
+                    == ErrorKind::CompileError(String::from(
+                        "Parameter b without a default follows one with a default"
+                    ))
+            ),
+            Ok(_) => panic!("a required parameter after a default compiled"),
+        }
+    }
+
+    #[test]
+    fn keyword_arguments_are_passed_by_name() {
+        let generator =
+            compile_source("try { str(x: 1); } catch e { caught = message(e) }\n").unwrap();
+        assert!(generator
+            .code()
+            .iter()
+            .any(|op| matches!(op, Opcode::CallKeywords { arg_count: 1, .. })));
+
+        let mem = Memory::new();
+        let caught = mem.mutate(&Run(&generator), "caught").unwrap();
+        assert!(caught.contains("does not take keyword arguments"));
+
+        match compile_source("str(x: 1, 2);") {
+            Err(e) => assert!(
+                *e.error_kind()
+                    == ErrorKind::CompileError(String::from(
+                        "Positional argument follows keyword arguments"
+                    ))
+            ),
+            Ok(_) => panic!("a positional argument after a keyword argument compiled"),
+        }
+    }
+
+    #[test]
+    fn placeholders_make_partial_applications() {
+        let generator = compile_source(
+            "first = char_at(_, 0)\n\
+             c = first(\"xyz\")\n\
+             upto3 = slice(_, _, 3)\n\
+             from1 = upto3(_, 1)\n\
+             s = from1(\"abcdef\")\n\
+             shown = str(upto3)\n\
+             prefix = bind(from1, \"hello\")\n\
+             p = prefix()\n",
+        )
+        .unwrap();
+
+        let mem = Memory::new();
+        assert!(mem.mutate(&Run(&generator), "c").unwrap() == "\"x\"");
+        assert!(mem.mutate(&Run(&generator), "s").unwrap() == "\"bc\"");
+        assert!(mem.mutate(&Run(&generator), "shown").unwrap() == "\"(Partial slice (_ _ 3))\"");
+        assert!(mem.mutate(&Run(&generator), "p").unwrap() == "\"el\"");
+    }
+
+    #[test]
+    fn break_outside_loop_is_a_compile_error() {
+        match compile_source("break") {
+            Err(e) => assert!(
+                *e.error_kind() == ErrorKind::CompileError(String::from("break outside of a loop"))
+            ),
+            Ok(_) => panic!("break outside of a loop compiled"),
+        }
+    }
+
+    #[test]
+    fn returned_calls_from_source_run_in_constant_space() {
+        // walk() recurses once for each character and stops when char_at() finds none left
+        let generator = compile_source(&format!(
+            "fn walk(s) {{\n\
+                 left = s\n\
+                 char_at(s, 0);\n\
+                 return walk(slice(s, 1, len(s)));\n\
+             }}\n\
+             fn twice(x) {{ return \"${{x}}${{x}}\"; }}\n\
+             fn nothing() {{ return; }}\n\
+             doubled = twice(\"ab\")\n\
+             none = nothing()\n\
+             try {{ walk(\"{}\"); }} catch e {{ error = message(e) }}\n",
+            "x".repeat(1000)
+        ))
+        .unwrap();
+
+        let walk = &generator.functions[0];
+        assert!(walk
+            .code()
+            .iter()
+            .any(|op| matches!(op, Opcode::TailCall { .. })));
+
+        struct Shallow<'a>(&'a Generator);
+
+        impl<'a> Mutator for Shallow<'a> {
+            type Input = &'static str;
+            type Output = String;
+
+            fn run(&self, mem: &MutatorView, result: &'static str) -> Result<String, RuntimeError> {
+                // Far too few frames for the recursion unless each call reuses its caller's
+                let thread = Thread::alloc(mem)?;
+                thread.set_limits(StackLimits {
+                    max_call_depth: 100,
+                    ..StackLimits::default()
+                });
+                thread.quick_vm_eval(mem, self.0.function(mem)?)?;
+
+                match thread.global(mem, result) {
+                    Some(value) => Ok(format!("{}", value)),
+                    None => panic!("{} is not bound", result),
+                }
+            }
+        }
+
+        let mem = Memory::new();
+        assert!(mem.mutate(&Shallow(&generator), "left").unwrap() == "\"\"");
+        assert!(mem.mutate(&Shallow(&generator), "doubled").unwrap() == "\"abab\"");
+        assert!(mem.mutate(&Shallow(&generator), "none").unwrap() == "nil");
 
+        match compile_source("return 1;") {
+            Err(e) => assert!(
+                *e.error_kind()
+                    == ErrorKind::CompileError(String::from("return outside of a function"))
+            ),
+            Ok(_) => panic!("return outside of a function compiled"),
         }
     }
 }
@@ -1,5 +1,7 @@
 /// Defines an `ObjectHeader` type to immediately preceed each heap allocated
 /// object, which also contains a type tag but with space for many more types.
+use std::ptr::NonNull;
+
 use crate::allocator::{
     AllocHeader, AllocObject, AllocRaw, AllocTypeId, ArraySize, Mark, SizeClass,
 };
@@ -8,16 +10,23 @@ use crate::raw_ptr::RawPtr;
 use crate::array::{ArrayU16, ArrayU32, ArrayU8};
 use crate::bytecode::{ArrayOpcode, ByteCode, InstructionStream};
 use crate::dict::Dict;
+use crate::exception::Exception;
 use crate::function::{Function, Partial};
+use crate::iter::{Iter, Range};
 use crate::list::List;
 use crate::memory::HeapStorage;
-use crate::number::NumberObject;
+use crate::module::Module;
+use crate::native::NativeFunction;
+use crate::number::{Float, NumberObject};
 use crate::pair::Pair;
 use crate::ptr_ops::{AsNonNull, Tagged};
+use crate::safe_ptr::MutatorScope;
 use crate::symbol::Symbol;
 use crate::tagged_ptr::FatPtr;
 use crate::text::Text;
+use crate::trace::{Trace, Visitor};
 use crate::vm::{CallFrameList, Thread, Upvalue};
+use crate::weak::WeakRef;
 
 /// Recognized heap-allocated types.
 /// This should represent every type native to the runtime with the exception of tagged pointer inline value
@@ -34,16 +43,23 @@ pub enum TypeList {
     ByteCode,
     CallFrameList,
     Dict,
+    Exception,
+    Float,
     Function,
     InstructionStream,
+    Iter,
     List,
+    Module,
+    NativeFunction,
     NumberObject,
     Pair,
     Partial,
+    Range,
     Symbol,
     Text,
     Thread,
     Upvalue,
+    WeakRef,
 }
 
 // Mark this as a Stickyimmix type-identifier type
@@ -75,22 +91,94 @@ impl ObjectHeader {
             TypeList::ArrayU16 => FatPtr::ArrayU16(RawPtr::untag(object_addr.cast::<ArrayU16>())),
             TypeList::ArrayU32 => FatPtr::ArrayU32(RawPtr::untag(object_addr.cast::<ArrayU32>())),
             TypeList::Dict => FatPtr::Dict(RawPtr::untag(object_addr.cast::<Dict>())),
+            TypeList::Exception => {
+                FatPtr::Exception(RawPtr::untag(object_addr.cast::<Exception>()))
+            }
+            TypeList::Float => FatPtr::Float(RawPtr::untag(object_addr.cast::<Float>())),
             TypeList::Function => FatPtr::Function(RawPtr::untag(object_addr.cast::<Function>())),
+            TypeList::Iter => FatPtr::Iter(RawPtr::untag(object_addr.cast::<Iter>())),
             TypeList::List => FatPtr::List(RawPtr::untag(object_addr.cast::<List>())),
+            TypeList::Module => FatPtr::Module(RawPtr::untag(object_addr.cast::<Module>())),
+            TypeList::NativeFunction => {
+                FatPtr::NativeFunction(RawPtr::untag(object_addr.cast::<NativeFunction>()))
+            }
             TypeList::NumberObject => {
                 FatPtr::NumberObject(RawPtr::untag(object_addr.cast::<NumberObject>()))
             }
             TypeList::Pair => FatPtr::Pair(RawPtr::untag(object_addr.cast::<Pair>())),
             TypeList::Partial => FatPtr::Partial(RawPtr::untag(object_addr.cast::<Partial>())),
+            TypeList::Range => FatPtr::Range(RawPtr::untag(object_addr.cast::<Range>())),
             TypeList::Symbol => FatPtr::Symbol(RawPtr::untag(object_addr.cast::<Symbol>())),
             TypeList::Text => FatPtr::Text(RawPtr::untag(object_addr.cast::<Text>())),
             TypeList::Upvalue => FatPtr::Upvalue(RawPtr::untag(object_addr.cast::<Upvalue>())),
+            TypeList::WeakRef => FatPtr::WeakRef(RawPtr::untag(object_addr.cast::<WeakRef>())),
 
             // Other types not represented by FatPtr are an error to id here
             _ => panic!("Invalid ObjectHeader type tag {:?}!", self.type_id),
         }
     }
     // ANCHOR_END: DefObjectHeaderGetObjectFatPtr
+
+    /// Clear the mark, making the object young again. Only a full collection should do this.
+    pub fn unmark(&mut self) {
+        self.mark = Mark::Unmarked;
+    }
+
+    /// Report each heap object referenced by the object following this header.
+    // NOTE Any type that holds pointers to heap objects must be added to the below list
+    pub unsafe fn trace_object<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+        visit: &mut Visitor,
+    ) {
+        let object_addr = HeapStorage::get_object(self.non_null_ptr());
+
+        match self.type_id {
+            TypeList::ArrayBackingBytes => (),
+            TypeList::ArrayOpcode => object_addr.cast::<ArrayOpcode>().as_ref().trace(guard, visit),
+            TypeList::ArrayU8 => object_addr.cast::<ArrayU8>().as_ref().trace(guard, visit),
+            TypeList::ArrayU16 => object_addr.cast::<ArrayU16>().as_ref().trace(guard, visit),
+            TypeList::ArrayU32 => object_addr.cast::<ArrayU32>().as_ref().trace(guard, visit),
+            TypeList::ByteCode => object_addr.cast::<ByteCode>().as_ref().trace(guard, visit),
+            TypeList::CallFrameList => {
+                object_addr.cast::<CallFrameList>().as_ref().trace(guard, visit)
+            }
+            TypeList::Dict => object_addr.cast::<Dict>().as_ref().trace(guard, visit),
+            TypeList::Exception => object_addr.cast::<Exception>().as_ref().trace(guard, visit),
+            TypeList::Float => (),
+            TypeList::Function => object_addr.cast::<Function>().as_ref().trace(guard, visit),
+            TypeList::InstructionStream => {
+                object_addr.cast::<InstructionStream>().as_ref().trace(guard, visit)
+            }
+            TypeList::Iter => object_addr.cast::<Iter>().as_ref().trace(guard, visit),
+            TypeList::List => object_addr.cast::<List>().as_ref().trace(guard, visit),
+            TypeList::Module => object_addr.cast::<Module>().as_ref().trace(guard, visit),
+            TypeList::NativeFunction => {
+                object_addr.cast::<NativeFunction>().as_ref().trace(guard, visit)
+            }
+            TypeList::NumberObject => {
+                object_addr.cast::<NumberObject>().as_ref().trace(guard, visit)
+            }
+            TypeList::Pair => object_addr.cast::<Pair>().as_ref().trace(guard, visit),
+            TypeList::Partial => object_addr.cast::<Partial>().as_ref().trace(guard, visit),
+            TypeList::Range => object_addr.cast::<Range>().as_ref().trace(guard, visit),
+            TypeList::Symbol => (),
+            TypeList::Text => object_addr.cast::<Text>().as_ref().trace(guard, visit),
+            TypeList::Thread => object_addr.cast::<Thread>().as_ref().trace(guard, visit),
+            TypeList::Upvalue => object_addr.cast::<Upvalue>().as_ref().trace(guard, visit),
+            TypeList::WeakRef => (),
+        }
+    }
+}
+
+/// Return the header of the heap object at the given untagged address
+pub unsafe fn header_of<'a>(object: NonNull<()>) -> &'a ObjectHeader {
+    &*HeapStorage::get_header(object).as_ptr()
+}
+
+/// Return the header of the heap object at the given untagged address for modification
+pub unsafe fn header_of_mut<'a>(object: NonNull<()>) -> &'a mut ObjectHeader {
+    &mut *HeapStorage::get_header(object).as_ptr()
 }
 
 impl AsNonNull for ObjectHeader {}
@@ -157,13 +245,20 @@ declare_allocobject!(ArrayU32, ArrayU32);
 declare_allocobject!(ByteCode, ByteCode);
 declare_allocobject!(CallFrameList, CallFrameList);
 declare_allocobject!(Dict, Dict);
+declare_allocobject!(Exception, Exception);
+declare_allocobject!(Float, Float);
 declare_allocobject!(Function, Function);
 declare_allocobject!(InstructionStream, InstructionStream);
+declare_allocobject!(Iter, Iter);
 declare_allocobject!(List, List);
+declare_allocobject!(Module, Module);
+declare_allocobject!(NativeFunction, NativeFunction);
 declare_allocobject!(NumberObject, NumberObject);
 declare_allocobject!(Pair, Pair);
 declare_allocobject!(Partial, Partial);
+declare_allocobject!(Range, Range);
 declare_allocobject!(Symbol, Symbol);
 declare_allocobject!(Text, Text);
 declare_allocobject!(Thread, Thread);
 declare_allocobject!(Upvalue, Upvalue);
+declare_allocobject!(WeakRef, WeakRef);
@@ -0,0 +1,377 @@
+/// Heap snapshots for finding out why memory is being retained
+///
+/// A `HeapSnapshot` walks every object reachable from a set of roots, decoding each one through
+/// its `ObjectHeader`, and records the object's type, size and outgoing references. The snapshot
+/// can be written out as JSON or as a Graphviz DOT graph, and can report how many bytes each
+/// root keeps alive on its own.
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io;
+use std::ptr::NonNull;
+
+use crate::allocator::AllocHeader;
+use crate::array::ArraySize;
+use crate::header::{header_of, TypeList};
+use crate::safe_ptr::MutatorScope;
+use crate::tagged_ptr::TaggedPtr;
+use crate::vm::Thread;
+
+/// Where a root pointer lives
+#[derive(Clone, Debug, PartialEq)]
+pub enum RootKind {
+    /// A global binding, by name
+    Global(String),
+    /// An absolute index into the register stack
+    StackSlot(ArraySize),
+    /// An open Upvalue, by the absolute stack index it refers to
+    Upvalue(ArraySize),
+    /// An imported module, by the canonical path of its source file
+    Module(String),
+}
+
+impl fmt::Display for RootKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RootKind::Global(name) => write!(f, "global {}", name),
+            RootKind::StackSlot(index) => write!(f, "stack[{}]", index),
+            RootKind::Upvalue(location) => write!(f, "upvalue[{}]", location),
+            RootKind::Module(path) => write!(f, "module {}", path),
+        }
+    }
+}
+
+/// A pointer that keeps heap objects alive
+#[derive(Clone)]
+pub struct Root {
+    pub kind: RootKind,
+    pub ptr: TaggedPtr,
+}
+
+/// A single live object in a snapshot
+pub struct SnapshotObject {
+    pub type_id: TypeList,
+    pub size: u32,
+    /// Addresses of the objects this object points at
+    pub refs: Vec<usize>,
+}
+
+/// A root and the set of objects reachable from it
+struct SnapshotRoot {
+    kind: RootKind,
+    /// Address of the object the root points at, if it is a heap object at all
+    object: Option<usize>,
+    reachable: HashSet<usize>,
+}
+
+/// A point-in-time record of the live heap object graph
+pub struct HeapSnapshot {
+    objects: HashMap<usize, SnapshotObject>,
+    roots: Vec<SnapshotRoot>,
+}
+
+impl HeapSnapshot {
+    /// Walk the heap from the given roots, recording every reachable object
+    pub fn from_roots<'guard>(guard: &'guard dyn MutatorScope, roots: &[Root]) -> HeapSnapshot {
+        let mut objects = HashMap::new();
+        let mut snapshot_roots = Vec::new();
+
+        for root in roots {
+            let object = root.ptr.as_object_ptr();
+            let mut reachable = HashSet::new();
+
+            if let Some(start) = object {
+                let mut pending = vec![start];
+
+                while let Some(next) = pending.pop() {
+                    let addr = next.as_ptr() as usize;
+                    if !reachable.insert(addr) {
+                        continue;
+                    }
+
+                    if !objects.contains_key(&addr) {
+                        objects.insert(addr, snapshot_object(guard, next));
+                    }
+
+                    for child in &objects[&addr].refs {
+                        if !reachable.contains(child) {
+                            // addresses were taken from NonNull pointers
+                            pending.push(unsafe { NonNull::new_unchecked(*child as *mut ()) });
+                        }
+                    }
+                }
+            }
+
+            snapshot_roots.push(SnapshotRoot {
+                kind: root.kind.clone(),
+                object: object.map(|ptr| ptr.as_ptr() as usize),
+                reachable,
+            });
+        }
+
+        HeapSnapshot {
+            objects,
+            roots: snapshot_roots,
+        }
+    }
+
+    /// Walk the heap from the roots of the given Thread
+    pub fn from_thread<'guard>(guard: &'guard dyn MutatorScope, thread: &Thread) -> HeapSnapshot {
+        HeapSnapshot::from_roots(guard, &thread.roots(guard))
+    }
+
+    /// Return the number of live objects recorded
+    pub fn object_count(&self) -> usize {
+        self.objects.len()
+    }
+
+    /// Return the total size in bytes of all live objects recorded
+    pub fn total_size(&self) -> usize {
+        self.objects.values().map(|object| object.size as usize).sum()
+    }
+
+    /// Return the object recorded at the given address
+    pub fn object(&self, addr: usize) -> Option<&SnapshotObject> {
+        self.objects.get(&addr)
+    }
+
+    /// Return, for each root, the number of bytes that only that root keeps alive - the size
+    /// of everything that would become garbage if that root alone were cleared.
+    pub fn retained_sizes(&self) -> Vec<(RootKind, usize)> {
+        // count how many roots reach each object
+        let mut reached_by: HashMap<usize, usize> = HashMap::new();
+        for root in &self.roots {
+            for addr in &root.reachable {
+                *reached_by.entry(*addr).or_insert(0) += 1;
+            }
+        }
+
+        self.roots
+            .iter()
+            .map(|root| {
+                let retained = root
+                    .reachable
+                    .iter()
+                    .filter(|addr| reached_by[addr] == 1)
+                    .map(|addr| self.objects[addr].size as usize)
+                    .sum();
+
+                (root.kind.clone(), retained)
+            })
+            .collect()
+    }
+
+    /// Write the snapshot as a JSON document with `objects` and `roots` members
+    pub fn write_json<W: io::Write>(&self, out: &mut W) -> io::Result<()> {
+        let retained = self.retained_sizes();
+
+        writeln!(out, "{{")?;
+        writeln!(out, "  \"objects\": [")?;
+
+        let mut addrs: Vec<&usize> = self.objects.keys().collect();
+        addrs.sort();
+
+        for (index, addr) in addrs.iter().enumerate() {
+            let object = &self.objects[addr];
+            let refs: Vec<String> = object.refs.iter().map(|r| format!("\"{:#x}\"", r)).collect();
+
+            write!(
+                out,
+                "    {{\"id\": \"{:#x}\", \"type\": \"{:?}\", \"size\": {}, \"refs\": [{}]}}",
+                addr,
+                object.type_id,
+                object.size,
+                refs.join(", ")
+            )?;
+            writeln!(out, "{}", if index + 1 < addrs.len() { "," } else { "" })?;
+        }
+
+        writeln!(out, "  ],")?;
+        writeln!(out, "  \"roots\": [")?;
+
+        for (index, (root, (_, retained_size))) in self.roots.iter().zip(retained).enumerate() {
+            let object = match root.object {
+                Some(addr) => format!("\"{:#x}\"", addr),
+                None => String::from("null"),
+            };
+
+            write!(
+                out,
+                "    {{\"root\": \"{}\", \"object\": {}, \"retained_size\": {}}}",
+                json_escape(&format!("{}", root.kind)),
+                object,
+                retained_size
+            )?;
+            writeln!(out, "{}", if index + 1 < self.roots.len() { "," } else { "" })?;
+        }
+
+        writeln!(out, "  ]")?;
+        writeln!(out, "}}")
+    }
+
+    /// Write the snapshot as a Graphviz DOT digraph
+    pub fn write_dot<W: io::Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(out, "digraph heap {{")?;
+
+        for (index, root) in self.roots.iter().enumerate() {
+            writeln!(
+                out,
+                "  root{} [shape=box, label=\"{}\"];",
+                index,
+                json_escape(&format!("{}", root.kind))
+            )?;
+            if let Some(addr) = root.object {
+                writeln!(out, "  root{} -> \"{:#x}\";", index, addr)?;
+            }
+        }
+
+        // sorted by id so that dumps of the same heap can be diffed
+        let mut addrs: Vec<&usize> = self.objects.keys().collect();
+        addrs.sort();
+
+        for addr in addrs {
+            let object = &self.objects[addr];
+            writeln!(
+                out,
+                "  \"{:#x}\" [label=\"{:?}\\n{} bytes\"];",
+                addr, object.type_id, object.size
+            )?;
+            for child in &object.refs {
+                writeln!(out, "  \"{:#x}\" -> \"{:#x}\";", addr, child)?;
+            }
+        }
+
+        writeln!(out, "}}")
+    }
+}
+
+/// Decode a heap object through its header and record its type, size and references
+fn snapshot_object<'guard>(guard: &'guard dyn MutatorScope, object: NonNull<()>) -> SnapshotObject {
+    let header = unsafe { header_of(object) };
+
+    let mut refs = Vec::new();
+    unsafe {
+        header.trace_object(guard, &mut |child: NonNull<()>| refs.push(child.as_ptr() as usize))
+    };
+
+    SnapshotObject {
+        type_id: header.type_id(),
+        size: header.size(),
+        refs,
+    }
+}
+
+/// Escape a string for inclusion in a JSON or DOT string literal. Control characters have no
+/// literal form in JSON so they are written as escapes too.
+fn json_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+
+    for c in s.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+#[cfg(test)]
+mod test {
+    use super::{json_escape, HeapSnapshot, Root, RootKind};
+    use crate::container::StackAnyContainer;
+    use crate::error::RuntimeError;
+    use crate::list::List;
+    use crate::memory::{Memory, Mutator, MutatorView};
+    use crate::text::Text;
+
+    #[test]
+    fn heapdump_retained_sizes() {
+        let mem = Memory::new();
+
+        struct Test {}
+        impl Mutator for Test {
+            type Input = ();
+            type Output = ();
+
+            fn run(
+                &self,
+                mem: &MutatorView,
+                _input: Self::Input,
+            ) -> Result<Self::Output, RuntimeError> {
+                let shared = mem.alloc_tagged(Text::new_from_str(mem, "shared")?)?;
+
+                let first = List::alloc(mem)?;
+                StackAnyContainer::push(&*first, mem, shared)?;
+                let own = mem.alloc_tagged(Text::new_from_str(mem, "only in first")?)?;
+                StackAnyContainer::push(&*first, mem, own)?;
+
+                let second = List::alloc(mem)?;
+                StackAnyContainer::push(&*second, mem, shared)?;
+
+                let roots = [
+                    Root {
+                        kind: RootKind::Global(String::from("first")),
+                        ptr: first.as_tagged(mem).get_ptr(),
+                    },
+                    Root {
+                        kind: RootKind::StackSlot(2),
+                        ptr: second.as_tagged(mem).get_ptr(),
+                    },
+                    Root {
+                        kind: RootKind::StackSlot(3),
+                        ptr: mem.nil().get_ptr(),
+                    },
+                ];
+
+                let snapshot = HeapSnapshot::from_roots(mem, &roots);
+                let retained = snapshot.retained_sizes();
+
+                // the shared Text is retained by neither root alone
+                assert!(retained[0].1 > retained[1].1);
+                assert!(retained[1].1 > 0);
+                assert!(retained[2].1 == 0);
+                assert!(retained[0].1 + retained[1].1 < snapshot.total_size());
+
+                let mut json = Vec::new();
+                snapshot.write_json(&mut json).unwrap();
+                let json = String::from_utf8(json).unwrap();
+                assert!(json.contains("\"type\": \"List\""));
+                assert!(json.contains("\"root\": \"global first\""));
+
+                // objects are listed in id order, however they were reached
+                let mut dot = Vec::new();
+                snapshot.write_dot(&mut dot).unwrap();
+                let dot = String::from_utf8(dot).unwrap();
+                let ids: Vec<&str> = dot
+                    .lines()
+                    .filter(|line| line.contains("bytes"))
+                    .map(|line| line.trim())
+                    .collect();
+                let mut sorted = ids.clone();
+                sorted.sort_by_key(|line| {
+                    let id = line.trim_start_matches("\"0x").split('"').next().unwrap();
+                    usize::from_str_radix(id, 16).unwrap()
+                });
+                assert!(ids.len() >= 4);
+                assert!(ids == sorted);
+
+                Ok(())
+            }
+        }
+
+        let test = Test {};
+        mem.mutate(&test, ()).unwrap();
+    }
+
+    #[test]
+    fn json_escape_control_characters() {
+        assert!(json_escape("a \"b\" \\ c") == r#"a \"b\" \\ c"#);
+        assert!(json_escape("one\ntwo\r\tthree") == r#"one\ntwo\r\tthree"#);
+        assert!(json_escape("\u{0}\u{1b}") == r#"\u0000\u001b"#);
+    }
+}
@@ -0,0 +1,275 @@
+/// The `io` native module: reading and writing files, the environment and the script's arguments
+///
+/// Paths are taken relative to the current directory of the process, not of the script. A file
+/// that cannot be read or written is an IO error, which a script may catch.
+use std::env;
+use std::fs;
+use std::fs::OpenOptions;
+use std::io;
+use std::io::Write;
+
+use crate::builtins::{define, integer_arg, new_text, text_arg};
+use crate::container::{HashIndexedAnyContainer, StackAnyContainer};
+use crate::dict::Dict;
+use crate::error::{err_eval, err_io, ErrorKind, RuntimeError};
+use crate::list::List;
+use crate::memory::MutatorView;
+use crate::safe_ptr::{TaggedCellPtr, TaggedScopedPtr};
+use crate::vm::Thread;
+
+/// Bind every member of the io module in the module's globals dict. `args` is the list of
+/// command line arguments given to `thread`.
+pub fn install<'guard>(
+    mem: &'guard MutatorView,
+    globals: &Dict,
+    thread: &Thread,
+) -> Result<(), RuntimeError> {
+    define(mem, globals, "read_file", 1, read_file)?;
+    define(mem, globals, "write_file", 2, write_file)?;
+    define(mem, globals, "append_file", 2, append_file)?;
+    define(mem, globals, "lines", 1, lines)?;
+
+    define(mem, globals, "env", 1, env_var)?;
+    define(mem, globals, "exit", 1, exit)?;
+
+    let args = thread.args(mem).as_tagged(mem);
+    globals.assoc(mem, mem.lookup_sym("args"), args)?;
+
+    Ok(())
+}
+
+/// Build an IO error naming the path the operation failed on
+pub fn path_error(path: &str, error: io::Error) -> RuntimeError {
+    err_io(&format!("{}: {}", path, error))
+}
+
+/// Read the whole of the file at `path` as a string
+fn read_to_string(path: &str) -> Result<String, RuntimeError> {
+    fs::read_to_string(path).map_err(|error| path_error(path, error))
+}
+
+/// read_file(path): the contents of the file as a string
+fn read_file<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let path = text_arg(mem, "read_file", &args[0])?;
+    new_text(mem, &read_to_string(path.as_str(mem))?)
+}
+
+/// write_file(path, string): replace the contents of the file, creating it if need be
+fn write_file<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let path = text_arg(mem, "write_file", &args[0])?;
+    let path = path.as_str(mem);
+    let content = text_arg(mem, "write_file", &args[1])?;
+
+    fs::write(path, content.as_str(mem)).map_err(|error| path_error(path, error))?;
+    Ok(mem.nil())
+}
+
+/// append_file(path, string): add to the end of the file, creating it if need be
+fn append_file<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let path = text_arg(mem, "append_file", &args[0])?;
+    let path = path.as_str(mem);
+    let content = text_arg(mem, "append_file", &args[1])?;
+
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(content.as_str(mem).as_bytes()))
+        .map_err(|error| path_error(path, error))?;
+    Ok(mem.nil())
+}
+
+/// lines(path): a list of the lines of the file, without their line endings
+fn lines<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let path = text_arg(mem, "lines", &args[0])?;
+    let content = read_to_string(path.as_str(mem))?;
+
+    let lines = List::alloc(mem)?;
+    for line in content.lines() {
+        StackAnyContainer::push(&*lines, mem, new_text(mem, line)?)?;
+    }
+
+    Ok(lines.as_tagged(mem))
+}
+
+/// env(name): the value of the environment variable, or nil if it is not set
+fn env_var<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let name = text_arg(mem, "env", &args[0])?;
+
+    match env::var(name.as_str(mem)) {
+        Ok(value) => new_text(mem, &value),
+        Err(env::VarError::NotPresent) => Ok(mem.nil()),
+        Err(env::VarError::NotUnicode(_)) => Err(err_io(&format!(
+            "{} is not valid unicode",
+            name.as_str(mem)
+        ))),
+    }
+}
+
+/// exit(status): end the script, and the process, with the given status
+fn exit<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let status = integer_arg(mem, "exit", &args[0])?;
+
+    match i32::try_from(status) {
+        Ok(status) => Err(RuntimeError::new(ErrorKind::Exit(status))),
+        Err(_) => Err(err_eval("exit() status is out of range")),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::container::{Container, IndexedAnyContainer};
+    use crate::memory::{Memory, Mutator};
+    use crate::tagged_ptr::{TaggedPtr, Value};
+    use std::process;
+
+    /// Call the io function `name` with the given arguments
+    fn call_with<'guard>(
+        mem: &'guard MutatorView,
+        globals: &Dict,
+        name: &str,
+        args: &[TaggedScopedPtr<'guard>],
+    ) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+        let native = match *globals.lookup(mem, mem.lookup_sym(name))? {
+            Value::NativeFunction(native) => native,
+            _ => panic!("{} is not a native function", name),
+        };
+
+        let args: Vec<TaggedCellPtr> = args
+            .iter()
+            .map(|arg| TaggedCellPtr::new_with(*arg))
+            .collect();
+        native.call(mem, &args)
+    }
+
+    /// Call the io function `name` with string arguments and return its result as printed
+    fn call<'guard>(
+        mem: &'guard MutatorView,
+        globals: &Dict,
+        name: &str,
+        args: &[&str],
+    ) -> Result<String, RuntimeError> {
+        let mut texts = Vec::new();
+        for arg in args {
+            texts.push(new_text(mem, arg)?);
+        }
+        Ok(format!("{}", call_with(mem, globals, name, &texts)?))
+    }
+
+    #[test]
+    fn files_are_written_read_and_split_into_lines() {
+        let mem = Memory::new();
+
+        struct Test {}
+        impl Mutator for Test {
+            type Input = ();
+            type Output = ();
+
+            fn run(
+                &self,
+                mem: &MutatorView,
+                _input: Self::Input,
+            ) -> Result<Self::Output, RuntimeError> {
+                let globals = Dict::alloc(mem)?;
+                install(mem, &globals, &*Thread::alloc(mem)?)?;
+
+                let dir = env::temp_dir().join(format!("chorus-io-{}", process::id()));
+                fs::create_dir_all(&dir)?;
+                let file = dir.join("notes.txt").display().to_string();
+                let file = file.as_str();
+
+                call(mem, &globals, "write_file", &[file, "one\n"])?;
+                call(mem, &globals, "append_file", &[file, "two\n"])?;
+                assert!(call(mem, &globals, "read_file", &[file])? == r#""one\ntwo\n""#);
+
+                let path = new_text(mem, file)?;
+                match *call_with(mem, &globals, "lines", &[path])? {
+                    Value::List(lines) => {
+                        assert!(lines.length() == 2);
+                        assert!(format!("{}", lines.get(mem, 1)?) == r#""two""#);
+                    }
+                    _ => panic!("lines() did not return a list"),
+                }
+
+                let missing = dir.join("missing.txt").display().to_string();
+                let missing = missing.as_str();
+                match call(mem, &globals, "read_file", &[missing]) {
+                    Err(error) => match error.error_kind() {
+                        ErrorKind::IOError(reason) => assert!(reason.starts_with(missing)),
+                        _ => panic!("Reading a missing file is not an IO error"),
+                    },
+                    Ok(_) => panic!("A missing file was read"),
+                }
+
+                Ok(())
+            }
+        }
+
+        let test = Test {};
+        mem.mutate(&test, ()).unwrap();
+    }
+
+    #[test]
+    fn args_env_and_exit() {
+        let mem = Memory::new();
+
+        struct Test {}
+        impl Mutator for Test {
+            type Input = ();
+            type Output = ();
+
+            fn run(
+                &self,
+                mem: &MutatorView,
+                _input: Self::Input,
+            ) -> Result<Self::Output, RuntimeError> {
+                let thread = Thread::alloc(mem)?;
+                thread.set_args(mem, &[String::from("-v"), String::from("input.txt")])?;
+
+                let globals = Dict::alloc(mem)?;
+                install(mem, &globals, &thread)?;
+
+                match *globals.lookup(mem, mem.lookup_sym("args"))? {
+                    Value::List(args) => {
+                        assert!(args.length() == 2);
+                        assert!(format!("{}", args.get(mem, 1)?) == r#""input.txt""#);
+                    }
+                    _ => panic!("args is not a list"),
+                }
+
+                let unset = "CHORUS_IO_TEST_UNSET";
+                assert!(call(mem, &globals, "env", &[unset])? == "nil");
+
+                let status = TaggedScopedPtr::new(mem, TaggedPtr::number(3));
+                match call_with(mem, &globals, "exit", &[status]) {
+                    Err(error) => assert!(*error.error_kind() == ErrorKind::Exit(3)),
+                    Ok(_) => panic!("exit() returned"),
+                }
+
+                Ok(())
+            }
+        }
+
+        let test = Test {};
+        mem.mutate(&test, ()).unwrap();
+    }
+}
@@ -0,0 +1,267 @@
+/// Iteration over runtime containers
+///
+/// A `for` loop compiles to a `GetIter` instruction, which wraps the iterable value in an `Iter`
+/// object, followed by an `IterNext` instruction at the head of the loop that advances it. Lists
+/// yield their items, Dicts their keys (or keys and values), Text its characters and a Range
+/// the integers it describes, without ever materializing them in a list.
+use std::cell::Cell;
+use std::fmt;
+
+use crate::array::ArraySize;
+use crate::container::{Container, IndexedAnyContainer};
+use crate::error::{err_eval, RuntimeError};
+use crate::memory::MutatorView;
+use crate::printer::Print;
+use crate::safe_ptr::{MutatorScope, ScopedPtr, TaggedCellPtr, TaggedScopedPtr};
+use crate::tagged_ptr::{TaggedPtr, Value};
+use crate::text::Text;
+use crate::trace::{visit_tagged_cell, Trace, Visitor};
+
+/// Largest integer that can be represented inline in a TaggedPtr
+pub const MAX_INLINE_INTEGER: isize = isize::MAX >> 2;
+/// Smallest integer that can be represented inline in a TaggedPtr
+pub const MIN_INLINE_INTEGER: isize = isize::MIN >> 2;
+
+/// A lazy sequence of integers from `start` up to, but not including, `stop`
+// ANCHOR: DefRange
+pub struct Range {
+    start: isize,
+    stop: isize,
+    step: isize,
+}
+// ANCHOR_END: DefRange
+
+impl Range {
+    /// Allocate a new Range. The step may be negative but not zero.
+    pub fn alloc<'guard>(
+        mem: &'guard MutatorView,
+        start: isize,
+        stop: isize,
+        step: isize,
+    ) -> Result<ScopedPtr<'guard, Range>, RuntimeError> {
+        if step == 0 {
+            return Err(err_eval("range() step must not be zero"));
+        }
+
+        mem.alloc(Range { start, stop, step })
+    }
+
+    /// Return the value at the given step count, or None if it is past the end of the range
+    pub fn nth(&self, n: ArraySize) -> Option<isize> {
+        let value = (n as isize)
+            .checked_mul(self.step)
+            .and_then(|offset| self.start.checked_add(offset))?;
+
+        let in_range = match self.step > 0 {
+            true => value < self.stop,
+            false => value > self.stop,
+        };
+
+        match in_range && value >= MIN_INLINE_INTEGER && value <= MAX_INLINE_INTEGER {
+            true => Some(value),
+            false => None,
+        }
+    }
+}
+
+impl Print for Range {
+    fn print<'guard>(
+        &self,
+        _guard: &'guard dyn MutatorScope,
+        f: &mut fmt::Formatter,
+    ) -> fmt::Result {
+        write!(f, "range({}, {}, {})", self.start, self.stop, self.step)
+    }
+}
+
+impl Trace for Range {
+    fn trace<'guard>(&self, _guard: &'guard dyn MutatorScope, _visit: &mut Visitor) {}
+}
+
+/// The state of an iteration over a container
+// ANCHOR: DefIter
+pub struct Iter {
+    /// The List, Dict, Text or Range being iterated over
+    source: TaggedCellPtr,
+    /// Index, entry position, byte offset or step count, depending on the source type
+    position: Cell<ArraySize>,
+}
+// ANCHOR_END: DefIter
+
+impl Iter {
+    /// Allocate an iterator over the given value, or return an error if it is not iterable
+    pub fn alloc<'guard>(
+        mem: &'guard MutatorView,
+        source: TaggedScopedPtr<'guard>,
+    ) -> Result<ScopedPtr<'guard, Iter>, RuntimeError> {
+        match *source {
+            Value::List(_) | Value::Dict(_) | Value::Text(_) | Value::Range(_) => mem.alloc(Iter {
+                source: TaggedCellPtr::new_with(source),
+                position: Cell::new(0),
+            }),
+            _ => Err(err_eval(&format!("Type is not iterable: {}", source))),
+        }
+    }
+
+    /// Advance the iterator, returning the next value, or None when the iterator is exhausted.
+    /// Dicts yield their keys.
+    pub fn next<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+    ) -> Result<Option<TaggedScopedPtr<'guard>>, RuntimeError> {
+        let position = self.position.get();
+
+        match *self.source.get(mem) {
+            Value::List(list) => {
+                if position >= list.length() {
+                    return Ok(None);
+                }
+                self.position.set(position + 1);
+                Ok(Some(IndexedAnyContainer::get(&*list, mem, position)?))
+            }
+
+            Value::Dict(dict) => match dict.next_item(mem, position) {
+                Some((next, key, _)) => {
+                    self.position.set(next);
+                    Ok(Some(key))
+                }
+                None => Ok(None),
+            },
+
+            Value::Text(text) => {
+                let string = text.as_str(mem);
+                match string[position as usize..].chars().next() {
+                    Some(c) => {
+                        self.position.set(position + c.len_utf8() as ArraySize);
+                        let mut buf = [0; 4];
+                        let c = Text::new_from_str(mem, c.encode_utf8(&mut buf))?;
+                        Ok(Some(mem.alloc_tagged(c)?))
+                    }
+                    None => Ok(None),
+                }
+            }
+
+            Value::Range(range) => match range.nth(position) {
+                Some(value) => {
+                    self.position.set(position + 1);
+                    Ok(Some(TaggedScopedPtr::new(mem, TaggedPtr::number(value))))
+                }
+                None => Ok(None),
+            },
+
+            _ => Err(err_eval("Iterator source is not iterable")),
+        }
+    }
+
+    /// Advance a Dict iterator, returning the next key and value, or None when the iterator is
+    /// exhausted. Other iterators cannot be unpacked into two values.
+    pub fn next_pair<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+    ) -> Result<Option<(TaggedScopedPtr<'guard>, TaggedScopedPtr<'guard>)>, RuntimeError> {
+        match *self.source.get(mem) {
+            Value::Dict(dict) => match dict.next_item(mem, self.position.get()) {
+                Some((next, key, value)) => {
+                    self.position.set(next);
+                    Ok(Some((key, value)))
+                }
+                None => Ok(None),
+            },
+
+            _ => Err(err_eval("Only a dict can be iterated over as key, value pairs")),
+        }
+    }
+}
+
+impl Print for Iter {
+    fn print<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+        f: &mut fmt::Formatter,
+    ) -> fmt::Result {
+        write!(f, "(Iter {})", self.source.get(guard))
+    }
+}
+
+impl Trace for Iter {
+    fn trace<'guard>(&self, _guard: &'guard dyn MutatorScope, visit: &mut Visitor) {
+        visit_tagged_cell(&self.source, visit);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Iter, Range};
+    use crate::container::{HashIndexedAnyContainer, StackAnyContainer};
+    use crate::dict::Dict;
+    use crate::error::RuntimeError;
+    use crate::list::List;
+    use crate::memory::{Memory, Mutator, MutatorView};
+    use crate::safe_ptr::TaggedScopedPtr;
+    use crate::tagged_ptr::TaggedPtr;
+    use crate::text::Text;
+
+    #[test]
+    fn iter_over_containers() {
+        let mem = Memory::new();
+
+        struct Test {}
+        impl Mutator for Test {
+            type Input = ();
+            type Output = ();
+
+            fn run(
+                &self,
+                mem: &MutatorView,
+                _input: Self::Input,
+            ) -> Result<Self::Output, RuntimeError> {
+                let collect = |iter: &Iter| -> Result<Vec<String>, RuntimeError> {
+                    let mut items = Vec::new();
+                    while let Some(item) = iter.next(mem)? {
+                        items.push(format!("{}", item));
+                    }
+                    Ok(items)
+                };
+
+                let list = List::alloc(mem)?;
+                for name in &["a", "b", "c"] {
+                    StackAnyContainer::push(&*list, mem, mem.lookup_sym(name))?;
+                }
+                let iter = Iter::alloc(mem, list.as_tagged(mem))?;
+                assert!(collect(&iter)? == vec!["a", "b", "c"]);
+
+                let text = mem.alloc_tagged(Text::new_from_str(mem, "hé!")?)?;
+                let iter = Iter::alloc(mem, text)?;
+                assert!(collect(&iter)? == vec!["\"h\"", "\"é\"", "\"!\""]);
+
+                let range = Range::alloc(mem, 10, 0, -3)?;
+                let iter = Iter::alloc(mem, range.as_tagged(mem))?;
+                assert!(collect(&iter)? == vec!["10", "7", "4", "1"]);
+
+                let dict = Dict::alloc(mem)?;
+                for (index, name) in ["x", "y"].iter().enumerate() {
+                    let value = TaggedScopedPtr::new(mem, TaggedPtr::number(index as isize));
+                    dict.assoc(mem, mem.lookup_sym(name), value)?;
+                }
+                let iter = Iter::alloc(mem, dict.as_tagged(mem))?;
+                assert!(collect(&iter)? == vec!["x", "y"]);
+
+                let iter = Iter::alloc(mem, dict.as_tagged(mem))?;
+                let (key, value) = iter.next_pair(mem)?.unwrap();
+                assert!(key == mem.lookup_sym("x"));
+                assert!(format!("{}", value) == "0");
+
+                // only dicts unpack into pairs, and not everything is iterable
+                let iter = Iter::alloc(mem, list.as_tagged(mem))?;
+                assert!(iter.next_pair(mem).is_err());
+                assert!(Iter::alloc(mem, mem.lookup_sym("a")).is_err());
+                assert!(Range::alloc(mem, 0, 1, 0).is_err());
+
+                Ok(())
+            }
+        }
+
+        let test = Test {};
+        mem.mutate(&test, ()).unwrap();
+    }
+}
@@ -0,0 +1,692 @@
+/// The `json` native module: converting between JSON text and Chorus values
+///
+/// Objects parse to Dicts keyed by strings, in the order their keys appear in the document,
+/// arrays to Lists, numbers to integers where they fit and Floats otherwise, and `true` to the
+/// symbol `true`. Chorus has no false value, so `false` and `null` both parse to nil, and nil is
+/// written back as `null`. Dicts are written with their keys in insertion order.
+use std::iter::Peekable;
+use std::str::Chars;
+
+use crate::builtins::{define, define_variadic, integer_arg, new_text, text_arg, truth};
+use crate::container::{
+    Container, HashIndexedAnyContainer, IndexedAnyContainer, StackAnyContainer,
+};
+use crate::dict::Dict;
+use crate::error::{err_eval, RuntimeError};
+use crate::iter::{MAX_INLINE_INTEGER, MIN_INLINE_INTEGER};
+use crate::list::List;
+use crate::memory::MutatorView;
+use crate::number::Float;
+use crate::safe_ptr::{MutatorScope, TaggedCellPtr, TaggedScopedPtr};
+use crate::tagged_ptr::{TaggedPtr, Value};
+use crate::vm::Thread;
+
+/// The deepest nesting of arrays and objects that is read or written
+const MAX_DEPTH: usize = 512;
+
+/// Bind every member of the json module in the module's globals dict
+pub fn install<'guard>(
+    mem: &'guard MutatorView,
+    globals: &Dict,
+    _thread: &Thread,
+) -> Result<(), RuntimeError> {
+    define(mem, globals, "parse", 1, parse)?;
+    define_variadic(mem, globals, "stringify", 1, 2, stringify)?;
+
+    Ok(())
+}
+
+/// Reads a JSON document, keeping track of the line and column of the next character so that
+/// errors can point at it
+struct Reader<'a> {
+    chars: Peekable<Chars<'a>>,
+    line: u32,
+    column: u32,
+}
+
+impl<'a> Reader<'a> {
+    fn new(source: &'a str) -> Reader<'a> {
+        Reader {
+            chars: source.chars().peekable(),
+            line: 1,
+            column: 1,
+        }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    /// Build an error at the position of the next character
+    fn error(&self, reason: &str) -> RuntimeError {
+        err_eval(&format!(
+            "Invalid JSON at line {}, column {}: {}",
+            self.line, self.column, reason
+        ))
+    }
+
+    /// Build an error for finding the next character, or the end of the input, where `wanted`
+    /// was expected
+    fn unexpected(&mut self, wanted: &str) -> RuntimeError {
+        let found = match self.peek() {
+            Some(c) => format!("{:?}", c),
+            None => String::from("the end of the input"),
+        };
+        self.error(&format!("expected {}, found {}", wanted, found))
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(' ' | '\t' | '\n' | '\r') = self.peek() {
+            self.next();
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), RuntimeError> {
+        match self.peek() {
+            Some(c) if c == expected => {
+                self.next();
+                Ok(())
+            }
+            _ => Err(self.unexpected(&format!("{:?}", expected))),
+        }
+    }
+
+    /// Read the value starting at the next non-whitespace character, inside `depth` enclosing
+    /// arrays and objects
+    fn value<'guard>(
+        &mut self,
+        mem: &'guard MutatorView,
+        depth: usize,
+    ) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+        self.skip_whitespace();
+
+        match self.peek() {
+            Some('{') => self.object(mem, depth),
+            Some('[') => self.array(mem, depth),
+            Some('"') => new_text(mem, &self.string()?),
+            Some('-' | '0'..='9') => self.number(mem),
+            Some('t') => self.literal("true").map(|_| truth(mem, true)),
+            Some('f') => self.literal("false").map(|_| mem.nil()),
+            Some('n') => self.literal("null").map(|_| mem.nil()),
+            _ => Err(self.unexpected("a value")),
+        }
+    }
+
+    fn object<'guard>(
+        &mut self,
+        mem: &'guard MutatorView,
+        depth: usize,
+    ) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+        if depth == MAX_DEPTH {
+            return Err(self.error("arrays and objects are nested too deeply"));
+        }
+        self.next();
+
+        let dict = Dict::alloc(mem)?;
+
+        self.skip_whitespace();
+        if self.peek() == Some('}') {
+            self.next();
+            return Ok(dict.as_tagged(mem));
+        }
+
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some('"') {
+                return Err(self.unexpected("a string key"));
+            }
+            let key = new_text(mem, &self.string()?)?;
+
+            self.skip_whitespace();
+            self.expect(':')?;
+            let value = self.value(mem, depth + 1)?;
+            dict.assoc(mem, key, value)?;
+
+            self.skip_whitespace();
+            match self.peek() {
+                Some(',') => {
+                    self.next();
+                }
+                Some('}') => {
+                    self.next();
+                    return Ok(dict.as_tagged(mem));
+                }
+                _ => return Err(self.unexpected("',' or '}'")),
+            }
+        }
+    }
+
+    fn array<'guard>(
+        &mut self,
+        mem: &'guard MutatorView,
+        depth: usize,
+    ) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+        if depth == MAX_DEPTH {
+            return Err(self.error("arrays and objects are nested too deeply"));
+        }
+        self.next();
+
+        let list = List::alloc(mem)?;
+
+        self.skip_whitespace();
+        if self.peek() == Some(']') {
+            self.next();
+            return Ok(list.as_tagged(mem));
+        }
+
+        loop {
+            let value = self.value(mem, depth + 1)?;
+            StackAnyContainer::push(&*list, mem, value)?;
+
+            self.skip_whitespace();
+            match self.peek() {
+                Some(',') => {
+                    self.next();
+                }
+                Some(']') => {
+                    self.next();
+                    return Ok(list.as_tagged(mem));
+                }
+                _ => return Err(self.unexpected("',' or ']'")),
+            }
+        }
+    }
+
+    /// Read the keyword `word`, whose first character has been seen
+    fn literal(&mut self, word: &str) -> Result<(), RuntimeError> {
+        for c in word.chars() {
+            if self.peek() != Some(c) {
+                return Err(self.unexpected(&format!("'{}'", word)));
+            }
+            self.next();
+        }
+        Ok(())
+    }
+
+    /// Read a string from its opening quote to its closing one, resolving escapes
+    fn string(&mut self) -> Result<String, RuntimeError> {
+        self.next();
+
+        let mut content = String::new();
+        loop {
+            match self.peek() {
+                Some('"') => {
+                    self.next();
+                    return Ok(content);
+                }
+                Some('\\') => {
+                    self.next();
+                    content.push(self.escape()?);
+                }
+                Some(c) if c < ' ' => return Err(self.error("control character in string")),
+                Some(c) => {
+                    self.next();
+                    content.push(c);
+                }
+                None => return Err(self.error("unterminated string")),
+            }
+        }
+    }
+
+    /// Read the character after a backslash, and the hex digits that follow a `u`
+    fn escape(&mut self) -> Result<char, RuntimeError> {
+        let c = match self.peek() {
+            Some('"') => '"',
+            Some('\\') => '\\',
+            Some('/') => '/',
+            Some('b') => '\u{8}',
+            Some('f') => '\u{c}',
+            Some('n') => '\n',
+            Some('r') => '\r',
+            Some('t') => '\t',
+            Some('u') => {
+                self.next();
+                return self.unicode_escape();
+            }
+            _ => return Err(self.unexpected("an escape character")),
+        };
+
+        self.next();
+        Ok(c)
+    }
+
+    /// Read the code point of a `\u` escape, which is split across a second escape if it lies
+    /// outside the Basic Multilingual Plane
+    fn unicode_escape(&mut self) -> Result<char, RuntimeError> {
+        let high = self.hex_digits()?;
+
+        let code = if (0xd800..0xdc00).contains(&high) {
+            if self.next() != Some('\\') || self.next() != Some('u') {
+                return Err(self.error("unpaired surrogate in \\u escape"));
+            }
+
+            let low = self.hex_digits()?;
+            if !(0xdc00..0xe000).contains(&low) {
+                return Err(self.error("unpaired surrogate in \\u escape"));
+            }
+            0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00)
+        } else {
+            high
+        };
+
+        char::from_u32(code).ok_or_else(|| self.error("unpaired surrogate in \\u escape"))
+    }
+
+    /// Read the four hex digits of a `\u` escape
+    fn hex_digits(&mut self) -> Result<u32, RuntimeError> {
+        let mut code = 0;
+        for _ in 0..4 {
+            match self.peek().and_then(|c| c.to_digit(16)) {
+                Some(digit) => {
+                    self.next();
+                    code = code * 16 + digit;
+                }
+                None => return Err(self.unexpected("a hex digit")),
+            }
+        }
+        Ok(code)
+    }
+
+    /// Read a number, as an inline integer if it has no fraction or exponent and fits in one,
+    /// otherwise as a Float
+    fn number<'guard>(
+        &mut self,
+        mem: &'guard MutatorView,
+    ) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+        let mut text = String::new();
+        let mut integral = true;
+
+        if self.peek() == Some('-') {
+            self.push_next(&mut text);
+        }
+
+        // a leading zero is a number on its own
+        if self.peek() == Some('0') {
+            self.push_next(&mut text);
+        } else {
+            self.digits(&mut text)?;
+        }
+
+        if self.peek() == Some('.') {
+            integral = false;
+            self.push_next(&mut text);
+            self.digits(&mut text)?;
+        }
+
+        if let Some('e' | 'E') = self.peek() {
+            integral = false;
+            self.push_next(&mut text);
+            if let Some('+' | '-') = self.peek() {
+                self.push_next(&mut text);
+            }
+            self.digits(&mut text)?;
+        }
+
+        if integral {
+            if let Ok(n) = text.parse::<isize>() {
+                if (MIN_INLINE_INTEGER..=MAX_INLINE_INTEGER).contains(&n) {
+                    return Ok(TaggedScopedPtr::new(mem, TaggedPtr::number(n)));
+                }
+            }
+        }
+
+        match text.parse::<f64>() {
+            Ok(f) if f.is_finite() => Float::alloc_tagged(mem, f),
+            _ => Err(self.error(&format!("{} is out of range", text))),
+        }
+    }
+
+    fn push_next(&mut self, text: &mut String) {
+        if let Some(c) = self.next() {
+            text.push(c);
+        }
+    }
+
+    /// Read one or more decimal digits
+    fn digits(&mut self, text: &mut String) -> Result<(), RuntimeError> {
+        if !matches!(self.peek(), Some('0'..='9')) {
+            return Err(self.unexpected("a digit"));
+        }
+
+        while let Some('0'..='9') = self.peek() {
+            self.push_next(text);
+        }
+        Ok(())
+    }
+}
+
+/// parse(string): the value described by the JSON document
+fn parse<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let text = text_arg(mem, "parse", &args[0])?;
+
+    let mut reader = Reader::new(text.as_str(mem));
+    let value = reader.value(mem, 0)?;
+
+    reader.skip_whitespace();
+    match reader.peek() {
+        None => Ok(value),
+        Some(_) => Err(reader.unexpected("the end of the input")),
+    }
+}
+
+/// Writes values as JSON text, refusing any List or Dict that contains itself
+struct Writer<'guard> {
+    output: String,
+    /// The number of spaces to indent each level by, or 0 to write everything on one line
+    indent: usize,
+    /// The Lists and Dicts being written, outermost first
+    ancestors: Vec<TaggedScopedPtr<'guard>>,
+}
+
+impl<'guard> Writer<'guard> {
+    fn value(
+        &mut self,
+        guard: &'guard dyn MutatorScope,
+        value: TaggedScopedPtr<'guard>,
+    ) -> Result<(), RuntimeError> {
+        match *value {
+            Value::Nil => self.output.push_str("null"),
+            Value::Symbol(s) if s.as_str(guard) == "true" => self.output.push_str("true"),
+            Value::Symbol(s) => self.string(s.as_str(guard)),
+            Value::Number(n) => self.output.push_str(&n.to_string()),
+            Value::Float(f) if f.value().is_finite() => {
+                self.output.push_str(&format!("{:?}", f.value()))
+            }
+            Value::Text(t) => self.string(t.as_str(guard)),
+
+            Value::List(list) => {
+                self.enter(value)?;
+                self.output.push('[');
+
+                for index in 0..list.length() {
+                    if index > 0 {
+                        self.output.push(',');
+                    }
+                    self.newline();
+                    self.value(guard, IndexedAnyContainer::get(&*list, guard, index)?)?;
+                }
+
+                self.leave(']', list.length() > 0);
+            }
+
+            Value::Dict(dict) => {
+                self.enter(value)?;
+                self.output.push('{');
+
+                for (index, (key, item)) in dict.iter(guard).enumerate() {
+                    if index > 0 {
+                        self.output.push(',');
+                    }
+                    self.newline();
+                    self.key(guard, key)?;
+                    self.output.push(':');
+                    if self.indent > 0 {
+                        self.output.push(' ');
+                    }
+                    self.value(guard, item)?;
+                }
+
+                self.leave('}', dict.length() > 0);
+            }
+
+            _ => return Err(err_eval(&format!("stringify() cannot write {}", value))),
+        }
+
+        Ok(())
+    }
+
+    /// Write a Dict key, which must be a string, symbol or integer, as a string
+    fn key(
+        &mut self,
+        guard: &'guard dyn MutatorScope,
+        key: TaggedScopedPtr<'guard>,
+    ) -> Result<(), RuntimeError> {
+        match *key {
+            Value::Text(t) => self.string(t.as_str(guard)),
+            Value::Symbol(s) => self.string(s.as_str(guard)),
+            Value::Number(n) => self.string(&n.to_string()),
+            _ => return Err(err_eval(&format!("stringify() cannot write key {}", key))),
+        }
+
+        Ok(())
+    }
+
+    fn string(&mut self, content: &str) {
+        self.output.push('"');
+
+        for c in content.chars() {
+            match c {
+                '"' => self.output.push_str("\\\""),
+                '\\' => self.output.push_str("\\\\"),
+                '\n' => self.output.push_str("\\n"),
+                '\r' => self.output.push_str("\\r"),
+                '\t' => self.output.push_str("\\t"),
+                '\u{8}' => self.output.push_str("\\b"),
+                '\u{c}' => self.output.push_str("\\f"),
+                c if c < ' ' => self.output.push_str(&format!("\\u{:04x}", c as u32)),
+                c => self.output.push(c),
+            }
+        }
+
+        self.output.push('"');
+    }
+
+    /// Record that a List or Dict is being written, unless it is already being written further
+    /// out, in which case it contains itself
+    fn enter(&mut self, container: TaggedScopedPtr<'guard>) -> Result<(), RuntimeError> {
+        if self.ancestors.contains(&container) {
+            return Err(err_eval("stringify() value contains itself"));
+        }
+
+        if self.ancestors.len() == MAX_DEPTH {
+            return Err(err_eval("stringify() cannot write values nested so deeply"));
+        }
+
+        self.ancestors.push(container);
+        Ok(())
+    }
+
+    /// Close the innermost List or Dict, on a line of its own if it had any items
+    fn leave(&mut self, close: char, had_items: bool) {
+        self.ancestors.pop();
+
+        if had_items {
+            self.newline();
+        }
+        self.output.push(close);
+    }
+
+    /// Start a new line indented to the current depth, unless everything is on one line
+    fn newline(&mut self) {
+        if self.indent > 0 {
+            self.output.push('\n');
+            self.output
+                .push_str(&" ".repeat(self.indent * self.ancestors.len()));
+        }
+    }
+}
+
+/// stringify(value) or stringify(value, indent): the value as a JSON document, on one line, or
+/// with each item on a line of its own indented by `indent` spaces per level
+fn stringify<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let indent = match args.get(1) {
+        Some(arg) => integer_arg(mem, "stringify", arg)?,
+        None => 0,
+    };
+
+    if indent < 0 {
+        return Err(err_eval("stringify() indent must not be negative"));
+    }
+
+    let mut writer = Writer {
+        output: String::new(),
+        indent: indent as usize,
+        ancestors: Vec::new(),
+    };
+    writer.value(mem, args[0].get(mem))?;
+
+    new_text(mem, &writer.output)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::memory::{Memory, Mutator};
+
+    /// Call the json function `name`
+    fn call<'guard>(
+        mem: &'guard MutatorView,
+        globals: &Dict,
+        name: &str,
+        args: &[TaggedScopedPtr<'guard>],
+    ) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+        let native = match *globals.lookup(mem, mem.lookup_sym(name))? {
+            Value::NativeFunction(native) => native,
+            _ => panic!("{} is not a native function", name),
+        };
+
+        let args: Vec<TaggedCellPtr> = args
+            .iter()
+            .map(|arg| TaggedCellPtr::new_with(*arg))
+            .collect();
+        native.call(mem, &args)
+    }
+
+    /// Write `value` as JSON with the given indent
+    fn write<'guard>(
+        mem: &'guard MutatorView,
+        globals: &Dict,
+        value: TaggedScopedPtr<'guard>,
+        indent: isize,
+    ) -> Result<String, RuntimeError> {
+        let indent = TaggedScopedPtr::new(mem, TaggedPtr::number(indent));
+
+        match *call(mem, globals, "stringify", &[value, indent])? {
+            Value::Text(text) => Ok(String::from(text.as_str(mem))),
+            _ => panic!("stringify() did not return a string"),
+        }
+    }
+
+    /// Parse `source` and write it back as JSON with the given indent
+    fn round_trip<'guard>(
+        mem: &'guard MutatorView,
+        globals: &Dict,
+        source: &str,
+        indent: isize,
+    ) -> Result<String, RuntimeError> {
+        let value = call(mem, globals, "parse", &[new_text(mem, source)?])?;
+        write(mem, globals, value, indent)
+    }
+
+    /// Return the message of the error raised parsing `source`
+    fn parse_error<'guard>(mem: &'guard MutatorView, globals: &Dict, source: &str) -> String {
+        let source = new_text(mem, source).unwrap();
+
+        match call(mem, globals, "parse", &[source]) {
+            Err(error) => format!("{}", error),
+            Ok(value) => panic!("{} was parsed", value),
+        }
+    }
+
+    #[test]
+    fn documents_round_trip_in_key_order() {
+        let mem = Memory::new();
+
+        struct Test {}
+        impl Mutator for Test {
+            type Input = ();
+            type Output = ();
+
+            fn run(
+                &self,
+                mem: &MutatorView,
+                _input: Self::Input,
+            ) -> Result<Self::Output, RuntimeError> {
+                let globals = Dict::alloc(mem)?;
+                install(mem, &globals, &*Thread::alloc(mem)?)?;
+
+                let source = r#" {"name": "chorus", "tags": ["a", "b"], "n": -12, "x": 1.5e2,
+                    "yes": true, "no": false, "none": null, "s": "\u00e9\n\ud83d\ude00"} "#;
+                let compact = r#"{"name":"chorus","tags":["a","b"],"n":-12,"x":150.0,"yes":true,"no":null,"none":null,"s":"é\n😀"}"#;
+                assert!(round_trip(mem, &globals, source, 0)? == compact);
+
+                let indented = "{\n  \"a\": [\n    1,\n    2\n  ],\n  \"b\": {}\n}";
+                assert!(round_trip(mem, &globals, r#"{"a": [1, 2], "b": {}}"#, 2)? == indented);
+
+                let big = round_trip(mem, &globals, "[9223372036854775807]", 0)?;
+                assert!(big == "[9.223372036854776e18]");
+
+                Ok(())
+            }
+        }
+
+        let test = Test {};
+        mem.mutate(&test, ()).unwrap();
+    }
+
+    #[test]
+    fn malformed_documents_and_cycles_are_errors() {
+        let mem = Memory::new();
+
+        struct Test {}
+        impl Mutator for Test {
+            type Input = ();
+            type Output = ();
+
+            fn run(
+                &self,
+                mem: &MutatorView,
+                _input: Self::Input,
+            ) -> Result<Self::Output, RuntimeError> {
+                let globals = Dict::alloc(mem)?;
+                install(mem, &globals, &*Thread::alloc(mem)?)?;
+
+                let error = parse_error(mem, &globals, "{\n  \"a\" 1\n}");
+                assert!(error.ends_with("line 2, column 7: expected ':', found '1'"));
+
+                let error = parse_error(mem, &globals, "[1, 2");
+                assert!(error.ends_with("expected ',' or ']', found the end of the input"));
+                assert!(parse_error(mem, &globals, "[01]").contains("column 3"));
+                assert!(parse_error(mem, &globals, "\"\\ud800\"").contains("surrogate"));
+                assert!(parse_error(mem, &globals, "nul").contains("expected 'null'"));
+                assert!(parse_error(mem, &globals, "1 2").contains("end of the input"));
+
+                let list = List::alloc(mem)?;
+                StackAnyContainer::push(&*list, mem, list.as_tagged(mem))?;
+                match write(mem, &globals, list.as_tagged(mem), 0) {
+                    Err(error) => assert!(format!("{}", error).contains("value contains itself")),
+                    Ok(_) => panic!("A cyclic list was written"),
+                }
+
+                // the same list twice is not a cycle
+                let shared = List::alloc(mem)?;
+                let outer = List::alloc(mem)?;
+                StackAnyContainer::push(&*outer, mem, shared.as_tagged(mem))?;
+                StackAnyContainer::push(&*outer, mem, shared.as_tagged(mem))?;
+                assert!(write(mem, &globals, outer.as_tagged(mem), 0)? == "[[],[]]");
+
+                Ok(())
+            }
+        }
+
+        let test = Test {};
+        mem.mutate(&test, ()).unwrap();
+    }
+}
@@ -1,10 +1,17 @@
+use crate::error::{err_parser, spos, RuntimeError, SourcePos};
 use crate::tokens::{Tok, TokID};
 use lexify::{Lexify, LexifyError, LexifyToken};
-use std::fs::File;
-use std::io::BufReader;
+use std::fs;
+use std::io::Cursor;
 
 pub struct Lexer {
-    lexer: lexify::Lexify<BufReader<File>, Tok>,
+    lexer: lexify::Lexify<Cursor<Vec<u8>>, Tok>,
+    /// The whole source being lexed, kept for locating tokens and showing errors in context
+    source: String,
+    /// Byte offset into the source just past the last token located
+    located: usize,
+    /// The text of the last token lexed
+    token: String,
 }
 
 impl Lexer {
@@ -18,36 +25,100 @@ impl Lexer {
     }
 
     pub fn next_token(&mut self) -> Result<LexifyToken<Tok>, ()> {
-        match self.lexer.next_token() {
-            Ok(t) => Ok(t),
-            Err(lex_err) => {
+        let (tid, attr) = match self.lexer.next_token() {
+            Ok(LexifyToken::Eof) => return Ok(LexifyToken::Eof),
+            Ok(LexifyToken::Tok(tid, attr)) => (tid, attr.map(str::to_string)),
+            Err(_lex_err) => {
                 // add lex error to errors
-                Err(())
+                return Err(());
             }
+        };
+
+        self.token = match (tid, attr) {
+            (Tok::String, Some(attr)) => self.complete_string(attr),
+            (_, Some(attr)) => attr,
+            (_, None) => return Ok(LexifyToken::Tok(tid, None)),
+        };
+
+        Ok(LexifyToken::Tok(tid, Some(&self.token)))
+    }
+
+    /// The String rule cannot count braces, so it ends a template string at a quote inside an
+    /// embedded `${...}` expression. Scan such a string to its real end in the source, skipping
+    /// nested braces and strings, and carry on lexing after it.
+    fn complete_string(&mut self, attr: String) -> String {
+        if string_end(attr.as_bytes(), 1) == Some(attr.len()) {
+            return attr;
         }
+
+        // An unterminated string is left for the parser to report
+        let start = match self.source[self.located..].find(&attr) {
+            Some(start) => self.located + start,
+            None => return attr,
+        };
+        let end = match string_end(self.source.as_bytes(), start + 1) {
+            Some(end) => end,
+            None => return attr,
+        };
+
+        self.restart_at(end);
+        self.source[start..end].to_string()
+    }
+
+    /// Lex on from a byte offset into the source
+    fn restart_at(&mut self, offset: usize) {
+        self.lexer = Lexify::new();
+        self.install_ignores();
+        self.install_terms();
+
+        let rest = self.source.as_bytes()[offset..].to_vec();
+        self.lexer.set_buf_reader(Cursor::new(rest));
     }
 
     fn new() -> Self {
         Self {
             lexer: Lexify::new(),
+            source: String::new(),
+            located: 0,
+            token: String::new(),
         }
     }
 
     pub fn open_file(&mut self, path: &str) -> Result<(), ()> {
-        let f = File::open(path);
+        let source = fs::read_to_string(path);
 
-        if f.is_err() {
+        if source.is_err() {
             println!("Unable to open file: {}", path);
             return Err(());
         }
 
-        let reader = BufReader::new(f.ok().unwrap());
-
-        self.lexer.set_buf_reader(reader);
+        self.open_str(&source.ok().unwrap());
 
         Ok(())
     }
 
+    /// Lex source code held in a string
+    pub fn open_str(&mut self, source: &str) {
+        self.source = source.to_string();
+        self.located = 0;
+        self.lexer
+            .set_buf_reader(Cursor::new(source.as_bytes().to_vec()));
+    }
+
+    /// The source code being lexed
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Find the source position of a token's text. Tokens must be located in the order they were
+    /// lexed, since the search starts just past the previously located token.
+    pub fn locate(&mut self, attr: &str) -> Option<SourcePos> {
+        let offset = self.located + self.source[self.located..].find(attr)?;
+        self.located = offset + attr.len();
+
+        Some(source_pos(&self.source, offset))
+    }
+
     fn install_ignores(&mut self) {
         self.lexer.set_ignore("\\w+");
         self.lexer.set_ignore(r#"/\*.*\*/"#);
@@ -69,6 +140,135 @@ impl Lexer {
         self.lexer.set_rule(r#"="#,  Tok::Eq,         false);
         self.lexer.set_rule(r#"\+"#, Tok::Plus,       false);
         self.lexer.set_rule(r#"-"#,  Tok::Minus,      false);
+        self.lexer.set_rule(r#"%"#,  Tok::Modulo,     false);
         self.lexer.set_rule(r#","#,  Tok::Comma,      false);
+        self.lexer.set_rule(r#":"#,  Tok::Colon,      false);
+        self.lexer.set_rule(r#"\."#, Tok::Dot,        false);
+        self.lexer.set_rule(r#"_"#,  Tok::Placeholder, false);
+        self.lexer.set_rule(r#"\*"#, Tok::Star,       false);
+    }
+}
+
+/// Convert a byte offset into a string to a line number, counting from 1, and a column in
+/// characters, counting from 0
+pub fn source_pos(source: &str, offset: usize) -> SourcePos {
+    let before = &source[..offset];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map(|n| n + 1).unwrap_or(0);
+    let column = before[line_start..].chars().count();
+
+    spos(line as u32, column as u32)
+}
+
+/// Return the index of the `}` closing an embedded expression that starts at `i`, skipping over
+/// nested braces and string literals, which may themselves contain templates
+pub fn closing_brace(bytes: &[u8], mut i: usize) -> Option<usize> {
+    let mut depth = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'"' => {
+                i = string_end(bytes, i + 1)?;
+                continue;
+            }
+            b'{' => depth += 1,
+            b'}' if depth == 0 => return Some(i),
+            b'}' => depth -= 1,
+            _ => (),
+        }
+
+        i += 1;
+    }
+
+    None
+}
+
+/// Return the index just past the closing quote of a string literal whose contents start at `i`
+pub fn string_end(bytes: &[u8], mut i: usize) -> Option<usize> {
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 2,
+            b'"' => return Some(i + 1),
+            b'$' if bytes.get(i + 1) == Some(&b'{') => i = closing_brace(bytes, i + 2)? + 1,
+            _ => i += 1,
+        }
+    }
+
+    None
+}
+
+/// Convert the source text of a string literal, including its surrounding quotes, to the string it
+/// denotes. Recognized escapes are `\n`, `\t`, `\r`, `\0`, `\\`, `\"`, `\$` and `\u{...}` with
+/// one to six hex digits.
+pub fn unescape(quoted: &str) -> Result<String, RuntimeError> {
+    unescape_raw(&quoted[1..quoted.len() - 1])
+}
+
+/// Process the escapes in part of a string literal, without surrounding quotes
+pub fn unescape_raw(raw: &str) -> Result<String, RuntimeError> {
+    let mut result = String::with_capacity(raw.len());
+    let mut chars = raw.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') => result.push('\n'),
+            Some('t') => result.push('\t'),
+            Some('r') => result.push('\r'),
+            Some('0') => result.push('\0'),
+            Some('\\') => result.push('\\'),
+            Some('"') => result.push('"'),
+            Some('$') => result.push('$'),
+            Some('u') => result.push(unescape_unicode(&mut chars)?),
+            Some(other) => {
+                return Err(err_parser(&format!("Unknown string escape \\{}", other)));
+            }
+            None => return Err(err_parser("String ends with an incomplete escape")),
+        }
+    }
+
+    Ok(result)
+}
+
+/// Read the `{...}` part of a `\u{...}` escape and return the code point it names
+fn unescape_unicode(chars: &mut std::str::Chars) -> Result<char, RuntimeError> {
+    if chars.next() != Some('{') {
+        return Err(err_parser("Expected { after \\u in string escape"));
+    }
+
+    let mut digits = String::new();
+    loop {
+        match chars.next() {
+            Some('}') => break,
+            Some(c) if c.is_ascii_hexdigit() && digits.len() < 6 => digits.push(c),
+            _ => return Err(err_parser("Invalid \\u{...} string escape")),
+        }
+    }
+
+    u32::from_str_radix(&digits, 16)
+        .ok()
+        .and_then(std::char::from_u32)
+        .ok_or_else(|| err_parser(&format!("\\u{{{}}} is not a valid code point", digits)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::unescape;
+
+    #[test]
+    fn unescape_string_literals() {
+        assert!(unescape(r#""plain""#).unwrap() == "plain");
+        assert!(unescape(r#""a\tb\nc""#).unwrap() == "a\tb\nc");
+        assert!(unescape(r#""say \"hi\" \\o/""#).unwrap() == "say \"hi\" \\o/");
+        assert!(unescape(r#""\u{48}\u{e9}\u{1F600}""#).unwrap() == "Hé😀");
+
+        assert!(unescape(r#""\q""#).is_err());
+        assert!(unescape(r#""\u{110000}""#).is_err());
+        assert!(unescape(r#""\u{}""#).is_err());
+        assert!(unescape(r#""\u48""#).is_err());
     }
 }
@@ -0,0 +1,383 @@
+/// Register liveness analysis over generated code
+///
+/// A register is live at a point in the code if some path from that point reads it before
+/// writing it. The analysis is the usual backward dataflow over the control flow graph, iterated
+/// until nothing changes, and is used to remove the spills that the generator makes without
+/// knowing which of its registers will be read again.
+///
+/// Generated code only keeps temporaries in registers and never closes over them, so a call is
+/// assumed to read its function and argument registers and to clobber every register from its
+/// frame base upward, and nothing else. Any instruction protected by an exception handler may
+/// continue at the handler instead of the next instruction.
+use std::collections::HashSet;
+
+use crate::bytecode::{successors, Handler, LocalId, Opcode, Register};
+use crate::optimizer::remove_dead_code;
+use crate::vm::{ENV_REG, FIRST_ARG_REG};
+
+/// A set of registers
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RegSet([u64; 4]);
+
+impl RegSet {
+    fn new() -> RegSet {
+        RegSet([0; 4])
+    }
+
+    pub fn contains(&self, reg: Register) -> bool {
+        self.0[reg as usize / 64] & (1 << (reg % 64)) != 0
+    }
+
+    pub fn insert(&mut self, reg: Register) {
+        self.0[reg as usize / 64] |= 1 << (reg % 64);
+    }
+
+    fn union(&mut self, other: &RegSet) {
+        for (word, other) in self.0.iter_mut().zip(other.0.iter()) {
+            *word |= *other;
+        }
+    }
+
+    fn range(from: usize, to: usize) -> RegSet {
+        let mut set = RegSet::new();
+        for reg in from..to.min(256) {
+            set.insert(reg as Register);
+        }
+        set
+    }
+}
+
+/// The registers an instruction reads
+fn uses(op: Opcode) -> RegSet {
+    let mut set = RegSet::new();
+
+    let regs: &[Register] = match op {
+        Opcode::Return { reg } => &[reg],
+        Opcode::IsNil { test, .. } | Opcode::IsAtom { test, .. } => &[test],
+        Opcode::FirstOfPair { reg, .. } | Opcode::SecondOfPair { reg, .. } => &[reg],
+        Opcode::MakePair { reg1, reg2, .. }
+        | Opcode::Add { reg1, reg2, .. }
+        | Opcode::Multiply { reg1, reg2, .. } => &[reg1, reg2],
+        Opcode::IsIdentical { test1, test2, .. } => &[test1, test2],
+        Opcode::JumpIfTrue { test, .. } | Opcode::JumpIfNotTrue { test, .. } => &[test],
+        Opcode::LoadGlobal { name, .. } => &[name],
+        Opcode::StoreGlobal { src, name } => &[src, name],
+        Opcode::MakeClosure { function, .. } => &[function],
+        Opcode::CopyRegister { src, .. } => &[src],
+        Opcode::Subtract { left, right, .. } => &[left, right],
+        Opcode::DivideInteger { num, denom, .. } | Opcode::Modulo { num, denom, .. } => {
+            &[num, denom]
+        }
+        Opcode::SetUpvalue { src, .. } => &[src],
+        Opcode::CloseUpvalues { reg1, reg2, reg3 } => &[reg1, reg2, reg3],
+        Opcode::GetIter { src, .. } => &[src],
+        Opcode::IterNext { iter, .. } | Opcode::IterNextPair { iter, .. } => &[iter],
+        Opcode::StoreLocal { src, .. } => &[src],
+        Opcode::Throw { reg } => &[reg],
+        Opcode::Import { path, .. } => &[path],
+        Opcode::GetField { object, name, .. } => &[object, name],
+
+        Opcode::Call {
+            function,
+            dest,
+            arg_count,
+        }
+        | Opcode::TailCall {
+            function,
+            dest,
+            arg_count,
+        } => {
+            let first = dest as usize + FIRST_ARG_REG;
+            set = RegSet::range(first, first + arg_count as usize);
+            set.insert(function);
+            return set;
+        }
+
+        // The function to call is in the result register, and the keyword names in their own
+        Opcode::CallKeywords {
+            dest,
+            arg_count,
+            names,
+        } => {
+            let first = dest as usize + FIRST_ARG_REG;
+            set = RegSet::range(first, first + arg_count as usize);
+            set.insert(dest);
+            set.insert(names);
+            return set;
+        }
+
+        // The placeholder positions are passed in the callee's closure environment register
+        Opcode::MakePartial {
+            function,
+            dest,
+            arg_count,
+        } => {
+            let first = dest as usize + FIRST_ARG_REG;
+            set = RegSet::range(first, first + arg_count as usize);
+            set.insert(function);
+            set.insert(dest.wrapping_add(ENV_REG as Register));
+            return set;
+        }
+
+        Opcode::NoOp
+        | Opcode::LoadLiteral { .. }
+        | Opcode::Jump { .. }
+        | Opcode::LoadNil { .. }
+        | Opcode::LoadInteger { .. }
+        | Opcode::GetUpvalue { .. }
+        | Opcode::LoadLocal { .. }
+        | Opcode::IsSupplied { .. }
+        | Opcode::Wide { .. } => &[],
+    };
+
+    for reg in regs {
+        set.insert(*reg);
+    }
+    set
+}
+
+/// The registers an instruction always overwrites. The iteration instructions only write when
+/// they produce a value, so they are not counted.
+fn defs(op: Opcode) -> RegSet {
+    let mut set = RegSet::new();
+
+    match op {
+        Opcode::LoadLiteral { dest, .. }
+        | Opcode::IsNil { dest, .. }
+        | Opcode::IsAtom { dest, .. }
+        | Opcode::FirstOfPair { dest, .. }
+        | Opcode::SecondOfPair { dest, .. }
+        | Opcode::MakePair { dest, .. }
+        | Opcode::IsIdentical { dest, .. }
+        | Opcode::LoadNil { dest }
+        | Opcode::LoadGlobal { dest, .. }
+        | Opcode::MakeClosure { dest, .. }
+        | Opcode::LoadInteger { dest, .. }
+        | Opcode::CopyRegister { dest, .. }
+        | Opcode::Add { dest, .. }
+        | Opcode::Subtract { dest, .. }
+        | Opcode::Multiply { dest, .. }
+        | Opcode::DivideInteger { dest, .. }
+        | Opcode::Modulo { dest, .. }
+        | Opcode::GetUpvalue { dest, .. }
+        | Opcode::GetIter { dest, .. }
+        | Opcode::LoadLocal { dest, .. }
+        | Opcode::IsSupplied { dest, .. }
+        | Opcode::MakePartial { dest, .. }
+        | Opcode::GetField { dest, .. } => set.insert(dest),
+
+        // The callee's register window starts at `dest`
+        Opcode::Call { dest, .. }
+        | Opcode::TailCall { dest, .. }
+        | Opcode::CallKeywords { dest, .. }
+        | Opcode::Import { dest, .. } => set = RegSet::range(dest as usize, 256),
+
+        _ => (),
+    }
+
+    set
+}
+
+/// Return the set of registers live after each instruction
+pub fn live_out(code: &[Opcode], handlers: &[Handler]) -> Vec<RegSet> {
+    let mut live_in = vec![RegSet::new(); code.len()];
+    let mut live_out = vec![RegSet::new(); code.len()];
+
+    let mut changed = true;
+    while changed {
+        changed = false;
+
+        for index in (0..code.len()).rev() {
+            let mut next = successors(code, index);
+            for handler in handlers.iter().filter(|handler| handler.covers(index)) {
+                next.push(handler.target as usize);
+            }
+
+            let mut out = RegSet::new();
+            for next in next {
+                if let Some(next_in) = live_in.get(next) {
+                    out.union(next_in);
+                }
+            }
+
+            let mut entry = out;
+            for (word, def) in entry.0.iter_mut().zip(defs(code[index]).0.iter()) {
+                *word &= !def;
+            }
+            entry.union(&uses(code[index]));
+
+            if entry != live_in[index] || out != live_out[index] {
+                live_in[index] = entry;
+                live_out[index] = out;
+                changed = true;
+            }
+        }
+    }
+
+    live_out
+}
+
+/// Remove reloads of spilled registers that are never read afterwards, and then the spills of
+/// locals that are never reloaded. Returns true if any instructions were removed.
+pub fn remove_dead_spills(code: &mut Vec<Opcode>, handlers: &mut Vec<Handler>) -> bool {
+    let mut removed = false;
+
+    loop {
+        let live = live_out(code, handlers);
+        let mut changed = false;
+
+        for index in 0..code.len() {
+            if let Opcode::LoadLocal { dest, .. } = code[index] {
+                if !live[index].contains(dest) {
+                    code[index] = Opcode::NoOp;
+                    changed = true;
+                }
+            }
+        }
+
+        let reloaded: HashSet<LocalId> = code
+            .iter()
+            .filter_map(|op| match op {
+                Opcode::LoadLocal { local, .. } => Some(*local),
+                _ => None,
+            })
+            .collect();
+
+        for op in code.iter_mut() {
+            if let Opcode::StoreLocal { local, .. } = *op {
+                if !reloaded.contains(&local) {
+                    *op = Opcode::NoOp;
+                    changed = true;
+                }
+            }
+        }
+
+        if !changed {
+            break;
+        }
+        removed = true;
+    }
+
+    if removed {
+        remove_dead_code(code, handlers);
+    }
+
+    removed
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn liveness_through_calls_and_loops() {
+        let code = vec![
+            /* 0 */
+            Opcode::LoadInteger {
+                dest: 2,
+                integer: 1,
+            },
+            /* 1 */
+            Opcode::LoadInteger {
+                dest: 7,
+                integer: 2,
+            },
+            /* 2 */
+            Opcode::LoadLiteral {
+                dest: 3,
+                literal_id: 0,
+            },
+            /* 3 */ Opcode::LoadGlobal { dest: 3, name: 3 },
+            /* 4 */
+            Opcode::Call {
+                function: 3,
+                dest: 5,
+                arg_count: 1,
+            },
+            /* 5 */
+            Opcode::JumpIfTrue {
+                test: 5,
+                offset: -5,
+            },
+            /* 6 */ Opcode::Return { reg: 2 },
+        ];
+
+        let live = live_out(&code, &[]);
+
+        // r2 is returned and r7 is the call's argument, loaded again on each pass of the loop
+        assert!(live[0].contains(2) && !live[0].contains(7));
+        assert!(live[1].contains(7));
+        assert!(live[2].contains(3) && live[2].contains(7));
+
+        // the call clobbers r7 but r2 survives it
+        assert!(live[4].contains(2) && live[4].contains(5) && !live[4].contains(7));
+        assert!(live[5].contains(2) && !live[5].contains(5));
+        assert!(live[6] == RegSet::new());
+    }
+
+    #[test]
+    fn remove_spills_that_are_never_read() {
+        let mut code = vec![
+            Opcode::LoadInteger {
+                dest: 2,
+                integer: 1,
+            },
+            Opcode::StoreLocal { src: 2, local: 0 },
+            Opcode::StoreLocal { src: 3, local: 1 },
+            Opcode::LoadInteger {
+                dest: 2,
+                integer: 5,
+            },
+            Opcode::LoadInteger {
+                dest: 3,
+                integer: 6,
+            },
+            Opcode::Add {
+                dest: 4,
+                reg1: 2,
+                reg2: 3,
+            },
+            Opcode::LoadLocal { dest: 2, local: 0 },
+            Opcode::LoadLocal { dest: 3, local: 1 },
+            Opcode::Add {
+                dest: 4,
+                reg1: 4,
+                reg2: 2,
+            },
+            Opcode::Return { reg: 4 },
+        ];
+
+        assert!(remove_dead_spills(&mut code, &mut Vec::new()));
+        assert!(
+            code == vec![
+                Opcode::LoadInteger {
+                    dest: 2,
+                    integer: 1
+                },
+                Opcode::StoreLocal { src: 2, local: 0 },
+                Opcode::LoadInteger {
+                    dest: 2,
+                    integer: 5
+                },
+                Opcode::LoadInteger {
+                    dest: 3,
+                    integer: 6
+                },
+                Opcode::Add {
+                    dest: 4,
+                    reg1: 2,
+                    reg2: 3
+                },
+                Opcode::LoadLocal { dest: 2, local: 0 },
+                Opcode::Add {
+                    dest: 4,
+                    reg1: 4,
+                    reg2: 2
+                },
+                Opcode::Return { reg: 4 },
+            ],
+            "{:?}",
+            code
+        );
+    }
+}
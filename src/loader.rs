@@ -0,0 +1,303 @@
+/// Finding and compiling the modules a script imports
+///
+/// An import names a module by a path, which is looked for relative to the directory of the
+/// importing file and then in each directory listed in the `CHORUS_PATH` environment variable.
+/// Each module is compiled once, however many files import it, and is known from then on by the
+/// canonical path of its source file. A module that imports itself, directly or through others, is
+/// an error. Native modules, such as `math`, are known by their name and need no compiling.
+use std::env;
+use std::iter;
+use std::path::{Path, PathBuf};
+
+use crate::ast::Ast;
+use crate::error::{err_import, RuntimeError};
+use crate::generator::Generator;
+use crate::lexer::Lexer;
+use crate::module;
+use crate::parser::Parser;
+
+/// The environment variable listing the directories searched for modules
+pub const SEARCH_PATH_VAR: &str = "CHORUS_PATH";
+
+/// The generated top-level code of a module
+pub struct CompiledModule {
+    /// The canonical path of the module's source file
+    pub path: String,
+    pub generator: Generator,
+}
+
+pub struct Loader {
+    /// Directories searched for a module that is not found relative to the importing file
+    search_path: Vec<PathBuf>,
+    /// Whether generated code is optimized
+    optimize: bool,
+    /// Every module compiled so far, each after the modules it imports
+    modules: Vec<CompiledModule>,
+    /// The canonical paths of the files whose imports are being loaded, outermost first
+    loading: Vec<PathBuf>,
+    /// The source of the module that failed to compile, to show the error in context
+    failed_source: Option<String>,
+}
+
+impl Loader {
+    /// Initialize a Loader that searches the given directories
+    pub fn new(search_path: Vec<PathBuf>) -> Loader {
+        Loader {
+            search_path,
+            optimize: false,
+            modules: Vec::new(),
+            loading: Vec::new(),
+            failed_source: None,
+        }
+    }
+
+    /// Initialize a Loader that searches the directories listed in `CHORUS_PATH`
+    pub fn from_env() -> Loader {
+        let search_path = match env::var_os(SEARCH_PATH_VAR) {
+            Some(paths) => env::split_paths(&paths).collect(),
+            None => Vec::new(),
+        };
+
+        Loader::new(search_path)
+    }
+
+    /// Enable or disable the optimization passes over generated code
+    pub fn set_optimize(&mut self, optimize: bool) {
+        self.optimize = optimize;
+    }
+
+    /// The modules compiled so far, each after the modules it imports
+    pub fn modules(&self) -> &[CompiledModule] {
+        &self.modules
+    }
+
+    /// The source of the module that failed to compile, if it was not the importing script
+    pub fn failed_source(&self) -> Option<&str> {
+        self.failed_source.as_deref()
+    }
+
+    /// Forget every compiled module, so that they are compiled again for the next script
+    pub fn clear(&mut self) {
+        self.modules.clear();
+        self.loading.clear();
+        self.failed_source = None;
+    }
+
+    /// Compile each module imported by the code generated from the file at `importer`, and the
+    /// modules they import in turn, and replace the path of each import with the canonical path
+    /// of the module
+    pub fn load_imports(
+        &mut self,
+        parser: &mut Parser,
+        importer: &Path,
+        generator: &mut Generator,
+    ) -> Result<(), RuntimeError> {
+        let importer = importer.canonicalize()?;
+        let dir = importer.parent().unwrap_or(Path::new("/")).to_path_buf();
+
+        self.loading.push(importer);
+        let result = generator.resolve_imports(|path| self.load(parser, &dir, path));
+        self.loading.pop();
+
+        result
+    }
+
+    /// Compile the module at `path`, from a file in the directory `dir`, unless it has been
+    /// compiled already. Returns its canonical path, or the name of a native module.
+    fn load(
+        &mut self,
+        parser: &mut Parser,
+        dir: &Path,
+        path: &str,
+    ) -> Result<String, RuntimeError> {
+        if module::is_native(path) {
+            return Ok(String::from(path));
+        }
+
+        let file = self.resolve(dir, path)?;
+        let canonical = file.display().to_string();
+
+        if self.modules.iter().any(|module| module.path == canonical) {
+            return Ok(canonical);
+        }
+
+        if let Some(start) = self.loading.iter().position(|loading| *loading == file) {
+            let cycle: Vec<String> = self.loading[start..]
+                .iter()
+                .chain(iter::once(&file))
+                .map(|path| path.display().to_string())
+                .collect();
+            return Err(err_import(&format!("Import cycle: {}", cycle.join(" -> "))));
+        }
+
+        let generator = self.compile(parser, &file)?;
+        self.modules.push(CompiledModule {
+            path: canonical.clone(),
+            generator,
+        });
+
+        Ok(canonical)
+    }
+
+    /// Find the file that a module path refers to, relative to `dir` or else to one of the search
+    /// path directories
+    fn resolve(&self, dir: &Path, path: &str) -> Result<PathBuf, RuntimeError> {
+        let search_path = self.search_path.iter().map(PathBuf::as_path);
+
+        for base in iter::once(dir).chain(search_path) {
+            match base.join(path).canonicalize() {
+                Ok(file) if file.is_file() => return Ok(file),
+                _ => (),
+            }
+        }
+
+        Err(err_import(&format!("Cannot find module {}", path)))
+    }
+
+    /// Parse and generate code for the module in `file`, and then load its own imports
+    fn compile(&mut self, parser: &mut Parser, file: &Path) -> Result<Generator, RuntimeError> {
+        let path = file.display().to_string();
+
+        let mut lexer = Lexer::init();
+        if lexer.open_file(&path).is_err() {
+            return Err(err_import(&format!("Unable to open module {}", path)));
+        }
+
+        let mut ast = Ast::init();
+        let mut generator = Generator::init();
+
+        let parsed = parser.build_ast(&mut lexer, &mut ast);
+        if let Err(error) = parsed.and_then(|_| ast.traverse(&mut generator)) {
+            self.failed_source = Some(String::from(lexer.source()));
+            return Err(error);
+        }
+
+        if self.optimize {
+            generator.optimize();
+        }
+
+        self.load_imports(parser, file, &mut generator)?;
+        Ok(generator)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::memory::{Memory, Mutator, MutatorView};
+    use crate::vm::Thread;
+    use std::fs;
+    use std::process;
+
+    /// Write each (name, source) pair to a fresh directory, returning the directory
+    fn write_sources(test_name: &str, sources: &[(&str, &str)]) -> PathBuf {
+        let dir = env::temp_dir().join(format!("chorus-{}-{}", test_name, process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        for (name, source) in sources {
+            fs::write(dir.join(name), source).unwrap();
+        }
+
+        dir
+    }
+
+    fn compile(loader: &mut Loader, file: &Path) -> Result<Generator, RuntimeError> {
+        let mut lexer = Lexer::init();
+        lexer.open_file(file.to_str().unwrap()).unwrap();
+
+        let mut parser = Parser::init();
+        let mut ast = Ast::init();
+        let mut generator = Generator::init();
+        parser.build_ast(&mut lexer, &mut ast)?;
+        ast.traverse(&mut generator)?;
+
+        loader.load_imports(&mut parser, file, &mut generator)?;
+        Ok(generator)
+    }
+
+    struct Run<'a>(&'a Generator, &'a [CompiledModule]);
+
+    impl<'a> Mutator for Run<'a> {
+        type Input = ();
+        type Output = Vec<String>;
+
+        fn run(&self, mem: &MutatorView, _input: ()) -> Result<Vec<String>, RuntimeError> {
+            let thread = Thread::alloc(mem)?;
+            for module in self.1 {
+                thread.add_module(mem, &module.path, module.generator.function(mem)?)?;
+            }
+            thread.quick_vm_eval(mem, self.0.function(mem)?)?;
+
+            let global = |name: &str| thread.global(mem, name).unwrap();
+            assert!(global("a") == global("b"));
+
+            Ok(["x", "a_x", "shout", "loud"]
+                .iter()
+                .map(|name| format!("{}", global(*name)))
+                .collect())
+        }
+    }
+
+    #[test]
+    fn imported_modules_run_once_with_their_own_globals() {
+        let dir = write_sources(
+            "import",
+            &[
+                (
+                    "lib.ch",
+                    "x = \"lib\"\nshout = upper(\"hi\")\nloud = bind(upper)\n",
+                ),
+                (
+                    "main.ch",
+                    "import \"lib.ch\" as a\nimport \"./lib.ch\" as b;\n\
+                     from \"lib.ch\" import shout\n\
+                     x = \"main\"\na_x = a.x\nloud = a.loud(\"hey\")\n",
+                ),
+            ],
+        );
+
+        let mut loader = Loader::new(Vec::new());
+        let generator = compile(&mut loader, &dir.join("main.ch")).unwrap();
+        assert!(loader.modules().len() == 1);
+
+        let mem = Memory::new();
+        let values = mem.mutate(&Run(&generator, loader.modules()), ()).unwrap();
+        assert!(values == [r#""main""#, r#""lib""#, r#""HI""#, r#""HEY""#]);
+    }
+
+    #[test]
+    fn modules_are_found_on_the_search_path() {
+        let lib_dir = write_sources("search-lib", &[("lib.ch", "x = \"lib\"\n")]);
+        let dir = write_sources("search", &[("main.ch", "import \"lib.ch\" as a\n")]);
+
+        let mut loader = Loader::new(vec![lib_dir.clone()]);
+        compile(&mut loader, &dir.join("main.ch")).unwrap();
+
+        let lib = lib_dir.join("lib.ch").canonicalize().unwrap();
+        assert!(loader.modules()[0].path == lib.display().to_string());
+    }
+
+    #[test]
+    fn import_cycles_and_missing_modules_are_errors() {
+        let dir = write_sources(
+            "cycle",
+            &[
+                ("a.ch", "import \"b.ch\" as b\n"),
+                ("b.ch", "import \"a.ch\" as a;\n"),
+                ("c.ch", "import \"nowhere.ch\" as n\n"),
+            ],
+        );
+
+        let mut loader = Loader::new(Vec::new());
+        match compile(&mut loader, &dir.join("a.ch")) {
+            Err(error) => assert!(format!("{}", error).contains("Import cycle")),
+            Ok(_) => panic!("An import cycle was compiled"),
+        }
+
+        loader.clear();
+        match compile(&mut loader, &dir.join("c.ch")) {
+            Err(error) => assert!(format!("{}", error).contains("Cannot find module nowhere.ch")),
+            Ok(_) => panic!("A missing module was compiled"),
+        }
+    }
+}
@@ -2,20 +2,36 @@ mod app;
 mod arena;
 mod array;
 mod ast;
+mod builtins;
 mod bytecode;
+mod chc;
 mod config;
 mod constants;
 mod container;
 mod error;
 mod dict;
+mod disassemble;
+mod exception;
+mod fs;
 mod function;
+mod gc;
 mod generator;
 mod hashable;
 mod header;
+mod heapdump;
+mod io;
+mod iter;
+mod json;
 mod lexer;
 mod list;
+mod liveness;
+mod loader;
+mod math;
 mod memory;
+mod module;
+mod native;
 mod number;
+mod optimizer;
 mod parser;
 mod pair;
 mod ptr_ops;
@@ -27,7 +43,10 @@ mod symbol;
 mod tagged_ptr;
 mod text;
 mod tokens;
+mod trace;
+mod verifier;
 mod vm;
+mod weak;
 
 use crate::app::App;
 use crate::config::Config;
@@ -45,7 +64,34 @@ fn main() {
 }
 
 fn run(config: &Config) {
-    let mut app = App::init();
+    let mut app = match config.max_heap {
+        Some(limit) => App::with_heap_limit(limit),
+        None => App::init(),
+    };
+    app.set_optimize(config.optimize);
+    app.set_script_args(&config.script_args);
+    if let Some(depth) = config.max_depth {
+        app.set_max_call_depth(depth);
+    }
+    if let Some(slots) = config.max_stack {
+        app.set_max_stack_size(slots);
+    }
 
-    app.run(config.filename);
+    if config.compile {
+        let output = match config.output {
+            Some(output) => String::from(output),
+            None => compiled_filename(config.filename),
+        };
+        app.compile_file(config.filename, &output);
+    } else if config.disassemble {
+        app.disassemble(config.filename);
+    } else {
+        app.run(config.filename);
+    }
+}
+
+/// Replace the source file's extension with `.chc`
+fn compiled_filename(filename: &str) -> String {
+    let path = std::path::Path::new(filename);
+    path.with_extension("chc").to_string_lossy().into_owned()
 }
@@ -0,0 +1,580 @@
+/// The `math` native module: numeric functions over inline integers, NumberObjects and Floats
+///
+/// Functions defined only on real numbers, such as `sqrt` and the trig functions, return a Float
+/// whatever they are given, while `floor`, `ceil` and `round` return integers. An argument outside
+/// a function's domain is a domain error rather than a NaN result. A NumberObject too large for a
+/// 64 bit integer is treated as the nearest Float, and integer-only functions reject it.
+use std::cmp::Ordering;
+use std::f64::consts;
+
+use crate::builtins::{define, define_variadic};
+use crate::container::HashIndexedAnyContainer;
+use crate::dict::Dict;
+use crate::error::{err_domain, err_eval, RuntimeError};
+use crate::memory::MutatorView;
+use crate::number::{floor_div, floor_mod, floor_mod_float, Float, NumberObject, Numeric};
+use crate::pair::cons;
+use crate::safe_ptr::{MutatorScope, TaggedCellPtr, TaggedScopedPtr};
+use crate::tagged_ptr::Value;
+use crate::vm::Thread;
+
+/// Bind every member of the math module in the module's globals dict
+pub fn install<'guard>(
+    mem: &'guard MutatorView,
+    globals: &Dict,
+    _thread: &Thread,
+) -> Result<(), RuntimeError> {
+    define(mem, globals, "abs", 1, abs)?;
+    define_variadic(mem, globals, "min", 1, u8::MAX, min)?;
+    define_variadic(mem, globals, "max", 1, u8::MAX, max)?;
+    define(mem, globals, "pow", 2, pow)?;
+    define(mem, globals, "sqrt", 1, sqrt)?;
+
+    define(mem, globals, "floor", 1, floor)?;
+    define(mem, globals, "ceil", 1, ceil)?;
+    define(mem, globals, "round", 1, round)?;
+
+    define(mem, globals, "sin", 1, sin)?;
+    define(mem, globals, "cos", 1, cos)?;
+    define(mem, globals, "tan", 1, tan)?;
+    define(mem, globals, "asin", 1, asin)?;
+    define(mem, globals, "acos", 1, acos)?;
+    define(mem, globals, "atan", 1, atan)?;
+    define(mem, globals, "atan2", 2, atan2)?;
+
+    define(mem, globals, "gcd", 2, gcd)?;
+    define(mem, globals, "divmod", 2, divmod)?;
+
+    let pi = Float::alloc_tagged(mem, consts::PI)?;
+    globals.assoc(mem, mem.lookup_sym("pi"), pi)?;
+    let e = Float::alloc_tagged(mem, consts::E)?;
+    globals.assoc(mem, mem.lookup_sym("e"), e)?;
+
+    Ok(())
+}
+
+/// Extract a number from an argument or return an error naming the function
+fn number_arg<'guard>(
+    guard: &'guard dyn MutatorScope,
+    function: &str,
+    arg: &TaggedCellPtr,
+) -> Result<Numeric, RuntimeError> {
+    match Numeric::from_value(*arg.get(guard)) {
+        Some(number) => Ok(number),
+        None => Err(err_eval(&format!("{}() expects a number", function))),
+    }
+}
+
+/// Extract an integer from an argument or return an error naming the function
+fn integer_arg<'guard>(
+    guard: &'guard dyn MutatorScope,
+    function: &str,
+    arg: &TaggedCellPtr,
+) -> Result<isize, RuntimeError> {
+    match number_arg(guard, function, arg)? {
+        Numeric::Integer(n) => Ok(n),
+        // The number is a NumberObject that was rounded to a Float
+        Numeric::Float(_) if matches!(*arg.get(guard), Value::NumberObject(_)) => Err(err_eval(
+            &format!("{}() expects integers that fit in 64 bits", function),
+        )),
+        _ => Err(err_eval(&format!(
+            "{}() expects integer arguments",
+            function
+        ))),
+    }
+}
+
+/// abs(x): the magnitude of x
+fn abs<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    match number_arg(mem, "abs", &args[0])? {
+        Numeric::Integer(n) => match n.checked_abs() {
+            Some(n) => Numeric::Integer(n).as_tagged(mem),
+            None => Err(err_eval(&format!("Integer overflow in abs({})", n))),
+        },
+        Numeric::Float(f) => Float::alloc_tagged(mem, f.abs()),
+    }
+}
+
+/// Return the argument that orders `wanted` against all the others, the first of any equals
+fn extreme<'guard>(
+    mem: &'guard MutatorView,
+    function: &str,
+    args: &[TaggedCellPtr],
+    wanted: Ordering,
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let mut best = &args[0];
+    let mut best_number = number_arg(mem, function, best)?;
+
+    for arg in &args[1..] {
+        let number = number_arg(mem, function, arg)?;
+
+        match number.compare(best_number) {
+            Some(ordering) if ordering == wanted => {
+                best = arg;
+                best_number = number;
+            }
+            Some(_) => (),
+            None => return Err(err_domain(&format!("{}() cannot order NaN", function))),
+        }
+    }
+
+    Ok(best.get(mem))
+}
+
+/// min(x, ...): the smallest argument
+fn min<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    extreme(mem, "min", args, Ordering::Less)
+}
+
+/// max(x, ...): the largest argument
+fn max<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    extreme(mem, "max", args, Ordering::Greater)
+}
+
+/// pow(base, exponent): an integer if both are integers and the exponent is not negative,
+/// otherwise a Float
+fn pow<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let base = number_arg(mem, "pow", &args[0])?;
+    let exponent = number_arg(mem, "pow", &args[1])?;
+
+    if let (Numeric::Integer(b), Numeric::Integer(e)) = (base, exponent) {
+        if e >= 0 {
+            return match u32::try_from(e).ok().and_then(|e| b.checked_pow(e)) {
+                Some(n) => Numeric::Integer(n).as_tagged(mem),
+                None => Err(err_eval(&format!("Integer overflow in pow({}, {})", b, e))),
+            };
+        }
+    }
+
+    let (b, e) = (base.as_f64(), exponent.as_f64());
+    if b == 0.0 && e < 0.0 {
+        return Err(err_domain("pow() of zero to a negative power"));
+    }
+    if b < 0.0 && e.fract() != 0.0 {
+        return Err(err_domain("pow() of a negative base to a fractional power"));
+    }
+
+    Float::alloc_tagged(mem, b.powf(e))
+}
+
+/// sqrt(x): the non-negative square root of x
+fn sqrt<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let x = number_arg(mem, "sqrt", &args[0])?.as_f64();
+
+    if x < 0.0 {
+        return Err(err_domain(&format!("sqrt() of negative number {}", x)));
+    }
+
+    Float::alloc_tagged(mem, x.sqrt())
+}
+
+/// Round a Float to an integer with `apply`. An integer is already rounded.
+fn to_integer<'guard>(
+    mem: &'guard MutatorView,
+    function: &str,
+    arg: &TaggedCellPtr,
+    apply: fn(f64) -> f64,
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    match number_arg(mem, function, arg)? {
+        Numeric::Integer(_) => Ok(arg.get(mem)),
+        Numeric::Float(f) if f.is_finite() => exact_integer(mem, apply(f)),
+        Numeric::Float(f) => Err(err_domain(&format!("{}() of {}", function, f))),
+    }
+}
+
+/// Return the integer a finite, whole Float is equal to, as a NumberObject if it is too large for
+/// a 64 bit integer
+fn exact_integer<'guard>(
+    mem: &'guard MutatorView,
+    f: f64,
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    // isize::MAX rounds up to 2^63, which is itself out of range
+    if f >= isize::MIN as f64 && f < isize::MAX as f64 {
+        return Numeric::Integer(f as isize).as_tagged(mem);
+    }
+
+    // At least 2^63 in magnitude, so the 53 bit mantissa is shifted left by at least 11 bits
+    let bits = f.abs().to_bits();
+    let shift = ((bits >> 52) & 0x7ff) as usize - 1075;
+    let mantissa = (bits & ((1 << 52) - 1)) | (1 << 52);
+
+    let mut digits = vec![0; shift / 64 + 2];
+    digits[shift / 64] = mantissa << (shift % 64);
+    if shift % 64 > 0 {
+        digits[shift / 64 + 1] = mantissa >> (64 - shift % 64);
+    }
+
+    NumberObject::alloc_tagged(mem, f < 0.0, &digits)
+}
+
+/// floor(x): the largest integer not greater than x
+fn floor<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    to_integer(mem, "floor", &args[0], f64::floor)
+}
+
+/// ceil(x): the smallest integer not less than x
+fn ceil<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    to_integer(mem, "ceil", &args[0], f64::ceil)
+}
+
+/// round(x): the nearest integer to x, rounding halves away from zero
+fn round<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    to_integer(mem, "round", &args[0], f64::round)
+}
+
+/// Apply a function of one real number, with no restriction on its domain
+fn real_fn<'guard>(
+    mem: &'guard MutatorView,
+    function: &str,
+    arg: &TaggedCellPtr,
+    apply: fn(f64) -> f64,
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let x = number_arg(mem, function, arg)?.as_f64();
+    Float::alloc_tagged(mem, apply(x))
+}
+
+/// Apply an inverse sine or cosine, whose domain is -1 to 1
+fn inverse_fn<'guard>(
+    mem: &'guard MutatorView,
+    function: &str,
+    arg: &TaggedCellPtr,
+    apply: fn(f64) -> f64,
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let x = number_arg(mem, function, arg)?.as_f64();
+
+    if !(-1.0..=1.0).contains(&x) {
+        return Err(err_domain(&format!(
+            "{}() of {}, which is not between -1 and 1",
+            function, x
+        )));
+    }
+
+    Float::alloc_tagged(mem, apply(x))
+}
+
+/// sin(x): the sine of x radians
+fn sin<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    real_fn(mem, "sin", &args[0], f64::sin)
+}
+
+/// cos(x): the cosine of x radians
+fn cos<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    real_fn(mem, "cos", &args[0], f64::cos)
+}
+
+/// tan(x): the tangent of x radians
+fn tan<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    real_fn(mem, "tan", &args[0], f64::tan)
+}
+
+/// asin(x): the angle in radians, from -pi/2 to pi/2, whose sine is x
+fn asin<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    inverse_fn(mem, "asin", &args[0], f64::asin)
+}
+
+/// acos(x): the angle in radians, from 0 to pi, whose cosine is x
+fn acos<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    inverse_fn(mem, "acos", &args[0], f64::acos)
+}
+
+/// atan(x): the angle in radians, from -pi/2 to pi/2, whose tangent is x
+fn atan<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    real_fn(mem, "atan", &args[0], f64::atan)
+}
+
+/// atan2(y, x): the angle in radians, from -pi to pi, of the point (x, y)
+fn atan2<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let y = number_arg(mem, "atan2", &args[0])?.as_f64();
+    let x = number_arg(mem, "atan2", &args[1])?.as_f64();
+    Float::alloc_tagged(mem, y.atan2(x))
+}
+
+/// gcd(a, b): the greatest common divisor of two integers, which is never negative
+fn gcd<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let mut a = integer_arg(mem, "gcd", &args[0])?.unsigned_abs();
+    let mut b = integer_arg(mem, "gcd", &args[1])?.unsigned_abs();
+
+    while b != 0 {
+        let remainder = a % b;
+        a = b;
+        b = remainder;
+    }
+
+    // Only the gcd of isize::MIN with itself or zero is out of range
+    match isize::try_from(a) {
+        Ok(n) => Numeric::Integer(n).as_tagged(mem),
+        Err(_) => NumberObject::alloc_tagged(mem, false, &[a as u64]),
+    }
+}
+
+/// divmod(a, b): the pair (quotient . remainder) of dividing a by b, the quotient rounded down
+/// as for the `%` operator
+fn divmod<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let num = number_arg(mem, "divmod", &args[0])?;
+    let denom = number_arg(mem, "divmod", &args[1])?;
+
+    if denom.as_f64() == 0.0 {
+        return Err(err_domain("divmod() by zero"));
+    }
+
+    let (quotient, remainder) = match (num, denom) {
+        (Numeric::Integer(n), Numeric::Integer(d)) => match (floor_div(n, d), floor_mod(n, d)) {
+            (Some(q), Some(r)) => (Numeric::Integer(q), Numeric::Integer(r)),
+            _ => return Err(err_eval("Integer overflow in divmod()")),
+        },
+        (n, d) => {
+            let (n, d) = (n.as_f64(), d.as_f64());
+            let r = floor_mod_float(n, d);
+            // n - r is a whole multiple of d, up to rounding error
+            (Numeric::Float(((n - r) / d).round()), Numeric::Float(r))
+        }
+    };
+
+    cons(mem, quotient.as_tagged(mem)?, remainder.as_tagged(mem)?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::error::ErrorKind;
+    use crate::memory::{Memory, Mutator};
+    use crate::number::NumberObject;
+    use crate::tagged_ptr::{TaggedPtr, Value};
+
+    /// Call the math function `name` and return its result as printed
+    fn call<'guard>(
+        mem: &'guard MutatorView,
+        globals: &Dict,
+        name: &str,
+        args: &[TaggedScopedPtr<'guard>],
+    ) -> Result<String, RuntimeError> {
+        let native = match *globals.lookup(mem, mem.lookup_sym(name))? {
+            Value::NativeFunction(native) => native,
+            _ => panic!("{} is not a native function", name),
+        };
+
+        let args: Vec<TaggedCellPtr> = args
+            .iter()
+            .map(|arg| TaggedCellPtr::new_with(*arg))
+            .collect();
+        Ok(format!("{}", native.call(mem, &args)?))
+    }
+
+    /// Return true if calling the math function `name` is a domain error
+    fn domain_error<'guard>(
+        mem: &'guard MutatorView,
+        globals: &Dict,
+        name: &str,
+        args: &[TaggedScopedPtr<'guard>],
+    ) -> bool {
+        match call(mem, globals, name, args) {
+            Err(error) => matches!(error.error_kind(), ErrorKind::DomainError(_)),
+            Ok(_) => false,
+        }
+    }
+
+    #[test]
+    fn math_functions_mix_integers_and_floats() {
+        let mem = Memory::new();
+
+        struct Test {}
+        impl Mutator for Test {
+            type Input = ();
+            type Output = ();
+
+            fn run(
+                &self,
+                mem: &MutatorView,
+                _input: Self::Input,
+            ) -> Result<Self::Output, RuntimeError> {
+                let globals = Dict::alloc(mem)?;
+                install(mem, &globals, &*Thread::alloc(mem)?)?;
+
+                let int = |n| TaggedScopedPtr::new(mem, TaggedPtr::number(n));
+                let float = |f| Float::alloc_tagged(mem, f).unwrap();
+
+                assert!(call(mem, &globals, "abs", &[int(-3)])? == "3");
+                assert!(call(mem, &globals, "abs", &[float(-1.5)])? == "1.5");
+                assert!(call(mem, &globals, "min", &[int(3), float(2.5), int(7)])? == "2.5");
+                assert!(call(mem, &globals, "max", &[int(3), float(2.5), int(7)])? == "7");
+
+                assert!(call(mem, &globals, "pow", &[int(2), int(10)])? == "1024");
+                assert!(call(mem, &globals, "pow", &[int(2), int(-1)])? == "0.5");
+                assert!(call(mem, &globals, "sqrt", &[int(16)])? == "4.0");
+
+                assert!(call(mem, &globals, "floor", &[float(-1.5)])? == "-2");
+                assert!(call(mem, &globals, "ceil", &[float(-1.5)])? == "-1");
+                assert!(call(mem, &globals, "round", &[float(2.5)])? == "3");
+                assert!(call(mem, &globals, "round", &[int(7)])? == "7");
+
+                // Floats beyond 64 bit integers are converted exactly rather than clamped
+                assert!(call(mem, &globals, "floor", &[float(1e20)])? == "100000000000000000000");
+                assert!(call(mem, &globals, "ceil", &[float(-1e20)])? == "-100000000000000000000");
+                assert!(call(mem, &globals, "round", &[float(-9.3e18)])? == "-9300000000000000000");
+
+                assert!(call(mem, &globals, "cos", &[int(0)])? == "1.0");
+                assert!(call(mem, &globals, "atan2", &[int(0), int(-1)])? == "3.141592653589793");
+                let pi = globals.lookup(mem, mem.lookup_sym("pi"))?;
+                assert!(format!("{}", pi) == "3.141592653589793");
+
+                assert!(call(mem, &globals, "gcd", &[int(-12), int(18)])? == "6");
+                assert!(call(mem, &globals, "divmod", &[int(-7), int(2)])? == "(-4 . 1)");
+                assert!(call(mem, &globals, "divmod", &[float(7.5), int(-2)])? == "(-4.0 . -0.5)");
+
+                Ok(())
+            }
+        }
+
+        let test = Test {};
+        mem.mutate(&test, ()).unwrap();
+    }
+
+    #[test]
+    fn math_functions_accept_number_objects() {
+        let mem = Memory::new();
+
+        struct Test {}
+        impl Mutator for Test {
+            type Input = ();
+            type Output = ();
+
+            fn run(
+                &self,
+                mem: &MutatorView,
+                _input: Self::Input,
+            ) -> Result<Self::Output, RuntimeError> {
+                let globals = Dict::alloc(mem)?;
+                install(mem, &globals, &*Thread::alloc(mem)?)?;
+
+                let int = |n| TaggedScopedPtr::new(mem, TaggedPtr::number(n));
+                let big = |negative, digits: &[u64]| {
+                    NumberObject::alloc_tagged(mem, negative, digits).unwrap()
+                };
+
+                // -2^62 is too large to be inline but is still an exact integer
+                let large = big(true, &[1 << 62]);
+                assert!(format!("{}", large) == "-4611686018427387904");
+                assert!(call(mem, &globals, "abs", &[large])? == "4611686018427387904");
+                assert!(call(mem, &globals, "min", &[int(1), large])? == "-4611686018427387904");
+                assert!(call(mem, &globals, "max", &[int(1), large])? == "1");
+                assert!(call(mem, &globals, "gcd", &[large, int(6)])? == "2");
+                assert!(
+                    call(mem, &globals, "divmod", &[large, int(3)])?
+                        == "(-1537228672809129302 . 2)"
+                );
+                assert!(call(mem, &globals, "pow", &[int(2), int(62)])? == "4611686018427387904");
+
+                // 2^64 is only approximated by a Float
+                let huge = big(false, &[0, 1]);
+                assert!(format!("{}", huge) == "18446744073709551616");
+                assert!(call(mem, &globals, "max", &[int(1), huge])? == "18446744073709551616");
+                assert!(call(mem, &globals, "abs", &[huge])? == "1.8446744073709552e19");
+                assert!(call(mem, &globals, "gcd", &[huge, int(2)]).is_err());
+
+                // -2^63 is the one integer whose magnitude does not fit back in 64 bits
+                let min = big(true, &[1 << 63]);
+                assert!(call(mem, &globals, "gcd", &[min, int(0)])? == "9223372036854775808");
+                assert!(call(mem, &globals, "gcd", &[min, min])? == "9223372036854775808");
+
+                Ok(())
+            }
+        }
+
+        let test = Test {};
+        mem.mutate(&test, ()).unwrap();
+    }
+
+    #[test]
+    fn arguments_outside_the_domain_are_errors() {
+        let mem = Memory::new();
+
+        struct Test {}
+        impl Mutator for Test {
+            type Input = ();
+            type Output = ();
+
+            fn run(
+                &self,
+                mem: &MutatorView,
+                _input: Self::Input,
+            ) -> Result<Self::Output, RuntimeError> {
+                let globals = Dict::alloc(mem)?;
+                install(mem, &globals, &*Thread::alloc(mem)?)?;
+
+                let int = |n| TaggedScopedPtr::new(mem, TaggedPtr::number(n));
+                let float = |f| Float::alloc_tagged(mem, f).unwrap();
+
+                let nan = float(f64::NAN);
+
+                assert!(domain_error(mem, &globals, "sqrt", &[int(-1)]));
+                assert!(domain_error(mem, &globals, "asin", &[int(2)]));
+                assert!(domain_error(mem, &globals, "pow", &[int(0), int(-1)]));
+                assert!(domain_error(mem, &globals, "pow", &[int(-8), float(0.5)]));
+                assert!(domain_error(mem, &globals, "floor", &[nan]));
+                assert!(domain_error(mem, &globals, "divmod", &[int(1), int(0)]));
+                assert!(domain_error(mem, &globals, "max", &[int(1), nan]));
+
+                // Integer overflow and the wrong type of argument are errors too
+                assert!(call(mem, &globals, "pow", &[int(2), int(100)]).is_err());
+                assert!(call(mem, &globals, "gcd", &[float(1.0), int(2)]).is_err());
+                assert!(call(mem, &globals, "abs", &[mem.nil()]).is_err());
+
+                Ok(())
+            }
+        }
+
+        let test = Test {};
+        mem.mutate(&test, ()).unwrap();
+    }
+}
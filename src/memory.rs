@@ -2,11 +2,16 @@
 ///
 /// Defines Stack, Heap and Memory types, and a MemoryView type that gives a mutator a safe
 /// view into the stack and heap.
+use std::cell::Cell;
+use std::mem::size_of;
+
 use crate::heap::ImmixHeap;
 use crate::allocator::{AllocObject, AllocRaw, ArraySize};
 use crate::raw_ptr::RawPtr;
 
-use crate::error::RuntimeError;
+use crate::arena::Arena;
+use crate::error::{ErrorKind, RuntimeError};
+use crate::gc::{ActiveRegistry, GcRegistry};
 use crate::header::{ObjectHeader, TypeList};
 use crate::ptr_ops::ScopedRef;
 use crate::safe_ptr::{MutatorScope, ScopedPtr, TaggedScopedPtr};
@@ -57,11 +62,48 @@ impl<'memory> MutatorView<'memory> {
     }
     // ANCHOR_END: DefMutatorViewAllocTagged
 
+    /// Write an object into the compilation arena and return a scope-limited pointer to it. The
+    /// object will never be collected; it lives until the arena is released.
+    pub fn alloc_in_arena<T>(&self, object: T) -> Result<ScopedPtr<'_, T>, RuntimeError>
+    where
+        T: AllocObject<TypeList>,
+    {
+        self.heap.reserve_arena(size_of::<T>())?;
+        Ok(ScopedPtr::new(
+            self,
+            self.heap.arena.alloc(object)?.scoped_ref(self),
+        ))
+    }
+
+    /// Write an object into the compilation arena and return a scope-limited runtime-tagged
+    /// pointer to it
+    pub fn alloc_tagged_in_arena<T>(&self, object: T) -> Result<TaggedScopedPtr<'_>, RuntimeError>
+    where
+        FatPtr: From<RawPtr<T>>,
+        T: AllocObject<TypeList>,
+    {
+        self.heap.reserve_arena(size_of::<T>())?;
+        let ptr = self.heap.arena.alloc(object)?;
+        Ok(TaggedScopedPtr::new(self, TaggedPtr::from(FatPtr::from(ptr))))
+    }
+
     /// Make space for an array of bytes
     pub fn alloc_array(&self, capacity: ArraySize) -> Result<RawPtr<u8>, RuntimeError> {
         self.heap.alloc_array(capacity)
     }
 
+    /// Make space for an array of bytes in the compilation arena
+    pub fn alloc_array_in_arena(&self, capacity: ArraySize) -> Result<RawPtr<u8>, RuntimeError> {
+        self.heap.reserve_arena(capacity as usize)?;
+        Ok(self.heap.arena.alloc_array(capacity)?)
+    }
+
+    /// Return true, once, after enough has been allocated that the heap wants a collection. The
+    /// caller should run one at its next safe point.
+    pub fn take_collection_request(&self) -> bool {
+        self.heap.collection_requested.replace(false)
+    }
+
     /// Return a nil-initialized runtime-tagged pointer
     pub fn nil(&self) -> TaggedScopedPtr<'_> {
         TaggedScopedPtr::new(self, TaggedPtr::nil())
@@ -75,20 +117,97 @@ impl<'memory> MutatorScope for MutatorView<'memory> {}
 pub type HeapStorage = ImmixHeap<ObjectHeader>;
 // ANCHOR_END: DefHeapStorage
 
+/// Number of bytes allocated between the collections the heap asks for
+const COLLECTION_INTERVAL: usize = 1024 * 1024;
+
 /// Heap memory types.
 // ANCHOR: DefHeap
 struct Heap {
     heap: HeapStorage,
     syms: SymbolMap,
+    /// Region for compile-time objects that are freed all at once
+    arena: Arena,
+    /// Count of bytes handed out so far, including object headers
+    allocated: Cell<usize>,
+    /// Optional ceiling on the number of bytes that may be allocated, counting the blocks of the
+    /// arena and of the interned Symbols
+    limit: Option<usize>,
+    /// Count of allocated bytes past which the heap asks for a collection
+    next_collection: Cell<usize>,
+    /// Set when the heap has asked for a collection, until a safe point runs it
+    collection_requested: Cell<bool>,
+    /// The collector's remembered set and WeakRef and finalizer registrations for this heap
+    gc: GcRegistry,
 }
 // ANCHOR_END: DefHeap
 
 impl Heap {
     fn new() -> Heap {
+        Heap::with_limit(None)
+    }
+
+    fn with_limit(limit: Option<usize>) -> Heap {
         Heap {
             heap: HeapStorage::new(),
             syms: SymbolMap::new(),
+            arena: Arena::new(),
+            allocated: Cell::new(0),
+            limit,
+            next_collection: Cell::new(COLLECTION_INTERVAL),
+            collection_requested: Cell::new(false),
+            gc: GcRegistry::new(),
+        }
+    }
+
+    /// Account for an allocation of `size_bytes` plus its header, returning `OutOfMemory` if
+    /// that would take the heap past its limit.
+    ///
+    /// The heap does not reclaim memory yet, so a collection cannot make room and there is none
+    /// to attempt before giving up: the limit is a hard ceiling on everything allocated during a
+    /// run. Collections are still asked for every `COLLECTION_INTERVAL` bytes so that WeakRefs
+    /// are cleared and finalizers run.
+    fn reserve(&self, size_bytes: usize) -> Result<(), RuntimeError> {
+        let requested = size_bytes
+            .checked_add(size_of::<ObjectHeader>())
+            .and_then(|size| self.allocated.get().checked_add(size))
+            .ok_or(RuntimeError::new(ErrorKind::BadAllocationRequest))?;
+
+        self.check_limit(requested)?;
+        self.allocated.set(requested);
+
+        if requested >= self.next_collection.get() {
+            self.next_collection.set(requested + COLLECTION_INTERVAL);
+            self.collection_requested.set(true);
         }
+
+        Ok(())
+    }
+
+    /// Account for any new arena block that an allocation of `size_bytes` would need
+    fn reserve_arena(&self, size_bytes: usize) -> Result<(), RuntimeError> {
+        let requested = self
+            .allocated
+            .get()
+            .checked_add(self.arena.growth_for(size_bytes))
+            .ok_or(RuntimeError::new(ErrorKind::BadAllocationRequest))?;
+
+        self.check_limit(requested)
+    }
+
+    /// Return `OutOfMemory` if `allocated` heap bytes, along with the blocks of the arena and of
+    /// the interned Symbols, would be past the limit
+    fn check_limit(&self, allocated: usize) -> Result<(), RuntimeError> {
+        if let Some(limit) = self.limit {
+            let in_use = allocated
+                .checked_add(self.arena.capacity() + self.syms.capacity())
+                .ok_or(RuntimeError::new(ErrorKind::BadAllocationRequest))?;
+
+            if in_use > limit {
+                return Err(RuntimeError::new(ErrorKind::OutOfMemory));
+            }
+        }
+
+        Ok(())
     }
 
     /// Get a Symbol pointer from its name
@@ -104,6 +223,7 @@ impl Heap {
     where
         T: AllocObject<TypeList>,
     {
+        self.reserve(size_of::<T>())?;
         Ok(self.heap.alloc(object)?)
     }
     // ANCHOR_END: DefHeapAlloc
@@ -115,11 +235,13 @@ impl Heap {
         FatPtr: From<RawPtr<T>>,
         T: AllocObject<TypeList>,
     {
+        self.reserve(size_of::<T>())?;
         Ok(TaggedPtr::from(FatPtr::from(self.heap.alloc(object)?)))
     }
     // ANCHOR_END: DefHeapAllocTagged
 
     fn alloc_array(&self, capacity: ArraySize) -> Result<RawPtr<u8>, RuntimeError> {
+        self.reserve(capacity as usize)?;
         Ok(self.heap.alloc_array(capacity)?)
     }
 }
@@ -137,9 +259,33 @@ impl Memory {
         Memory { heap: Heap::new() }
     }
 
+    /// Instantiate a new memory environment that will refuse to allocate more than `limit` bytes,
+    /// counting the compilation arena and interned Symbols. Allocations past the limit fail with
+    /// `ErrorKind::OutOfMemory`.
+    pub fn with_limit(limit: usize) -> Memory {
+        Memory {
+            heap: Heap::with_limit(Some(limit)),
+        }
+    }
+
+    /// Return the number of bytes allocated so far
+    pub fn allocated(&self) -> usize {
+        self.heap.allocated.get()
+    }
+
+    /// Free every object in the compilation arena in one shot. Nothing reachable from the heap
+    /// may still point into the arena when this is called; WeakRefs to arena objects are cleared.
+    pub fn release_arena(&mut self) {
+        let arena = &self.heap.arena;
+        self.heap.gc.forget(|addr| arena.contains(addr));
+
+        self.heap.arena.reset();
+    }
+
     /// Run a mutator process
     // ANCHOR: DefMemoryMutate
     pub fn mutate<M: Mutator>(&self, m: &M, input: M::Input) -> Result<M::Output, RuntimeError> {
+        let _registry = ActiveRegistry::enter(&self.heap.gc);
         let mut guard = MutatorView::new(self);
         m.run(&mut guard, input)
     }
@@ -158,3 +304,195 @@ pub trait Mutator: Sized {
     // function to return iterator that iterates over roots
 }
 // ANCHOR_END: DefMutator
+
+#[cfg(test)]
+mod test {
+    use super::{Memory, Mutator, MutatorView, COLLECTION_INTERVAL};
+    use crate::allocator::ArraySize;
+    use crate::constants::BLOCK_SIZE;
+    use crate::error::{ErrorKind, RuntimeError};
+    use crate::gc::full_collection;
+    use crate::list::List;
+    use crate::safe_ptr::TaggedScopedPtr;
+    use crate::tagged_ptr::{TaggedPtr, Value};
+    use crate::text::Text;
+    use crate::weak::WeakRef;
+
+    #[test]
+    fn memory_limit_out_of_memory() {
+        let mem = Memory::with_limit(1024);
+
+        struct Test {}
+        impl Mutator for Test {
+            type Input = ();
+            type Output = ();
+
+            fn run(
+                &self,
+                view: &MutatorView,
+                _input: Self::Input,
+            ) -> Result<Self::Output, RuntimeError> {
+                // fits within the limit
+                let small = Text::new_from_str(view, "hello")?;
+                view.alloc_tagged(small)?;
+
+                // does not
+                let big = "x".repeat(2048);
+                match Text::new_from_str(view, &big) {
+                    Ok(_) => panic!("Allocation should have exceeded the heap limit!"),
+                    Err(e) => assert!(*e.error_kind() == ErrorKind::OutOfMemory),
+                }
+
+                Ok(())
+            }
+        }
+
+        let test = Test {};
+        mem.mutate(&test, ()).unwrap();
+    }
+
+    #[test]
+    fn memory_limit_is_a_hard_ceiling() {
+        let mem = Memory::with_limit(4096);
+
+        struct Test {}
+        impl Mutator for Test {
+            type Input = ();
+            type Output = ();
+
+            fn run(
+                &self,
+                view: &MutatorView,
+                _input: Self::Input,
+            ) -> Result<Self::Output, RuntimeError> {
+                let roots = [];
+
+                // garbage that nothing reaches still counts, since nothing is reclaimed yet
+                let make_garbage = || -> Result<(), RuntimeError> {
+                    for _ in 0..200 {
+                        view.alloc_tagged(Text::new_from_str(view, "garbage")?)?;
+                    }
+                    Ok(())
+                };
+
+                match make_garbage() {
+                    Ok(_) => panic!("Garbage should have exceeded the heap limit!"),
+                    Err(e) => assert!(*e.error_kind() == ErrorKind::OutOfMemory),
+                }
+
+                // and a collection does not make room for more
+                full_collection(view, &roots);
+                match Text::new_from_str(view, "more") {
+                    Ok(_) => panic!("Allocation after a collection should still fail!"),
+                    Err(e) => assert!(*e.error_kind() == ErrorKind::OutOfMemory),
+                }
+
+                Ok(())
+            }
+        }
+
+        let test = Test {};
+        mem.mutate(&test, ()).unwrap();
+        assert!(mem.allocated() <= 4096);
+    }
+
+    #[test]
+    fn memory_limit_counts_symbols() {
+        let mem = Memory::with_limit(BLOCK_SIZE / 2);
+
+        struct Test {}
+        impl Mutator for Test {
+            type Input = ();
+            type Output = ();
+
+            fn run(
+                &self,
+                view: &MutatorView,
+                _input: Self::Input,
+            ) -> Result<Self::Output, RuntimeError> {
+                Text::new_from_str(view, "fits")?;
+
+                // the first Symbol takes a whole block, which is more than the limit
+                view.lookup_sym("interned");
+                match Text::new_from_str(view, "no longer fits") {
+                    Ok(_) => panic!("Symbols should count toward the heap limit!"),
+                    Err(e) => assert!(*e.error_kind() == ErrorKind::OutOfMemory),
+                }
+
+                Ok(())
+            }
+        }
+
+        let test = Test {};
+        mem.mutate(&test, ()).unwrap();
+    }
+
+    #[test]
+    fn memory_asks_for_collections() {
+        let mem = Memory::new();
+
+        struct Test {}
+        impl Mutator for Test {
+            type Input = ();
+            type Output = ();
+
+            fn run(
+                &self,
+                view: &MutatorView,
+                _input: Self::Input,
+            ) -> Result<Self::Output, RuntimeError> {
+                assert!(!view.take_collection_request());
+
+                view.alloc_array(COLLECTION_INTERVAL as ArraySize)?;
+                assert!(view.take_collection_request());
+
+                // the request is handed out once
+                assert!(!view.take_collection_request());
+
+                Ok(())
+            }
+        }
+
+        let test = Test {};
+        mem.mutate(&test, ()).unwrap();
+    }
+
+    #[test]
+    fn release_arena_clears_weak_refs_into_it() {
+        let mut mem = Memory::new();
+
+        struct Compile {}
+        impl Mutator for Compile {
+            type Input = ();
+            type Output = TaggedPtr;
+
+            fn run(&self, view: &MutatorView, _input: ()) -> Result<TaggedPtr, RuntimeError> {
+                let items = [view.lookup_sym("literal")];
+                let list = List::from_slice_in_arena(view, &items)?;
+                let weak = WeakRef::alloc(view, list.as_tagged(view))?;
+                assert!(!weak.is_cleared());
+
+                Ok(weak.as_tagged(view).get_ptr())
+            }
+        }
+
+        struct Check {}
+        impl Mutator for Check {
+            type Input = TaggedPtr;
+            type Output = bool;
+
+            fn run(&self, view: &MutatorView, weak: TaggedPtr) -> Result<bool, RuntimeError> {
+                match *TaggedScopedPtr::new(view, weak) {
+                    Value::WeakRef(weak) => Ok(weak.is_cleared()),
+                    _ => panic!("Expected a WeakRef"),
+                }
+            }
+        }
+
+        let weak = mem.mutate(&Compile {}, ()).unwrap();
+        assert!(!mem.mutate(&Check {}, weak).unwrap());
+
+        mem.release_arena();
+        assert!(mem.mutate(&Check {}, weak).unwrap());
+    }
+}
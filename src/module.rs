@@ -0,0 +1,180 @@
+/// Modules loaded by `import`
+///
+/// Each module's top-level code runs once, the first time it is imported, and binds its globals in
+/// a Dict of its own. Importing it again, from any other module, gives the same `Module` object,
+/// whose members are the values bound in that Dict.
+///
+/// Native modules, such as `math` and `io`, are imported by name rather than by path. They have no
+/// top-level code; their members are installed by a Rust function when first imported.
+use std::cell::Cell;
+use std::fmt;
+
+use crate::builtins;
+use crate::container::{HashIndexedAnyContainer, SliceableContainer};
+use crate::dict::Dict;
+use crate::error::{err_eval, RuntimeError};
+use crate::fs;
+use crate::function::Function;
+use crate::io;
+use crate::json;
+use crate::math;
+use crate::memory::MutatorView;
+use crate::printer::Print;
+use crate::safe_ptr::{CellPtr, MutatorScope, ScopedPtr, TaggedCellPtr, TaggedScopedPtr};
+use crate::tagged_ptr::Value;
+use crate::text::Text;
+use crate::trace::{visit_cell, visit_tagged_cell, Trace, Visitor};
+use crate::vm::Thread;
+
+/// Binds the members of a native module in its globals Dict, for the Thread importing it
+pub type InstallFn = fn(&MutatorView, &Dict, &Thread) -> Result<(), RuntimeError>;
+
+/// The native modules, by the name they are imported with
+const NATIVE_MODULES: [(&str, InstallFn); 4] = [
+    ("fs", fs::install),
+    ("io", io::install),
+    ("json", json::install),
+    ("math", math::install),
+];
+
+/// Return true if `name` is imported as a native module rather than from a file
+pub fn is_native(name: &str) -> bool {
+    NATIVE_MODULES.iter().any(|(native, _)| *native == name)
+}
+
+/// A module, named by the canonical path of its source file or by its native module name
+// ANCHOR: DefModule
+pub struct Module {
+    path: CellPtr<Text>,
+    globals: CellPtr<Dict>,
+    /// The top-level code, or nil for a native module
+    function: TaggedCellPtr,
+    loaded: Cell<bool>,
+}
+// ANCHOR_END: DefModule
+
+impl Module {
+    /// Allocate a new Module whose top-level code is `function`, with a globals Dict of its own
+    /// holding the builtin functions. The Function and every Function nested in its literals bind
+    /// their globals in that Dict.
+    pub fn alloc<'guard>(
+        mem: &'guard MutatorView,
+        path: &str,
+        function: ScopedPtr<'guard, Function>,
+    ) -> Result<ScopedPtr<'guard, Module>, RuntimeError> {
+        let globals = Dict::alloc(mem)?;
+        builtins::install(mem, &globals)?;
+
+        let mut pending = vec![function];
+        while let Some(function) = pending.pop() {
+            function.set_globals(mem, globals);
+
+            function.code(mem).literals().access_slice(mem, |items| {
+                for item in items.iter() {
+                    if let Value::Function(f) = *item.get(mem) {
+                        pending.push(f);
+                    }
+                }
+            });
+        }
+
+        let path = mem.alloc(Text::new_from_str(mem, path)?)?;
+
+        mem.alloc(Module {
+            path: CellPtr::new_with(path),
+            globals: CellPtr::new_with(globals),
+            function: TaggedCellPtr::new_with(function.as_tagged(mem)),
+            loaded: Cell::new(false),
+        })
+    }
+
+    /// Allocate the native module imported with `name` by `thread`, with its members installed,
+    /// or return None if there is no such native module
+    pub fn alloc_native<'guard>(
+        mem: &'guard MutatorView,
+        name: &str,
+        thread: &Thread,
+    ) -> Result<Option<ScopedPtr<'guard, Module>>, RuntimeError> {
+        let install = match NATIVE_MODULES.iter().find(|(native, _)| *native == name) {
+            Some((_, install)) => install,
+            None => return Ok(None),
+        };
+
+        let globals = Dict::alloc(mem)?;
+        install(mem, &globals, thread)?;
+
+        let path = mem.alloc(Text::new_from_str(mem, name)?)?;
+
+        let module = mem.alloc(Module {
+            path: CellPtr::new_with(path),
+            globals: CellPtr::new_with(globals),
+            function: TaggedCellPtr::new_nil(),
+            loaded: Cell::new(true),
+        })?;
+        Ok(Some(module))
+    }
+
+    /// Return the canonical path of the module's source file
+    pub fn path<'guard>(&self, guard: &'guard dyn MutatorScope) -> String {
+        String::from(self.path.get(guard).as_str(guard))
+    }
+
+    /// Return the module's top-level code, or None for a native module
+    pub fn function<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+    ) -> Option<ScopedPtr<'guard, Function>> {
+        match *self.function.get(guard) {
+            Value::Function(function) => Some(function),
+            _ => None,
+        }
+    }
+
+    /// Return the Dict the module's globals are bound in
+    pub fn globals<'guard>(&self, guard: &'guard dyn MutatorScope) -> ScopedPtr<'guard, Dict> {
+        self.globals.get(guard)
+    }
+
+    /// Return true once the module's top-level code has started running
+    pub fn is_loaded(&self) -> bool {
+        self.loaded.get()
+    }
+
+    /// Record that the module's top-level code has started running, so it is not run again
+    pub fn set_loaded(&self) {
+        self.loaded.set(true);
+    }
+
+    /// Return the value the module bound to the global `name`
+    pub fn member<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+        name: TaggedScopedPtr<'guard>,
+    ) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+        self.globals(guard).lookup(guard, name).map_err(|_| {
+            err_eval(&format!(
+                "Module {} has no member {}",
+                self.path(guard),
+                name
+            ))
+        })
+    }
+}
+
+impl Print for Module {
+    fn print<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+        f: &mut fmt::Formatter,
+    ) -> fmt::Result {
+        write!(f, "(Module {})", self.path(guard))
+    }
+}
+
+impl Trace for Module {
+    fn trace<'guard>(&self, _guard: &'guard dyn MutatorScope, visit: &mut Visitor) {
+        visit_cell(&self.path, visit);
+        visit_cell(&self.globals, visit);
+        visit_tagged_cell(&self.function, visit);
+    }
+}
@@ -0,0 +1,106 @@
+/// Functions implemented in Rust that can be called from Chorus code like any other function
+use std::fmt;
+
+use crate::error::RuntimeError;
+use crate::memory::MutatorView;
+use crate::printer::Print;
+use crate::safe_ptr::{MutatorScope, ScopedPtr, TaggedCellPtr, TaggedScopedPtr};
+use crate::tagged_ptr::Value;
+use crate::trace::{visit_tagged_cell, Trace, Visitor};
+
+/// The signature of a native function. The arguments are the caller's argument registers.
+pub type NativeFn = for<'guard> fn(
+    &'guard MutatorView,
+    &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError>;
+
+/// A function object whose body is a Rust function
+// ANCHOR: DefNativeFunction
+pub struct NativeFunction {
+    /// name is a Symbol
+    name: TaggedCellPtr,
+    /// Number of arguments required to call the function
+    arity: u8,
+    /// Number of arguments the function accepts at most
+    max_arity: u8,
+    /// The Rust implementation
+    function: NativeFn,
+}
+// ANCHOR_END: DefNativeFunction
+
+impl NativeFunction {
+    /// Allocate a NativeFunction object on the heap
+    pub fn alloc<'guard>(
+        mem: &'guard MutatorView,
+        name: &str,
+        arity: u8,
+        function: NativeFn,
+    ) -> Result<ScopedPtr<'guard, NativeFunction>, RuntimeError> {
+        NativeFunction::alloc_variadic(mem, name, arity, arity, function)
+    }
+
+    /// Allocate a NativeFunction object that accepts between `arity` and `max_arity` arguments
+    pub fn alloc_variadic<'guard>(
+        mem: &'guard MutatorView,
+        name: &str,
+        arity: u8,
+        max_arity: u8,
+        function: NativeFn,
+    ) -> Result<ScopedPtr<'guard, NativeFunction>, RuntimeError> {
+        mem.alloc(NativeFunction {
+            name: TaggedCellPtr::new_with(mem.lookup_sym(name)),
+            arity,
+            max_arity,
+            function,
+        })
+    }
+
+    /// Return the function's name as a string slice
+    pub fn name<'guard>(&self, guard: &'guard dyn MutatorScope) -> &'guard str {
+        match *self.name.get(guard) {
+            Value::Symbol(s) => s.as_str(guard),
+            _ => "<native>",
+        }
+    }
+
+    /// Return the number of arguments the function requires
+    pub fn arity(&self) -> u8 {
+        self.arity
+    }
+
+    /// Return the number of arguments the function accepts at most
+    pub fn max_arity(&self) -> u8 {
+        self.max_arity
+    }
+
+    /// Return true if the function can be called with the given number of arguments
+    pub fn accepts(&self, arg_count: u8) -> bool {
+        arg_count >= self.arity && arg_count <= self.max_arity
+    }
+
+    /// Call the function. The caller must have checked the number of arguments with
+    /// `accepts()`.
+    pub fn call<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        args: &[TaggedCellPtr],
+    ) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+        (self.function)(mem, args)
+    }
+}
+
+impl Print for NativeFunction {
+    fn print<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+        f: &mut fmt::Formatter,
+    ) -> fmt::Result {
+        write!(f, "(NativeFunction {})", self.name(guard))
+    }
+}
+
+impl Trace for NativeFunction {
+    fn trace<'guard>(&self, _guard: &'guard dyn MutatorScope, visit: &mut Visitor) {
+        visit_tagged_cell(&self.name, visit);
+    }
+}
@@ -1,22 +1,306 @@
-/// An integer type - TODO
+/// Numbers that do not fit in a tagged pointer
+///
+/// Integers too large to be inline are boxed in a `NumberObject` and floating point numbers in a
+/// `Float` on the heap. Arithmetic on either goes through `Numeric`, which gives a float result if
+/// either side is a float.
+use std::cmp::Ordering;
 use std::fmt;
+use std::hash::{Hash, Hasher};
 
-use crate::array::Array;
+use crate::array::{Array, ArraySize};
+use crate::container::{Container, SliceableContainer, StackContainer};
+use crate::error::RuntimeError;
+use crate::hashable::Hashable;
+use crate::iter::{MAX_INLINE_INTEGER, MIN_INLINE_INTEGER};
+use crate::memory::MutatorView;
 use crate::printer::Print;
-use crate::safe_ptr::MutatorScope;
+use crate::safe_ptr::{MutatorScope, TaggedScopedPtr};
+use crate::tagged_ptr::{TaggedPtr, Value};
+use crate::trace::{Trace, Visitor};
 
-/// TODO A heap-allocated number
+/// A heap-allocated integer of any size, as a sign and a magnitude in 64 bit digits
 pub struct NumberObject {
-    _value: Array<u64>,
+    negative: bool,
+    /// The magnitude, least significant digit first and without leading zero digits
+    value: Array<u64>,
+}
+
+impl NumberObject {
+    /// Allocate a new NumberObject from a sign and a magnitude given least significant digit
+    /// first, returned as a tagged pointer
+    pub fn alloc_tagged<'guard>(
+        mem: &'guard MutatorView,
+        negative: bool,
+        digits: &[u64],
+    ) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+        // Trimming leading zeros gives every number one representation, and zero no sign
+        let length = digits
+            .iter()
+            .rposition(|digit| *digit != 0)
+            .map_or(0, |i| i + 1);
+
+        let value = Array::with_capacity(mem, length as ArraySize)?;
+        for digit in &digits[..length] {
+            StackContainer::push(&value, mem, *digit)?;
+        }
+
+        mem.alloc_tagged(NumberObject {
+            negative: negative && length > 0,
+            value,
+        })
+    }
+
+    /// Allocate a new NumberObject holding an integer, returned as a tagged pointer
+    pub fn alloc_from_isize<'guard>(
+        mem: &'guard MutatorView,
+        value: isize,
+    ) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+        NumberObject::alloc_tagged(mem, value < 0, &[value.unsigned_abs() as u64])
+    }
+
+    /// Return the number as an integer, or None if it is too large for one
+    pub fn as_isize<'guard>(&self, guard: &'guard dyn MutatorScope) -> Option<isize> {
+        let magnitude = self.value.access_slice(guard, |digits| match digits {
+            [] => Some(0),
+            [digit] => Some(*digit as i128),
+            _ => None,
+        })?;
+
+        match self.negative {
+            true => isize::try_from(-magnitude).ok(),
+            false => isize::try_from(magnitude).ok(),
+        }
+    }
+
+    /// Return the number as a float, rounded to one that can be represented
+    pub fn as_f64<'guard>(&self, guard: &'guard dyn MutatorScope) -> f64 {
+        let magnitude = self.value.access_slice(guard, |digits| {
+            digits
+                .iter()
+                .rev()
+                .fold(0.0, |high, digit| high * 2f64.powi(64) + *digit as f64)
+        });
+
+        match self.negative {
+            true => -magnitude,
+            false => magnitude,
+        }
+    }
+
+    /// Compare two numbers by sign and digit by digit
+    pub fn equals<'guard>(&self, guard: &'guard dyn MutatorScope, other: &NumberObject) -> bool {
+        self.negative == other.negative
+            && self.value.access_slice(guard, |digits| {
+                let digits = digits.to_vec();
+                other
+                    .value
+                    .access_slice(guard, |other_digits| digits[..] == other_digits[..])
+            })
+    }
 }
 
 impl Print for NumberObject {
+    fn print<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+        f: &mut fmt::Formatter,
+    ) -> fmt::Result {
+        // Divide the magnitude down into decimal chunks of 19 digits, the most a u64 holds
+        const CHUNK: u128 = 10_000_000_000_000_000_000;
+
+        let mut digits = self.value.access_slice(guard, |digits| digits.to_vec());
+        let mut chunks = Vec::new();
+        while !digits.is_empty() {
+            let mut remainder = 0u128;
+            for digit in digits.iter_mut().rev() {
+                let current = (remainder << 64) | *digit as u128;
+                *digit = (current / CHUNK) as u64;
+                remainder = current % CHUNK;
+            }
+            chunks.push(remainder);
+
+            while digits.last() == Some(&0) {
+                digits.pop();
+            }
+        }
+
+        if self.negative {
+            write!(f, "-")?;
+        }
+
+        match chunks.pop() {
+            Some(first) => write!(f, "{}", first)?,
+            None => write!(f, "0")?,
+        }
+
+        for chunk in chunks.iter().rev() {
+            write!(f, "{:019}", chunk)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Hashable for NumberObject {
+    fn hash<'guard, H: Hasher>(&self, guard: &'guard dyn MutatorScope, h: &mut H) {
+        self.negative.hash(h);
+        self.value.access_slice(guard, |digits| digits.hash(h))
+    }
+}
+
+impl Trace for NumberObject {
+    fn trace<'guard>(&self, _guard: &'guard dyn MutatorScope, visit: &mut Visitor) {
+        self.value.trace_backing(visit);
+    }
+}
+
+/// A heap-allocated double precision floating point number
+// ANCHOR: DefFloat
+pub struct Float {
+    value: f64,
+}
+// ANCHOR_END: DefFloat
+
+impl Float {
+    /// Allocate a new Float, returned as a tagged pointer
+    pub fn alloc_tagged<'guard>(
+        mem: &'guard MutatorView,
+        value: f64,
+    ) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+        mem.alloc_tagged(Float { value })
+    }
+
+    pub fn value(&self) -> f64 {
+        self.value
+    }
+}
+
+impl Print for Float {
     fn print<'guard>(
         &self,
         _guard: &'guard dyn MutatorScope,
         f: &mut fmt::Formatter,
     ) -> fmt::Result {
-        // TODO
-        write!(f, "NumberObject(nan)")
+        // Debug formatting keeps the fraction of a whole number, so 2.0 does not print as 2
+        write!(f, "{:?}", self.value)
+    }
+}
+
+/// A number in either of the representations arithmetic works on
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Numeric {
+    Integer(isize),
+    Float(f64),
+}
+
+impl Numeric {
+    /// Return the number a value holds, or None if it is not a number. A NumberObject too large
+    /// for an integer is rounded to the nearest Float, as there is no arithmetic on larger ones.
+    pub fn from_value(value: Value) -> Option<Numeric> {
+        match value {
+            Value::Number(n) => Some(Numeric::Integer(n)),
+            Value::NumberObject(n) => match n.as_isize(&n) {
+                Some(i) => Some(Numeric::Integer(i)),
+                None => Some(Numeric::Float(n.as_f64(&n))),
+            },
+            Value::Float(f) => Some(Numeric::Float(f.value())),
+            _ => None,
+        }
+    }
+
+    /// Return the number as a float, rounding integers too large to be represented exactly
+    pub fn as_f64(self) -> f64 {
+        match self {
+            Numeric::Integer(n) => n as f64,
+            Numeric::Float(f) => f,
+        }
+    }
+
+    /// Order two numbers. Integers are compared exactly; NaN is not ordered against anything.
+    pub fn compare(self, other: Numeric) -> Option<Ordering> {
+        match (self, other) {
+            (Numeric::Integer(l), Numeric::Integer(r)) => Some(l.cmp(&r)),
+            (l, r) => l.as_f64().partial_cmp(&r.as_f64()),
+        }
+    }
+
+    /// Return the number as a tagged pointer, allocating a Float if it is one and a NumberObject
+    /// for an integer outside the inline integer range
+    pub fn as_tagged<'guard>(
+        self,
+        mem: &'guard MutatorView,
+    ) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+        match self {
+            Numeric::Integer(n) if n >= MIN_INLINE_INTEGER && n <= MAX_INLINE_INTEGER => {
+                Ok(TaggedScopedPtr::new(mem, TaggedPtr::number(n)))
+            }
+            Numeric::Integer(n) => NumberObject::alloc_from_isize(mem, n),
+            Numeric::Float(f) => Float::alloc_tagged(mem, f),
+        }
+    }
+}
+
+/// The quotient of an integer division rounded down, or None if it overflows or divides by zero
+pub fn floor_div(num: isize, denom: isize) -> Option<isize> {
+    let quotient = num.checked_div(denom)?;
+
+    match num % denom != 0 && (num < 0) != (denom < 0) {
+        true => Some(quotient - 1),
+        false => Some(quotient),
+    }
+}
+
+/// The remainder of `floor_div`, which takes the sign of the denominator, or None if it divides
+/// by zero
+pub fn floor_mod(num: isize, denom: isize) -> Option<isize> {
+    let remainder = num.checked_rem(denom)?;
+
+    match remainder != 0 && (remainder < 0) != (denom < 0) {
+        true => Some(remainder + denom),
+        false => Some(remainder),
+    }
+}
+
+/// The floating point counterpart of `floor_mod`
+pub fn floor_mod_float(num: f64, denom: f64) -> f64 {
+    let remainder = num % denom;
+
+    match remainder != 0.0 && (remainder < 0.0) != (denom < 0.0) {
+        true => remainder + denom,
+        false => remainder,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn floor_division_rounds_toward_negative_infinity() {
+        assert!(floor_div(7, 2) == Some(3));
+        assert!(floor_div(-7, 2) == Some(-4));
+        assert!(floor_div(7, -2) == Some(-4));
+        assert!(floor_div(-7, -2) == Some(3));
+        assert!(floor_div(1, 0) == None);
+        assert!(floor_div(isize::MIN, -1) == None);
+
+        assert!(floor_mod(7, 2) == Some(1));
+        assert!(floor_mod(-7, 2) == Some(1));
+        assert!(floor_mod(7, -2) == Some(-1));
+        assert!(floor_mod(-6, 2) == Some(0));
+        assert!(floor_mod(1, 0) == None);
+
+        assert!(floor_mod_float(-7.5, 2.0) == 0.5);
+        assert!(floor_mod_float(7.5, -2.0) == -0.5);
+    }
+
+    #[test]
+    fn mixed_numbers_compare_by_value() {
+        let two = Numeric::Integer(2);
+        let half = Numeric::Float(0.5);
+
+        assert!(two.compare(half) == Some(Ordering::Greater));
+        assert!(half.compare(Numeric::Float(0.5)) == Some(Ordering::Equal));
+        assert!(two.compare(Numeric::Float(f64::NAN)) == None);
     }
 }
@@ -0,0 +1,726 @@
+/// Optimization passes over generated code
+///
+/// The passes work on the `Vec<Opcode>` and literal list held by the `Generator`, before anything
+/// is copied to the heap, and run in this order:
+///
+/// 1. `LoadLiteral`s of small integers become `LoadInteger`s
+/// 2. constant values and register copies are tracked through each basic block, folding
+///    arithmetic, comparisons and conditional jumps on known values and shortening `CopyRegister`
+///    chains
+/// 3. jumps to unconditional jumps are redirected to the final target
+/// 4. unreachable instructions, `NoOp`s and jumps to the next instruction are removed and the
+///    remaining jump offsets recalculated
+///
+/// An instruction with a `Wide` prefix is treated as a unit with its prefix: both are replaced or
+/// removed together, and no pass ever needs to insert a new prefix. Exception handler targets
+/// start basic blocks and are always reachable, and the handler table is renumbered along with
+/// the code when instructions are removed.
+use crate::bytecode::{
+    jump_target, split_operand, successors, wide_operand, wide_prefix, Handler, JumpOffset,
+    LiteralId, LiteralInteger, Opcode, Register, JUMP_UNKNOWN,
+};
+use crate::generator::Literal;
+use crate::iter::{MAX_INLINE_INTEGER, MIN_INLINE_INTEGER};
+use crate::number::floor_mod;
+
+/// A value known at compile time to be in a register
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Const {
+    Nil,
+    True,
+    Number(isize),
+}
+
+/// Run every pass over the code, adding to the literals if a folded value needs one
+pub fn optimize(code: &mut Vec<Opcode>, literals: &mut Vec<Literal>, handlers: &mut Vec<Handler>) {
+    compact_literals(code, literals);
+    fold_constants(code, literals, handlers);
+    thread_jumps(code);
+    remove_dead_code(code, handlers);
+}
+
+/// Replace loads of integer literals that fit in an instruction with `LoadInteger`
+fn compact_literals(code: &mut [Opcode], literals: &[Literal]) {
+    for index in 0..code.len() {
+        if let Opcode::LoadLiteral { dest, literal_id } = code[index] {
+            let literal_id = extended_operand(code, index, literal_id);
+
+            if let Some(Literal::Number(n)) = literals.get(literal_id as usize) {
+                if let Some(integer) = as_literal_integer(*n) {
+                    replace(code, index, Opcode::LoadInteger { dest, integer });
+                }
+            }
+        }
+    }
+}
+
+/// The operand of the instruction at `index`, including the high half from a Wide prefix
+fn extended_operand(code: &[Opcode], index: usize, low: u16) -> u32 {
+    match wide_prefix(code, index) {
+        Some(high) => wide_operand(high, low),
+        None => low as u32,
+    }
+}
+
+/// Replace the instruction at `index` with one that needs no Wide prefix, dropping the prefix
+fn replace(code: &mut [Opcode], index: usize, op: Opcode) {
+    if wide_prefix(code, index).is_some() {
+        code[index - 1] = Opcode::NoOp;
+    }
+    code[index] = op;
+}
+
+/// Register contents known at some point in a basic block
+struct Registers {
+    /// A constant the register is known to hold
+    values: Vec<Option<Const>>,
+    /// Another register the register is known to be a copy of
+    copies: Vec<Option<Register>>,
+}
+
+impl Registers {
+    fn new() -> Registers {
+        Registers {
+            values: vec![None; 256],
+            copies: vec![None; 256],
+        }
+    }
+
+    fn clear(&mut self) {
+        *self = Registers::new();
+    }
+
+    fn value(&self, reg: Register) -> Option<Const> {
+        self.values[reg as usize]
+    }
+
+    /// Record a new value for `reg`, which is no longer a copy of anything, and which nothing is
+    /// a copy of any more
+    fn write(&mut self, reg: Register, value: Option<Const>) {
+        self.values[reg as usize] = value;
+        self.copies[reg as usize] = None;
+        for copy in self.copies.iter_mut() {
+            if *copy == Some(reg) {
+                *copy = None;
+            }
+        }
+    }
+}
+
+/// Fold instructions whose operands are known constants, one basic block at a time
+fn fold_constants(code: &mut [Opcode], literals: &mut Vec<Literal>, handlers: &[Handler]) {
+    let leaders = block_leaders(code, handlers);
+    let mut regs = Registers::new();
+
+    for index in 0..code.len() {
+        if leaders[index] {
+            regs.clear();
+        }
+
+        let op = code[index];
+        let folded = match op {
+            Opcode::Add { dest, reg1, reg2 } => {
+                fold_arithmetic(dest, &regs, reg1, reg2, isize::checked_add)
+            }
+            Opcode::Subtract { dest, left, right } => {
+                fold_arithmetic(dest, &regs, left, right, isize::checked_sub)
+            }
+            Opcode::Multiply { dest, reg1, reg2 } => {
+                fold_arithmetic(dest, &regs, reg1, reg2, isize::checked_mul)
+            }
+            // Division by zero is left as a runtime error
+            Opcode::DivideInteger { dest, num, denom } => match regs.value(denom) {
+                Some(Const::Number(0)) => None,
+                _ => fold_arithmetic(dest, &regs, num, denom, isize::checked_div),
+            },
+            Opcode::Modulo { dest, num, denom } => {
+                fold_arithmetic(dest, &regs, num, denom, floor_mod)
+            }
+
+            Opcode::IsNil { dest, test } => regs
+                .value(test)
+                .map(|value| (dest, truth(value == Const::Nil))),
+            Opcode::IsAtom { dest, test } => regs
+                .value(test)
+                .map(|value| (dest, truth(value != Const::Nil))),
+            Opcode::IsIdentical { dest, test1, test2 } => {
+                match (regs.value(test1), regs.value(test2)) {
+                    (Some(left), Some(right)) => Some((dest, truth(left == right))),
+                    _ => None,
+                }
+            }
+
+            _ => None,
+        };
+
+        if let Some(op) = folded.and_then(|(dest, value)| load_const(dest, value, literals)) {
+            code[index] = op;
+        }
+
+        match code[index] {
+            Opcode::JumpIfTrue { test, offset } => match regs.value(test) {
+                Some(Const::True) => code[index] = Opcode::Jump { offset },
+                Some(_) => replace(code, index, Opcode::NoOp),
+                None => (),
+            },
+            Opcode::JumpIfNotTrue { test, offset } => match regs.value(test) {
+                Some(Const::True) => replace(code, index, Opcode::NoOp),
+                Some(_) => code[index] = Opcode::Jump { offset },
+                None => (),
+            },
+
+            Opcode::CopyRegister { dest, src } => {
+                let src = regs.copies[src as usize].unwrap_or(src);
+
+                if src == dest || regs.copies[dest as usize] == Some(src) {
+                    // dest already holds the value
+                    code[index] = Opcode::NoOp;
+                    continue;
+                }
+
+                let constant = regs
+                    .value(src)
+                    .and_then(|value| Some((load_const(dest, value, literals)?, value)));
+
+                match constant {
+                    Some((op, value)) => {
+                        code[index] = op;
+                        regs.write(dest, Some(value));
+                    }
+                    None => {
+                        code[index] = Opcode::CopyRegister { dest, src };
+                        regs.write(dest, None);
+                        regs.copies[dest as usize] = Some(src);
+                    }
+                }
+            }
+
+            _ => record_writes(&mut regs, code, index, literals),
+        }
+    }
+}
+
+/// Update the known register contents for the effects of the instruction at `index`
+fn record_writes(regs: &mut Registers, code: &[Opcode], index: usize, literals: &[Literal]) {
+    match code[index] {
+        Opcode::LoadNil { dest } => regs.write(dest, Some(Const::Nil)),
+        Opcode::LoadInteger { dest, integer } => {
+            let integer = match wide_prefix(code, index) {
+                Some(high) => wide_operand(high, integer as u16) as i32 as isize,
+                None => integer as isize,
+            };
+            regs.write(dest, Some(Const::Number(integer)))
+        }
+        Opcode::LoadLiteral { dest, literal_id } => {
+            let literal_id = extended_operand(code, index, literal_id);
+            let value = match literals.get(literal_id as usize) {
+                Some(Literal::Number(n)) => Some(Const::Number(*n)),
+                Some(Literal::Symbol(s)) if s == "true" => Some(Const::True),
+                _ => None,
+            };
+            regs.write(dest, value);
+        }
+
+        Opcode::IsNil { dest, .. }
+        | Opcode::IsAtom { dest, .. }
+        | Opcode::FirstOfPair { dest, .. }
+        | Opcode::SecondOfPair { dest, .. }
+        | Opcode::MakePair { dest, .. }
+        | Opcode::IsIdentical { dest, .. }
+        | Opcode::LoadGlobal { dest, .. }
+        | Opcode::MakeClosure { dest, .. }
+        | Opcode::CopyRegister { dest, .. }
+        | Opcode::Add { dest, .. }
+        | Opcode::Subtract { dest, .. }
+        | Opcode::Multiply { dest, .. }
+        | Opcode::DivideInteger { dest, .. }
+        | Opcode::Modulo { dest, .. }
+        | Opcode::GetUpvalue { dest, .. }
+        | Opcode::GetIter { dest, .. }
+        | Opcode::IterNext { dest, .. }
+        | Opcode::LoadLocal { dest, .. }
+        | Opcode::IsSupplied { dest, .. }
+        | Opcode::MakePartial { dest, .. }
+        | Opcode::GetField { dest, .. } => regs.write(dest, None),
+
+        Opcode::IterNextPair { dest, .. } => {
+            regs.write(dest, None);
+            regs.write(dest.wrapping_add(1), None);
+        }
+
+        // The called function or imported module runs in a register window that overlaps this
+        // one, and a function may modify any register through an upvalue
+        Opcode::Call { .. }
+        | Opcode::TailCall { .. }
+        | Opcode::CallKeywords { .. }
+        | Opcode::Import { .. } => regs.clear(),
+
+        Opcode::NoOp
+        | Opcode::Return { .. }
+        | Opcode::Jump { .. }
+        | Opcode::JumpIfTrue { .. }
+        | Opcode::JumpIfNotTrue { .. }
+        | Opcode::StoreGlobal { .. }
+        | Opcode::SetUpvalue { .. }
+        | Opcode::CloseUpvalues { .. }
+        | Opcode::StoreLocal { .. }
+        | Opcode::Wide { .. }
+        | Opcode::Throw { .. } => (),
+    }
+}
+
+fn fold_arithmetic(
+    dest: Register,
+    regs: &Registers,
+    left: Register,
+    right: Register,
+    apply: fn(isize, isize) -> Option<isize>,
+) -> Option<(Register, Const)> {
+    match (regs.value(left), regs.value(right)) {
+        (Some(Const::Number(l)), Some(Const::Number(r))) => match apply(l, r) {
+            // Overflow is left as a runtime error
+            Some(n) if n >= MIN_INLINE_INTEGER && n <= MAX_INLINE_INTEGER => {
+                Some((dest, Const::Number(n)))
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn truth(value: bool) -> Const {
+    match value {
+        true => Const::True,
+        false => Const::Nil,
+    }
+}
+
+/// Return the instruction that loads a constant into `dest`. There is no room to insert a Wide
+/// prefix, so a constant whose literal id needs one is not folded.
+fn load_const(dest: Register, value: Const, literals: &mut Vec<Literal>) -> Option<Opcode> {
+    let literal = match value {
+        Const::Nil => return Some(Opcode::LoadNil { dest }),
+        Const::Number(n) => match as_literal_integer(n) {
+            Some(integer) => return Some(Opcode::LoadInteger { dest, integer }),
+            None => Literal::Number(n),
+        },
+        Const::True => Literal::Symbol(String::from("true")),
+    };
+
+    let literal_id = match literals.iter().position(|l| *l == literal) {
+        Some(index) => index,
+        None if literals.len() > LiteralId::MAX as usize => return None,
+        None => {
+            literals.push(literal);
+            literals.len() - 1
+        }
+    };
+
+    LiteralId::try_from(literal_id)
+        .ok()
+        .map(|literal_id| Opcode::LoadLiteral { dest, literal_id })
+}
+
+fn as_literal_integer(n: isize) -> Option<LiteralInteger> {
+    match n >= LiteralInteger::MIN as isize && n <= LiteralInteger::MAX as isize {
+        true => Some(n as LiteralInteger),
+        false => None,
+    }
+}
+
+/// Mark the instructions that can be reached other than by falling through from the previous
+/// instruction
+fn block_leaders(code: &[Opcode], handlers: &[Handler]) -> Vec<bool> {
+    let mut leaders = vec![false; code.len()];
+
+    for (index, op) in code.iter().enumerate() {
+        let target = match op {
+            Opcode::IterNext { .. } | Opcode::IterNextPair { .. } => successors(code, index).pop(),
+            _ => jump_target(code, index),
+        };
+
+        if let Some(leader) = target.and_then(|target| leaders.get_mut(target)) {
+            *leader = true;
+        }
+    }
+
+    for handler in handlers {
+        if let Some(leader) = leaders.get_mut(handler.target as usize) {
+            *leader = true;
+        }
+    }
+
+    leaders
+}
+
+/// Redirect jumps whose target is an unconditional jump to that jump's target
+fn thread_jumps(code: &mut [Opcode]) {
+    for index in 0..code.len() {
+        let mut target = match jump_target(code, index) {
+            Some(target) => target,
+            None => continue,
+        };
+
+        // The hop count limit stops a cycle of jumps from threading forever
+        let mut hops = 0;
+        while let Some(Opcode::Jump { .. }) = code.get(target) {
+            match jump_target(code, target) {
+                Some(next) if hops < code.len() => target = next,
+                _ => break,
+            }
+            hops += 1;
+        }
+
+        retarget(code, index, target);
+    }
+}
+
+/// Drop unreachable instructions, `NoOp`s and jumps to the next instruction, and any exception
+/// handler left protecting nothing
+pub fn remove_dead_code(code: &mut Vec<Opcode>, handlers: &mut Vec<Handler>) {
+    if code.is_empty() {
+        return;
+    }
+
+    let reachable = reachable(code, handlers);
+    let last = code.len() - 1;
+
+    let keep: Vec<bool> = (0..code.len())
+        .map(|index| {
+            // The instruction after an iteration opcode is skipped over by position, and the
+            // final Return must stay in place
+            let pinned = index == last
+                || (index > 0
+                    && matches!(
+                        code[index - 1],
+                        Opcode::IterNext { .. } | Opcode::IterNextPair { .. }
+                    ));
+
+            let redundant = match code[index] {
+                Opcode::NoOp => true,
+                _ => {
+                    wide_prefix(code, index).is_none()
+                        && jump_target(code, index) == Some(index + 1)
+                }
+            };
+
+            pinned || (reachable[index] && !redundant)
+        })
+        .collect();
+
+    // The new index of each instruction, or of the next kept instruction if it is removed
+    let mut new_index = Vec::with_capacity(code.len() + 1);
+    let mut count = 0;
+    for kept in &keep {
+        new_index.push(count);
+        if *kept {
+            count += 1;
+        }
+    }
+    new_index.push(count);
+
+    let mut compacted = Vec::with_capacity(count);
+    let mut jumps = Vec::new();
+    for (index, op) in code.iter().enumerate() {
+        if !keep[index] {
+            continue;
+        }
+
+        if let Some(target) = jump_target(code, index) {
+            jumps.push((new_index[index], new_index[target]));
+        }
+        compacted.push(*op);
+    }
+
+    // Removing instructions only ever shortens jumps, so every offset still fits
+    for (jump, target) in jumps {
+        retarget(&mut compacted, jump, target);
+    }
+
+    for handler in handlers.iter_mut() {
+        handler.renumber(&new_index);
+    }
+    handlers.retain(|handler| handler.start < handler.end);
+
+    *code = compacted;
+}
+
+/// Mark the instructions control can reach from the start of the code or an exception handler
+fn reachable(code: &[Opcode], handlers: &[Handler]) -> Vec<bool> {
+    let mut reached = vec![false; code.len()];
+    let mut pending = vec![0];
+    pending.extend(handlers.iter().map(|handler| handler.target as usize));
+
+    while let Some(index) = pending.pop() {
+        if index >= code.len() || reached[index] {
+            continue;
+        }
+        reached[index] = true;
+        pending.extend(successors(code, index));
+    }
+
+    reached
+}
+
+/// Point the jump at `index` to `target`. A jump without a Wide prefix is left as it is if its
+/// offset cannot reach.
+fn retarget(code: &mut [Opcode], index: usize, target: usize) {
+    let offset = target as isize - (index as isize + 1);
+
+    match wide_prefix(code, index) {
+        Some(_) => {
+            let (high, low) = split_operand(offset as i32 as u32);
+            code[index - 1] = Opcode::Wide { high };
+            code[index] = with_offset(code[index], low as JumpOffset);
+        }
+        None if offset >= JumpOffset::MIN as isize && offset < JUMP_UNKNOWN as isize => {
+            code[index] = with_offset(code[index], offset as JumpOffset);
+        }
+        None => (),
+    }
+}
+
+fn with_offset(op: Opcode, offset: JumpOffset) -> Opcode {
+    match op {
+        Opcode::Jump { .. } => Opcode::Jump { offset },
+        Opcode::JumpIfTrue { test, .. } => Opcode::JumpIfTrue { test, offset },
+        Opcode::JumpIfNotTrue { test, .. } => Opcode::JumpIfNotTrue { test, offset },
+        op => op,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn optimized(mut code: Vec<Opcode>, literals: &mut Vec<Literal>) -> Vec<Opcode> {
+        optimize(&mut code, literals, &mut Vec::new());
+        code
+    }
+
+    #[test]
+    fn fold_constant_arithmetic_and_comparisons() {
+        let mut literals = vec![Literal::Number(40000), Literal::Number(3)];
+
+        let code = optimized(
+            vec![
+                Opcode::LoadLiteral {
+                    dest: 2,
+                    literal_id: 0,
+                },
+                Opcode::LoadLiteral {
+                    dest: 3,
+                    literal_id: 1,
+                },
+                Opcode::Multiply {
+                    dest: 4,
+                    reg1: 2,
+                    reg2: 3,
+                },
+                Opcode::DivideInteger {
+                    dest: 5,
+                    num: 4,
+                    denom: 3,
+                },
+                Opcode::IsIdentical {
+                    dest: 6,
+                    test1: 5,
+                    test2: 2,
+                },
+                Opcode::Return { reg: 6 },
+            ],
+            &mut literals,
+        );
+
+        assert!(
+            code == vec![
+                Opcode::LoadLiteral {
+                    dest: 2,
+                    literal_id: 0
+                },
+                Opcode::LoadInteger {
+                    dest: 3,
+                    integer: 3
+                },
+                Opcode::LoadLiteral {
+                    dest: 4,
+                    literal_id: 2
+                },
+                Opcode::LoadLiteral {
+                    dest: 5,
+                    literal_id: 0
+                },
+                Opcode::LoadLiteral {
+                    dest: 6,
+                    literal_id: 3
+                },
+                Opcode::Return { reg: 6 },
+            ],
+            "{:?}",
+            code
+        );
+        assert!(literals[2] == Literal::Number(120000));
+        assert!(literals[3] == Literal::Symbol(String::from("true")));
+
+        // Division by zero is not folded
+        let code = optimized(
+            vec![
+                Opcode::LoadInteger {
+                    dest: 2,
+                    integer: 1,
+                },
+                Opcode::LoadInteger {
+                    dest: 3,
+                    integer: 0,
+                },
+                Opcode::DivideInteger {
+                    dest: 2,
+                    num: 2,
+                    denom: 3,
+                },
+                Opcode::Return { reg: 2 },
+            ],
+            &mut literals,
+        );
+        assert!(matches!(code[2], Opcode::DivideInteger { .. }));
+    }
+
+    #[test]
+    fn fold_conditional_jumps_and_remove_dead_code() {
+        let mut literals = Vec::new();
+
+        // if nil is nil then return 2 else return 3
+        let code = optimized(
+            vec![
+                Opcode::LoadNil { dest: 2 },
+                Opcode::IsNil { dest: 3, test: 2 },
+                Opcode::JumpIfNotTrue { test: 3, offset: 2 },
+                Opcode::LoadInteger {
+                    dest: 0,
+                    integer: 2,
+                },
+                Opcode::Return { reg: 0 },
+                Opcode::LoadInteger {
+                    dest: 0,
+                    integer: 3,
+                },
+                Opcode::Return { reg: 0 },
+            ],
+            &mut literals,
+        );
+
+        assert!(
+            code == vec![
+                Opcode::LoadNil { dest: 2 },
+                Opcode::LoadLiteral {
+                    dest: 3,
+                    literal_id: 0
+                },
+                Opcode::LoadInteger {
+                    dest: 0,
+                    integer: 2
+                },
+                Opcode::Return { reg: 0 },
+                Opcode::Return { reg: 0 },
+            ],
+            "{:?}",
+            code
+        );
+    }
+
+    #[test]
+    fn remove_copy_chains() {
+        let mut literals = Vec::new();
+
+        let code = optimized(
+            vec![
+                Opcode::LoadGlobal { dest: 2, name: 9 },
+                Opcode::CopyRegister { dest: 3, src: 2 },
+                Opcode::CopyRegister { dest: 4, src: 3 },
+                Opcode::CopyRegister { dest: 3, src: 4 },
+                Opcode::CopyRegister { dest: 5, src: 5 },
+                Opcode::Return { reg: 4 },
+            ],
+            &mut literals,
+        );
+
+        assert!(
+            code == vec![
+                Opcode::LoadGlobal { dest: 2, name: 9 },
+                Opcode::CopyRegister { dest: 3, src: 2 },
+                Opcode::CopyRegister { dest: 4, src: 2 },
+                Opcode::Return { reg: 4 },
+            ],
+            "{:?}",
+            code
+        );
+    }
+
+    #[test]
+    fn thread_jumps_to_jumps() {
+        let mut literals = Vec::new();
+
+        let code = optimized(
+            vec![
+                Opcode::LoadGlobal { dest: 2, name: 9 },
+                Opcode::JumpIfTrue { test: 2, offset: 1 },
+                Opcode::Return { reg: 2 },
+                Opcode::Jump { offset: 0 },
+                Opcode::Jump { offset: 0 },
+                Opcode::Return { reg: 0 },
+            ],
+            &mut literals,
+        );
+
+        assert!(
+            code == vec![
+                Opcode::LoadGlobal { dest: 2, name: 9 },
+                Opcode::JumpIfTrue { test: 2, offset: 1 },
+                Opcode::Return { reg: 2 },
+                Opcode::Return { reg: 0 },
+            ],
+            "{:?}",
+            code
+        );
+    }
+
+    #[test]
+    fn keep_wide_prefixes_with_their_instructions() {
+        let mut literals = vec![Literal::Number(7)];
+
+        let code = optimized(
+            vec![
+                Opcode::Wide { high: 0 },
+                Opcode::LoadLiteral {
+                    dest: 2,
+                    literal_id: 0,
+                },
+                Opcode::NoOp,
+                Opcode::Wide { high: 0xffff },
+                Opcode::JumpIfTrue {
+                    test: 3,
+                    offset: -5,
+                },
+                Opcode::Return { reg: 2 },
+            ],
+            &mut literals,
+        );
+
+        assert!(
+            code == vec![
+                Opcode::LoadInteger {
+                    dest: 2,
+                    integer: 7
+                },
+                Opcode::Wide { high: 0xffff },
+                Opcode::JumpIfTrue {
+                    test: 3,
+                    offset: -3
+                },
+                Opcode::Return { reg: 2 },
+            ],
+            "{:?}",
+            code
+        );
+    }
+}
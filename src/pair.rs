@@ -6,16 +6,19 @@ use crate::memory::MutatorView;
 use crate::printer::Print;
 use crate::safe_ptr::{MutatorScope, ScopedPtr, TaggedCellPtr, TaggedScopedPtr};
 use crate::tagged_ptr::Value;
+use crate::trace::{visit_tagged_cell, Trace, Visitor};
 
-/// A Pair of pointers, like a Cons cell of old
+/// A Pair of pointers, like a Cons cell of old. `first` and `second` are set while the Pair is
+/// built; once it is part of a Dict key it is frozen and every setter refuses to change it.
 // ANCHOR: DefPair
 #[derive(Clone)]
 pub struct Pair {
-    pub first: TaggedCellPtr,
-    pub second: TaggedCellPtr,
+    first: TaggedCellPtr,
+    second: TaggedCellPtr,
     // Possible source code positions of the first and second values
     pub first_pos: Cell<Option<SourcePos>>,
     pub second_pos: Cell<Option<SourcePos>>,
+    frozen: Cell<bool>,
 }
 // ANCHOR_END: DefPair
 
@@ -28,10 +31,53 @@ impl Pair {
             second: TaggedCellPtr::new_nil(),
             first_pos: Cell::new(None),
             second_pos: Cell::new(None),
+            frozen: Cell::new(false),
         }
     }
     // ANCHOR_END: DefPairNew
 
+    /// Mark the Pair as part of a Dict key, which changing it would corrupt
+    pub fn freeze(&self) {
+        self.frozen.set(true);
+    }
+
+    /// Return true if the Pair is part of a Dict key and cannot be changed
+    pub fn is_frozen(&self) -> bool {
+        self.frozen.get()
+    }
+
+    /// Return an error if the Pair cannot be changed
+    fn check_mutable(&self) -> Result<(), RuntimeError> {
+        match self.frozen.get() {
+            true => Err(err_eval("Cannot modify a Pair that is part of a Dict key")),
+            false => Ok(()),
+        }
+    }
+
+    /// Return the first value of the Pair
+    pub fn first<'guard>(&self, guard: &'guard dyn MutatorScope) -> TaggedScopedPtr<'guard> {
+        self.first.get(guard)
+    }
+
+    /// Return the second value of the Pair
+    pub fn second<'guard>(&self, guard: &'guard dyn MutatorScope) -> TaggedScopedPtr<'guard> {
+        self.second.get(guard)
+    }
+
+    /// Set Pair.first to the given value
+    pub fn set_first<'guard>(&self, value: TaggedScopedPtr<'guard>) -> Result<(), RuntimeError> {
+        self.check_mutable()?;
+        self.first.set(value);
+        Ok(())
+    }
+
+    /// Set Pair.second to the given value
+    pub fn set_second<'guard>(&self, value: TaggedScopedPtr<'guard>) -> Result<(), RuntimeError> {
+        self.check_mutable()?;
+        self.second.set(value);
+        Ok(())
+    }
+
     /// Set Pair.second to a new Pair with newPair.first set to the value
     // ANCHOR: DefPairAppend
     pub fn append<'guard>(
@@ -39,6 +85,8 @@ impl Pair {
         mem: &'guard MutatorView,
         value: TaggedScopedPtr<'guard>,
     ) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+        self.check_mutable()?;
+
         let pair = Pair::new();
         pair.first.set(value);
 
@@ -51,8 +99,8 @@ impl Pair {
 
     /// Set Pair.second to the given value
     // ANCHOR: DefPairDot
-    pub fn dot<'guard>(&self, value: TaggedScopedPtr<'guard>) {
-        self.second.set(value);
+    pub fn dot<'guard>(&self, value: TaggedScopedPtr<'guard>) -> Result<(), RuntimeError> {
+        self.set_second(value)
     }
     // ANCHOR_END: DefPairDot
 
@@ -105,6 +153,13 @@ impl Print for Pair {
     }
 }
 
+impl Trace for Pair {
+    fn trace<'guard>(&self, _guard: &'guard dyn MutatorScope, visit: &mut Visitor) {
+        visit_tagged_cell(&self.first, visit);
+        visit_tagged_cell(&self.second, visit);
+    }
+}
+
 /// Link the two values `head` and `rest` into a Pair instance
 // ANCHOR: DefCons
 pub fn cons<'guard>(
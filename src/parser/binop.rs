@@ -2,11 +2,14 @@ use crate::parser::Parser;
 use crate::tokens::Tok;
 
 impl Parser {
-    pub fn install_binops(&mut self) {
-        self.install_prod(Tok::BinOp, &vec![Tok::Plus], None);
-        self.install_prod(Tok::BinOp, &vec![Tok::Minus], None);
-        //self.install_prod(Tok::BinOp, &vec![Tok::Times], None);
-        //self.install_prod(Tok::BinOp, &vec![Tok::Divide], None);
-        //self.install_prod(Tok::BinOp, &vec![Tok::Modulo], None);
+    pub fn install_add_ops(&mut self) {
+        self.install_prod(Tok::AddOp, &vec![Tok::Plus], None);
+        self.install_prod(Tok::AddOp, &vec![Tok::Minus], None);
+    }
+
+    pub fn install_mul_ops(&mut self) {
+        //self.install_prod(Tok::MulOp, &vec![Tok::Times], None);
+        //self.install_prod(Tok::MulOp, &vec![Tok::Divide], None);
+        self.install_prod(Tok::MulOp, &vec![Tok::Modulo], None);
     }
 }
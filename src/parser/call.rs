@@ -21,4 +21,21 @@ impl Parser {
             Some(action),
         );
     }
+
+    pub fn install_keyword_arg(&mut self) {
+        fn action(ast: &mut Ast) {
+            let expr = ast.node_stack.pop().unwrap();
+            let mut var = ast.node_stack.pop().unwrap();
+
+            var.token = Tok::KeywordArg;
+            var.children.push(expr);
+            ast.node_stack.push(var);
+        }
+
+        self.install_prod(
+            Tok::KeywordArg,
+            &vec![Tok::Var, Tok::Colon, Tok::Expr],
+            Some(action),
+        );
+    }
 }
\ No newline at end of file
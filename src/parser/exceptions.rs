@@ -0,0 +1,101 @@
+use crate::ast::Ast;
+use crate::parser::Parser;
+use crate::tokens::Tok;
+
+impl Parser {
+    pub fn install_try_catch(&mut self) {
+        fn action(ast: &mut Ast) {
+            let catch_stmts = ast.node_stack.pop().unwrap();
+            let var = ast.node_stack.pop().unwrap();
+            let mut catch_kw = ast.node_stack.pop().unwrap();
+            let stmts = ast.node_stack.pop().unwrap();
+            let mut try_kw = ast.node_stack.pop().unwrap();
+
+            catch_kw.children.push(catch_stmts);
+            catch_kw.children.push(var);
+
+            try_kw.children.push(catch_kw);
+            try_kw.children.push(stmts);
+            ast.node_stack.push(try_kw);
+        }
+
+        self.install_prod(
+            Tok::Control,
+            &vec![Tok::TryKW, Tok::Block, Tok::CatchKW, Tok::Var, Tok::Block],
+            Some(action),
+        );
+    }
+
+    pub fn install_try_finally(&mut self) {
+        fn action(ast: &mut Ast) {
+            let finally_stmts = ast.node_stack.pop().unwrap();
+            let mut finally_kw = ast.node_stack.pop().unwrap();
+            let stmts = ast.node_stack.pop().unwrap();
+            let mut try_kw = ast.node_stack.pop().unwrap();
+
+            finally_kw.children.push(finally_stmts);
+
+            try_kw.children.push(finally_kw);
+            try_kw.children.push(stmts);
+            ast.node_stack.push(try_kw);
+        }
+
+        self.install_prod(
+            Tok::Control,
+            &vec![Tok::TryKW, Tok::Block, Tok::FinallyKW, Tok::Block],
+            Some(action),
+        );
+    }
+
+    pub fn install_try_catch_finally(&mut self) {
+        fn action(ast: &mut Ast) {
+            let finally_stmts = ast.node_stack.pop().unwrap();
+            let mut finally_kw = ast.node_stack.pop().unwrap();
+            let catch_stmts = ast.node_stack.pop().unwrap();
+            let var = ast.node_stack.pop().unwrap();
+            let mut catch_kw = ast.node_stack.pop().unwrap();
+            let stmts = ast.node_stack.pop().unwrap();
+            let mut try_kw = ast.node_stack.pop().unwrap();
+
+            finally_kw.children.push(finally_stmts);
+
+            catch_kw.children.push(catch_stmts);
+            catch_kw.children.push(var);
+
+            try_kw.children.push(finally_kw);
+            try_kw.children.push(catch_kw);
+            try_kw.children.push(stmts);
+            ast.node_stack.push(try_kw);
+        }
+
+        self.install_prod(
+            Tok::Control,
+            &vec![
+                Tok::TryKW,
+                Tok::Block,
+                Tok::CatchKW,
+                Tok::Var,
+                Tok::Block,
+                Tok::FinallyKW,
+                Tok::Block,
+            ],
+            Some(action),
+        );
+    }
+
+    pub fn install_stmt_throw(&mut self) {
+        fn action(ast: &mut Ast) {
+            let expr = ast.node_stack.pop().unwrap();
+            let mut throw_kw = ast.node_stack.pop().unwrap();
+
+            throw_kw.children.push(expr);
+            ast.node_stack.push(throw_kw);
+        }
+
+        self.install_prod(
+            Tok::Stmt,
+            &vec![Tok::ThrowKW, Tok::Expr, Tok::SemiColon],
+            Some(action),
+        );
+    }
+}
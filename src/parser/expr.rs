@@ -5,7 +5,7 @@ use crate::tokens::Tok;
 impl Parser {
     pub fn install_expr_call(&mut self) {
         self.install_prod(
-            Tok::Expr,
+            Tok::Operand,
             &vec![Tok::FuncCall],
             None,
         );
@@ -13,43 +13,64 @@ impl Parser {
 
     pub fn install_expr_nested(&mut self) {
         self.install_prod(
-            Tok::Expr,
+            Tok::Operand,
             &vec![Tok::LeftParen, Tok::Expr, Tok::RightParen],
             None,
         );
     }
 
-    pub fn install_expr_binop(&mut self) {
-        fn action(ast: &mut Ast) {
-            let right_expr = ast.node_stack.pop().unwrap();
-            let mut op = ast.node_stack.pop().unwrap();
-            let left_expr = ast.node_stack.pop().unwrap();
+    pub fn install_expr_add(&mut self) {
+        self.install_prod(
+            Tok::Expr,
+            &vec![Tok::Expr, Tok::AddOp, Tok::Term],
+            Some(binop_action),
+        );
+    }
 
-            if left_expr.has_const_val() && right_expr.has_const_val() {
-                ast.synthesize_expr(op.token, left_expr.val.unwrap(), right_expr.val.unwrap());
-            } else {
-                op.children.push(left_expr);
-                op.children.push(right_expr);
-                ast.node_stack.push(op);
-            }
-        }
+    pub fn install_expr_term(&mut self) {
+        self.install_prod(Tok::Expr, &vec![Tok::Term], None);
+    }
 
+    pub fn install_term_mul(&mut self) {
         self.install_prod(
-            Tok::Expr,
-            &vec![Tok::Expr, Tok::BinOp, Tok::Expr],
-            Some(action),
+            Tok::Term,
+            &vec![Tok::Term, Tok::MulOp, Tok::Operand],
+            Some(binop_action),
         );
     }
 
+    pub fn install_term_operand(&mut self) {
+        self.install_prod(Tok::Term, &vec![Tok::Operand], None);
+    }
+
     pub fn install_expr_string(&mut self) {
-        self.install_prod(Tok::Expr, &vec![Tok::String], None);
+        self.install_prod(Tok::Operand, &vec![Tok::String], None);
     }
 
     pub fn install_expr_int(&mut self) {
-        self.install_prod(Tok::Expr, &vec![Tok::Int], None);
+        self.install_prod(Tok::Operand, &vec![Tok::Int], None);
     }
 
     pub fn install_expr_var(&mut self) {
-        self.install_prod(Tok::Expr, &vec![Tok::Var], None);
+        self.install_prod(Tok::Operand, &vec![Tok::Var], None);
+    }
+}
+
+/// Reduce `left op right`, folding it into a single literal when both sides are constants
+fn binop_action(ast: &mut Ast) {
+    let right_expr = ast.node_stack.pop().unwrap();
+    let mut op = ast.node_stack.pop().unwrap();
+    let left_expr = ast.node_stack.pop().unwrap();
+
+    if left_expr.has_const_val() && right_expr.has_const_val() {
+        let left_val = left_expr.val.as_ref().unwrap();
+        let right_val = right_expr.val.as_ref().unwrap();
+        if ast.synthesize_expr(op.token, left_val, right_val) {
+            return;
+        }
     }
+
+    op.children.push(left_expr);
+    op.children.push(right_expr);
+    ast.node_stack.push(op);
 }
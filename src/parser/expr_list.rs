@@ -31,6 +31,62 @@ impl Parser {
         self.install_prod(Tok::ExprList, &vec![Tok::Expr], Some(action));
     }
 
+    pub fn install_expr_list_keyword_comma(&mut self) {
+        fn action(ast: &mut Ast) {
+            let mut expr_list = ast.node_stack.pop().unwrap();
+            let keyword_arg = ast.node_stack.pop().unwrap();
+
+            expr_list.children.push(keyword_arg);
+            ast.node_stack.push(expr_list);
+        }
+
+        self.install_prod(
+            Tok::ExprList,
+            &vec![Tok::KeywordArg, Tok::Comma, Tok::ExprList],
+            Some(action),
+        );
+    }
+
+    pub fn install_expr_list_keyword_last(&mut self) {
+        fn action(ast: &mut Ast) {
+            let keyword_arg = ast.node_stack.pop().unwrap();
+            let mut expr_list = ast.new_node(Tok::ExprList, None);
+
+            expr_list.children.push(keyword_arg);
+            ast.node_stack.push(expr_list);
+        }
+
+        self.install_prod(Tok::ExprList, &vec![Tok::KeywordArg], Some(action));
+    }
+
+    pub fn install_expr_list_placeholder_comma(&mut self) {
+        fn action(ast: &mut Ast) {
+            let mut expr_list = ast.node_stack.pop().unwrap();
+            let placeholder = ast.node_stack.pop().unwrap();
+
+            expr_list.children.push(placeholder);
+            ast.node_stack.push(expr_list);
+        }
+
+        self.install_prod(
+            Tok::ExprList,
+            &vec![Tok::Placeholder, Tok::Comma, Tok::ExprList],
+            Some(action),
+        );
+    }
+
+    pub fn install_expr_list_placeholder_last(&mut self) {
+        fn action(ast: &mut Ast) {
+            let placeholder = ast.node_stack.pop().unwrap();
+            let mut expr_list = ast.new_node(Tok::ExprList, None);
+
+            expr_list.children.push(placeholder);
+            ast.node_stack.push(expr_list);
+        }
+
+        self.install_prod(Tok::ExprList, &vec![Tok::Placeholder], Some(action));
+    }
+
     pub fn install_expr_list_empty(&mut self) {
         fn action(ast: &mut Ast) {
             ast.push_node(Tok::ExprList, None);
@@ -0,0 +1,136 @@
+use crate::ast::Ast;
+use crate::parser::Parser;
+use crate::tokens::Tok;
+
+impl Parser {
+    /// Install an import statement both with and without a trailing `;`, so that imports end as
+    /// declarations do, with nothing, while still accepting one
+    fn install_import_stmt(&mut self, mut body: Vec<Tok>, action: fn(&mut Ast)) {
+        self.install_prod(Tok::Stmt, &body, Some(action));
+
+        body.push(Tok::SemiColon);
+        self.install_prod(Tok::Stmt, &body, Some(action));
+    }
+
+    pub fn install_import(&mut self) {
+        fn action(ast: &mut Ast) {
+            let var = ast.node_stack.pop().unwrap();
+            let path = ast.node_stack.pop().unwrap();
+            let mut import_kw = ast.node_stack.pop().unwrap();
+
+            import_kw.children.push(var);
+            import_kw.children.push(path);
+            ast.node_stack.push(import_kw);
+        }
+
+        self.install_import_stmt(
+            vec![Tok::ImportKW, Tok::String, Tok::AsKW, Tok::Var],
+            action,
+        );
+    }
+
+    /// The module is named by either a path or a variable holding an imported module
+    fn install_from_import_action(&mut self, source: Tok) {
+        fn action(ast: &mut Ast) {
+            let name_list = ast.node_stack.pop().unwrap();
+            let _import_kw = ast.node_stack.pop().unwrap();
+            let source = ast.node_stack.pop().unwrap();
+            let mut from_kw = ast.node_stack.pop().unwrap();
+
+            from_kw.children.push(name_list);
+            from_kw.children.push(source);
+            ast.node_stack.push(from_kw);
+        }
+
+        self.install_import_stmt(
+            vec![Tok::FromKW, source, Tok::ImportKW, Tok::NameList],
+            action,
+        );
+    }
+
+    pub fn install_from_import_path(&mut self) {
+        self.install_from_import_action(Tok::String);
+    }
+
+    pub fn install_from_import_var(&mut self) {
+        self.install_from_import_action(Tok::Var);
+    }
+
+    pub fn install_name_list_comma(&mut self) {
+        fn action(ast: &mut Ast) {
+            let mut name_list = ast.node_stack.pop().unwrap();
+            let var = ast.node_stack.pop().unwrap();
+
+            name_list.children.push(var);
+            ast.node_stack.push(name_list);
+        }
+
+        self.install_prod(
+            Tok::NameList,
+            &vec![Tok::Var, Tok::Comma, Tok::NameList],
+            Some(action),
+        );
+    }
+
+    pub fn install_name_list_last(&mut self) {
+        fn action(ast: &mut Ast) {
+            let var = ast.node_stack.pop().unwrap();
+            let mut name_list = ast.new_node(Tok::NameList, None);
+
+            name_list.children.push(var);
+            ast.node_stack.push(name_list);
+        }
+
+        self.install_prod(Tok::NameList, &vec![Tok::Var], Some(action));
+    }
+
+    pub fn install_expr_field(&mut self) {
+        fn action(ast: &mut Ast) {
+            let mut member = ast.node_stack.pop().unwrap();
+            let object = ast.node_stack.pop().unwrap();
+
+            member.token = Tok::Field;
+            member.children.push(object);
+            ast.node_stack.push(member);
+        }
+
+        self.install_prod(
+            Tok::Operand,
+            &vec![Tok::Var, Tok::Dot, Tok::Var],
+            Some(action),
+        );
+    }
+
+    /// A call to a member of a module. The callee is held after the arguments, which are in
+    /// reverse source order.
+    pub fn install_field_call(&mut self) {
+        fn action(ast: &mut Ast) {
+            let expr_list = ast.node_stack.pop().unwrap();
+            let mut member = ast.node_stack.pop().unwrap();
+            let object = ast.node_stack.pop().unwrap();
+
+            member.token = Tok::Field;
+            member.children.push(object);
+
+            let mut call = ast.new_node(Tok::FuncCall, None);
+            for child in expr_list.children {
+                call.children.push(child);
+            }
+            call.children.push(member);
+            ast.node_stack.push(call);
+        }
+
+        self.install_prod(
+            Tok::FuncCall,
+            &vec![
+                Tok::Var,
+                Tok::Dot,
+                Tok::Var,
+                Tok::LeftParen,
+                Tok::ExprList,
+                Tok::RightParen,
+            ],
+            Some(action),
+        );
+    }
+}
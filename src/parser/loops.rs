@@ -0,0 +1,63 @@
+use crate::ast::Ast;
+use crate::parser::Parser;
+use crate::tokens::Tok;
+
+impl Parser {
+    pub fn install_for(&mut self) {
+        fn action(ast: &mut Ast) {
+            let stmts = ast.node_stack.pop().unwrap();
+            let expr = ast.node_stack.pop().unwrap();
+            let var = ast.node_stack.pop().unwrap();
+            let mut for_kw = ast.node_stack.pop().unwrap();
+
+            for_kw.children.push(stmts);
+            for_kw.children.push(expr);
+            for_kw.children.push(var);
+            ast.node_stack.push(for_kw);
+        }
+
+        self.install_prod(
+            Tok::Control,
+            &vec![Tok::ForKW, Tok::Var, Tok::InKW, Tok::Expr, Tok::Block],
+            Some(action),
+        );
+    }
+
+    pub fn install_for_pair(&mut self) {
+        fn action(ast: &mut Ast) {
+            let stmts = ast.node_stack.pop().unwrap();
+            let expr = ast.node_stack.pop().unwrap();
+            let value_var = ast.node_stack.pop().unwrap();
+            let key_var = ast.node_stack.pop().unwrap();
+            let mut for_kw = ast.node_stack.pop().unwrap();
+
+            for_kw.children.push(stmts);
+            for_kw.children.push(expr);
+            for_kw.children.push(value_var);
+            for_kw.children.push(key_var);
+            ast.node_stack.push(for_kw);
+        }
+
+        self.install_prod(
+            Tok::Control,
+            &vec![
+                Tok::ForKW,
+                Tok::Var,
+                Tok::Comma,
+                Tok::Var,
+                Tok::InKW,
+                Tok::Expr,
+                Tok::Block,
+            ],
+            Some(action),
+        );
+    }
+
+    pub fn install_break(&mut self) {
+        self.install_prod(Tok::Control, &vec![Tok::BreakKW], None);
+    }
+
+    pub fn install_continue(&mut self) {
+        self.install_prod(Tok::Control, &vec![Tok::ContinueKW], None);
+    }
+}
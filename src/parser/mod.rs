@@ -1,24 +1,32 @@
 use crate::ast::{Ast, NodeVal};
+use crate::error::{err_parser, err_parser_wpos, RuntimeError, SourcePos};
 use crate::lexer::Lexer;
 use crate::tokens::{keyword_check, Tok};
 use bovidae::{Bovidae, ParseResult};
-use lexify::{LexifyToken, LexifyError};
+use lexify::LexifyToken;
 
-//pub mod var_list;
-//pub mod binop;
-//pub mod expr;
-//pub mod expr_list;
+pub mod var_list;
+pub mod binop;
+pub mod expr;
+pub mod expr_list;
 pub mod stmts;
 pub mod block;
 pub mod stmt;
 pub mod decl;
-//pub mod call;
+pub mod call;
+pub mod loops;
+pub mod exceptions;
+pub mod imports;
+pub mod template;
 
 type ProdID = usize;
 
 pub struct Parser {
     parser: Bovidae<Tok>,
     reduction_actions: Vec<Option<fn(&mut Ast)>>,
+    /// A second parser for the expressions embedded in template strings, created on first use
+    /// since this one is mid-parse whenever it meets a template
+    template_parser: Option<Box<Parser>>,
 }
 
 impl Parser {
@@ -33,35 +41,40 @@ impl Parser {
         Self {
             parser: Bovidae::new(),
             reduction_actions: Vec::<Option<fn(&mut Ast)>>::new(),
+            template_parser: None,
         }
     }
 
-    pub fn build_ast(&mut self, lexer: &mut Lexer, ast: &mut Ast) -> Result<(), ()> {
+    pub fn build_ast(&mut self, lexer: &mut Lexer, ast: &mut Ast) -> Result<(), RuntimeError> {
+        self.parser.reset();
+
         loop {
-            let lex_tok = lexer.next_token()?;
+            let (tid, attr) = match lexer.next_token() {
+                Ok(LexifyToken::Eof) => break,
+                Ok(LexifyToken::Tok(tid, attr)) => (tid, attr.map(str::to_string)),
+                Err(_) => return Err(err_parser("Unrecognized input")),
+            };
 
-            match lex_tok {
-                LexifyToken::Eof => {
-                    self.parse_end(ast);
-                    break;
-                }
-                LexifyToken::Tok(tid, attr) => self.parse(tid, attr, ast),
-            }
+            let pos = attr.as_ref().and_then(|attr| lexer.locate(attr));
+            self.parse(tid, attr.as_deref(), pos, ast)?;
         }
 
-        Ok(())
+        self.parse_end(ast)
     }
 
-    fn shift_node(&mut self, tok: Tok, attr: Option<&str>, ast: &mut Ast) {
+    fn shift_node(
+        &mut self,
+        tok: Tok,
+        attr: Option<&str>,
+        pos: Option<SourcePos>,
+        ast: &mut Ast,
+    ) -> Result<(), RuntimeError> {
         if tok.non_semantic_token() {
-            return;
+            return Ok(());
         }
 
         match tok {
-            Tok::String => ast.push_node(
-                Tok::String,
-                Some(NodeVal::String(attr.unwrap().to_string())),
-            ),
+            Tok::String => self.shift_string(attr.unwrap(), pos, ast)?,
             Tok::Int => ast.push_node(
                 Tok::Int,
                 Some(NodeVal::Int(
@@ -75,6 +88,8 @@ impl Parser {
             }
             _ => ast.push_node(tok, None),
         }
+
+        Ok(())
     }
 
     fn reduce_node(&mut self, pid: ProdID, ast: &mut Ast) {
@@ -96,21 +111,43 @@ impl Parser {
         }
     }
 
-    pub fn parse(&mut self, raw_tid: Tok, attr: Option<&str>, ast: &mut Ast) {
-        self.parse_until_shift(Some(self.process_raw_tid(raw_tid, attr)), attr, ast);
+    pub fn parse(
+        &mut self,
+        raw_tid: Tok,
+        attr: Option<&str>,
+        pos: Option<SourcePos>,
+        ast: &mut Ast,
+    ) -> Result<(), RuntimeError> {
+        let tid = self.process_raw_tid(raw_tid, attr);
+        self.parse_until_shift(Some(tid), attr, pos, ast)
     }
 
-    fn parse_until_shift(&mut self, tid: Option<Tok>, attr: Option<&str>, ast: &mut Ast) {
+    fn parse_until_shift(
+        &mut self,
+        tid: Option<Tok>,
+        attr: Option<&str>,
+        pos: Option<SourcePos>,
+        ast: &mut Ast,
+    ) -> Result<(), RuntimeError> {
         loop {
             let parse_result = self.parser.parse(tid);
 
             if parse_result.is_err() {
-                panic!("Parsing error")
+                let reason = match (tid, attr) {
+                    (Some(Tok::End), _) | (None, _) => String::from("Unexpected end of input"),
+                    (_, Some(attr)) => format!("Unexpected {}", attr),
+                    (Some(tok), None) => format!("Unexpected {:?}", tok),
+                };
+
+                return Err(match pos {
+                    Some(pos) => err_parser_wpos(pos, &reason),
+                    None => err_parser(&reason),
+                });
             } else {
                 match parse_result.ok().unwrap() {
                     ParseResult::Accept => {
                         //println!("==== PARSER ACCEPTED :) ====");
-                        break;
+                        return Ok(());
                     }
                     ParseResult::Reduction(_, pid) => {
                         // TODO remove tid from ParseResult
@@ -119,17 +156,16 @@ impl Parser {
                         continue;
                     }
                     ParseResult::Shift => {
-                        self.shift_node(tid.unwrap(), attr, ast);
-                        break;
+                        return self.shift_node(tid.unwrap(), attr, pos, ast);
                     }
                 }
             }
         }
     }
 
-    fn parse_end(&mut self, ast: &mut Ast) {
-        self.parse_until_shift(Some(Tok::End), None, ast);
-        self.parse_until_shift(None, None, ast);
+    fn parse_end(&mut self, ast: &mut Ast) -> Result<(), RuntimeError> {
+        self.parse_until_shift(Some(Tok::End), None, None, ast)?;
+        self.parse_until_shift(None, None, None, ast)
     }
 
     pub fn install_prod(&mut self, head: Tok, body: &Vec<Tok>, action: Option<fn(&mut Ast)>) {
@@ -152,6 +188,16 @@ impl Parser {
         self.install_stmt_decl();   // STMT => DECL
         self.install_stmt_control();// STMT => CONTROL
         self.install_stmt_expr();   // STMT => EXPR ;
+        self.install_stmt_throw();  // STMT => THROW_KW EXPR ;
+        self.install_stmt_return(); // STMT => RETURN_KW EXPR ;
+        self.install_stmt_return_nil(); // STMT => RETURN_KW ;
+        self.install_import();      // STMT => IMPORT_KW STRING AS_KW VAR [;]
+        self.install_from_import_path(); // STMT => FROM_KW STRING IMPORT_KW NAME_LIST [;]
+        self.install_from_import_var();  // STMT => FROM_KW VAR IMPORT_KW NAME_LIST [;]
+
+        // NAME_LIST
+        self.install_name_list_comma(); // NAME_LIST => VAR , NAME_LIST
+        self.install_name_list_last();  // NAME_LIST => VAR
 
         // DECL
         self.install_decl_var();    // DECL => VAR = EXPR ;
@@ -161,21 +207,58 @@ impl Parser {
         // self.install_if();          // CONTROL => IF_KW EXPR BLOCK
         // self.install_if_else();     // CONTROL => IF_KW EXPR BLOCK ELSE BLOCK
         // self.install_while();       // CONTROL => WHILE_KW EXPR BLOCK
-        // self.install_continue();    // CONTROL => CONTINUE_KW
-        // self.install_return();      // CONTROL => RETURN EXPRLIST
-        
-        // EXPR
-        // self.install_if();          // CONTROL => IF_KW EXPR BLOCK
-        // self.install_if_else();     // CONTROL => IF_KW EXPR BLOCK ELSE BLOCK
-        // self.install_while();       // CONTROL => WHILE_KW EXPR BLOCK
-        // self.install_continue();    // CONTROL => CONTINUE_KW
-        // self.install_return();      // CONTROL => RETURN EXPRLIST
-        //
-        //
-        // EXPRLIST
-        //
-        // PARAMS
-        //
+        self.install_for();            // CONTROL => FOR_KW VAR IN_KW EXPR BLOCK
+        self.install_for_pair();       // CONTROL => FOR_KW VAR , VAR IN_KW EXPR BLOCK
+        self.install_break();          // CONTROL => BREAK_KW
+        self.install_continue();       // CONTROL => CONTINUE_KW
+        self.install_try_catch();      // CONTROL => TRY_KW BLOCK CATCH_KW VAR BLOCK
+        self.install_try_finally();    // CONTROL => TRY_KW BLOCK FINALLY_KW BLOCK
+        self.install_try_catch_finally(); // CONTROL => TRY_KW BLOCK CATCH_KW VAR BLOCK FINALLY_KW BLOCK
+
+        // EXPR, one level per precedence so that the grammar stays unambiguous
+        self.install_expr_add();       // EXPR => EXPR ADD_OP TERM
+        self.install_expr_term();      // EXPR => TERM
+
+        // TERM
+        self.install_term_mul();       // TERM => TERM MUL_OP OPERAND
+        self.install_term_operand();   // TERM => OPERAND
+
+        // OPERAND
+        self.install_expr_int();       // OPERAND => INT
+        self.install_expr_string();    // OPERAND => STRING
+        self.install_expr_var();       // OPERAND => VAR
+        self.install_expr_nested();    // OPERAND => ( EXPR )
+        self.install_expr_call();      // OPERAND => FUNC_CALL
+        self.install_expr_field();     // OPERAND => VAR . VAR
+
+        // ADD_OP, MUL_OP
+        self.install_add_ops();        // ADD_OP => + | -
+        self.install_mul_ops();        // MUL_OP => %
+
+        // FUNC_CALL
+        self.install_call();           // FUNC_CALL => VAR ( EXPR_LIST )
+        self.install_field_call();     // FUNC_CALL => VAR . VAR ( EXPR_LIST )
+
+        // KEYWORD_ARG
+        self.install_keyword_arg();    // KEYWORD_ARG => VAR : EXPR
+
+        // EXPR_LIST
+        self.install_expr_list_comma(); // EXPR_LIST => EXPR , EXPR_LIST
+        self.install_expr_list_last();  // EXPR_LIST => EXPR
+        self.install_expr_list_keyword_comma(); // EXPR_LIST => KEYWORD_ARG , EXPR_LIST
+        self.install_expr_list_keyword_last();  // EXPR_LIST => KEYWORD_ARG
+        self.install_expr_list_placeholder_comma(); // EXPR_LIST => _ , EXPR_LIST
+        self.install_expr_list_placeholder_last();  // EXPR_LIST => _
+        self.install_expr_list_empty(); // EXPR_LIST => EMPTY
+
+        // VAR_LIST
+        self.install_arg_list_comma();  // VAR_LIST => VAR , VAR_LIST
+        self.install_arg_list_last();   // VAR_LIST => VAR
+        self.install_param_default_comma(); // VAR_LIST => VAR = EXPR , VAR_LIST
+        self.install_param_default_last();  // VAR_LIST => VAR = EXPR
+        self.install_param_rest();      // VAR_LIST => * VAR
+        self.install_arg_list_empty();  // VAR_LIST => EMPTY
+
         // VALUE
     }
 }
@@ -1,23 +1,27 @@
+use crate::ast::Ast;
 use crate::parser::Parser;
 use crate::tokens::Tok;
 
 impl Parser {
-    /*
     pub fn install_stmt_return(&mut self) {
         fn action(ast: &mut Ast) {
             let expr = ast.node_stack.pop().unwrap();
-            let mut return_kw = ast.new_node(Tok::ReturnKW, None);
-            return_kw.children.push(expr);
+            let mut return_kw = ast.node_stack.pop().unwrap();
 
+            return_kw.children.push(expr);
             ast.node_stack.push(return_kw);
         }
 
         self.install_prod(
-            Tok::Stmt, 
-            &vec![Tok::ReturnKW, Tok::Expr], 
-            Some(action));
+            Tok::Stmt,
+            &vec![Tok::ReturnKW, Tok::Expr, Tok::SemiColon],
+            Some(action),
+        );
+    }
+
+    pub fn install_stmt_return_nil(&mut self) {
+        self.install_prod(Tok::Stmt, &vec![Tok::ReturnKW, Tok::SemiColon], None);
     }
-    */
 
     pub fn install_stmt_decl(&mut self) {
         self.install_prod(Tok::Stmt, &vec![Tok::Decl], None);
@@ -0,0 +1,230 @@
+use crate::ast::{Ast, Node, NodeVal};
+use crate::error::{err_parser, err_parser_wpos, spos, RuntimeError, SourcePos};
+use crate::lexer::{closing_brace, source_pos, unescape_raw, Lexer};
+use crate::parser::Parser;
+use crate::tokens::Tok;
+
+/// A piece of a string literal: either text, with its escapes still unprocessed, or the source of
+/// an embedded `${...}` expression along with its byte offset into the literal's contents
+#[derive(Debug, PartialEq)]
+enum Segment<'a> {
+    Text(&'a str),
+    Expr(&'a str, usize),
+}
+
+impl Parser {
+    /// Push a node for a string literal. A plain string becomes a String node; a template string
+    /// containing `${...}` becomes a Template node whose children are the String and expression
+    /// nodes of its segments, in reverse source order.
+    pub fn shift_string(
+        &mut self,
+        quoted: &str,
+        pos: Option<SourcePos>,
+        ast: &mut Ast,
+    ) -> Result<(), RuntimeError> {
+        let raw = &quoted[1..quoted.len() - 1];
+
+        // Positions within the contents are relative to the opening quote
+        let locate = |offset: usize| pos.map(|pos| offset_pos(pos, source_pos(quoted, offset + 1)));
+
+        let segments = split_template(raw).map_err(|(offset, reason)| match locate(offset) {
+            Some(pos) => err_parser_wpos(pos, reason),
+            None => err_parser(reason),
+        })?;
+
+        if !segments.iter().any(|s| matches!(s, Segment::Expr(..))) {
+            let value = unescape_raw(raw)?;
+            ast.push_node(Tok::String, Some(NodeVal::String(value)));
+            return Ok(());
+        }
+
+        let mut template = ast.new_node(Tok::Template, None);
+
+        for segment in segments {
+            let node = match segment {
+                Segment::Text(text) => {
+                    ast.new_node(Tok::String, Some(NodeVal::String(unescape_raw(text)?)))
+                }
+                Segment::Expr(source, offset) => {
+                    self.parse_embedded(source, ast).map_err(|mut e| {
+                        if let Some(expr_pos) = locate(offset) {
+                            let inner = e.error_pos().unwrap_or(spos(1, 0));
+                            e.set_pos(offset_pos(expr_pos, inner));
+                        }
+                        e
+                    })?
+                }
+            };
+
+            template.children.push(node);
+        }
+
+        template.children.reverse();
+        ast.node_stack.push(template);
+
+        Ok(())
+    }
+
+    /// Parse the source of an embedded expression into a node, using the same Ast so that symbol
+    /// ids are shared with the enclosing script
+    fn parse_embedded(&mut self, source: &str, ast: &mut Ast) -> Result<Node, RuntimeError> {
+        if source.trim().is_empty() {
+            return Err(err_parser("Empty expression in ${}"));
+        }
+
+        let parser = self
+            .template_parser
+            .get_or_insert_with(|| Box::new(Parser::init()));
+
+        // Parsed as an expression statement, which is then unwrapped
+        let mut lexer = Lexer::init();
+        lexer.open_str(&format!("{};", source));
+        parser.build_ast(&mut lexer, ast)?;
+
+        let mut stmts = ast.node_stack.pop().unwrap();
+
+        match (stmts.children.pop(), stmts.children.is_empty()) {
+            (Some(expr), true) if is_expression(expr.token) => Ok(expr),
+            _ => Err(err_parser("Expected a single expression in ${...}")),
+        }
+    }
+}
+
+fn is_expression(tok: Tok) -> bool {
+    match tok {
+        Tok::Int
+        | Tok::String
+        | Tok::Var
+        | Tok::FuncCall
+        | Tok::Template
+        | Tok::Plus
+        | Tok::Minus
+        | Tok::Modulo => true,
+        _ => false,
+    }
+}
+
+/// Convert a position relative to some point in the source to an absolute position, given the
+/// absolute position of that point
+fn offset_pos(base: SourcePos, relative: SourcePos) -> SourcePos {
+    match relative.line {
+        1 => spos(base.line, base.column + relative.column),
+        line => spos(base.line + line - 1, relative.column),
+    }
+}
+
+/// Split the contents of a string literal into text and `${...}` expression segments. Errors
+/// carry the byte offset of the problem.
+fn split_template(raw: &str) -> Result<Vec<Segment>, (usize, &'static str)> {
+    let bytes = raw.as_bytes();
+    let mut segments = Vec::new();
+    let mut text_start = 0;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 2,
+
+            b'$' if bytes.get(i + 1) == Some(&b'{') => {
+                let expr_start = i + 2;
+                let expr_end = closing_brace(bytes, expr_start)
+                    .ok_or((i, "Unterminated ${ in string"))?;
+
+                if text_start < i {
+                    segments.push(Segment::Text(&raw[text_start..i]));
+                }
+                segments.push(Segment::Expr(&raw[expr_start..expr_end], expr_start));
+
+                i = expr_end + 1;
+                text_start = i;
+            }
+
+            _ => i += 1,
+        }
+    }
+
+    if text_start < bytes.len() {
+        segments.push(Segment::Text(&raw[text_start..]));
+    }
+
+    Ok(segments)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{split_template, Segment};
+    use crate::ast::Ast;
+    use crate::error::RuntimeError;
+    use crate::generator::Generator;
+    use crate::lexer::Lexer;
+    use crate::memory::{Memory, Mutator, MutatorView};
+    use crate::parser::Parser;
+    use crate::vm::Thread;
+
+    struct Run<'a>(&'a Generator);
+
+    impl<'a> Mutator for Run<'a> {
+        type Input = &'static [&'static str];
+        type Output = Vec<String>;
+
+        fn run(
+            &self,
+            mem: &MutatorView,
+            names: &'static [&'static str],
+        ) -> Result<Vec<String>, RuntimeError> {
+            let thread = Thread::alloc(mem)?;
+            thread.quick_vm_eval(mem, self.0.function(mem)?)?;
+
+            Ok(names
+                .iter()
+                .map(|name| format!("{}", thread.global(mem, name).unwrap()))
+                .collect())
+        }
+    }
+
+    /// Lex, parse, compile and run a whole script, returning the printed value of each global
+    fn run_script(source: &str, names: &'static [&'static str]) -> Vec<String> {
+        let mut lexer = Lexer::init();
+        lexer.open_str(source);
+
+        let mut parser = Parser::init();
+        let mut ast = Ast::init();
+        let mut generator = Generator::init();
+        parser.build_ast(&mut lexer, &mut ast).unwrap();
+        ast.traverse(&mut generator).unwrap();
+
+        Memory::new().mutate(&Run(&generator), names).unwrap()
+    }
+
+    #[test]
+    fn split_template_segments() {
+        assert!(split_template("plain").unwrap() == vec![Segment::Text("plain")]);
+
+        assert!(
+            split_template("a ${x} b").unwrap()
+                == vec![Segment::Text("a "), Segment::Expr("x", 4), Segment::Text(" b")]
+        );
+
+        // nested quotes and braces stay inside the expression
+        assert!(
+            split_template(r#"${f("}", "${g("{")}")}!"#).unwrap()
+                == vec![Segment::Expr(r#"f("}", "${g("{")}")"#, 2), Segment::Text("!")]
+        );
+
+        // an escaped dollar is text
+        assert!(split_template(r#"\${x}"#).unwrap() == vec![Segment::Text(r#"\${x}"#)]);
+
+        assert!(split_template("a ${x").unwrap_err() == (2, "Unterminated ${ in string"));
+    }
+
+    #[test]
+    fn templates_with_nested_quotes_in_scripts() {
+        let source = "inner = \"${\"in\"}\"\n\
+                      nested = \"a ${upper(\"b${\"c\"}\")} ${str(12)}!\"\n\
+                      brace = \"<${str(\"}\")}>\"\n\
+                      after = \"x\"\n";
+
+        let globals = run_script(source, &["inner", "nested", "brace", "after"]);
+        assert!(globals == vec!["\"in\"", "\"a BC 12!\"", "\"<}>\"", "\"x\""]);
+    }
+}
@@ -38,4 +38,63 @@ impl Parser {
 
         self.install_prod(Tok::VarList, &vec![], Some(action));
     }
+
+    /// A parameter with a default is an Eq node named for the parameter, holding the default
+    pub fn install_param_default_comma(&mut self) {
+        fn action(ast: &mut Ast) {
+            let mut var_list = ast.node_stack.pop().unwrap();
+            let expr = ast.node_stack.pop().unwrap();
+            let mut eq = ast.node_stack.pop().unwrap();
+            let var = ast.node_stack.pop().unwrap();
+
+            eq.val = var.val;
+            eq.children.push(expr);
+
+            var_list.children.push(eq);
+            ast.node_stack.push(var_list);
+        }
+
+        self.install_prod(
+            Tok::VarList,
+            &vec![Tok::Var, Tok::Eq, Tok::Expr, Tok::Comma, Tok::VarList],
+            Some(action),
+        );
+    }
+
+    pub fn install_param_default_last(&mut self) {
+        fn action(ast: &mut Ast) {
+            let expr = ast.node_stack.pop().unwrap();
+            let mut eq = ast.node_stack.pop().unwrap();
+            let var = ast.node_stack.pop().unwrap();
+            let mut var_list = ast.new_node(Tok::VarList, None);
+
+            eq.val = var.val;
+            eq.children.push(expr);
+
+            var_list.children.push(eq);
+            ast.node_stack.push(var_list);
+        }
+
+        self.install_prod(
+            Tok::VarList,
+            &vec![Tok::Var, Tok::Eq, Tok::Expr],
+            Some(action),
+        );
+    }
+
+    /// The rest parameter is a Star node named for the parameter, and always comes last
+    pub fn install_param_rest(&mut self) {
+        fn action(ast: &mut Ast) {
+            let var = ast.node_stack.pop().unwrap();
+            let mut star = ast.node_stack.pop().unwrap();
+            let mut var_list = ast.new_node(Tok::VarList, None);
+
+            star.val = var.val;
+
+            var_list.children.push(star);
+            ast.node_stack.push(var_list);
+        }
+
+        self.install_prod(Tok::VarList, &vec![Tok::Star, Tok::Var], Some(action));
+    }
 }
@@ -5,6 +5,7 @@ use std::slice::from_raw_parts_mut;
 pub use crate::allocator::ArraySize;
 
 use crate::error::{ErrorKind, RuntimeError};
+use crate::gc::write_barrier_raw_array;
 use crate::memory::MutatorView;
 
 pub const DEFAULT_ARRAY_SIZE: ArraySize = 8;
@@ -57,6 +58,22 @@ impl<T: Sized> RawArray<T> {
         })
     }
 
+    /// Allocate the backing storage in the compilation arena. If the array is later resized, its
+    /// new storage goes onto the heap and the old is freed with the arena.
+    pub fn with_capacity_in_arena<'scope>(
+        mem: &'scope MutatorView,
+        capacity: u32,
+    ) -> Result<RawArray<T>, RuntimeError> {
+        let capacity_bytes = capacity
+            .checked_mul(size_of::<T>() as ArraySize)
+            .ok_or(RuntimeError::new(ErrorKind::BadAllocationRequest))?;
+
+        Ok(RawArray {
+            capacity,
+            ptr: NonNull::new(mem.alloc_array_in_arena(capacity_bytes)?.as_ptr() as *mut T),
+        })
+    }
+
     /// TODO the inner implementation of this should live in the allocator API to make
     pub fn resize<'scope>(
         &mut self,
@@ -95,11 +112,15 @@ impl<T: Sized> RawArray<T> {
                 self.ptr = NonNull::new(new_ptr);
                 self.capacity = new_capacity;
 
+                // the owning container may be old and now points at new storage
+                write_barrier_raw_array(self);
+
                 Ok(())
             }
 
             None => {
                 *self = Self::with_capacity(mem, new_capacity)?;
+                write_barrier_raw_array(self);
                 Ok(())
             }
         }
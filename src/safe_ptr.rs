@@ -1,10 +1,12 @@
 use std::cell::Cell;
 use std::fmt;
 use std::ops::Deref;
+use std::ptr::NonNull;
 
 use crate::allocator::AllocObject;
 use crate::raw_ptr::RawPtr;
 
+use crate::gc::{write_barrier, write_barrier_tagged};
 use crate::header::TypeList;
 use crate::ptr_ops::ScopedRef;
 use crate::printer::Print;
@@ -98,12 +100,20 @@ impl<'guard, T: Sized + PartialEq> PartialEq for ScopedPtr<'guard, T> {
 /// structures with interior mutability, allowing pointers to be updated to point at different
 /// target objects.
 // ANCHOR: DefCellPtr
-#[derive(Clone)]
 pub struct CellPtr<T: Sized> {
     inner: Cell<RawPtr<T>>,
 }
 // ANCHOR_END: DefCellPtr
 
+/// Cloning copies the pointer, not the object, so the target type need not be Clone
+impl<T: Sized> Clone for CellPtr<T> {
+    fn clone(&self) -> CellPtr<T> {
+        CellPtr {
+            inner: Cell::new(self.inner.get()),
+        }
+    }
+}
+
 impl<T: Sized> CellPtr<T> {
     /// Construct a new CellPtr from a ScopedPtr
     pub fn new_with(source: ScopedPtr<T>) -> CellPtr<T> {
@@ -121,7 +131,14 @@ impl<T: Sized> CellPtr<T> {
     // the explicit 'guard lifetime bound to MutatorScope is omitted here since the ScopedPtr
     // carries this lifetime already so we can assume that this operation is safe
     pub fn set(&self, source: ScopedPtr<T>) {
-        self.inner.set(RawPtr::new(source.value))
+        let ptr = RawPtr::new(source.value);
+        write_barrier(ptr.as_untyped());
+        self.inner.set(ptr)
+    }
+
+    /// Return the untyped address of the object pointed to
+    pub fn as_untyped(&self) -> NonNull<()> {
+        self.inner.get().as_untyped()
     }
 }
 
@@ -230,11 +247,13 @@ impl TaggedCellPtr {
     /// The explicit 'guard lifetime bound to MutatorScope is omitted here since the TaggedScopedPtr
     /// carries this lifetime already so we can assume that this operation is safe
     pub fn set(&self, source: TaggedScopedPtr) {
+        write_barrier_tagged(source.ptr);
         self.inner.set(TaggedPtr::from(source.ptr))
     }
 
     /// Take the pointer of another `TaggedCellPtr` and set this instance to point at that object too
     pub fn copy_from(&self, other: &TaggedCellPtr) {
+        write_barrier_tagged(other.inner.get());
         self.inner.set(other.inner.get());
     }
 
@@ -250,6 +269,7 @@ impl TaggedCellPtr {
 
     /// Set this pointer to another TaggedPtr
     pub fn set_to_ptr(&self, ptr: TaggedPtr) {
+        write_barrier_tagged(ptr);
         self.inner.set(ptr)
     }
 
@@ -32,4 +32,9 @@ impl SymbolMap {
         self.map.borrow_mut().insert(name, ptr);
         ptr
     }
+
+    /// Return the number of bytes of block memory holding interned Symbols
+    pub fn capacity(&self) -> usize {
+        self.arena.capacity()
+    }
 }
@@ -19,10 +19,14 @@ use crate::raw_ptr::RawPtr;
 
 use crate::array::{ArrayU16, ArrayU32, ArrayU8};
 use crate::dict::Dict;
+use crate::exception::Exception;
 use crate::function::{Function, Partial};
+use crate::iter::{Iter, Range};
 use crate::list::List;
 use crate::memory::HeapStorage;
-use crate::number::NumberObject;
+use crate::module::Module;
+use crate::native::NativeFunction;
+use crate::number::{Float, NumberObject};
 use crate::pair::Pair;
 use crate::ptr_ops::{get_tag, ScopedRef, Tagged, TAG_NUMBER, TAG_OBJECT, TAG_PAIR, TAG_SYMBOL};
 use crate::printer::Print;
@@ -30,6 +34,7 @@ use crate::safe_ptr::{MutatorScope, ScopedPtr};
 use crate::symbol::Symbol;
 use crate::text::Text;
 use crate::vm::Upvalue;
+use crate::weak::WeakRef;
 
 /// A safe interface to GC-heap managed objects. The `'guard` lifetime must be a safe lifetime for
 /// the GC not to move or collect the referenced object.
@@ -41,16 +46,23 @@ pub enum Value<'guard> {
     ArrayU16(ScopedPtr<'guard, ArrayU16>),
     ArrayU32(ScopedPtr<'guard, ArrayU32>),
     Dict(ScopedPtr<'guard, Dict>),
+    Exception(ScopedPtr<'guard, Exception>),
+    Float(ScopedPtr<'guard, Float>),
     Function(ScopedPtr<'guard, Function>),
+    Iter(ScopedPtr<'guard, Iter>),
     List(ScopedPtr<'guard, List>),
+    Module(ScopedPtr<'guard, Module>),
     Nil,
     Number(isize),
+    NativeFunction(ScopedPtr<'guard, NativeFunction>),
     NumberObject(ScopedPtr<'guard, NumberObject>),
     Pair(ScopedPtr<'guard, Pair>),
     Partial(ScopedPtr<'guard, Partial>),
+    Range(ScopedPtr<'guard, Range>),
     Symbol(ScopedPtr<'guard, Symbol>),
     Text(ScopedPtr<'guard, Text>),
     Upvalue(ScopedPtr<'guard, Upvalue>),
+    WeakRef(ScopedPtr<'guard, WeakRef>),
 }
 // ANCHOR_END: DefValue
 
@@ -68,9 +80,16 @@ impl<'guard> fmt::Display for Value<'guard> {
             Value::ArrayU16(a) => a.print(self, f),
             Value::ArrayU32(a) => a.print(self, f),
             Value::Dict(d) => d.print(self, f),
+            Value::Exception(e) => e.print(self, f),
+            Value::Float(n) => n.print(self, f),
             Value::Function(n) => n.print(self, f),
+            Value::Iter(n) => n.print(self, f),
+            Value::Module(m) => m.print(self, f),
             Value::Partial(p) => p.print(self, f),
+            Value::Range(p) => p.print(self, f),
+            Value::NativeFunction(n) => n.print(self, f),
             Value::Upvalue(_) => write!(f, "Upvalue"),
+            Value::WeakRef(o) => o.print(self, f),
             _ => write!(f, "<unidentified-object-type>"),
         }
     }
@@ -83,15 +102,22 @@ impl<'guard> fmt::Debug for Value<'guard> {
             Value::ArrayU16(a) => a.debug(self, f),
             Value::ArrayU32(a) => a.debug(self, f),
             Value::Dict(d) => d.debug(self, f),
+            Value::Exception(e) => e.debug(self, f),
+            Value::Float(n) => n.debug(self, f),
             Value::Function(n) => n.debug(self, f),
+            Value::Iter(n) => n.debug(self, f),
             Value::List(a) => a.debug(self, f),
+            Value::Module(m) => m.debug(self, f),
+            Value::NativeFunction(n) => n.debug(self, f),
             Value::Nil => write!(f, "nil"),
             Value::Number(n) => write!(f, "{}", *n),
             Value::Pair(p) => p.debug(self, f),
             Value::Partial(p) => p.debug(self, f),
+            Value::Range(p) => p.debug(self, f),
             Value::Symbol(s) => s.debug(self, f),
             Value::Text(t) => t.debug(self, f),
             Value::Upvalue(_) => write!(f, "Upvalue"),
+            Value::WeakRef(o) => o.debug(self, f),
             _ => write!(f, "<unidentified-object-type>"),
         }
     }
@@ -108,16 +134,23 @@ pub enum FatPtr {
     ArrayU16(RawPtr<ArrayU16>),
     ArrayU32(RawPtr<ArrayU32>),
     Dict(RawPtr<Dict>),
+    Exception(RawPtr<Exception>),
+    Float(RawPtr<Float>),
     Function(RawPtr<Function>),
+    Iter(RawPtr<Iter>),
     List(RawPtr<List>),
+    Module(RawPtr<Module>),
     Nil,
     Number(isize),
+    NativeFunction(RawPtr<NativeFunction>),
     NumberObject(RawPtr<NumberObject>),
     Pair(RawPtr<Pair>),
     Partial(RawPtr<Partial>),
+    Range(RawPtr<Range>),
     Symbol(RawPtr<Symbol>),
     Text(RawPtr<Text>),
     Upvalue(RawPtr<Upvalue>),
+    WeakRef(RawPtr<WeakRef>),
 }
 // ANCHOR_END: DefFatPtr
 
@@ -137,12 +170,25 @@ impl FatPtr {
                 Value::ArrayU32(ScopedPtr::new(guard, raw_ptr.scoped_ref(guard)))
             }
             FatPtr::Dict(raw_ptr) => Value::Dict(ScopedPtr::new(guard, raw_ptr.scoped_ref(guard))),
+            FatPtr::Exception(raw_ptr) => {
+                Value::Exception(ScopedPtr::new(guard, raw_ptr.scoped_ref(guard)))
+            }
+            FatPtr::Float(raw_ptr) => {
+                Value::Float(ScopedPtr::new(guard, raw_ptr.scoped_ref(guard)))
+            }
             FatPtr::Function(raw_ptr) => {
                 Value::Function(ScopedPtr::new(guard, raw_ptr.scoped_ref(guard)))
             }
+            FatPtr::Iter(raw_ptr) => Value::Iter(ScopedPtr::new(guard, raw_ptr.scoped_ref(guard))),
             FatPtr::List(raw_ptr) => Value::List(ScopedPtr::new(guard, raw_ptr.scoped_ref(guard))),
+            FatPtr::Module(raw_ptr) => {
+                Value::Module(ScopedPtr::new(guard, raw_ptr.scoped_ref(guard)))
+            }
             FatPtr::Nil => Value::Nil,
             FatPtr::Number(num) => Value::Number(*num),
+            FatPtr::NativeFunction(raw_ptr) => {
+                Value::NativeFunction(ScopedPtr::new(guard, raw_ptr.scoped_ref(guard)))
+            }
             FatPtr::NumberObject(raw_ptr) => {
                 Value::NumberObject(ScopedPtr::new(guard, raw_ptr.scoped_ref(guard)))
             }
@@ -150,6 +196,7 @@ impl FatPtr {
             FatPtr::Partial(raw_ptr) => {
                 Value::Partial(ScopedPtr::new(guard, raw_ptr.scoped_ref(guard)))
             }
+            FatPtr::Range(raw_ptr) => Value::Range(ScopedPtr::new(guard, raw_ptr.scoped_ref(guard))),
             FatPtr::Symbol(raw_ptr) => {
                 Value::Symbol(ScopedPtr::new(guard, raw_ptr.scoped_ref(guard)))
             }
@@ -157,6 +204,9 @@ impl FatPtr {
             FatPtr::Upvalue(raw_ptr) => {
                 Value::Upvalue(ScopedPtr::new(guard, raw_ptr.scoped_ref(guard)))
             }
+            FatPtr::WeakRef(raw_ptr) => {
+                Value::WeakRef(ScopedPtr::new(guard, raw_ptr.scoped_ref(guard)))
+            }
         }
     }
     // ANCHOR_END: DefFatPtrAsValue
@@ -177,14 +227,21 @@ fatptr_from_rawptr!(ArrayU8, ArrayU8);
 fatptr_from_rawptr!(ArrayU16, ArrayU16);
 fatptr_from_rawptr!(ArrayU32, ArrayU32);
 fatptr_from_rawptr!(Dict, Dict);
+fatptr_from_rawptr!(Exception, Exception);
+fatptr_from_rawptr!(Float, Float);
 fatptr_from_rawptr!(Function, Function);
+fatptr_from_rawptr!(Iter, Iter);
 fatptr_from_rawptr!(List, List);
+fatptr_from_rawptr!(Module, Module);
+fatptr_from_rawptr!(NativeFunction, NativeFunction);
 fatptr_from_rawptr!(NumberObject, NumberObject);
 fatptr_from_rawptr!(Pair, Pair);
 fatptr_from_rawptr!(Partial, Partial);
+fatptr_from_rawptr!(Range, Range);
 fatptr_from_rawptr!(Symbol, Symbol);
 fatptr_from_rawptr!(Text, Text);
 fatptr_from_rawptr!(Upvalue, Upvalue);
+fatptr_from_rawptr!(WeakRef, WeakRef);
 
 /// Conversion from an integer type
 impl From<isize> for FatPtr {
@@ -244,6 +301,22 @@ impl TaggedPtr {
         unsafe { self.tag == 0 }
     }
 
+    /// Return the untagged address of the heap object this pointer refers to, if any. Nil, inline
+    /// numbers and Symbols are not allocated on the heap and return `None`.
+    pub fn as_object_ptr(&self) -> Option<NonNull<()>> {
+        unsafe {
+            if self.tag == 0 {
+                None
+            } else {
+                match get_tag(self.tag) {
+                    TAG_PAIR => Some(RawPtr::untag(self.pair).as_untyped()),
+                    TAG_OBJECT => Some(RawPtr::untag(self.object).as_untyped()),
+                    _ => None,
+                }
+            }
+        }
+    }
+
     /// Construct a generic object TaggedPtr
     fn object<T>(ptr: RawPtr<T>) -> TaggedPtr {
         TaggedPtr {
@@ -320,16 +393,23 @@ impl From<FatPtr> for TaggedPtr {
             FatPtr::ArrayU16(raw) => TaggedPtr::object(raw),
             FatPtr::ArrayU32(raw) => TaggedPtr::object(raw),
             FatPtr::Dict(raw) => TaggedPtr::object(raw),
+            FatPtr::Exception(raw) => TaggedPtr::object(raw),
+            FatPtr::Float(raw) => TaggedPtr::object(raw),
             FatPtr::Function(raw) => TaggedPtr::object(raw),
+            FatPtr::Iter(raw) => TaggedPtr::object(raw),
             FatPtr::List(raw) => TaggedPtr::object(raw),
+            FatPtr::Module(raw) => TaggedPtr::object(raw),
             FatPtr::Nil => TaggedPtr::nil(),
             FatPtr::Number(value) => TaggedPtr::number(value),
+            FatPtr::NativeFunction(raw) => TaggedPtr::object(raw),
             FatPtr::NumberObject(raw) => TaggedPtr::object(raw),
             FatPtr::Pair(raw) => TaggedPtr::pair(raw),
             FatPtr::Partial(raw) => TaggedPtr::object(raw),
+            FatPtr::Range(raw) => TaggedPtr::object(raw),
             FatPtr::Text(raw) => TaggedPtr::object(raw),
             FatPtr::Symbol(raw) => TaggedPtr::symbol(raw),
             FatPtr::Upvalue(raw) => TaggedPtr::object(raw),
+            FatPtr::WeakRef(raw) => TaggedPtr::object(raw),
         }
     }
 }
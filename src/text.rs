@@ -1,3 +1,4 @@
+use std::cmp::Ordering;
 use std::fmt;
 use std::hash::{Hash, Hasher};
 use std::slice;
@@ -9,6 +10,7 @@ use crate::memory::MutatorView;
 use crate::printer::Print;
 use crate::raw_array::{ArraySize, RawArray};
 use crate::safe_ptr::MutatorScope;
+use crate::trace::{visit_raw_array, Trace, Visitor};
 
 /// While Text is somewhat similar to Symbol, it is instead garbage-collected heap allocated and not interned.
 #[derive(Copy, Clone)]
@@ -59,6 +61,78 @@ impl Text {
     pub fn as_str<'guard>(&self, _guard: &'guard dyn MutatorScope) -> &str {
         unsafe { self.unguarded_as_str() }
     }
+
+    /// Return the number of code points in the Text
+    pub fn char_length<'guard>(&self, guard: &'guard dyn MutatorScope) -> usize {
+        self.as_str(guard).chars().count()
+    }
+
+    /// Return the code point at the given code point index as a new single-character Text
+    pub fn char_at<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        index: usize,
+    ) -> Result<Text, RuntimeError> {
+        self.slice(mem, index, index + 1)
+    }
+
+    /// Return the code points from `start` up to, but not including, `end` as a new Text
+    pub fn slice<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        start: usize,
+        end: usize,
+    ) -> Result<Text, RuntimeError> {
+        let content = self.as_str(mem);
+
+        let from = byte_offset(content, start);
+        let to = byte_offset(content, end);
+
+        match (from, to) {
+            (Some(from), Some(to)) if from <= to => Text::new_from_str(mem, &content[from..to]),
+            _ => Err(RuntimeError::new(ErrorKind::BoundsError)),
+        }
+    }
+
+    /// Return the code point index of the first occurrence of `needle`, if any
+    pub fn find<'guard>(&self, guard: &'guard dyn MutatorScope, needle: &str) -> Option<usize> {
+        let content = self.as_str(guard);
+        content
+            .find(needle)
+            .map(|offset| content[..offset].chars().count())
+    }
+
+    /// Compare two Texts by code point, lexicographically
+    pub fn compare<'guard>(&self, guard: &'guard dyn MutatorScope, other: &Text) -> Ordering {
+        self.as_str(guard).cmp(other.as_str(guard))
+    }
+}
+
+/// Return the byte offset of the code point at `index`, where an index of one past the last code
+/// point gives the length of the string
+fn byte_offset(content: &str, index: usize) -> Option<usize> {
+    content
+        .char_indices()
+        .map(|(offset, _)| offset)
+        .chain(Some(content.len()))
+        .nth(index)
+}
+
+/// Write a string with quotes, backslashes and control characters escaped the way they would be
+/// written in source code
+pub fn write_escaped(f: &mut fmt::Formatter, content: &str) -> fmt::Result {
+    for c in content.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\t' => write!(f, "\\t")?,
+            '\r' => write!(f, "\\r")?,
+            c if c.is_control() => write!(f, "\\u{{{:x}}}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    Ok(())
 }
 
 impl Print for Text {
@@ -67,8 +141,15 @@ impl Print for Text {
         guard: &'guard dyn MutatorScope,
         f: &mut fmt::Formatter,
     ) -> fmt::Result {
-        // TODO this will need to be printed with certain string escape codes embedded
-        write!(f, "\"{}\"", self.as_str(guard))
+        write!(f, "\"")?;
+        write_escaped(f, self.as_str(guard))?;
+        write!(f, "\"")
+    }
+}
+
+impl Trace for Text {
+    fn trace<'guard>(&self, _guard: &'guard dyn MutatorScope, visit: &mut Visitor) {
+        visit_raw_array(&self.content, visit);
     }
 }
 
@@ -81,7 +162,8 @@ impl Hashable for Text {
 #[cfg(test)]
 mod test {
     use super::Text;
-    use crate::error::RuntimeError;
+    use crate::error::{ErrorKind, RuntimeError};
+    use std::cmp::Ordering;
     use crate::memory::{Memory, Mutator, MutatorView};
 
     #[test]
@@ -169,4 +251,72 @@ mod test {
         let test = Test {};
         mem.mutate(&test, ()).unwrap();
     }
+
+    #[test]
+    fn text_code_point_operations() {
+        let mem = Memory::new();
+
+        struct Test {}
+        impl Mutator for Test {
+            type Input = ();
+            type Output = ();
+
+            fn run(
+                &self,
+                view: &MutatorView,
+                _input: Self::Input,
+            ) -> Result<Self::Output, RuntimeError> {
+                let text = Text::new_from_str(view, "naïve café")?;
+
+                assert!(text.char_length(view) == 10);
+                assert!(text.char_at(view, 2)?.as_str(view) == "ï");
+                assert!(text.slice(view, 6, 10)?.as_str(view) == "café");
+                assert!(text.slice(view, 10, 10)?.as_str(view) == "");
+                assert!(text.find(view, "café") == Some(6));
+                assert!(text.find(view, "tea") == None);
+
+                match text.slice(view, 3, 11) {
+                    Err(e) => assert!(*e.error_kind() == ErrorKind::BoundsError),
+                    Ok(_) => panic!("slice past the end succeeded"),
+                }
+
+                let other = Text::new_from_str(view, "naïvety")?;
+                assert!(text.compare(view, &other) == Ordering::Less);
+
+                Ok(())
+            }
+        }
+
+        let test = Test {};
+        mem.mutate(&test, ()).unwrap();
+    }
+
+    #[test]
+    fn text_prints_escaped() {
+        let mem = Memory::new();
+
+        struct Test {}
+        impl Mutator for Test {
+            type Input = ();
+            type Output = ();
+
+            fn run(
+                &self,
+                view: &MutatorView,
+                _input: Self::Input,
+            ) -> Result<Self::Output, RuntimeError> {
+                let text = Text::new_from_str(view, "say \"hi\"\n\tthen\\leave")?;
+                let heap_text = view.alloc_tagged(text)?;
+
+                let got = format!("{}", heap_text.value());
+
+                assert!(got == r#""say \"hi\"\n\tthen\\leave""#);
+
+                Ok(())
+            }
+        }
+
+        let test = Test {};
+        mem.mutate(&test, ()).unwrap();
+    }
 }
@@ -10,6 +10,15 @@ pub enum Tok {
     Block,
 
     Expr,
+    Term,
+    Operand,
+    ExprList,
+    FuncCall,
+    KeywordArg,
+    Field,
+    NameList,
+    AddOp,
+    MulOp,
 
     FuncDecl,
     Decl,
@@ -18,29 +27,58 @@ pub enum Tok {
     End,
 
     FnKW,
+    ForKW,
+    InKW,
+    BreakKW,
+    ContinueKW,
+    TryKW,
+    CatchKW,
+    FinallyKW,
+    ThrowKW,
+    ReturnKW,
+    ImportKW,
+    FromKW,
+    AsKW,
     VarList,
 
     Var,
     Int,
     String,
+    Template,
+    Placeholder,
     LeftCurly,
     RightCurly,
     LeftParen,
     RightParen,
     SemiColon,
     Minus,
+    Modulo,
     Eq,
     Comma,
+    Colon,
+    Dot,
+    Star,
     Plus
 }
 
 pub fn keyword_check(word: &str) -> Option<Tok> {
     match word {
-        /*
         "fn" => Some(Tok::FnKW),
+        /*
         "if" => Some(Tok::IfKW),
-        "return" => Some(Tok::ReturnKW),
         */
+        "return" => Some(Tok::ReturnKW),
+        "for" => Some(Tok::ForKW),
+        "in" => Some(Tok::InKW),
+        "break" => Some(Tok::BreakKW),
+        "continue" => Some(Tok::ContinueKW),
+        "try" => Some(Tok::TryKW),
+        "catch" => Some(Tok::CatchKW),
+        "finally" => Some(Tok::FinallyKW),
+        "throw" => Some(Tok::ThrowKW),
+        "import" => Some(Tok::ImportKW),
+        "from" => Some(Tok::FromKW),
+        "as" => Some(Tok::AsKW),
         _ => None,
     }
 }
@@ -55,10 +93,13 @@ impl Tok {
             | Tok::RightParen
             | Tok::SemiColon
             | Tok::Comma
-            /*
+            | Tok::Colon
+            | Tok::Dot
+            | Tok::InKW
+            | Tok::AsKW
             | Tok::FnKW
+            /*
             | Tok::LetKW
-            | Tok::ReturnKW
             */
             | Tok::End => true,
             _ => false,
@@ -0,0 +1,49 @@
+/// Heap object graph traversal
+///
+/// Defines a `Trace` trait that heap-allocated types implement to report the heap objects they
+/// point at, and helpers for turning the various pointer types into untyped object addresses
+/// that can be resolved back to an `ObjectHeader`.
+use std::ptr::NonNull;
+
+use crate::raw_array::RawArray;
+use crate::safe_ptr::{CellPtr, MutatorScope, TaggedCellPtr};
+use crate::tagged_ptr::TaggedPtr;
+
+/// A callback that is given the address of each heap object found while tracing
+pub type Visitor<'v> = dyn FnMut(NonNull<()>) + 'v;
+
+/// Implemented by heap object types that hold pointers to other heap objects.
+///
+/// `trace` must call `visit` once for every heap object directly referenced by `self`,
+/// including array backing storage. It must not recurse - the caller drives the traversal.
+// ANCHOR: DefTrace
+pub trait Trace {
+    fn trace<'guard>(&self, guard: &'guard dyn MutatorScope, visit: &mut Visitor);
+}
+// ANCHOR_END: DefTrace
+
+/// Visit the object pointed to by a `TaggedPtr`, if it points at a heap object
+pub fn visit_tagged(ptr: TaggedPtr, visit: &mut Visitor) {
+    if let Some(object) = ptr.as_object_ptr() {
+        visit(object);
+    }
+}
+
+/// Visit the object pointed to by a `TaggedCellPtr`, if it points at a heap object
+pub fn visit_tagged_cell(ptr: &TaggedCellPtr, visit: &mut Visitor) {
+    visit_tagged(ptr.get_ptr(), visit);
+}
+
+/// Visit the object pointed to by a `CellPtr`
+pub fn visit_cell<T>(ptr: &CellPtr<T>, visit: &mut Visitor) {
+    visit(ptr.as_untyped());
+}
+
+/// Visit the backing storage of a `RawArray`, if it has any
+pub fn visit_raw_array<T>(array: &RawArray<T>, visit: &mut Visitor) {
+    if let Some(ptr) = array.as_ptr() {
+        if let Some(ptr) = NonNull::new(ptr as *mut ()) {
+            visit(ptr);
+        }
+    }
+}
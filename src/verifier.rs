@@ -0,0 +1,416 @@
+/// Static checks on compiled code
+///
+/// The VM trusts its instructions: registers index straight into the register window, literal and
+/// upvalue ids index into their lists and jumps move the instruction pointer without bounds
+/// checks. Code generated by the compiler upholds these invariants, but code loaded from a file
+/// might not, so every Function is verified before the VM is allowed to run it.
+use crate::bytecode::{
+    successors, wide_operand, wide_prefix, ByteCode, Handler, JumpOffset, Opcode, JUMP_UNKNOWN,
+};
+use crate::container::{Container, SliceableContainer};
+use crate::error::{err_verify, RuntimeError};
+use crate::function::Function;
+use crate::safe_ptr::{MutatorScope, ScopedPtr};
+use crate::tagged_ptr::Value;
+use crate::vm::FIRST_ARG_REG;
+
+/// The number of registers in a call frame's register window
+const WINDOW_SIZE: usize = 256;
+
+/// Verify the code of a Function and of every Function nested in its literals
+pub fn verify_function<'guard>(
+    guard: &'guard dyn MutatorScope,
+    function: ScopedPtr<'guard, Function>,
+) -> Result<(), RuntimeError> {
+    let mut pending = vec![function];
+
+    while let Some(function) = pending.pop() {
+        let upvalue_count = match function.is_closure() {
+            true => function.nonlocals(guard).length() as usize,
+            false => 0,
+        };
+
+        let code = function.code(guard);
+        verify_bytecode(guard, &code, upvalue_count).map_err(|reason| {
+            err_verify(&format!("in function {}: {}", function.name(guard), reason))
+        })?;
+
+        code.literals().access_slice(guard, |items| {
+            for item in items.iter() {
+                if let Value::Function(f) = *item.get(guard) {
+                    pending.push(f);
+                }
+            }
+        });
+    }
+
+    Ok(())
+}
+
+/// Check every instruction in a ByteCode object, given the number of upvalues the closure
+/// environment of its Function will hold. The error describes the first invalid instruction.
+pub fn verify_bytecode<'guard>(
+    guard: &'guard dyn MutatorScope,
+    code: &ByteCode,
+    upvalue_count: usize,
+) -> Result<(), String> {
+    let opcodes = code.opcodes(guard);
+    let literal_count = code.literals().length() as usize;
+
+    // A function may end by throwing, as one whose last statement is `throw` does
+    match opcodes.last() {
+        Some(Opcode::Return { .. }) | Some(Opcode::Throw { .. }) => (),
+        Some(_) => return Err(String::from("code does not end with a Return or a Throw")),
+        None => return Err(String::from("code is empty")),
+    }
+
+    for (index, opcode) in opcodes.iter().enumerate() {
+        verify_opcode(&opcodes, index, literal_count, upvalue_count)
+            .map_err(|reason| format!("instruction {:04} {:?}: {}", index, opcode, reason))?;
+    }
+
+    for (index, handler) in code.handlers(guard).iter().enumerate() {
+        verify_handler(&opcodes, handler)
+            .map_err(|reason| format!("handler {} {:?}: {}", index, handler, reason))?;
+    }
+
+    Ok(())
+}
+
+/// A handler must protect a range of instructions inside the code and continue from an
+/// instruction, following the same rule as a jump target
+fn verify_handler(code: &[Opcode], handler: &Handler) -> Result<(), String> {
+    if handler.start >= handler.end || handler.end as usize > code.len() {
+        return Err(String::from("protected range is empty or outside the code"));
+    }
+
+    let target = handler.target as usize;
+    if target >= code.len() {
+        return Err(format!("handler target {} is outside the code", target));
+    }
+
+    if wide_prefix(code, target).is_some() {
+        return Err(format!(
+            "handler target {} is inside a Wide instruction",
+            target
+        ));
+    }
+
+    Ok(())
+}
+
+fn verify_opcode(
+    code: &[Opcode],
+    index: usize,
+    literal_count: usize,
+    upvalue_count: usize,
+) -> Result<(), String> {
+    let upvalue = |id: u8| match (id as usize) < upvalue_count {
+        true => Ok(()),
+        false => Err(format!(
+            "upvalue {} is out of range, the function has {}",
+            id, upvalue_count
+        )),
+    };
+
+    let prefix = wide_prefix(code, index);
+
+    match code[index] {
+        Opcode::Wide { .. } => match code.get(index + 1) {
+            Some(Opcode::LoadLiteral { .. })
+            | Some(Opcode::LoadInteger { .. })
+            | Some(Opcode::Jump { .. })
+            | Some(Opcode::JumpIfTrue { .. })
+            | Some(Opcode::JumpIfNotTrue { .. }) => (),
+            _ => return Err(String::from("the next instruction cannot be extended")),
+        },
+
+        Opcode::LoadLiteral { literal_id, .. } => {
+            let literal_id = match prefix {
+                Some(high) => wide_operand(high, literal_id) as usize,
+                None => literal_id as usize,
+            };
+
+            if literal_id >= literal_count {
+                return Err(format!(
+                    "literal {} is out of range, there are {}",
+                    literal_id, literal_count
+                ));
+            }
+        }
+
+        Opcode::Jump { offset }
+        | Opcode::JumpIfTrue { offset, .. }
+        | Opcode::JumpIfNotTrue { offset, .. } => verify_jump(code, index, offset, prefix)?,
+
+        // The arguments follow the return value and closure environment registers
+        Opcode::Call {
+            dest, arg_count, ..
+        }
+        | Opcode::TailCall {
+            dest, arg_count, ..
+        }
+        | Opcode::CallKeywords {
+            dest, arg_count, ..
+        }
+        | Opcode::MakePartial {
+            dest, arg_count, ..
+        } => {
+            if dest as usize + FIRST_ARG_REG + arg_count as usize > WINDOW_SIZE {
+                return Err(String::from("arguments extend past the register window"));
+            }
+
+            if let Opcode::TailCall { .. } = code[index] {
+                if code.get(index + 1) != Some(&Opcode::Return { reg: dest }) {
+                    return Err(String::from(
+                        "a tail call must be followed by a Return of its result",
+                    ));
+                }
+            }
+        }
+
+        // The path is read again after the module's code has run in the window at `dest`
+        Opcode::Import { dest, path } if path >= dest => {
+            return Err(String::from(
+                "module path is inside the module's register window",
+            ));
+        }
+
+        Opcode::GetUpvalue { src, .. } => upvalue(src)?,
+        Opcode::SetUpvalue { dest, .. } => upvalue(dest)?,
+
+        Opcode::IterNextPair { dest, .. } if dest as usize + 1 >= WINDOW_SIZE => {
+            return Err(String::from("value register is past the register window"));
+        }
+
+        _ => (),
+    }
+
+    // Execution must continue at an instruction, and not at one extended by a Wide prefix since
+    // the prefix would be skipped, unless it continues from the prefix itself
+    for next in successors(code, index) {
+        if next >= code.len() {
+            return Err(format!(
+                "execution continues past the end of the code at {}",
+                next
+            ));
+        }
+
+        if next != index + 1 && wide_prefix(code, next).is_some() {
+            return Err(format!(
+                "execution continues inside a Wide instruction at {}",
+                next
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// A jump must land on an instruction. Offsets are relative to the instruction after the jump.
+fn verify_jump(
+    code: &[Opcode],
+    index: usize,
+    offset: JumpOffset,
+    prefix: Option<u16>,
+) -> Result<(), String> {
+    let offset = match prefix {
+        Some(high) => wide_operand(high, offset as u16) as i32 as isize,
+        None if offset == JUMP_UNKNOWN => {
+            return Err(String::from("jump offset was never patched"));
+        }
+        None => offset as isize,
+    };
+
+    let target = index as isize + 1 + offset;
+    if target < 0 || target >= code.len() as isize {
+        return Err(format!("jump target {} is outside the code", target));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::error::ErrorKind;
+    use crate::list::List;
+    use crate::memory::{Memory, Mutator, MutatorView};
+
+    #[test]
+    fn verify_rejects_invalid_code() {
+        let mem = Memory::new();
+
+        struct Test {}
+        impl Mutator for Test {
+            type Input = ();
+            type Output = ();
+
+            fn run(
+                &self,
+                mem: &MutatorView,
+                _input: Self::Input,
+            ) -> Result<Self::Output, RuntimeError> {
+                let verify = |ops: &[Opcode]| -> Result<(), RuntimeError> {
+                    let code = ByteCode::alloc(mem)?;
+                    code.push_lit(mem, mem.lookup_sym("x"))?;
+                    for op in ops {
+                        code.push(mem, *op)?;
+                    }
+                    let function =
+                        Function::alloc(mem, mem.lookup_sym("f"), List::alloc(mem)?, code, None)?;
+                    verify_function(mem, function)
+                };
+
+                let ret = Opcode::Return { reg: 0 };
+
+                // a loop that jumps back to its start is fine
+                verify(&[
+                    Opcode::LoadLiteral {
+                        dest: 0,
+                        literal_id: 0,
+                    },
+                    Opcode::JumpIfTrue {
+                        test: 0,
+                        offset: -2,
+                    },
+                    ret,
+                ])?;
+
+                // as is code that ends by throwing
+                verify(&[Opcode::Throw { reg: 0 }])?;
+
+                // as is a loop back over a Wide prefix
+                verify(&[
+                    Opcode::Wide { high: 0xffff },
+                    Opcode::JumpIfTrue {
+                        test: 0,
+                        offset: -2,
+                    },
+                    ret,
+                ])?;
+
+                let invalid: &[&[Opcode]] = &[
+                    &[],
+                    &[Opcode::NoOp],
+                    &[
+                        Opcode::LoadLiteral {
+                            dest: 0,
+                            literal_id: 1,
+                        },
+                        ret,
+                    ],
+                    &[Opcode::Jump { offset: 1 }, ret],
+                    &[Opcode::Jump { offset: -2 }, ret],
+                    &[
+                        Opcode::Jump {
+                            offset: JUMP_UNKNOWN,
+                        },
+                        ret,
+                    ],
+                    &[Opcode::GetUpvalue { dest: 0, src: 0 }, ret],
+                    &[
+                        Opcode::Call {
+                            function: 0,
+                            dest: 250,
+                            arg_count: 5,
+                        },
+                        ret,
+                    ],
+                    &[
+                        Opcode::CallKeywords {
+                            dest: 250,
+                            arg_count: 5,
+                            names: 0,
+                        },
+                        ret,
+                    ],
+                    &[
+                        Opcode::MakePartial {
+                            function: 0,
+                            dest: 250,
+                            arg_count: 5,
+                        },
+                        ret,
+                    ],
+                    &[Opcode::Import { dest: 2, path: 2 }, ret],
+                    &[Opcode::IterNextPair { dest: 255, iter: 0 }, ret, ret],
+                    &[Opcode::IterNext { dest: 0, iter: 0 }, ret],
+                    &[Opcode::Wide { high: 1 }, ret],
+                    &[
+                        Opcode::Wide { high: 1 },
+                        Opcode::LoadLiteral {
+                            dest: 0,
+                            literal_id: 0,
+                        },
+                        ret,
+                    ],
+                    &[
+                        Opcode::Jump { offset: 1 },
+                        Opcode::Wide { high: 0 },
+                        Opcode::LoadInteger {
+                            dest: 0,
+                            integer: 0,
+                        },
+                        ret,
+                    ],
+                    &[
+                        Opcode::TailCall {
+                            function: 0,
+                            dest: 2,
+                            arg_count: 0,
+                        },
+                        Opcode::Return { reg: 0 },
+                    ],
+                    &[
+                        Opcode::IterNext { dest: 0, iter: 0 },
+                        Opcode::Wide { high: 0 },
+                        Opcode::Jump { offset: 0 },
+                        ret,
+                    ],
+                ];
+
+                for ops in invalid {
+                    match verify(ops) {
+                        Err(e) => assert!(matches!(e.error_kind(), ErrorKind::VerifyError(_))),
+                        Ok(_) => panic!("{:?} should be rejected", ops),
+                    }
+                }
+
+                // exception handlers must protect code in the function and continue inside it
+                let handlers = [
+                    Handler {
+                        start: 0,
+                        end: 1,
+                        target: 1,
+                        dest: 2,
+                    },
+                    Handler {
+                        start: 0,
+                        end: 0,
+                        target: 0,
+                        dest: 2,
+                    },
+                ];
+
+                for handler in handlers {
+                    let code = ByteCode::alloc(mem)?;
+                    code.push(mem, ret)?;
+                    code.push_handler(mem, handler)?;
+                    let function =
+                        Function::alloc(mem, mem.lookup_sym("f"), List::alloc(mem)?, code, None)?;
+
+                    match verify_function(mem, function) {
+                        Err(e) => assert!(matches!(e.error_kind(), ErrorKind::VerifyError(_))),
+                        Ok(_) => panic!("{:?} should be rejected", handler),
+                    }
+                }
+
+                Ok(())
+            }
+        }
+
+        let test = Test {};
+        mem.mutate(&test, ()).unwrap();
+    }
+}
@@ -1,24 +1,57 @@
 use std::cell::Cell;
+use std::ptr::NonNull;
 
 use crate::array::{Array, ArraySize};
-use crate::bytecode::{ByteCode, InstructionStream, Opcode};
+use crate::builtins;
+use crate::bytecode::{wide_operand, ByteCode, InstructionStream, Opcode};
 use crate::container::{
     Container, FillAnyContainer, HashIndexedAnyContainer, IndexedAnyContainer, IndexedContainer,
     SliceableContainer, StackAnyContainer, StackContainer,
 };
 use crate::dict::Dict;
-use crate::error::{err_eval, RuntimeError};
+use crate::error::{err_eval, err_stack_overflow, ErrorKind, RuntimeError};
+use crate::exception::{is_catchable, Exception};
+use crate::gc::{full_collection_from, minor_collection_from, MarkStats};
+use crate::heapdump::{Root, RootKind};
 use crate::function::{Function, Partial};
+use crate::iter::{Iter, MAX_INLINE_INTEGER, MIN_INLINE_INTEGER};
 use crate::list::List;
 use crate::memory::MutatorView;
+use crate::module::Module;
+use crate::native::NativeFunction;
+use crate::number::{floor_mod, floor_mod_float, Float, Numeric};
 use crate::pair::Pair;
 use crate::safe_ptr::{CellPtr, MutatorScope, ScopedPtr, TaggedCellPtr, TaggedScopedPtr};
 use crate::tagged_ptr::{TaggedPtr, Value};
+use crate::text::Text;
+use crate::trace::{visit_cell, visit_tagged_cell, Trace, Visitor};
+use crate::verifier::verify_function;
 
 pub const RETURN_REG: usize = 0;
 pub const ENV_REG: usize = 1;
 pub const FIRST_ARG_REG: usize = 2;
 
+/// The number of frames shown at each end of a traceback before the middle is elided
+const TRACEBACK_FRAMES: usize = 10;
+
+/// Limits on how deep a Thread may recurse before raising a stack overflow error
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StackLimits {
+    /// The maximum number of call frames
+    pub max_call_depth: ArraySize,
+    /// The maximum number of value stack slots, counting each frame's full register window
+    pub max_stack_size: ArraySize,
+}
+
+impl Default for StackLimits {
+    fn default() -> StackLimits {
+        StackLimits {
+            max_call_depth: 10_000,
+            max_stack_size: 1024 * 1024,
+        }
+    }
+}
+
 /// Evaluation control flow flags
 #[derive(PartialEq)]
 pub enum EvalStatus<'guard> {
@@ -26,6 +59,8 @@ pub enum EvalStatus<'guard> {
     Pending,
     /// Eval is complete, here is the resulting value
     Return(TaggedScopedPtr<'guard>),
+    /// An instruction threw this value, which must be caught before eval can continue
+    Throw(TaggedScopedPtr<'guard>),
 }
 
 /// A call frame, separate from the register stack
@@ -38,16 +73,28 @@ pub struct CallFrame {
     ip: Cell<ArraySize>,
     /// Stack base - index into the register stack where register window for this function begins
     base: ArraySize,
+    /// A List of registers spilled by the compiler, or nil until the first one is spilled
+    locals: TaggedCellPtr,
+    /// Which parameters with defaults the caller supplied, as returned by `bind_arguments`
+    supplied: Cell<u64>,
+    /// The Dict the function looks up and binds global names in
+    globals: CellPtr<Dict>,
 }
 // ANCHOR_END: DefCallFrame
 
 impl CallFrame {
     /// Instantiate an outer-level call frame at the beginning of the stack
-    pub fn new_main<'guard>(main_fn: ScopedPtr<'guard, Function>) -> CallFrame {
+    pub fn new_main<'guard>(
+        main_fn: ScopedPtr<'guard, Function>,
+        globals: ScopedPtr<'guard, Dict>,
+    ) -> CallFrame {
         CallFrame {
             function: CellPtr::new_with(main_fn),
             ip: Cell::new(0),
             base: 0,
+            locals: TaggedCellPtr::new_nil(),
+            supplied: Cell::new(0),
+            globals: CellPtr::new_with(globals),
         }
     }
 
@@ -57,11 +104,15 @@ impl CallFrame {
         function: ScopedPtr<'guard, Function>,
         ip: ArraySize,
         base: ArraySize,
+        globals: ScopedPtr<'guard, Dict>,
     ) -> CallFrame {
         CallFrame {
             function: CellPtr::new_with(function),
             ip: Cell::new(ip),
             base,
+            locals: TaggedCellPtr::new_nil(),
+            supplied: Cell::new(0),
+            globals: CellPtr::new_with(globals),
         }
     }
 
@@ -78,6 +129,19 @@ impl CallFrame {
 pub type CallFrameList = Array<CallFrame>;
 // ANCHOR_END: DefCallFrameList
 
+impl Trace for CallFrameList {
+    fn trace<'guard>(&self, guard: &'guard dyn MutatorScope, visit: &mut Visitor) {
+        self.trace_backing(visit);
+        self.access_slice(guard, |frames| {
+            for frame in frames.iter() {
+                visit_cell(&frame.function, visit);
+                visit_tagged_cell(&frame.locals, visit);
+                visit_cell(&frame.globals, visit);
+            }
+        });
+    }
+}
+
 /// A closure upvalue as generally described by Lua 5.1 implementation.
 /// There is one main difference - in the Lua (and Crafting Interpreters) documentation, an upvalue
 /// is closed by pointing the `location` pointer at the `closed` pointer directly in the struct.
@@ -150,6 +214,12 @@ impl Upvalue {
     }
 }
 
+impl Trace for Upvalue {
+    fn trace<'guard>(&self, _guard: &'guard dyn MutatorScope, visit: &mut Visitor) {
+        visit_tagged_cell(&self.value, visit);
+    }
+}
+
 /// Get the Upvalue for the index into the given closure environment.
 /// Function will panic if types are not as expected.
 fn env_upvalue_lookup<'guard>(
@@ -170,6 +240,141 @@ fn env_upvalue_lookup<'guard>(
     }
 }
 
+/// Get the Iter held in a register, as written by the GetIter opcode
+fn iter_register<'guard>(
+    guard: &'guard dyn MutatorScope,
+    reg: &TaggedCellPtr,
+) -> Result<ScopedPtr<'guard, Iter>, RuntimeError> {
+    match *reg.get(guard) {
+        Value::Iter(iter) => Ok(iter),
+        _ => Err(err_eval("Register does not contain an iterator")),
+    }
+}
+
+/// Get the names of the keyword arguments to a CallKeywords from the List of Symbols in `reg`.
+/// There may be no more names than arguments.
+fn keyword_names<'guard>(
+    guard: &'guard dyn MutatorScope,
+    reg: &TaggedCellPtr,
+    arg_count: u8,
+) -> Result<Vec<&'guard str>, RuntimeError> {
+    let list = match *reg.get(guard) {
+        Value::List(list) => list,
+        _ => return Err(err_eval("Keyword argument names must be a List")),
+    };
+
+    let names = list.access_slice(guard, |items| {
+        items
+            .iter()
+            .map(|item| match *item.get(guard) {
+                Value::Symbol(name) => Ok(name.as_str(guard)),
+                _ => Err(err_eval("Keyword argument names must be Symbols")),
+            })
+            .collect::<Result<Vec<&'guard str>, RuntimeError>>()
+    })?;
+
+    if names.len() > arg_count as usize {
+        return Err(err_eval("More keyword argument names than arguments"));
+    }
+    Ok(names)
+}
+
+/// Call a NativeFunction, which runs to completion without a call frame of its own
+fn call_native<'guard>(
+    mem: &'guard MutatorView,
+    native: ScopedPtr<'guard, NativeFunction>,
+    args: &[TaggedCellPtr],
+    keywords: &[&str],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    if !keywords.is_empty() {
+        return Err(err_eval(&format!(
+            "Function {} does not take keyword arguments",
+            native.name(mem)
+        )));
+    }
+
+    if args.len() > u8::MAX as usize || !native.accepts(args.len() as u8) {
+        let expected = match native.arity() == native.max_arity() {
+            true => format!("{}", native.arity()),
+            false => format!("{} to {}", native.arity(), native.max_arity()),
+        };
+        return Err(err_eval(&format!(
+            "Function {} expected {} arguments, got {}",
+            native.name(mem),
+            expected,
+            args.len()
+        )));
+    }
+
+    native.call(mem, args)
+}
+
+/// Read the List of placeholder positions for a MakePartial from the given register
+fn placeholder_positions<'guard>(
+    guard: &'guard dyn MutatorScope,
+    reg: &TaggedCellPtr,
+) -> Result<Vec<u8>, RuntimeError> {
+    let list = match *reg.get(guard) {
+        Value::List(list) => list,
+        _ => return Err(err_eval("Placeholder positions must be a List")),
+    };
+
+    list.access_slice(guard, |items| {
+        items
+            .iter()
+            .map(|item| match *item.get(guard) {
+                Value::Number(n) if (0..=u8::MAX as isize).contains(&n) => Ok(n as u8),
+                _ => Err(err_eval("Placeholder positions must be argument indexes")),
+            })
+            .collect()
+    })
+}
+
+/// Apply a checked integer operation to the values in two registers. Both values must be inline
+/// integers and so must the result.
+fn integer_op<'guard>(
+    guard: &'guard dyn MutatorScope,
+    left: &TaggedCellPtr,
+    right: &TaggedCellPtr,
+    op: &str,
+    apply: fn(isize, isize) -> Option<isize>,
+) -> Result<TaggedPtr, RuntimeError> {
+    match (*left.get(guard), *right.get(guard)) {
+        (Value::Number(l), Value::Number(r)) => match apply(l, r) {
+            Some(n) if n >= MIN_INLINE_INTEGER && n <= MAX_INLINE_INTEGER => Ok(TaggedPtr::number(n)),
+            _ => Err(err_eval(&format!("Integer overflow in {} {} {}", l, op, r))),
+        },
+        (l, r) => Err(err_eval(&format!("Cannot apply {} to {} and {}", op, l, r))),
+    }
+}
+
+/// Apply an arithmetic operation to the numbers in two registers. Two integers give a checked
+/// integer result, as in `integer_op`; if either is a Float the result is a Float.
+fn numeric_op<'guard>(
+    mem: &'guard MutatorView,
+    left: &TaggedCellPtr,
+    right: &TaggedCellPtr,
+    op: &str,
+    apply: fn(isize, isize) -> Option<isize>,
+    apply_float: fn(f64, f64) -> f64,
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let (left, right) = (left.get(mem), right.get(mem));
+
+    match (Numeric::from_value(*left), Numeric::from_value(*right)) {
+        (Some(Numeric::Integer(l)), Some(Numeric::Integer(r))) => match apply(l, r) {
+            Some(n) if n >= MIN_INLINE_INTEGER && n <= MAX_INLINE_INTEGER => {
+                Ok(TaggedScopedPtr::new(mem, TaggedPtr::number(n)))
+            }
+            _ => Err(err_eval(&format!("Integer overflow in {} {} {}", l, op, r))),
+        },
+        (Some(l), Some(r)) => Float::alloc_tagged(mem, apply_float(l.as_f64(), r.as_f64())),
+        _ => Err(err_eval(&format!(
+            "Cannot apply {} to {} and {}",
+            op, left, right
+        ))),
+    }
+}
+
 /// An execution Thread object.
 /// It is composed of all the data structures required for execution of a bytecode stream -
 /// register stack, call frames, closure upvalues, thread-local global associations and the current
@@ -187,8 +392,14 @@ pub struct Thread {
     upvalues: CellPtr<Dict>,
     /// A dict that should only contain Symbol keys but any type as values
     globals: CellPtr<Dict>,
+    /// A dict of the loaded modules, keyed by the Symbol of each one's canonical path
+    modules: CellPtr<Dict>,
+    /// The command line arguments given after the script path, as a list of strings
+    args: CellPtr<List>,
     /// The current instruction location
     instr: CellPtr<InstructionStream>,
+    /// The call depth and value stack size past which a call raises a stack overflow error
+    limits: Cell<StackLimits>,
 }
 // ANCHOR_END: DefThread
 
@@ -208,8 +419,15 @@ impl Thread {
         // create an empty upvalue stack->heap mapping
         let upvalues = Dict::alloc(mem)?;
 
-        // create an empty globals dict
+        // create a globals dict holding the builtin functions
         let globals = Dict::alloc(mem)?;
+        builtins::install(mem, &globals)?;
+
+        // create an empty module registry
+        let modules = Dict::alloc(mem)?;
+
+        // create an empty list of script arguments
+        let args = List::alloc(mem)?;
 
         // create an empty instruction stream
         let blank_code = ByteCode::alloc(mem)?;
@@ -221,10 +439,143 @@ impl Thread {
             stack_base: Cell::new(0),
             upvalues: CellPtr::new_with(upvalues),
             globals: CellPtr::new_with(globals),
+            modules: CellPtr::new_with(modules),
+            args: CellPtr::new_with(args),
             instr: CellPtr::new_with(instr),
+            limits: Cell::new(StackLimits::default()),
         })
     }
 
+    /// Replace the limits on call depth and value stack size
+    pub fn set_limits(&self, limits: StackLimits) {
+        self.limits.set(limits);
+    }
+
+    /// Return the command line arguments given to the script
+    pub fn args<'guard>(&self, guard: &'guard dyn MutatorScope) -> ScopedPtr<'guard, List> {
+        self.args.get(guard)
+    }
+
+    /// Replace the command line arguments given to the script
+    pub fn set_args<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        args: &[String],
+    ) -> Result<(), RuntimeError> {
+        let list = List::alloc_with_capacity(mem, args.len() as ArraySize)?;
+        for arg in args {
+            let text = mem.alloc_tagged(Text::new_from_str(mem, arg)?)?;
+            StackAnyContainer::push(&*list, mem, text)?;
+        }
+
+        self.args.set(list);
+        Ok(())
+    }
+
+    /// Register the top-level code of a module under the canonical path of its source file, to
+    /// be run by the first Import of it. The Function and any Functions nested in it are verified
+    /// first.
+    pub fn add_module<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        path: &str,
+        function: ScopedPtr<'guard, Function>,
+    ) -> Result<(), RuntimeError> {
+        verify_function(mem, function)?;
+
+        let module = Module::alloc(mem, path, function)?;
+        self.modules
+            .get(mem)
+            .assoc(mem, mem.lookup_sym(path), module.as_tagged(mem))?;
+        Ok(())
+    }
+
+    /// Return the module imported with the path Symbol `path`. A native module is allocated the
+    /// first time it is imported.
+    fn module<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        path: TaggedScopedPtr<'guard>,
+    ) -> Result<ScopedPtr<'guard, Module>, RuntimeError> {
+        let modules = self.modules.get(mem);
+
+        if let Ok(module) = modules.lookup(mem, path) {
+            return match *module {
+                Value::Module(module) => Ok(module),
+                _ => Err(err_eval("Module registry holds a non-module type")),
+            };
+        }
+
+        let native = match *path {
+            Value::Symbol(name) => Module::alloc_native(mem, name.as_str(mem), self)?,
+            _ => None,
+        };
+
+        match native {
+            Some(module) => {
+                modules.assoc(mem, path, module.as_tagged(mem))?;
+                Ok(module)
+            }
+            None => Err(err_eval(&format!("Module {} is not loaded", path))),
+        }
+    }
+
+    /// Return every pointer that keeps heap objects alive on behalf of this Thread: each global
+    /// binding, each module, each non-nil stack slot and each open Upvalue.
+    pub fn roots<'guard>(&self, guard: &'guard dyn MutatorScope) -> Vec<Root> {
+        let mut roots = Vec::new();
+
+        for (name, value) in self.globals.get(guard).iter(guard) {
+            roots.push(Root {
+                kind: RootKind::Global(format!("{}", name)),
+                ptr: value.get_ptr(),
+            });
+        }
+
+        for (path, module) in self.modules.get(guard).iter(guard) {
+            roots.push(Root {
+                kind: RootKind::Module(format!("{}", path)),
+                ptr: module.get_ptr(),
+            });
+        }
+
+        self.stack.get(guard).access_slice(guard, |slots| {
+            for (index, slot) in slots.iter().enumerate() {
+                if !slot.is_nil() {
+                    roots.push(Root {
+                        kind: RootKind::StackSlot(index as ArraySize),
+                        ptr: slot.get_ptr(),
+                    });
+                }
+            }
+        });
+
+        for (location, upvalue) in self.upvalues.get(guard).iter(guard) {
+            if let Value::Number(location) = *location {
+                roots.push(Root {
+                    kind: RootKind::Upvalue(location as ArraySize),
+                    ptr: upvalue.get_ptr(),
+                });
+            }
+        }
+
+        roots
+    }
+
+    /// Run a collection rooted at this Thread, clearing WeakRefs and queueing finalizers for
+    /// whatever it can no longer reach. A minor collection only considers objects allocated since
+    /// the previous collection.
+    pub fn collect_garbage<'guard>(&self, guard: &'guard dyn MutatorScope, full: bool) -> MarkStats {
+        // The Thread traces its frames, stack, upvalues, globals, modules and instruction stream,
+        // so it is the only root needed
+        let roots = [NonNull::from(self).cast::<()>()];
+
+        match full {
+            true => full_collection_from(guard, &roots),
+            false => minor_collection_from(guard, &roots),
+        }
+    }
+
     /// Retrieve an Upvalue for the given absolute stack offset.
     fn upvalue_lookup<'guard>(
         &self,
@@ -281,7 +632,6 @@ impl Thread {
         // where needed
         let frames = self.frames.get(mem);
         let stack = self.stack.get(mem);
-        let globals = self.globals.get(mem);
         let instr = self.instr.get(mem);
 
         // Establish a 256-register window into the stack from the stack base
@@ -321,7 +671,7 @@ impl Thread {
 
                 // Load a literal into a register from the function literals array
                 Opcode::LoadLiteral { dest, literal_id } => {
-                    let literal_ptr = instr.get_literal(mem, literal_id)?;
+                    let literal_ptr = instr.get_literal(mem, literal_id as ArraySize)?;
                     window[dest as usize].set_to_ptr(literal_ptr);
                 }
 
@@ -354,7 +704,7 @@ impl Thread {
                     let reg_val = window[reg as usize].get(mem);
 
                     match *reg_val {
-                        Value::Pair(p) => window[dest as usize].set_to_ptr(p.first.get_ptr()),
+                        Value::Pair(p) => window[dest as usize].set_to_ptr(p.first(mem).get_ptr()),
                         Value::Nil => window[dest as usize].set_to_nil(),
                         _ => return Err(err_eval("Parameter to FirstOfPair is not a list")),
                     }
@@ -365,7 +715,7 @@ impl Thread {
                     let reg_val = window[reg as usize].get(mem);
 
                     match *reg_val {
-                        Value::Pair(p) => window[dest as usize].set_to_ptr(p.second.get_ptr()),
+                        Value::Pair(p) => window[dest as usize].set_to_ptr(p.second(mem).get_ptr()),
                         Value::Nil => window[dest as usize].set_to_nil(),
                         _ => return Err(err_eval("Parameter to SecondOfPair is not a list")),
                     }
@@ -373,12 +723,12 @@ impl Thread {
 
                 // CONS - create a Pair, pointing to `reg1` and `reg2`
                 Opcode::MakePair { dest, reg1, reg2 } => {
-                    let reg1_val = window[reg1 as usize].get_ptr();
-                    let reg2_val = window[reg2 as usize].get_ptr();
+                    let reg1_val = window[reg1 as usize].get(mem);
+                    let reg2_val = window[reg2 as usize].get(mem);
 
                     let new_pair = Pair::new();
-                    new_pair.first.set_to_ptr(reg1_val);
-                    new_pair.second.set_to_ptr(reg2_val);
+                    new_pair.set_first(reg1_val)?;
+                    new_pair.set_second(reg2_val)?;
 
                     window[dest as usize].set(mem.alloc_tagged(new_pair)?);
                 }
@@ -440,6 +790,7 @@ impl Thread {
                     let name_val = window[name as usize].get(mem);
 
                     if let Value::Symbol(_) = *name_val {
+                        let globals = self.frame_globals(mem, frames);
                         let lookup_result = globals.lookup(mem, name_val);
 
                         match lookup_result {
@@ -461,6 +812,7 @@ impl Thread {
                     let name_val = window[name as usize].get(mem);
                     if let Value::Symbol(_) = *name_val {
                         let src_val = window[src as usize].get(mem);
+                        let globals = self.frame_globals(mem, frames);
                         globals.assoc(mem, name_val, src_val)?;
                     } else {
                         return Err(err_eval("Cannot bind global to non-symbol type"));
@@ -470,23 +822,111 @@ impl Thread {
                 // Call the function referred to by the `function` register, put the result in the
                 // `dest` register.
                 //
-                // The function can be a Function object or a Partial.
+                // The function can be a Function object, a Partial or a NativeFunction.
                 //
-                // If the arg_count is less than the function arity, return a Partial instead of
-                // entering the function.
+                // The arguments are matched to the Function's parameters, any left over going to
+                // its rest parameter, and the Function is entered. Parameters with defaults that
+                // were not supplied are left nil, for the Function to fill in. Too few or too many
+                // arguments is an error; a Partial is only made by MakePartial, the `bind`
+                // builtin or MakeClosure.
                 //
-                // If the arg_count is equal to the Function or Partial arity, enter the Function
-                // object code.
-                Opcode::Call {
-                    function,
-                    dest,
-                    arg_count,
-                } => {
+                // The call's first arguments fill a Partial's placeholders, and the rest are passed
+                // after the Partial's own arguments.
+                //
+                // A TailCall enters the Function in the current call frame and register window
+                // rather than a new one, so a chain of tail calls runs in constant space. When no
+                // Function is entered it behaves as a Call, and the Return that always follows it
+                // returns the result.
+                //
+                // A CallKeywords passes its last arguments by name, and takes the function from the
+                // register that receives the result.
+                Opcode::Call { .. } | Opcode::TailCall { .. } | Opcode::CallKeywords { .. } => {
+                    let (function, dest, arg_count) = match opcode {
+                        Opcode::Call {
+                            function,
+                            dest,
+                            arg_count,
+                        }
+                        | Opcode::TailCall {
+                            function,
+                            dest,
+                            arg_count,
+                        } => (function, dest, arg_count),
+                        Opcode::CallKeywords {
+                            dest, arg_count, ..
+                        } => (dest, dest, arg_count),
+                        _ => unreachable!(),
+                    };
+
                     let binding = window[function as usize].get(mem);
+                    let tail = matches!(opcode, Opcode::TailCall { .. });
+
+                    let args_start = dest as usize + FIRST_ARG_REG;
+                    let args_end = args_start + arg_count as usize;
+
+                    let keywords = match opcode {
+                        Opcode::CallKeywords { names, .. } => {
+                            keyword_names(mem, &window[names as usize], arg_count)?
+                        }
+                        _ => Vec::new(),
+                    };
+                    let positional_end = args_end - keywords.len();
+
+                    // Replace the arguments in the window with the value of each parameter,
+                    // returning the mask of parameters with defaults that were supplied
+                    let bind_arguments = |function: ScopedPtr<'guard, Function>,
+                                          args: &[TaggedCellPtr],
+                                          window: &mut [TaggedCellPtr]|
+                     -> Result<u64, RuntimeError> {
+                        let named: Vec<(&str, TaggedCellPtr)> = keywords
+                            .iter()
+                            .zip(&window[positional_end..args_end])
+                            .map(|(name, arg)| (*name, arg.clone()))
+                            .collect();
+
+                        let bound = function.bind_arguments(mem, args, &named)?;
+                        if args_start + bound.values.len() > window.len() {
+                            return Err(err_eval("Arguments extend past the register window"));
+                        }
+
+                        for (index, value) in bound.values.into_iter().enumerate() {
+                            window[args_start + index] = value;
+                        }
+                        Ok(bound.supplied)
+                    };
 
                     // To avoid duplicating code in function and partial application cases,
                     // this is declared as a closure so it can access local variables
-                    let new_call_frame = |function| -> Result<(), RuntimeError> {
+                    let new_call_frame = |function: ScopedPtr<'guard, Function>,
+                                          window: &mut [TaggedCellPtr],
+                                          supplied: u64|
+                     -> Result<(), RuntimeError> {
+                        let globals = self.function_globals(mem, frames, function);
+
+                        if tail {
+                            // Registers in this window are about to be overwritten
+                            self.close_window_upvalues(mem, stack)?;
+
+                            // Move the closure environment and arguments down to where the
+                            // function expects them
+                            let args_end = FIRST_ARG_REG + function.param_count() as usize;
+                            for reg in ENV_REG..args_end {
+                                window[reg] = window[dest as usize + reg].clone();
+                            }
+
+                            // Replace the function in the current call frame
+                            frames.access_slice(mem, |f| {
+                                let frame = f.last().expect("No CallFrames in slice!");
+                                frame.function.set(function);
+                                frame.supplied.set(supplied);
+                                frame.locals.set_to_nil();
+                                frame.globals.set(globals);
+                            });
+
+                            instr.switch_frame(function.code(mem), 0);
+                            return Ok(());
+                        }
+
                         // Modify the current call frame, saving the return ip
                         let current_frame_ip = instr.get_next_ip();
                         frames.access_slice(mem, |f| {
@@ -496,9 +936,13 @@ impl Thread {
                                 .set(current_frame_ip)
                         });
 
-                        // Create a new call frame, pushing it to the frame stack
+                        // Refuse to grow either stack past its limit
                         let new_stack_base = self.stack_base.get() + dest as ArraySize;
-                        let frame = CallFrame::new(function, 0, new_stack_base);
+                        self.check_stack_limits(mem, frames, new_stack_base)?;
+
+                        // Create a new call frame, pushing it to the frame stack
+                        let frame = CallFrame::new(function, 0, new_stack_base, globals);
+                        frame.supplied.set(supplied);
                         frames.push(mem, frame)?;
 
                         // Update the instruction stream to point to the new function
@@ -515,101 +959,71 @@ impl Thread {
                         Ok(())
                     };
 
-                    // Handle the two similar-but-different cases: this might be a Function object
-                    // or a Partial application object
+                    // Handle the similar-but-different cases: this might be a Function object, a
+                    // Partial application object or a NativeFunction
                     match *binding {
                         Value::Function(function) => {
-                            let arity = function.arity();
-
-                            if arg_count < arity {
-                                // Too few args, return a Partial object
-                                let args_start = dest as usize + FIRST_ARG_REG;
-                                let args_end = args_start + arg_count as usize;
-
-                                let partial = Partial::alloc(
-                                    mem,
-                                    function,
-                                    None,
-                                    &window[args_start..args_end],
-                                )?;
-
-                                window[dest as usize].set(partial.as_tagged(mem));
+                            let args = window[args_start..positional_end].to_vec();
+                            let supplied = bind_arguments(function, &args, window)?;
 
-                                return Ok(EvalStatus::Pending);
-                            } else if arg_count > arity {
-                                // Too many args, we haven't got a continuations stack (yet)
-                                return Err(err_eval(&format!(
-                                    "Function {} expected {} arguments, got {}",
-                                    binding,
-                                    function.arity(),
-                                    arg_count
-                                )));
-                            }
+                            // A Function that is not a closure has no environment
+                            window[dest as usize + ENV_REG].set_to_nil();
 
-                            new_call_frame(function)?;
+                            new_call_frame(function, window, supplied)?;
                         }
 
                         Value::Partial(partial) => {
-                            let arity = partial.arity();
-
-                            if arg_count == 0 && arity > 0 {
-                                // Partial is unchanged, no args added, copy directly to dest
-                                window[dest as usize]
-                                    .set_to_ptr(window[function as usize].get_ptr());
-                                return Ok(EvalStatus::Pending);
-                            } else if arg_count < arity {
-                                // Too few args, bake a new Partial from the existing one, adding the new
-                                // arguments
-                                let args_start = dest as usize + FIRST_ARG_REG;
-                                let args_end = args_start + arg_count as usize;
-
-                                let new_partial = Partial::alloc_clone(
-                                    mem,
-                                    partial,
-                                    &window[args_start..args_end],
-                                )?;
-
-                                window[dest as usize].set(new_partial.as_tagged(mem));
-
-                                return Ok(EvalStatus::Pending);
-                            } else if arg_count > arity {
-                                // Too many args, we haven't got a continuations stack
-                                return Err(err_eval(&format!(
-                                    "Partial {} expected {} arguments, got {}",
-                                    binding,
-                                    partial.arity(),
-                                    arg_count
-                                )));
-                            }
+                            // The call's arguments fill the Partial's placeholders and then follow
+                            // its own arguments
+                            let args = partial.apply(mem, &window[args_start..positional_end])?;
 
-                            // Copy closure env pointer
-                            window[dest as usize + ENV_REG] = partial.closure_env();
+                            match *partial.callee(mem) {
+                                Value::Function(function) => {
+                                    let supplied = bind_arguments(function, &args, window)?;
 
-                            // Shunt _call_ args back into the window to make space for the
-                            // partially applied args
-                            let push_dist = partial.used();
-                            let from_reg = dest as usize + FIRST_ARG_REG;
-                            let to_reg = from_reg + push_dist as usize;
-                            for index in (0..arg_count as usize).rev() {
-                                window[to_reg + index] = window[from_reg + index].clone();
-                            }
+                                    // Copy closure env pointer
+                                    window[dest as usize + ENV_REG] = partial.closure_env();
 
-                            // copy args from Partial to the register window
-                            let args = partial.args(mem);
-                            let start_reg = dest as usize + FIRST_ARG_REG;
-                            args.access_slice(mem, |items| {
-                                for (index, item) in items.iter().enumerate() {
-                                    window[start_reg + index] = item.clone();
+                                    new_call_frame(function, window, supplied)?;
                                 }
-                            });
 
-                            new_call_frame(partial.function(mem))?;
+                                Value::NativeFunction(native) => {
+                                    let result = call_native(mem, native, &args, &keywords)?;
+                                    window[dest as usize].set(result);
+                                }
+
+                                _ => return Err(err_eval("Type is not callable")),
+                            }
+                        }
+
+                        Value::NativeFunction(native) => {
+                            let args = &window[args_start..args_end];
+                            let result = call_native(mem, native, args, &keywords)?;
+                            window[dest as usize].set(result);
                         }
 
                         _ => return Err(err_eval("Type is not callable")),
                     }
                 }
 
+                // Apply the function to the arguments without calling it, leaving placeholders
+                // for the arguments of a later call
+                Opcode::MakePartial {
+                    function,
+                    dest,
+                    arg_count,
+                } => {
+                    let holes = placeholder_positions(mem, &window[dest as usize + ENV_REG])?;
+
+                    let args_start = dest as usize + FIRST_ARG_REG;
+                    let args_end = args_start + arg_count as usize;
+
+                    let callee = window[function as usize].get(mem);
+                    let partial =
+                        Partial::alloc_bound(mem, callee, &window[args_start..args_end], &holes)?;
+                    window[dest as usize].set(partial.as_tagged(mem));
+                }
+
                 // ANCHOR: OpcodeMakeClosure
                 // This operation should be generated by the compiler after a function definition
                 // inside another function but only if the nested function refers to nonlocal
@@ -665,17 +1079,88 @@ impl Thread {
                     window[dest as usize] = window[src as usize].clone();
                 }
 
-                // TODO
-                Opcode::Add { dest, reg1, reg2 } => unimplemented!(),
+                // Arithmetic on two registers. An integer result must fit in an inline integer,
+                // and a Float on either side gives a Float. Add also concatenates two strings.
+                Opcode::Add { dest, reg1, reg2 } => {
+                    match (*window[reg1 as usize].get(mem), *window[reg2 as usize].get(mem)) {
+                        (Value::Text(left), Value::Text(right)) => {
+                            let joined = [left.as_str(mem), right.as_str(mem)].concat();
+                            let text = mem.alloc_tagged(Text::new_from_str(mem, &joined)?)?;
+                            window[dest as usize].set(text);
+                        }
+                        _ => {
+                            let result = numeric_op(
+                                mem,
+                                &window[reg1 as usize],
+                                &window[reg2 as usize],
+                                "+",
+                                isize::checked_add,
+                                |l, r| l + r,
+                            )?;
+                            window[dest as usize].set(result);
+                        }
+                    }
+                }
+
+                Opcode::Subtract { dest, left, right } => {
+                    let result = numeric_op(
+                        mem,
+                        &window[left as usize],
+                        &window[right as usize],
+                        "-",
+                        isize::checked_sub,
+                        |l, r| l - r,
+                    )?;
+                    window[dest as usize].set(result);
+                }
 
-                // TODO
-                Opcode::Subtract { dest, left, right } => unimplemented!(),
+                Opcode::Multiply { dest, reg1, reg2 } => {
+                    let result = numeric_op(
+                        mem,
+                        &window[reg1 as usize],
+                        &window[reg2 as usize],
+                        "*",
+                        isize::checked_mul,
+                        |l, r| l * r,
+                    )?;
+                    window[dest as usize].set(result);
+                }
 
-                // TODO
-                Opcode::Multiply { dest, reg1, reg2 } => unimplemented!(),
+                Opcode::DivideInteger { dest, num, denom } => {
+                    if let Value::Number(0) = *window[denom as usize].get(mem) {
+                        return Err(err_eval("Division by zero"));
+                    }
+                    let result = integer_op(
+                        mem,
+                        &window[num as usize],
+                        &window[denom as usize],
+                        "/",
+                        isize::checked_div,
+                    )?;
+                    window[dest as usize].set_to_ptr(result);
+                }
 
-                // TODO
-                Opcode::DivideInteger { dest, num, denom } => unimplemented!(),
+                Opcode::Modulo { dest, num, denom } => {
+                    let denom_val = window[denom as usize].get(mem);
+                    let is_zero = match Numeric::from_value(*denom_val) {
+                        Some(Numeric::Integer(0)) => true,
+                        Some(Numeric::Float(f)) => f == 0.0,
+                        _ => false,
+                    };
+                    if is_zero {
+                        return Err(err_eval("Division by zero"));
+                    }
+
+                    let result = numeric_op(
+                        mem,
+                        &window[num as usize],
+                        &window[denom as usize],
+                        "%",
+                        floor_mod,
+                        floor_mod_float,
+                    )?;
+                    window[dest as usize].set(result);
+                }
 
                 // Follow the indirection of an Upvalue to retrieve the value, copy the value to a
                 // local register
@@ -707,47 +1192,428 @@ impl Thread {
                         }
                     }
                 }
+
+                // Wrap the iterable value in the `src` register in a new Iter object
+                Opcode::GetIter { dest, src } => {
+                    let iter = Iter::alloc(mem, window[src as usize].get(mem))?;
+                    window[dest as usize].set(iter.as_tagged(mem));
+                }
+
+                // Advance the Iter in the `iter` register. If it produced a value, write it to
+                // `dest` and skip the next instruction. Otherwise the next instruction, a jump out
+                // of the loop, is executed.
+                Opcode::IterNext { dest, iter } => {
+                    let iter = iter_register(mem, &window[iter as usize])?;
+
+                    if let Some(value) = iter.next(mem)? {
+                        window[dest as usize].set(value);
+                        instr.skip_instruction();
+                    }
+                }
+
+                // As IterNext, but write a Dict key to `dest` and its value to `dest + 1`
+                Opcode::IterNextPair { dest, iter } => {
+                    let iter = iter_register(mem, &window[iter as usize])?;
+
+                    if let Some((key, value)) = iter.next_pair(mem)? {
+                        window[dest as usize].set(key);
+                        window[dest as usize + 1].set(value);
+                        instr.skip_instruction();
+                    }
+                }
+
+                // Restore a register the compiler spilled to the call frame's locals list
+                Opcode::LoadLocal { dest, local } => {
+                    let locals = self.frame_locals(mem, frames)?;
+                    let value = IndexedAnyContainer::get(&*locals, mem, local as ArraySize)?;
+                    window[dest as usize].set(value);
+                }
+
+                // Spill the `src` register to the call frame's locals list, growing it as needed
+                Opcode::StoreLocal { src, local } => {
+                    let locals = self.frame_locals(mem, frames)?;
+                    while locals.length() <= local as ArraySize {
+                        StackAnyContainer::push(&*locals, mem, mem.nil())?;
+                    }
+                    IndexedAnyContainer::set(
+                        &*locals,
+                        mem,
+                        local as ArraySize,
+                        window[src as usize].get(mem),
+                    )?;
+                }
+
+                // Execute the next instruction with `high` as the upper 16 bits of its operand
+                Opcode::Wide { high } => match instr.get_next_opcode(mem)? {
+                    Opcode::LoadLiteral { dest, literal_id } => {
+                        let literal_id = wide_operand(high, literal_id);
+                        let literal_ptr = instr.get_literal(mem, literal_id)?;
+                        window[dest as usize].set_to_ptr(literal_ptr);
+                    }
+
+                    Opcode::LoadInteger { dest, integer } => {
+                        let integer = wide_operand(high, integer as u16) as i32;
+                        window[dest as usize].set_to_ptr(TaggedPtr::number(integer as isize));
+                    }
+
+                    Opcode::Jump { offset } => {
+                        instr.jump_wide(wide_operand(high, offset as u16) as i32);
+                    }
+
+                    Opcode::JumpIfTrue { test, offset } => {
+                        if window[test as usize].get(mem) == mem.lookup_sym("true") {
+                            instr.jump_wide(wide_operand(high, offset as u16) as i32);
+                        }
+                    }
+
+                    Opcode::JumpIfNotTrue { test, offset } => {
+                        if window[test as usize].get(mem) != mem.lookup_sym("true") {
+                            instr.jump_wide(wide_operand(high, offset as u16) as i32);
+                        }
+                    }
+
+                    _ => return Err(err_eval("Wide prefix on an instruction it cannot extend")),
+                },
+
+                // Raise the value in `reg` as an exception
+                Opcode::Throw { reg } => {
+                    return Ok(EvalStatus::Throw(window[reg as usize].get(mem)))
+                }
+
+                // Set the `dest` register to "true" if the caller supplied parameter `param`,
+                // otherwise set it to `nil`
+                Opcode::IsSupplied { dest, param } => {
+                    let supplied = frames.access_slice(mem, |f| {
+                        let frame = f.last().expect("No CallFrames in slice!");
+                        let function = frame.function.get(mem);
+                        function.is_supplied(param, frame.supplied.get())
+                    });
+
+                    match supplied {
+                        true => window[dest as usize].set(mem.lookup_sym("true")),
+                        false => window[dest as usize].set_to_nil(),
+                    }
+                }
+
+                // Set the `dest` register to the Module named by the `path` register. The first
+                // time, the module's top-level code is run in a new call frame with its window at
+                // `dest`, and it returns to this Import to find the module loaded.
+                Opcode::Import { dest, path } => {
+                    let module = self.module(mem, window[path as usize].get(mem))?;
+
+                    match module.function(mem) {
+                        Some(function) if !module.is_loaded() => {
+                            module.set_loaded();
+
+                            // Return to this instruction rather than the next
+                            let import_ip = instr.get_next_ip() - 1;
+                            frames.access_slice(mem, |f| {
+                                f.last().expect("No CallFrames in slice!").ip.set(import_ip)
+                            });
+
+                            let new_stack_base = self.stack_base.get() + dest as ArraySize;
+                            self.check_stack_limits(mem, frames, new_stack_base)?;
+
+                            let frame =
+                                CallFrame::new(function, 0, new_stack_base, module.globals(mem));
+                            frames.push(mem, frame)?;
+
+                            self.stack_base.set(new_stack_base);
+                            instr.switch_frame(function.code(mem), 0);
+                            stack.fill(mem, new_stack_base + 256, mem.nil())?;
+                        }
+                        _ => window[dest as usize].set(module.as_tagged(mem)),
+                    }
+                }
+
+                // Look up the member named by the `name` register in the Module in the `object`
+                // register
+                Opcode::GetField { dest, object, name } => {
+                    let name_val = window[name as usize].get(mem);
+
+                    match *window[object as usize].get(mem) {
+                        Value::Module(module) => {
+                            window[dest as usize].set(module.member(mem, name_val)?)
+                        }
+                        other => {
+                            return Err(err_eval(&format!(
+                                "Cannot get field {} of {}",
+                                name_val, other
+                            )))
+                        }
+                    }
+                }
             }
 
             Ok(EvalStatus::Pending)
         })
     }
 
-    /// Given ByteCode, execute up to max_instr more instructions
+    /// Close every open Upvalue that refers to a register in the current register window, before
+    /// a tail call reuses the window
+    fn close_window_upvalues<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        stack: ScopedPtr<'guard, List>,
+    ) -> Result<(), RuntimeError> {
+        let window = self.stack_base.get()..self.stack_base.get() + 256;
+        self.close_upvalues(mem, stack, window)
+    }
+
+    /// Close every open Upvalue that refers to a stack location in the given range
+    fn close_upvalues<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        stack: ScopedPtr<'guard, List>,
+        locations: std::ops::Range<ArraySize>,
+    ) -> Result<(), RuntimeError> {
+        let upvalues = self.upvalues.get(mem);
+
+        let mut open = Vec::new();
+        for (location, upvalue) in upvalues.iter(mem) {
+            if let (Value::Number(n), Value::Upvalue(upvalue)) = (*location, *upvalue) {
+                if locations.contains(&(n as ArraySize)) {
+                    open.push((location, upvalue));
+                }
+            }
+        }
+
+        for (location, upvalue) in open {
+            upvalue.close(mem, stack)?;
+            upvalues.dissoc(mem, location)?;
+        }
+
+        Ok(())
+    }
+
+    /// Return the locals list of the current call frame, allocating it on first use
+    fn frame_locals<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        frames: ScopedPtr<'guard, CallFrameList>,
+    ) -> Result<ScopedPtr<'guard, List>, RuntimeError> {
+        let locals = frames.access_slice(mem, |f| {
+            f.last().expect("No CallFrames in slice!").locals.get(mem)
+        });
+
+        match *locals {
+            Value::List(list) => Ok(list),
+            _ => {
+                let list = List::alloc(mem)?;
+                frames.access_slice(mem, |f| {
+                    f.last()
+                        .expect("No CallFrames in slice!")
+                        .locals
+                        .set(list.as_tagged(mem))
+                });
+                Ok(list)
+            }
+        }
+    }
+
+    /// Return the globals Dict of the current call frame
+    fn frame_globals<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+        frames: ScopedPtr<'guard, CallFrameList>,
+    ) -> ScopedPtr<'guard, Dict> {
+        frames.access_slice(guard, |f| {
+            f.last()
+                .expect("No CallFrames in slice!")
+                .globals
+                .get(guard)
+        })
+    }
+
+    /// Return the globals Dict a call to `function` runs with: that of the module the Function
+    /// was loaded from, or otherwise the caller's
+    fn function_globals<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+        frames: ScopedPtr<'guard, CallFrameList>,
+        function: ScopedPtr<'guard, Function>,
+    ) -> ScopedPtr<'guard, Dict> {
+        match *function.globals(guard) {
+            Value::Dict(globals) => globals,
+            _ => self.frame_globals(guard, frames),
+        }
+    }
+
+    /// Refuse to push another call frame, with its register window at `new_stack_base`, if
+    /// either stack would grow past its limit
+    fn check_stack_limits<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+        frames: ScopedPtr<'guard, CallFrameList>,
+        new_stack_base: ArraySize,
+    ) -> Result<(), RuntimeError> {
+        let limits = self.limits.get();
+        if frames.length() >= limits.max_call_depth {
+            return Err(self.stack_overflow(
+                guard,
+                &format!("call depth exceeded {} frames", limits.max_call_depth),
+            ));
+        }
+        if new_stack_base + 256 > limits.max_stack_size {
+            return Err(self.stack_overflow(
+                guard,
+                &format!("value stack exceeded {} slots", limits.max_stack_size),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Describe each call frame above the main frame, outermost first. When there are more than
+    /// twice TRACEBACK_FRAMES of them, only the first and last TRACEBACK_FRAMES are shown.
+    fn traceback<'guard>(&self, guard: &'guard dyn MutatorScope) -> Vec<String> {
+        let mut lines = Vec::new();
+
+        self.frames.get(guard).access_slice(guard, |window| {
+            let frames = window.get(1..).unwrap_or(&[]);
+
+            if frames.len() > 2 * TRACEBACK_FRAMES {
+                let omitted = frames.len() - 2 * TRACEBACK_FRAMES;
+                for frame in &frames[..TRACEBACK_FRAMES] {
+                    lines.push(format!("  {}", frame.as_string(guard)));
+                }
+                lines.push(format!("  ... {} frames omitted ...", omitted));
+                for frame in &frames[frames.len() - TRACEBACK_FRAMES..] {
+                    lines.push(format!("  {}", frame.as_string(guard)));
+                }
+            } else {
+                for frame in frames {
+                    lines.push(format!("  {}", frame.as_string(guard)));
+                }
+            }
+        });
+
+        lines
+    }
+
+    /// Build a stack overflow error that includes a traceback of the current call frames
+    fn stack_overflow<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+        reason: &str,
+    ) -> RuntimeError {
+        let mut message = String::from(reason);
+        for line in self.traceback(guard) {
+            message.push('\n');
+            message.push_str(&line);
+        }
+
+        err_stack_overflow(&message)
+    }
+
+    /// Unwind to the innermost exception handler protecting the instruction being executed in
+    /// any call frame, popping the frames above it and closing their upvalues, and continue from
+    /// the handler with the exception in its register. Returns false, leaving every frame in
+    /// place, if no handler catches the exception.
+    fn throw<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        exception: TaggedScopedPtr<'guard>,
+    ) -> Result<bool, RuntimeError> {
+        let frames = self.frames.get(mem);
+        let stack = self.stack.get(mem);
+        let instr = self.instr.get(mem);
+
+        // Each frame below the top was suspended just after the instruction that called the next
+        let caught = frames.access_slice(mem, |window| {
+            let top = window.len().checked_sub(1)?;
+
+            window.iter().enumerate().rev().find_map(|(depth, frame)| {
+                let ip = match depth == top {
+                    true => instr.get_next_ip(),
+                    false => frame.ip.get(),
+                };
+
+                let code = frame.function.get(mem).code(mem);
+                code.handler_for(mem, ip.saturating_sub(1))
+                    .map(|handler| (depth as ArraySize, frame.base, handler))
+            })
+        });
+
+        let (depth, base, handler) = match caught {
+            Some(caught) => caught,
+            None => return Ok(false),
+        };
+
+        // The windows of the popped frames start where the handler's frame made its call
+        if depth + 1 < frames.length() {
+            let popped_base = frames.get(mem, depth + 1)?.base;
+            self.close_upvalues(mem, stack, popped_base..ArraySize::MAX)?;
+        }
+
+        while frames.length() > depth + 1 {
+            frames.pop(mem)?;
+        }
+
+        let frame = frames.top(mem)?;
+        self.stack_base.set(base);
+        instr.switch_frame(frame.function.get(mem).code(mem), handler.target);
+        IndexedAnyContainer::set(&*stack, mem, base + handler.dest as ArraySize, exception)?;
+
+        Ok(true)
+    }
+
+    /// Execute up to max_instr more instructions from the current instruction stream
     fn vm_eval_stream<'guard>(
         &self,
         mem: &'guard MutatorView,
-        code: ScopedPtr<'guard, ByteCode>,
         max_instr: ArraySize,
     ) -> Result<EvalStatus<'guard>, RuntimeError> {
-        let instr = self.instr.get(mem);
-        // TODO this is broken logic, this function shouldn't switch back to this code object every
-        // time it is called
-        instr.switch_frame(code, 0);
-
         for _ in 0..max_instr {
-            match self.eval_next_instr(mem) {
+            let result = match self.eval_next_instr(mem) {
                 // Evaluation paused or completed without error
-                Ok(exit_cond) => match exit_cond {
-                    EvalStatus::Return(value) => return Ok(EvalStatus::Return(value)),
-                    _ => (),
+                Ok(EvalStatus::Return(value)) => return Ok(EvalStatus::Return(value)),
+                Ok(EvalStatus::Pending) => Ok(()),
+
+                // A thrown value must be caught somewhere
+                Ok(EvalStatus::Throw(value)) => match self.throw(mem, value) {
+                    Ok(true) => Ok(()),
+                    Ok(false) => Err(err_eval(&format!("Uncaught exception {}", value))),
+                    Err(e) => Err(e),
                 },
 
+                // Errors raised by the runtime are thrown as Exceptions, and are reported as
+                // they are if nothing catches them
+                Err(rt_error) if is_catchable(&rt_error) => {
+                    let traceback = self.traceback(mem).join("\n");
+                    let thrown = Exception::alloc(mem, &format!("{}", rt_error), &traceback)
+                        .and_then(|exception| self.throw(mem, exception.as_tagged(mem)));
+
+                    match thrown {
+                        Ok(true) => Ok(()),
+                        Ok(false) => Err(rt_error),
+                        Err(e) => Err(e),
+                    }
+                }
+
+                Err(rt_error) => Err(rt_error),
+            };
+
+            match result {
+                Ok(()) => (),
+
                 // Evaluation hit an error
                 Err(rt_error) => {
                     // unwind the stack, printing a trace
                     let frames = self.frames.get(mem);
 
-                    // Print a stack trace if the error is multiple call frames deep
-                    frames.access_slice(mem, |window| {
-                        if window.len() > 1 {
+                    // Print a stack trace if the error is multiple call frames deep. A stack
+                    // overflow error already carries its own.
+                    if !matches!(rt_error.error_kind(), ErrorKind::StackOverflow(_)) {
+                        let traceback = self.traceback(mem);
+                        if !traceback.is_empty() {
                             println!("Error traceback:");
                         }
 
-                        for frame in &window[1..] {
-                            println!("  {}", frame.as_string(mem));
+                        for line in traceback {
+                            println!("{}", line);
                         }
-                    });
+                    }
 
                     // Unwind by clearing all frames from the stack
                     frames.clear(mem)?;
@@ -761,28 +1627,885 @@ impl Thread {
         Ok(EvalStatus::Pending)
     }
 
+    /// Return the value bound to a global name, or None if it is unbound
+    pub fn global<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        name: &str,
+    ) -> Option<TaggedScopedPtr<'guard>> {
+        self.globals.get(mem).lookup(mem, mem.lookup_sym(name)).ok()
+    }
+
     /// Evaluate a Function completely, returning the result. The Function passed in should expect
-    /// no arguments.
+    /// no arguments. The Function and any Functions nested in it are verified before any of their
+    /// code is run.
     pub fn quick_vm_eval<'guard>(
         &self,
         mem: &'guard MutatorView,
         function: ScopedPtr<'guard, Function>,
     ) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+        verify_function(mem, function)?;
+
         let mut status = EvalStatus::Pending;
 
         let frames = self.frames.get(mem);
-        frames.push(mem, CallFrame::new_main(function))?;
+        let globals = self.globals.get(mem);
+        frames.push(mem, CallFrame::new_main(function, globals))?;
 
+        // Start at the top of the function; each batch of instructions below resumes wherever the
+        // previous batch left off, which matters once loops run for longer than one batch
         let code = function.code(mem);
+        self.instr.get(mem).switch_frame(code, 0);
 
         while status == EvalStatus::Pending {
-            status = self.vm_eval_stream(mem, code, 1024)?;
+            status = self.vm_eval_stream(mem, 1024)?;
             match status {
                 EvalStatus::Return(value) => return Ok(value),
                 _ => (),
             }
+
+            // Between batches everything the program holds is reachable from this Thread, so
+            // this is a safe point to run a collection the heap has asked for
+            if mem.take_collection_request() {
+                self.collect_garbage(mem, false);
+            }
         }
 
         Err(err_eval("Unexpected end of evaluation"))
     }
 }
+
+impl Trace for Thread {
+    fn trace<'guard>(&self, _guard: &'guard dyn MutatorScope, visit: &mut Visitor) {
+        visit_cell(&self.frames, visit);
+        visit_cell(&self.stack, visit);
+        visit_cell(&self.upvalues, visit);
+        visit_cell(&self.globals, visit);
+        visit_cell(&self.modules, visit);
+        visit_cell(&self.args, visit);
+        visit_cell(&self.instr, visit);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bytecode::{Handler, Register};
+    use crate::container::{AnyContainerFromSlice, ContainerFromSlice};
+    use crate::memory::{Memory, Mutator};
+
+    fn function<'guard>(
+        mem: &'guard MutatorView,
+        name: &str,
+        params: &[&str],
+        literals: &[TaggedScopedPtr<'guard>],
+        code: &[Opcode],
+    ) -> Result<ScopedPtr<'guard, Function>, RuntimeError> {
+        let bytecode = ByteCode::alloc(mem)?;
+        for literal in literals {
+            bytecode.push_lit(mem, *literal)?;
+        }
+        for op in code {
+            bytecode.push(mem, *op)?;
+        }
+
+        let param_names = List::alloc(mem)?;
+        for param in params {
+            StackAnyContainer::push(&*param_names, mem, mem.lookup_sym(param))?;
+        }
+
+        Function::alloc(mem, mem.lookup_sym(name), param_names, bytecode, None)
+    }
+
+    #[test]
+    fn tail_calls_run_in_constant_space() {
+        let mem = Memory::new();
+
+        struct Test {}
+        impl Mutator for Test {
+            type Input = ();
+            type Output = ();
+
+            fn run(
+                &self,
+                mem: &MutatorView,
+                _input: Self::Input,
+            ) -> Result<Self::Output, RuntimeError> {
+                // countdown(n): if n is 0 return n, otherwise return countdown(n - 1)
+                let countdown = function(
+                    mem,
+                    "countdown",
+                    &["n"],
+                    &[mem.lookup_sym("countdown")],
+                    &[
+                        /* 0 */
+                        Opcode::LoadInteger {
+                            dest: 3,
+                            integer: 0,
+                        },
+                        /* 1 */
+                        Opcode::IsIdentical {
+                            dest: 4,
+                            test1: 2,
+                            test2: 3,
+                        },
+                        /* 2 */ Opcode::JumpIfTrue { test: 4, offset: 6 },
+                        /* 3 */
+                        Opcode::LoadLiteral {
+                            dest: 5,
+                            literal_id: 0,
+                        },
+                        /* 4 */ Opcode::LoadGlobal { dest: 5, name: 5 },
+                        /* 5 */
+                        Opcode::LoadInteger {
+                            dest: 8,
+                            integer: 1,
+                        },
+                        /* 6 */
+                        Opcode::Subtract {
+                            dest: 7,
+                            left: 2,
+                            right: 8,
+                        },
+                        /* 7 */
+                        Opcode::TailCall {
+                            function: 5,
+                            dest: 5,
+                            arg_count: 1,
+                        },
+                        /* 8 */ Opcode::Return { reg: 5 },
+                        /* 9 */ Opcode::Return { reg: 2 },
+                    ],
+                )?;
+
+                // step_down(step, n): as countdown, but recursing through the Partial `down`
+                let step_down = function(
+                    mem,
+                    "step_down",
+                    &["step", "n"],
+                    &[mem.lookup_sym("down")],
+                    &[
+                        /* 0 */
+                        Opcode::LoadInteger {
+                            dest: 4,
+                            integer: 0,
+                        },
+                        /* 1 */
+                        Opcode::IsIdentical {
+                            dest: 5,
+                            test1: 3,
+                            test2: 4,
+                        },
+                        /* 2 */ Opcode::JumpIfTrue { test: 5, offset: 5 },
+                        /* 3 */
+                        Opcode::LoadLiteral {
+                            dest: 6,
+                            literal_id: 0,
+                        },
+                        /* 4 */ Opcode::LoadGlobal { dest: 6, name: 6 },
+                        /* 5 */
+                        Opcode::Subtract {
+                            dest: 8,
+                            left: 3,
+                            right: 2,
+                        },
+                        /* 6 */
+                        Opcode::TailCall {
+                            function: 6,
+                            dest: 6,
+                            arg_count: 1,
+                        },
+                        /* 7 */ Opcode::Return { reg: 6 },
+                        /* 8 */ Opcode::Return { reg: 3 },
+                    ],
+                )?;
+
+                // countdown(100000) + down(100000), where down = bind(step_down, 1)
+                let main = function(
+                    mem,
+                    "main",
+                    &[],
+                    &[
+                        countdown.as_tagged(mem),
+                        mem.lookup_sym("countdown"),
+                        step_down.as_tagged(mem),
+                        mem.lookup_sym("down"),
+                        TaggedScopedPtr::new(mem, TaggedPtr::number(100000)),
+                        mem.lookup_sym("bind"),
+                    ],
+                    &[
+                        Opcode::LoadLiteral {
+                            dest: 2,
+                            literal_id: 0,
+                        },
+                        Opcode::LoadLiteral {
+                            dest: 3,
+                            literal_id: 1,
+                        },
+                        Opcode::StoreGlobal { src: 2, name: 3 },
+                        Opcode::LoadLiteral {
+                            dest: 5,
+                            literal_id: 4,
+                        },
+                        Opcode::Call {
+                            function: 2,
+                            dest: 3,
+                            arg_count: 1,
+                        },
+                        Opcode::LoadLiteral {
+                            dest: 7,
+                            literal_id: 2,
+                        },
+                        Opcode::LoadInteger {
+                            dest: 8,
+                            integer: 1,
+                        },
+                        Opcode::LoadLiteral {
+                            dest: 4,
+                            literal_id: 5,
+                        },
+                        Opcode::LoadGlobal { dest: 4, name: 4 },
+                        Opcode::Call {
+                            function: 4,
+                            dest: 5,
+                            arg_count: 2,
+                        },
+                        Opcode::LoadLiteral {
+                            dest: 6,
+                            literal_id: 3,
+                        },
+                        Opcode::StoreGlobal { src: 5, name: 6 },
+                        Opcode::LoadLiteral {
+                            dest: 8,
+                            literal_id: 4,
+                        },
+                        Opcode::Call {
+                            function: 5,
+                            dest: 6,
+                            arg_count: 1,
+                        },
+                        Opcode::Add {
+                            dest: 3,
+                            reg1: 3,
+                            reg2: 6,
+                        },
+                        Opcode::Return { reg: 3 },
+                    ],
+                )?;
+
+                let thread = Thread::alloc(mem)?;
+                let result = thread.quick_vm_eval(mem, main)?;
+                assert!(matches!(*result, Value::Number(0)), "got {}", result);
+
+                // Each recursion reused its caller's register window
+                assert!(thread.stack.get(mem).length() < 1024);
+
+                Ok(())
+            }
+        }
+
+        let test = Test {};
+        mem.mutate(&test, ()).unwrap();
+    }
+
+    #[test]
+    fn exceptions_unwind_to_the_nearest_handler() {
+        let mem = Memory::new();
+
+        struct Test {}
+        impl Mutator for Test {
+            type Input = ();
+            type Output = ();
+
+            fn run(
+                &self,
+                mem: &MutatorView,
+                _input: Self::Input,
+            ) -> Result<Self::Output, RuntimeError> {
+                // fail(): throw 42
+                let fail = function(
+                    mem,
+                    "fail",
+                    &[],
+                    &[],
+                    &[
+                        Opcode::LoadInteger {
+                            dest: 2,
+                            integer: 42,
+                        },
+                        Opcode::Throw { reg: 2 },
+                    ],
+                )?;
+
+                // middle(): return fail()
+                let middle = function(
+                    mem,
+                    "middle",
+                    &[],
+                    &[mem.lookup_sym("fail")],
+                    &[
+                        Opcode::LoadLiteral {
+                            dest: 2,
+                            literal_id: 0,
+                        },
+                        Opcode::LoadGlobal { dest: 2, name: 2 },
+                        Opcode::Call {
+                            function: 2,
+                            dest: 3,
+                            arg_count: 0,
+                        },
+                        Opcode::Return { reg: 3 },
+                    ],
+                )?;
+
+                // try { middle() } catch e { try { e / 0 } catch e2 { return e2 } }
+                let main = function(
+                    mem,
+                    "main",
+                    &[],
+                    &[
+                        fail.as_tagged(mem),
+                        mem.lookup_sym("fail"),
+                        middle.as_tagged(mem),
+                    ],
+                    &[
+                        /* 0 */
+                        Opcode::LoadLiteral {
+                            dest: 2,
+                            literal_id: 0,
+                        },
+                        /* 1 */
+                        Opcode::LoadLiteral {
+                            dest: 3,
+                            literal_id: 1,
+                        },
+                        /* 2 */ Opcode::StoreGlobal { src: 2, name: 3 },
+                        /* 3 */
+                        Opcode::LoadLiteral {
+                            dest: 2,
+                            literal_id: 2,
+                        },
+                        /* 4 */
+                        Opcode::Call {
+                            function: 2,
+                            dest: 3,
+                            arg_count: 0,
+                        },
+                        /* 5 */ Opcode::Return { reg: 3 },
+                        /* 6 */
+                        Opcode::LoadInteger {
+                            dest: 4,
+                            integer: 0,
+                        },
+                        /* 7 */
+                        Opcode::DivideInteger {
+                            dest: 5,
+                            num: 9,
+                            denom: 4,
+                        },
+                        /* 8 */ Opcode::Return { reg: 9 },
+                        /* 9 */ Opcode::Return { reg: 8 },
+                    ],
+                )?;
+
+                let code = main.code(mem);
+                code.push_handler(
+                    mem,
+                    Handler {
+                        start: 4,
+                        end: 5,
+                        target: 6,
+                        dest: 9,
+                    },
+                )?;
+                code.push_handler(
+                    mem,
+                    Handler {
+                        start: 7,
+                        end: 8,
+                        target: 9,
+                        dest: 8,
+                    },
+                )?;
+
+                // The thrown value unwinds through middle's frame, and dividing it by zero raises
+                // an error that is caught as an Exception
+                let thread = Thread::alloc(mem)?;
+                let result = thread.quick_vm_eval(mem, main)?;
+                match *result {
+                    Value::Exception(exception) => {
+                        let message = exception.message(mem);
+                        assert!(message.as_str(mem).contains("Division by zero"));
+                    }
+                    _ => panic!("expected an exception, got {}", result),
+                }
+
+                // Nothing catches a throw from the top level
+                let uncaught = function(
+                    mem,
+                    "uncaught",
+                    &[],
+                    &[],
+                    &[
+                        Opcode::LoadInteger {
+                            dest: 2,
+                            integer: 7,
+                        },
+                        Opcode::Throw { reg: 2 },
+                    ],
+                )?;
+                let thread = Thread::alloc(mem)?;
+                assert!(thread.quick_vm_eval(mem, uncaught).is_err());
+
+                Ok(())
+            }
+        }
+
+        let test = Test {};
+        mem.mutate(&test, ()).unwrap();
+    }
+
+    #[test]
+    fn default_rest_and_keyword_parameters() {
+        let mem = Memory::new();
+
+        // Call `callee` with the given positional arguments followed by the given keyword
+        // arguments, in a new Thread
+        fn call<'guard>(
+            mem: &'guard MutatorView,
+            callee: TaggedScopedPtr<'guard>,
+            args: &[i16],
+            keywords: &[(&str, i16)],
+        ) -> Result<(TaggedScopedPtr<'guard>, ScopedPtr<'guard, Thread>), RuntimeError> {
+            let names: Vec<TaggedScopedPtr<'_>> = keywords
+                .iter()
+                .map(|(name, _)| mem.lookup_sym(name))
+                .collect();
+            let names: ScopedPtr<'_, List> = AnyContainerFromSlice::from_slice(mem, &names)?;
+
+            let mut code = vec![Opcode::LoadLiteral {
+                dest: 2,
+                literal_id: 0,
+            }];
+            let values = args.iter().chain(keywords.iter().map(|(_, value)| value));
+            for (index, value) in values.enumerate() {
+                code.push(Opcode::LoadInteger {
+                    dest: 5 + index as Register,
+                    integer: *value,
+                });
+            }
+
+            let arg_count = (args.len() + keywords.len()) as u8;
+            if keywords.is_empty() {
+                code.push(Opcode::Call {
+                    function: 2,
+                    dest: 3,
+                    arg_count,
+                });
+            } else {
+                code.push(Opcode::CopyRegister { dest: 3, src: 2 });
+                code.push(Opcode::LoadLiteral {
+                    dest: 2,
+                    literal_id: 1,
+                });
+                code.push(Opcode::CallKeywords {
+                    dest: 3,
+                    arg_count,
+                    names: 2,
+                });
+            }
+            code.push(Opcode::Return { reg: 3 });
+
+            let main = function(mem, "main", &[], &[callee, names.as_tagged(mem)], &code)?;
+            let thread = Thread::alloc(mem)?;
+            let result = thread.quick_vm_eval(mem, main)?;
+            Ok((result, thread))
+        }
+
+        struct Test {}
+        impl Mutator for Test {
+            type Input = ();
+            type Output = ();
+
+            fn run(
+                &self,
+                mem: &MutatorView,
+                _input: Self::Input,
+            ) -> Result<Self::Output, RuntimeError> {
+                // fn f(a, b = 10, *rest) { rest_seen = rest; return a + b }
+                let code = ByteCode::alloc(mem)?;
+                code.push_lit(mem, mem.lookup_sym("rest_seen"))?;
+                for op in &[
+                    /* 0 */ Opcode::IsSupplied { dest: 5, param: 1 },
+                    /* 1 */ Opcode::JumpIfTrue { test: 5, offset: 1 },
+                    /* 2 */
+                    Opcode::LoadInteger {
+                        dest: 3,
+                        integer: 10,
+                    },
+                    /* 3 */
+                    Opcode::LoadLiteral {
+                        dest: 6,
+                        literal_id: 0,
+                    },
+                    /* 4 */ Opcode::StoreGlobal { src: 4, name: 6 },
+                    /* 5 */
+                    Opcode::Add {
+                        dest: 7,
+                        reg1: 2,
+                        reg2: 3,
+                    },
+                    /* 6 */ Opcode::Return { reg: 7 },
+                ] {
+                    code.push(mem, *op)?;
+                }
+                let params = AnyContainerFromSlice::from_slice(
+                    mem,
+                    &[
+                        mem.lookup_sym("a"),
+                        mem.lookup_sym("b"),
+                        mem.lookup_sym("rest"),
+                    ],
+                )?;
+                let f = Function::alloc_variadic(
+                    mem,
+                    mem.lookup_sym("f"),
+                    params,
+                    1,
+                    true,
+                    code,
+                    None,
+                )?;
+                let f_ptr = f.as_tagged(mem);
+
+                let number = |n: isize| TaggedScopedPtr::new(mem, TaggedPtr::number(n));
+                let rest_length =
+                    |thread: ScopedPtr<'_, Thread>| match thread.global(mem, "rest_seen") {
+                        Some(value) => match *value {
+                            Value::List(rest) => rest.length(),
+                            _ => panic!("expected rest to be a List, got {}", value),
+                        },
+                        None => panic!("rest was not bound"),
+                    };
+
+                // The default is computed by the function when b is not given
+                let (result, thread) = call(mem, f_ptr, &[1], &[])?;
+                assert!(result == number(11));
+                assert!(rest_length(thread) == 0);
+
+                // Arguments beyond b are collected into rest
+                let (result, thread) = call(mem, f_ptr, &[1, 2, 3, 4], &[])?;
+                assert!(result == number(3));
+                assert!(rest_length(thread) == 2);
+
+                // Keyword arguments may be given in any order, after the positional ones
+                let (result, _) = call(mem, f_ptr, &[], &[("b", 1), ("a", 5)])?;
+                assert!(result == number(6));
+                let (result, _) = call(mem, f_ptr, &[1], &[("b", 2)])?;
+                assert!(result == number(3));
+
+                // A Partial's arguments come first
+                let one = TaggedCellPtr::new_with(number(1));
+                let partial = Partial::alloc(mem, f, None, &[one])?;
+                let (result, _) = call(mem, partial.as_tagged(mem), &[], &[("b", 5)])?;
+                assert!(result == number(6));
+
+                let message = |result: Result<_, RuntimeError>| match result {
+                    Err(error) => match error.error_kind() {
+                        ErrorKind::EvalError(message) => message.clone(),
+                        kind => panic!("unexpected error {:?}", kind),
+                    },
+                    Ok(_) => panic!("expected an error"),
+                };
+
+                let error = message(call(mem, f_ptr, &[], &[]));
+                assert!(error.contains("missing argument a"));
+                let error = message(call(mem, f_ptr, &[1], &[("c", 2)]));
+                assert!(error.contains("unexpected keyword argument c"));
+                let error = message(call(mem, f_ptr, &[1], &[("a", 2)]));
+                assert!(error.contains("multiple values for argument a"));
+
+                let len = thread.global(mem, "len").unwrap();
+                let error = message(call(mem, len, &[], &[("x", 1)]));
+                assert!(error.contains("does not take keyword arguments"));
+
+                Ok(())
+            }
+        }
+
+        let test = Test {};
+        mem.mutate(&test, ()).unwrap();
+    }
+
+    #[test]
+    fn partial_applications_of_closures() {
+        let mem = Memory::new();
+
+        struct Test {}
+        impl Mutator for Test {
+            type Input = ();
+            type Output = ();
+
+            fn run(
+                &self,
+                mem: &MutatorView,
+                _input: Self::Input,
+            ) -> Result<Self::Output, RuntimeError> {
+                // add_to(a, b): a + b + the captured register 2 of main
+                let code = ByteCode::alloc(mem)?;
+                for op in &[
+                    Opcode::GetUpvalue { dest: 4, src: 0 },
+                    Opcode::Add {
+                        dest: 5,
+                        reg1: 2,
+                        reg2: 3,
+                    },
+                    Opcode::Add {
+                        dest: 5,
+                        reg1: 5,
+                        reg2: 4,
+                    },
+                    Opcode::Return { reg: 5 },
+                ] {
+                    code.push(mem, *op)?;
+                }
+                let params = AnyContainerFromSlice::from_slice(
+                    mem,
+                    &[mem.lookup_sym("a"), mem.lookup_sym("b")],
+                )?;
+                let refs = ContainerFromSlice::from_slice(mem, &[0x0102u16])?;
+                let add_to =
+                    Function::alloc(mem, mem.lookup_sym("add_to"), params, code, Some(refs))?;
+
+                let number = |n: isize| TaggedScopedPtr::new(mem, TaggedPtr::number(n));
+                let holes: ScopedPtr<'_, List> =
+                    AnyContainerFromSlice::from_slice(mem, &[number(0)])?;
+
+                // captured = 100; closure = add_to; plus5 = closure(_, 5); return plus5(7)
+                let main = function(
+                    mem,
+                    "main",
+                    &[],
+                    &[add_to.as_tagged(mem), holes.as_tagged(mem)],
+                    &[
+                        Opcode::LoadInteger {
+                            dest: 2,
+                            integer: 100,
+                        },
+                        Opcode::LoadLiteral {
+                            dest: 3,
+                            literal_id: 0,
+                        },
+                        Opcode::MakeClosure {
+                            dest: 3,
+                            function: 3,
+                        },
+                        Opcode::LoadNil { dest: 6 },
+                        Opcode::LoadInteger {
+                            dest: 7,
+                            integer: 5,
+                        },
+                        Opcode::LoadLiteral {
+                            dest: 5,
+                            literal_id: 1,
+                        },
+                        Opcode::MakePartial {
+                            function: 3,
+                            dest: 4,
+                            arg_count: 2,
+                        },
+                        Opcode::LoadInteger {
+                            dest: 11,
+                            integer: 7,
+                        },
+                        Opcode::Call {
+                            function: 4,
+                            dest: 9,
+                            arg_count: 1,
+                        },
+                        Opcode::Return { reg: 9 },
+                    ],
+                )?;
+
+                // The Partial keeps the closure's environment
+                let thread = Thread::alloc(mem)?;
+                let result = thread.quick_vm_eval(mem, main)?;
+                assert!(result == number(112));
+
+                // Binding a Partial fills its placeholders first, and new placeholders stay open
+                let nil = TaggedCellPtr::new_nil();
+                let one = TaggedCellPtr::new_with(number(1));
+                let two = TaggedCellPtr::new_with(number(2));
+                let three = TaggedCellPtr::new_with(number(3));
+                let slice = thread.global(mem, "slice").unwrap();
+
+                let outer =
+                    Partial::alloc_bound(mem, slice, &[nil.clone(), nil.clone(), three], &[0, 1])?;
+                assert!(format!("{}", outer.as_tagged(mem)) == "(Partial slice (_ _ 3))");
+
+                let inner = Partial::alloc_bound(mem, outer.as_tagged(mem), &[nil, one], &[0])?;
+                assert!(format!("{}", inner.as_tagged(mem)) == "(Partial slice (_ 1 3))");
+                assert!(inner.holes(mem) == vec![0]);
+
+                let text = mem.alloc_tagged(Text::new_from_str(mem, "abc")?)?;
+                let applied = inner.apply(mem, &[TaggedCellPtr::new_with(text), two])?;
+                assert!(applied.len() == 4);
+                assert!(inner.apply(mem, &[]).is_err());
+
+                Ok(())
+            }
+        }
+
+        let test = Test {};
+        mem.mutate(&test, ()).unwrap();
+    }
+
+    #[test]
+    fn deep_recursion_raises_stack_overflow() {
+        let mem = Memory::new();
+
+        struct Test {}
+        impl Mutator for Test {
+            type Input = ();
+            type Output = ();
+
+            fn run(
+                &self,
+                mem: &MutatorView,
+                _input: Self::Input,
+            ) -> Result<Self::Output, RuntimeError> {
+                // depth(n): if n is 0 return n, otherwise return depth(n - 1) + 1
+                let depth = function(
+                    mem,
+                    "depth",
+                    &["n"],
+                    &[mem.lookup_sym("depth")],
+                    &[
+                        /* 0 */
+                        Opcode::LoadInteger {
+                            dest: 3,
+                            integer: 0,
+                        },
+                        /* 1 */
+                        Opcode::IsIdentical {
+                            dest: 4,
+                            test1: 2,
+                            test2: 3,
+                        },
+                        /* 2 */ Opcode::JumpIfTrue { test: 4, offset: 8 },
+                        /* 3 */
+                        Opcode::LoadLiteral {
+                            dest: 5,
+                            literal_id: 0,
+                        },
+                        /* 4 */ Opcode::LoadGlobal { dest: 5, name: 5 },
+                        /* 5 */
+                        Opcode::LoadInteger {
+                            dest: 8,
+                            integer: 1,
+                        },
+                        /* 6 */
+                        Opcode::Subtract {
+                            dest: 7,
+                            left: 2,
+                            right: 8,
+                        },
+                        /* 7 */
+                        Opcode::Call {
+                            function: 5,
+                            dest: 5,
+                            arg_count: 1,
+                        },
+                        /* 8 */
+                        Opcode::LoadInteger {
+                            dest: 4,
+                            integer: 1,
+                        },
+                        /* 9 */
+                        Opcode::Add {
+                            dest: 5,
+                            reg1: 5,
+                            reg2: 4,
+                        },
+                        /* 10 */ Opcode::Return { reg: 5 },
+                        /* 11 */ Opcode::Return { reg: 2 },
+                    ],
+                )?;
+
+                // depth(1000)
+                let main = function(
+                    mem,
+                    "main",
+                    &[],
+                    &[
+                        depth.as_tagged(mem),
+                        mem.lookup_sym("depth"),
+                        TaggedScopedPtr::new(mem, TaggedPtr::number(1000)),
+                    ],
+                    &[
+                        Opcode::LoadLiteral {
+                            dest: 2,
+                            literal_id: 0,
+                        },
+                        Opcode::LoadLiteral {
+                            dest: 3,
+                            literal_id: 1,
+                        },
+                        Opcode::StoreGlobal { src: 2, name: 3 },
+                        Opcode::LoadLiteral {
+                            dest: 5,
+                            literal_id: 2,
+                        },
+                        Opcode::Call {
+                            function: 2,
+                            dest: 3,
+                            arg_count: 1,
+                        },
+                        Opcode::Return { reg: 3 },
+                    ],
+                )?;
+
+                // Within the default limits the recursion completes
+                let thread = Thread::alloc(mem)?;
+                let result = thread.quick_vm_eval(mem, main)?;
+                assert!(matches!(*result, Value::Number(1000)), "got {}", result);
+
+                let overflow = |limits: StackLimits| -> Result<String, RuntimeError> {
+                    let thread = Thread::alloc(mem)?;
+                    thread.set_limits(limits);
+                    match thread.quick_vm_eval(mem, main) {
+                        Err(e) => match e.error_kind() {
+                            ErrorKind::StackOverflow(reason) => Ok(reason.clone()),
+                            _ => panic!("expected a stack overflow, got {}", e),
+                        },
+                        Ok(value) => panic!("expected a stack overflow, got {}", value),
+                    }
+                };
+
+                // Too many call frames
+                let reason = overflow(StackLimits {
+                    max_call_depth: 100,
+                    ..StackLimits::default()
+                })?;
+                assert!(reason.starts_with("call depth exceeded 100 frames"));
+
+                // The traceback shows only each end of the 99 frames above main
+                let lines: Vec<&str> = reason.lines().skip(1).collect();
+                assert!(lines.len() == 2 * TRACEBACK_FRAMES + 1);
+                assert!(lines[TRACEBACK_FRAMES] == "  ... 79 frames omitted ...");
+
+                // Too many value stack slots
+                let reason = overflow(StackLimits {
+                    max_stack_size: 1024,
+                    ..StackLimits::default()
+                })?;
+                assert!(reason.starts_with("value stack exceeded 1024 slots"));
+
+                Ok(())
+            }
+        }
+
+        let test = Test {};
+        mem.mutate(&test, ()).unwrap();
+    }
+}
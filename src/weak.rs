@@ -0,0 +1,73 @@
+/// Weak references to heap objects
+///
+/// A `WeakRef` points at another object without keeping it alive: its target is not traced, so
+/// if nothing else reaches the target a collection leaves it unmarked and then clears the
+/// `WeakRef`, which reads as `nil` from then on.
+use std::cell::Cell;
+use std::fmt;
+
+use crate::error::RuntimeError;
+use crate::gc::register_weak_ref;
+use crate::memory::MutatorView;
+use crate::printer::Print;
+use crate::safe_ptr::{MutatorScope, ScopedPtr, TaggedScopedPtr};
+use crate::tagged_ptr::TaggedPtr;
+
+/// A reference that does not keep its target alive
+// ANCHOR: DefWeakRef
+pub struct WeakRef {
+    // Deliberately not a TaggedCellPtr: writes here must not go through the write barrier,
+    // which would remember the target and keep it alive for another cycle.
+    target: Cell<TaggedPtr>,
+}
+// ANCHOR_END: DefWeakRef
+
+impl WeakRef {
+    /// Allocate a new WeakRef to the given target and register it with the collector of the heap
+    /// it is allocated in
+    pub fn alloc<'guard>(
+        mem: &'guard MutatorView,
+        target: TaggedScopedPtr<'guard>,
+    ) -> Result<ScopedPtr<'guard, WeakRef>, RuntimeError> {
+        let weak = mem.alloc(WeakRef {
+            target: Cell::new(target.get_ptr()),
+        })?;
+
+        register_weak_ref(&*weak);
+
+        Ok(weak)
+    }
+
+    /// Return the target, or nil if it has been collected
+    pub fn get<'guard>(&self, guard: &'guard dyn MutatorScope) -> TaggedScopedPtr<'guard> {
+        TaggedScopedPtr::new(guard, self.target.get())
+    }
+
+    /// Return the raw target pointer
+    pub fn get_ptr(&self) -> TaggedPtr {
+        self.target.get()
+    }
+
+    /// Return true if the target has been collected
+    pub fn is_cleared(&self) -> bool {
+        self.target.get().is_nil()
+    }
+
+    /// Drop the reference to the target
+    pub fn clear(&self) {
+        self.target.set(TaggedPtr::nil())
+    }
+}
+
+impl Print for WeakRef {
+    fn print<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+        f: &mut fmt::Formatter,
+    ) -> fmt::Result {
+        match self.is_cleared() {
+            true => write!(f, "(WeakRef)"),
+            false => write!(f, "(WeakRef {})", self.get(guard)),
+        }
+    }
+}